@@ -0,0 +1,747 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::csr;
+use crate::encode::encode;
+use crate::instructions::Instruction::{self, *};
+use crate::instructions::{FREG_NAMES, REG_NAMES};
+
+/// Error assembling a line of source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// Line number, starting from 1
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles RV32 source into machine code for a program placed at `base`.
+///
+/// Each line holds optional `label:`s followed by an instruction or `.word` directive, and
+/// `#` starts a comment. Operands are written as `llvm-mc` prints them: x or ABI register
+/// names, immediates in decimal, hex or binary, loads and stores as `imm(rs1)`, atomics as
+/// `(rs1)` with optional `.aq`/`.rl`/`.aqrl` suffixes, and CSRs by number or name. Branch
+/// and jump targets are labels or byte offsets from the instruction. The common
+/// pseudo-instructions are accepted (`li`, `la`, `mv`, `j`, `ret`, `beqz`, `csrr`, ...).
+/// Nothing is compressed.
+pub fn assemble(src: &str, base: u32) -> Result<Vec<u32>, AsmError> {
+    // A label's address only depends on the size of the lines before it, so the first pass
+    // finds them all with labels that are not known yet resolving to the instruction itself
+    let mut labels = HashMap::new();
+    let mut words = Vec::new();
+    for final_pass in [false, true] {
+        words.clear();
+        for (idx, text) in src.lines().enumerate() {
+            let error = |msg| AsmError { line: idx + 1, msg };
+            let pc = base.wrapping_add(4 * words.len() as u32);
+            let mut text = text.split('#').next().unwrap_or_default().trim();
+            while let Some((label, rest)) = text.split_once(':') {
+                if !is_label(label.trim()) {
+                    break;
+                }
+                if !final_pass && labels.insert(label.trim(), pc).is_some() {
+                    return Err(error(format!("label {} defined twice", label.trim())));
+                }
+                text = rest.trim();
+            }
+            if text.is_empty() {
+                continue;
+            }
+            let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+            let ops: Vec<&str> = match rest.trim() {
+                "" => Vec::new(),
+                rest => rest.split(',').map(str::trim).collect(),
+            };
+            if mnemonic == ".word" {
+                for op in ops {
+                    let val = int(op).map_err(error)?;
+                    if !(-(1 << 31)..1 << 32).contains(&val) {
+                        return Err(error(format!("{} does not fit in a word", op)));
+                    }
+                    words.push(val as u32);
+                }
+                continue;
+            }
+            let line = Line {
+                labels: &labels,
+                pc,
+                final_pass,
+            };
+            let insts = line.assemble(mnemonic, &ops).map_err(error)?;
+            words.extend(insts.iter().map(encode));
+        }
+    }
+    Ok(words)
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Operands in order, or an error if there are the wrong number of them
+fn args<'a, const N: usize>(ops: &[&'a str]) -> Result<[&'a str; N], String> {
+    ops.try_into()
+        .map_err(|_| format!("expected {} operands, found {}", N, ops.len()))
+}
+
+fn int(s: &str) -> Result<i64, String> {
+    let (neg, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let val = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        digits.parse()
+    }
+    .map_err(|_| format!("bad immediate {}", s))?;
+    Ok(if neg { -val } else { val })
+}
+
+/// Integer in `min..=max`
+fn ranged(s: &str, min: i64, max: i64) -> Result<i64, String> {
+    let val = int(s)?;
+    if (min..=max).contains(&val) {
+        Ok(val)
+    } else {
+        Err(format!("{} is outside {}..={}", s, min, max))
+    }
+}
+
+fn imm12(s: &str) -> Result<i32, String> {
+    ranged(s, -2048, 2047).map(|val| val as i32)
+}
+
+/// Shift amounts, bit indices and CSR immediates
+fn uimm5(s: &str) -> Result<u32, String> {
+    ranged(s, 0, 31).map(|val| val as u32)
+}
+
+fn reg(s: &str) -> Result<u32, String> {
+    let num = match s.strip_prefix('x') {
+        Some(num) => num.parse().ok().filter(|&num| num < 32),
+        None if s == "fp" => Some(8),
+        None => REG_NAMES
+            .iter()
+            .position(|&name| name == s)
+            .map(|num| num as u32),
+    };
+    num.ok_or_else(|| format!("bad register {}", s))
+}
+
+fn freg(s: &str) -> Result<u32, String> {
+    let num = match s.strip_prefix('f').and_then(|num| num.parse().ok()) {
+        Some(num) if num < 32 => Some(num),
+        _ => FREG_NAMES
+            .iter()
+            .position(|&name| name == s)
+            .map(|num| num as u32),
+    };
+    num.ok_or_else(|| format!("bad FP register {}", s))
+}
+
+/// `imm(rs1)`, the immediate may be left out
+fn mem(s: &str) -> Result<(i32, u32), String> {
+    let (imm, rest) = s
+        .split_once('(')
+        .ok_or_else(|| format!("expected imm(reg), found {}", s))?;
+    let rs1 = rest
+        .strip_suffix(')')
+        .ok_or_else(|| format!("expected imm(reg), found {}", s))?;
+    let imm = match imm.trim() {
+        "" => 0,
+        imm => imm12(imm)?,
+    };
+    Ok((imm, reg(rs1.trim())?))
+}
+
+/// `(rs1)` of an atomic
+fn amo_addr(s: &str) -> Result<u32, String> {
+    match mem(s)? {
+        (0, rs1) => Ok(rs1),
+        _ => Err(format!("atomics take no offset, found {}", s)),
+    }
+}
+
+fn rounding_mode(s: &str) -> Result<u32, String> {
+    match s {
+        "rne" => Ok(0),
+        "rtz" => Ok(1),
+        "rdn" => Ok(2),
+        "rup" => Ok(3),
+        "rmm" => Ok(4),
+        "dyn" => Ok(7),
+        // Reserved modes can only be given by number
+        _ => ranged(s, 0, 7)
+            .map(|rm| rm as u32)
+            .map_err(|_| format!("bad rounding mode {}", s)),
+    }
+}
+
+fn csr_num(s: &str) -> Result<u32, String> {
+    match csr::from_name(s) {
+        Some(num) => Ok(num),
+        None => ranged(s, 0, 0xFFF).map(|num| num as u32),
+    }
+}
+
+/// IORW set of a fence
+fn fence_set(s: &str) -> Result<u32, String> {
+    if s == "0" {
+        return Ok(0);
+    }
+    let mut set = 0;
+    let mut rest = s;
+    for (bit, c) in [(8, 'i'), (4, 'o'), (2, 'r'), (1, 'w')] {
+        if let Some(after) = rest.strip_prefix(c) {
+            set |= bit;
+            rest = after;
+        }
+    }
+    if set == 0 || !rest.is_empty() {
+        return Err(format!("bad fence set {}", s));
+    }
+    Ok(set)
+}
+
+/// Splits `lui`+`addi` style so the low part is a sign extended 12-bit immediate
+fn hi_lo(val: u32) -> (i32, i32) {
+    let hi = val.wrapping_add(0x800) & 0xFFFF_F000;
+    (hi as i32, val.wrapping_sub(hi) as i32)
+}
+
+/// Context of the line being assembled
+struct Line<'a> {
+    labels: &'a HashMap<&'a str, u32>,
+    pc: u32,
+    final_pass: bool,
+}
+
+impl Line<'_> {
+    /// Offset from this instruction to a label, or a literal offset, that must fit a signed
+    /// `bits`-bit immediate and be even
+    fn offset(&self, s: &str, bits: u32) -> Result<i32, String> {
+        let offset = if is_label(s) {
+            match self.labels.get(s) {
+                Some(&addr) => addr.wrapping_sub(self.pc) as i32 as i64,
+                None if !self.final_pass => 0,
+                None => return Err(format!("undefined label {}", s)),
+            }
+        } else {
+            int(s)?
+        };
+        let limit = 1 << (bits - 1);
+        if !(-limit..limit).contains(&offset) || offset % 2 != 0 {
+            return Err(format!("target {} out of range or misaligned", s));
+        }
+        Ok(offset as i32)
+    }
+
+    #[rustfmt::skip]
+    fn assemble(&self, mnemonic: &str, ops: &[&str]) -> Result<Vec<Instruction>, String> {
+        if let Some(insts) = self.pseudo(mnemonic, ops)? {
+            return Ok(insts);
+        }
+        // Ordering bits of atomics come as a suffix
+        let (mnemonic, aq, rl) = if let Some(base) = mnemonic.strip_suffix(".aqrl") {
+            (base, true, true)
+        } else if let Some(base) = mnemonic.strip_suffix(".aq") {
+            (base, true, false)
+        } else if let Some(base) = mnemonic.strip_suffix(".rl") {
+            (base, false, true)
+        } else {
+            (mnemonic, false, false)
+        };
+        if (aq || rl) && !(mnemonic.starts_with("amo") || matches!(mnemonic, "lr.w" | "sc.w")) {
+            return Err(format!("{} takes no ordering suffix", mnemonic));
+        }
+
+        // Operand shapes, named after the register files and immediates they take
+        let xxx = || -> Result<(u32, u32, u32), String> {
+            let [rd, rs1, rs2] = args(ops)?;
+            Ok((reg(rd)?, reg(rs1)?, reg(rs2)?))
+        };
+        let xx = || -> Result<(u32, u32), String> {
+            let [rd, rs1] = args(ops)?;
+            Ok((reg(rd)?, reg(rs1)?))
+        };
+        let xxi = || -> Result<(u32, u32, i32), String> {
+            let [rd, rs1, imm] = args(ops)?;
+            Ok((reg(rd)?, reg(rs1)?, imm12(imm)?))
+        };
+        let xxu = || -> Result<(u32, u32, u32), String> {
+            let [rd, rs1, shamt] = args(ops)?;
+            Ok((reg(rd)?, reg(rs1)?, uimm5(shamt)?))
+        };
+        let xm = || -> Result<(u32, u32, i32), String> {
+            let [rd, addr] = args(ops)?;
+            let (imm, rs1) = mem(addr)?;
+            Ok((reg(rd)?, rs1, imm))
+        };
+        let fm = || -> Result<(u32, u32, i32), String> {
+            let [rd, addr] = args(ops)?;
+            let (imm, rs1) = mem(addr)?;
+            Ok((freg(rd)?, rs1, imm))
+        };
+        let branch = || -> Result<(u32, u32, i32), String> {
+            let [rs1, rs2, target] = args(ops)?;
+            Ok((reg(rs1)?, reg(rs2)?, self.offset(target, 13)?))
+        };
+        let amo = || -> Result<(u32, u32, u32), String> {
+            let [rd, rs2, addr] = args(ops)?;
+            Ok((reg(rd)?, amo_addr(addr)?, reg(rs2)?))
+        };
+        let csr = || -> Result<(u32, u32, u32), String> {
+            let [rd, csr, rs1] = args(ops)?;
+            Ok((reg(rd)?, csr_num(csr)?, reg(rs1)?))
+        };
+        let csri = || -> Result<(u32, u32, u32), String> {
+            let [rd, csr, uimm] = args(ops)?;
+            Ok((reg(rd)?, csr_num(csr)?, uimm5(uimm)?))
+        };
+        let fff = || -> Result<(u32, u32, u32), String> {
+            let [rd, rs1, rs2] = args(ops)?;
+            Ok((freg(rd)?, freg(rs1)?, freg(rs2)?))
+        };
+        let xff = || -> Result<(u32, u32, u32), String> {
+            let [rd, rs1, rs2] = args(ops)?;
+            Ok((reg(rd)?, freg(rs1)?, freg(rs2)?))
+        };
+        // Rounding mode is an optional last operand
+        let with_rm = |count: usize, default: u32| -> Result<u32, String> {
+            match ops.len() {
+                n if n == count => Ok(default),
+                n if n == count + 1 => rounding_mode(ops[count]),
+                n => Err(format!("expected {} operands, found {}", count, n)),
+            }
+        };
+        let ffff_rm = || -> Result<(u32, u32, u32, u32, u32), String> {
+            let rm = with_rm(4, 7)?;
+            let (rd, rs1, rs2, rs3) = (freg(ops[0])?, freg(ops[1])?, freg(ops[2])?, freg(ops[3])?);
+            Ok((rd, rs1, rs2, rs3, rm))
+        };
+        let fff_rm = || -> Result<(u32, u32, u32, u32), String> {
+            let rm = with_rm(3, 7)?;
+            Ok((freg(ops[0])?, freg(ops[1])?, freg(ops[2])?, rm))
+        };
+        // Conversions, `to`/`from` parse the destination and source, exact ones default to rne
+        let cvt = |to: fn(&str) -> Result<u32, String>,
+                   from: fn(&str) -> Result<u32, String>,
+                   default: u32|
+         -> Result<(u32, u32, u32), String> {
+            let rm = with_rm(2, default)?;
+            Ok((to(ops[0])?, from(ops[1])?, rm))
+        };
+
+        let inst = match mnemonic {
+            "lb" => xm().map(|(rd, rs1, imm)| Lb { rd, rs1, imm }),
+            "lh" => xm().map(|(rd, rs1, imm)| Lh { rd, rs1, imm }),
+            "lw" => xm().map(|(rd, rs1, imm)| Lw { rd, rs1, imm }),
+            "lbu" => xm().map(|(rd, rs1, imm)| Lbu { rd, rs1, imm }),
+            "lhu" => xm().map(|(rd, rs1, imm)| Lhu { rd, rs1, imm }),
+            "sb" => xm().map(|(rs2, rs1, imm)| Sb { rs1, rs2, imm }),
+            "sh" => xm().map(|(rs2, rs1, imm)| Sh { rs1, rs2, imm }),
+            "sw" => xm().map(|(rs2, rs1, imm)| Sw { rs1, rs2, imm }),
+
+            "sll" => xxx().map(|(rd, rs1, rs2)| Sll { rd, rs1, rs2 }),
+            "slli" => xxu().map(|(rd, rs1, shamt)| Slli { rd, rs1, shamt }),
+            "srl" => xxx().map(|(rd, rs1, rs2)| Srl { rd, rs1, rs2 }),
+            "srli" => xxu().map(|(rd, rs1, shamt)| Srli { rd, rs1, shamt }),
+            "sra" => xxx().map(|(rd, rs1, rs2)| Sra { rd, rs1, rs2 }),
+            "srai" => xxu().map(|(rd, rs1, shamt)| Srai { rd, rs1, shamt }),
+
+            "add" => xxx().map(|(rd, rs1, rs2)| Add { rd, rs1, rs2 }),
+            "addi" => xxi().map(|(rd, rs1, imm)| Addi { rd, rs1, imm }),
+            "sub" => xxx().map(|(rd, rs1, rs2)| Sub { rd, rs1, rs2 }),
+            "lui" | "auipc" => {
+                let [rd, imm] = args(ops)?;
+                let (rd, imm) = (reg(rd)?, (ranged(imm, 0, 0xFFFFF)? << 12) as i32);
+                Ok(if mnemonic == "lui" { Lui { rd, imm } } else { Auipc { rd, imm } })
+            }
+
+            "xor" => xxx().map(|(rd, rs1, rs2)| Xor { rd, rs1, rs2 }),
+            "xori" => xxi().map(|(rd, rs1, imm)| Xori { rd, rs1, imm }),
+            "or" => xxx().map(|(rd, rs1, rs2)| Or { rd, rs1, rs2 }),
+            "ori" => xxi().map(|(rd, rs1, imm)| Ori { rd, rs1, imm }),
+            "and" => xxx().map(|(rd, rs1, rs2)| And { rd, rs1, rs2 }),
+            "andi" => xxi().map(|(rd, rs1, imm)| Andi { rd, rs1, imm }),
+
+            "slt" => xxx().map(|(rd, rs1, rs2)| Slt { rd, rs1, rs2 }),
+            "slti" => xxi().map(|(rd, rs1, imm)| Slti { rd, rs1, imm }),
+            "sltu" => xxx().map(|(rd, rs1, rs2)| Sltu { rd, rs1, rs2 }),
+            "sltiu" => xxi().map(|(rd, rs1, imm)| Sltiu { rd, rs1, imm }),
+
+            "beq" => branch().map(|(rs1, rs2, imm)| Beq { rs1, rs2, imm }),
+            "bne" => branch().map(|(rs1, rs2, imm)| Bne { rs1, rs2, imm }),
+            "blt" => branch().map(|(rs1, rs2, imm)| Blt { rs1, rs2, imm }),
+            "bge" => branch().map(|(rs1, rs2, imm)| Bge { rs1, rs2, imm }),
+            "bltu" => branch().map(|(rs1, rs2, imm)| Bltu { rs1, rs2, imm }),
+            "bgeu" => branch().map(|(rs1, rs2, imm)| Bgeu { rs1, rs2, imm }),
+
+            "jal" => match *ops {
+                [target] => Ok(Jal { rd: 1, imm: self.offset(target, 21)? }),
+                [rd, target] => Ok(Jal { rd: reg(rd)?, imm: self.offset(target, 21)? }),
+                _ => Err(format!("expected 2 operands, found {}", ops.len())),
+            },
+            "jalr" => match *ops {
+                [rs1] => Ok(Jalr { rd: 1, rs1: reg(rs1)?, imm: 0 }),
+                [_, _] => xm().map(|(rd, rs1, imm)| Jalr { rd, rs1, imm }),
+                _ => xxi().map(|(rd, rs1, imm)| Jalr { rd, rs1, imm }),
+            },
+
+            "mul" => xxx().map(|(rd, rs1, rs2)| Mul { rd, rs1, rs2 }),
+            "mulh" => xxx().map(|(rd, rs1, rs2)| Mulh { rd, rs1, rs2 }),
+            "mulhsu" => xxx().map(|(rd, rs1, rs2)| Mulhsu { rd, rs1, rs2 }),
+            "mulhu" => xxx().map(|(rd, rs1, rs2)| Mulhu { rd, rs1, rs2 }),
+            "div" => xxx().map(|(rd, rs1, rs2)| Div { rd, rs1, rs2 }),
+            "divu" => xxx().map(|(rd, rs1, rs2)| Divu { rd, rs1, rs2 }),
+            "rem" => xxx().map(|(rd, rs1, rs2)| Rem { rd, rs1, rs2 }),
+            "remu" => xxx().map(|(rd, rs1, rs2)| Remu { rd, rs1, rs2 }),
+
+            "sh1add" => xxx().map(|(rd, rs1, rs2)| Sh1add { rd, rs1, rs2 }),
+            "sh2add" => xxx().map(|(rd, rs1, rs2)| Sh2add { rd, rs1, rs2 }),
+            "sh3add" => xxx().map(|(rd, rs1, rs2)| Sh3add { rd, rs1, rs2 }),
+
+            "andn" => xxx().map(|(rd, rs1, rs2)| Andn { rd, rs1, rs2 }),
+            "orn" => xxx().map(|(rd, rs1, rs2)| Orn { rd, rs1, rs2 }),
+            "xnor" => xxx().map(|(rd, rs1, rs2)| Xnor { rd, rs1, rs2 }),
+            "clz" => xx().map(|(rd, rs1)| Clz { rd, rs1 }),
+            "ctz" => xx().map(|(rd, rs1)| Ctz { rd, rs1 }),
+            "cpop" => xx().map(|(rd, rs1)| Cpop { rd, rs1 }),
+            "max" => xxx().map(|(rd, rs1, rs2)| Max { rd, rs1, rs2 }),
+            "maxu" => xxx().map(|(rd, rs1, rs2)| Maxu { rd, rs1, rs2 }),
+            "min" => xxx().map(|(rd, rs1, rs2)| Min { rd, rs1, rs2 }),
+            "minu" => xxx().map(|(rd, rs1, rs2)| Minu { rd, rs1, rs2 }),
+            "sext.b" => xx().map(|(rd, rs1)| SextB { rd, rs1 }),
+            "sext.h" => xx().map(|(rd, rs1)| SextH { rd, rs1 }),
+            "zext.h" => xx().map(|(rd, rs1)| ZextH { rd, rs1 }),
+            "rol" => xxx().map(|(rd, rs1, rs2)| Rol { rd, rs1, rs2 }),
+            "ror" => xxx().map(|(rd, rs1, rs2)| Ror { rd, rs1, rs2 }),
+            "rori" => xxu().map(|(rd, rs1, shamt)| Rori { rd, rs1, shamt }),
+            "orc.b" => xx().map(|(rd, rs1)| OrcB { rd, rs1 }),
+            "rev8" => xx().map(|(rd, rs1)| Rev8 { rd, rs1 }),
+
+            "bclr" => xxx().map(|(rd, rs1, rs2)| Bclr { rd, rs1, rs2 }),
+            "bclri" => xxu().map(|(rd, rs1, shamt)| Bclri { rd, rs1, shamt }),
+            "bext" => xxx().map(|(rd, rs1, rs2)| Bext { rd, rs1, rs2 }),
+            "bexti" => xxu().map(|(rd, rs1, shamt)| Bexti { rd, rs1, shamt }),
+            "binv" => xxx().map(|(rd, rs1, rs2)| Binv { rd, rs1, rs2 }),
+            "binvi" => xxu().map(|(rd, rs1, shamt)| Binvi { rd, rs1, shamt }),
+            "bset" => xxx().map(|(rd, rs1, rs2)| Bset { rd, rs1, rs2 }),
+            "bseti" => xxu().map(|(rd, rs1, shamt)| Bseti { rd, rs1, shamt }),
+
+            "clmul" => xxx().map(|(rd, rs1, rs2)| Clmul { rd, rs1, rs2 }),
+            "clmulh" => xxx().map(|(rd, rs1, rs2)| Clmulh { rd, rs1, rs2 }),
+            "clmulr" => xxx().map(|(rd, rs1, rs2)| Clmulr { rd, rs1, rs2 }),
+
+            "lr.w" => {
+                let [rd, addr] = args(ops)?;
+                Ok(LrW { rd: reg(rd)?, rs1: amo_addr(addr)?, aq, rl })
+            }
+            "sc.w" => amo().map(|(rd, rs1, rs2)| ScW { rd, rs1, rs2, aq, rl }),
+            "amoswap.w" => amo().map(|(rd, rs1, rs2)| AmoswapW { rd, rs1, rs2, aq, rl }),
+            "amoadd.w" => amo().map(|(rd, rs1, rs2)| AmoaddW { rd, rs1, rs2, aq, rl }),
+            "amoxor.w" => amo().map(|(rd, rs1, rs2)| AmoxorW { rd, rs1, rs2, aq, rl }),
+            "amoand.w" => amo().map(|(rd, rs1, rs2)| AmoandW { rd, rs1, rs2, aq, rl }),
+            "amoor.w" => amo().map(|(rd, rs1, rs2)| AmoorW { rd, rs1, rs2, aq, rl }),
+            "amomin.w" => amo().map(|(rd, rs1, rs2)| AmominW { rd, rs1, rs2, aq, rl }),
+            "amomax.w" => amo().map(|(rd, rs1, rs2)| AmomaxW { rd, rs1, rs2, aq, rl }),
+            "amominu.w" => amo().map(|(rd, rs1, rs2)| AmominuW { rd, rs1, rs2, aq, rl }),
+            "amomaxu.w" => amo().map(|(rd, rs1, rs2)| AmomaxuW { rd, rs1, rs2, aq, rl }),
+
+            "flw" => fm().map(|(rd, rs1, imm)| Flw { rd, rs1, imm }),
+            "fld" => fm().map(|(rd, rs1, imm)| Fld { rd, rs1, imm }),
+            "fsw" => fm().map(|(rs2, rs1, imm)| Fsw { rs1, rs2, imm }),
+            "fsd" => fm().map(|(rs2, rs1, imm)| Fsd { rs1, rs2, imm }),
+
+            "fmadd.s" => ffff_rm().map(|(rd, rs1, rs2, rs3, rm)| FmaddS { rd, rs1, rs2, rs3, rm }),
+            "fmsub.s" => ffff_rm().map(|(rd, rs1, rs2, rs3, rm)| FmsubS { rd, rs1, rs2, rs3, rm }),
+            "fnmsub.s" => ffff_rm().map(|(rd, rs1, rs2, rs3, rm)| FnmsubS { rd, rs1, rs2, rs3, rm }),
+            "fnmadd.s" => ffff_rm().map(|(rd, rs1, rs2, rs3, rm)| FnmaddS { rd, rs1, rs2, rs3, rm }),
+            "fmadd.d" => ffff_rm().map(|(rd, rs1, rs2, rs3, rm)| FmaddD { rd, rs1, rs2, rs3, rm }),
+            "fmsub.d" => ffff_rm().map(|(rd, rs1, rs2, rs3, rm)| FmsubD { rd, rs1, rs2, rs3, rm }),
+            "fnmsub.d" => ffff_rm().map(|(rd, rs1, rs2, rs3, rm)| FnmsubD { rd, rs1, rs2, rs3, rm }),
+            "fnmadd.d" => ffff_rm().map(|(rd, rs1, rs2, rs3, rm)| FnmaddD { rd, rs1, rs2, rs3, rm }),
+
+            "fadd.s" => fff_rm().map(|(rd, rs1, rs2, rm)| FaddS { rd, rs1, rs2, rm }),
+            "fsub.s" => fff_rm().map(|(rd, rs1, rs2, rm)| FsubS { rd, rs1, rs2, rm }),
+            "fmul.s" => fff_rm().map(|(rd, rs1, rs2, rm)| FmulS { rd, rs1, rs2, rm }),
+            "fdiv.s" => fff_rm().map(|(rd, rs1, rs2, rm)| FdivS { rd, rs1, rs2, rm }),
+            "fsqrt.s" => cvt(freg, freg, 7).map(|(rd, rs1, rm)| FsqrtS { rd, rs1, rm }),
+            "fadd.d" => fff_rm().map(|(rd, rs1, rs2, rm)| FaddD { rd, rs1, rs2, rm }),
+            "fsub.d" => fff_rm().map(|(rd, rs1, rs2, rm)| FsubD { rd, rs1, rs2, rm }),
+            "fmul.d" => fff_rm().map(|(rd, rs1, rs2, rm)| FmulD { rd, rs1, rs2, rm }),
+            "fdiv.d" => fff_rm().map(|(rd, rs1, rs2, rm)| FdivD { rd, rs1, rs2, rm }),
+            "fsqrt.d" => cvt(freg, freg, 7).map(|(rd, rs1, rm)| FsqrtD { rd, rs1, rm }),
+
+            "fsgnj.s" => fff().map(|(rd, rs1, rs2)| FsgnjS { rd, rs1, rs2 }),
+            "fsgnjn.s" => fff().map(|(rd, rs1, rs2)| FsgnjnS { rd, rs1, rs2 }),
+            "fsgnjx.s" => fff().map(|(rd, rs1, rs2)| FsgnjxS { rd, rs1, rs2 }),
+            "fmin.s" => fff().map(|(rd, rs1, rs2)| FminS { rd, rs1, rs2 }),
+            "fmax.s" => fff().map(|(rd, rs1, rs2)| FmaxS { rd, rs1, rs2 }),
+            "fsgnj.d" => fff().map(|(rd, rs1, rs2)| FsgnjD { rd, rs1, rs2 }),
+            "fsgnjn.d" => fff().map(|(rd, rs1, rs2)| FsgnjnD { rd, rs1, rs2 }),
+            "fsgnjx.d" => fff().map(|(rd, rs1, rs2)| FsgnjxD { rd, rs1, rs2 }),
+            "fmin.d" => fff().map(|(rd, rs1, rs2)| FminD { rd, rs1, rs2 }),
+            "fmax.d" => fff().map(|(rd, rs1, rs2)| FmaxD { rd, rs1, rs2 }),
+
+            "feq.s" => xff().map(|(rd, rs1, rs2)| FeqS { rd, rs1, rs2 }),
+            "flt.s" => xff().map(|(rd, rs1, rs2)| FltS { rd, rs1, rs2 }),
+            "fle.s" => xff().map(|(rd, rs1, rs2)| FleS { rd, rs1, rs2 }),
+            "fclass.s" => cvt(reg, freg, 0).map(|(rd, rs1, _)| FclassS { rd, rs1 }),
+            "feq.d" => xff().map(|(rd, rs1, rs2)| FeqD { rd, rs1, rs2 }),
+            "flt.d" => xff().map(|(rd, rs1, rs2)| FltD { rd, rs1, rs2 }),
+            "fle.d" => xff().map(|(rd, rs1, rs2)| FleD { rd, rs1, rs2 }),
+            "fclass.d" => cvt(reg, freg, 0).map(|(rd, rs1, _)| FclassD { rd, rs1 }),
+
+            "fcvt.w.s" => cvt(reg, freg, 7).map(|(rd, rs1, rm)| FcvtWS { rd, rs1, rm }),
+            "fcvt.wu.s" => cvt(reg, freg, 7).map(|(rd, rs1, rm)| FcvtWuS { rd, rs1, rm }),
+            "fcvt.s.w" => cvt(freg, reg, 7).map(|(rd, rs1, rm)| FcvtSW { rd, rs1, rm }),
+            "fcvt.s.wu" => cvt(freg, reg, 7).map(|(rd, rs1, rm)| FcvtSWu { rd, rs1, rm }),
+            "fcvt.w.d" => cvt(reg, freg, 7).map(|(rd, rs1, rm)| FcvtWD { rd, rs1, rm }),
+            "fcvt.wu.d" => cvt(reg, freg, 7).map(|(rd, rs1, rm)| FcvtWuD { rd, rs1, rm }),
+            "fcvt.d.w" => cvt(freg, reg, 0).map(|(rd, rs1, rm)| FcvtDW { rd, rs1, rm }),
+            "fcvt.d.wu" => cvt(freg, reg, 0).map(|(rd, rs1, rm)| FcvtDWu { rd, rs1, rm }),
+            "fcvt.s.d" => cvt(freg, freg, 7).map(|(rd, rs1, rm)| FcvtSD { rd, rs1, rm }),
+            "fcvt.d.s" => cvt(freg, freg, 0).map(|(rd, rs1, rm)| FcvtDS { rd, rs1, rm }),
+            "fmv.x.w" => cvt(reg, freg, 0).map(|(rd, rs1, _)| FmvXW { rd, rs1 }),
+            "fmv.w.x" => cvt(freg, reg, 0).map(|(rd, rs1, _)| FmvWX { rd, rs1 }),
+
+            "fence" => match *ops {
+                [] => Ok(Fence { pred: 0xF, succ: 0xF, fm: 0 }),
+                [pred, succ] => Ok(Fence { pred: fence_set(pred)?, succ: fence_set(succ)?, fm: 0 }),
+                _ => Err(format!("expected 2 operands, found {}", ops.len())),
+            },
+            "fence.tso" => args::<0>(ops).map(|_| Fence { pred: 0x3, succ: 0x3, fm: 0x8 }),
+            "fence.i" => args::<0>(ops).map(|_| FenceI),
+            "pause" => args::<0>(ops).map(|_| Fence { pred: 0x1, succ: 0x0, fm: 0 }),
+
+            "ecall" => args::<0>(ops).map(|_| Ecall),
+            "ebreak" => args::<0>(ops).map(|_| Ebreak),
+            "mret" => args::<0>(ops).map(|_| Mret),
+            "unimp" => args::<0>(ops).map(|_| Ill),
+
+            "csrrw" => csr().map(|(rd, csr, rs1)| Csrrw { rd, rs1, csr }),
+            "csrrs" => csr().map(|(rd, csr, rs1)| Csrrs { rd, rs1, csr }),
+            "csrrc" => csr().map(|(rd, csr, rs1)| Csrrc { rd, rs1, csr }),
+            "csrrwi" => csri().map(|(rd, csr, uimm)| Csrrwi { rd, uimm, csr }),
+            "csrrsi" => csri().map(|(rd, csr, uimm)| Csrrsi { rd, uimm, csr }),
+            "csrrci" => csri().map(|(rd, csr, uimm)| Csrrci { rd, uimm, csr }),
+
+            _ => Err(format!("unknown instruction {}", mnemonic)),
+        }?;
+        Ok(vec![inst])
+    }
+
+    /// Expands a pseudo-instruction, or returns `None` if `mnemonic` is not one
+    #[rustfmt::skip]
+    fn pseudo(&self, mnemonic: &str, ops: &[&str]) -> Result<Option<Vec<Instruction>>, String> {
+        // Pseudo-instructions that are one real instruction with some operands filled in
+        let inst = match (mnemonic, ops) {
+            ("nop", []) => Addi { rd: 0, rs1: 0, imm: 0 },
+            ("mv", &[rd, rs1]) => Addi { rd: reg(rd)?, rs1: reg(rs1)?, imm: 0 },
+            ("not", &[rd, rs1]) => Xori { rd: reg(rd)?, rs1: reg(rs1)?, imm: -1 },
+            ("neg", &[rd, rs2]) => Sub { rd: reg(rd)?, rs1: 0, rs2: reg(rs2)? },
+            ("seqz", &[rd, rs1]) => Sltiu { rd: reg(rd)?, rs1: reg(rs1)?, imm: 1 },
+            ("snez", &[rd, rs2]) => Sltu { rd: reg(rd)?, rs1: 0, rs2: reg(rs2)? },
+            ("sltz", &[rd, rs1]) => Slt { rd: reg(rd)?, rs1: reg(rs1)?, rs2: 0 },
+            ("sgtz", &[rd, rs2]) => Slt { rd: reg(rd)?, rs1: 0, rs2: reg(rs2)? },
+            ("zext.b", &[rd, rs1]) => Andi { rd: reg(rd)?, rs1: reg(rs1)?, imm: 0xFF },
+
+            ("beqz", &[rs1, target]) => Beq { rs1: reg(rs1)?, rs2: 0, imm: self.offset(target, 13)? },
+            ("bnez", &[rs1, target]) => Bne { rs1: reg(rs1)?, rs2: 0, imm: self.offset(target, 13)? },
+            ("blez", &[rs2, target]) => Bge { rs1: 0, rs2: reg(rs2)?, imm: self.offset(target, 13)? },
+            ("bgez", &[rs1, target]) => Bge { rs1: reg(rs1)?, rs2: 0, imm: self.offset(target, 13)? },
+            ("bltz", &[rs1, target]) => Blt { rs1: reg(rs1)?, rs2: 0, imm: self.offset(target, 13)? },
+            ("bgtz", &[rs2, target]) => Blt { rs1: 0, rs2: reg(rs2)?, imm: self.offset(target, 13)? },
+            ("bgt", &[rs2, rs1, target]) => Blt { rs1: reg(rs1)?, rs2: reg(rs2)?, imm: self.offset(target, 13)? },
+            ("ble", &[rs2, rs1, target]) => Bge { rs1: reg(rs1)?, rs2: reg(rs2)?, imm: self.offset(target, 13)? },
+            ("bgtu", &[rs2, rs1, target]) => Bltu { rs1: reg(rs1)?, rs2: reg(rs2)?, imm: self.offset(target, 13)? },
+            ("bleu", &[rs2, rs1, target]) => Bgeu { rs1: reg(rs1)?, rs2: reg(rs2)?, imm: self.offset(target, 13)? },
+
+            ("j", &[target]) => Jal { rd: 0, imm: self.offset(target, 21)? },
+            ("jr", &[rs1]) => Jalr { rd: 0, rs1: reg(rs1)?, imm: 0 },
+            ("ret", []) => Jalr { rd: 0, rs1: 1, imm: 0 },
+
+            ("csrr", &[rd, csr]) => Csrrs { rd: reg(rd)?, rs1: 0, csr: csr_num(csr)? },
+            ("csrw", &[csr, rs1]) => Csrrw { rd: 0, rs1: reg(rs1)?, csr: csr_num(csr)? },
+            ("csrs", &[csr, rs1]) => Csrrs { rd: 0, rs1: reg(rs1)?, csr: csr_num(csr)? },
+            ("csrc", &[csr, rs1]) => Csrrc { rd: 0, rs1: reg(rs1)?, csr: csr_num(csr)? },
+            ("csrwi", &[csr, uimm]) => Csrrwi { rd: 0, uimm: uimm5(uimm)?, csr: csr_num(csr)? },
+            ("csrsi", &[csr, uimm]) => Csrrsi { rd: 0, uimm: uimm5(uimm)?, csr: csr_num(csr)? },
+            ("csrci", &[csr, uimm]) => Csrrci { rd: 0, uimm: uimm5(uimm)?, csr: csr_num(csr)? },
+            ("rdcycle", &[rd]) => Csrrs { rd: reg(rd)?, rs1: 0, csr: csr::CYCLE },
+            ("rdtime", &[rd]) => Csrrs { rd: reg(rd)?, rs1: 0, csr: csr::TIME },
+            ("rdinstret", &[rd]) => Csrrs { rd: reg(rd)?, rs1: 0, csr: csr::INSTRET },
+
+            ("fmv.s", &[rd, rs]) => FsgnjS { rd: freg(rd)?, rs1: freg(rs)?, rs2: freg(rs)? },
+            ("fneg.s", &[rd, rs]) => FsgnjnS { rd: freg(rd)?, rs1: freg(rs)?, rs2: freg(rs)? },
+            ("fabs.s", &[rd, rs]) => FsgnjxS { rd: freg(rd)?, rs1: freg(rs)?, rs2: freg(rs)? },
+            ("fmv.d", &[rd, rs]) => FsgnjD { rd: freg(rd)?, rs1: freg(rs)?, rs2: freg(rs)? },
+            ("fneg.d", &[rd, rs]) => FsgnjnD { rd: freg(rd)?, rs1: freg(rs)?, rs2: freg(rs)? },
+            ("fabs.d", &[rd, rs]) => FsgnjxD { rd: freg(rd)?, rs1: freg(rs)?, rs2: freg(rs)? },
+
+            // Pseudo-instructions that expand to a pair. Their size only depends on the
+            // immediate, never on a label, so the first pass sees the final layout.
+            ("li", &[rd, imm]) => {
+                let rd = reg(rd)?;
+                let val = ranged(imm, -(1 << 31), u32::MAX as i64)? as u32;
+                let (hi, lo) = hi_lo(val);
+                return Ok(Some(match hi {
+                    0 => vec![Addi { rd, rs1: 0, imm: lo }],
+                    _ if lo == 0 => vec![Lui { rd, imm: hi }],
+                    _ => vec![Lui { rd, imm: hi }, Addi { rd, rs1: rd, imm: lo }],
+                }));
+            }
+            ("la", &[rd, label]) => {
+                let rd = reg(rd)?;
+                let (hi, lo) = hi_lo(self.offset(label, 32)? as u32);
+                return Ok(Some(vec![Auipc { rd, imm: hi }, Addi { rd, rs1: rd, imm: lo }]));
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(vec![inst]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::core::CoreConfig;
+    use crate::components::memory::QueueMem;
+    use crate::cosim::CoSim;
+    use crate::instructions::decode_inst;
+
+    #[test]
+    fn matches_llvm_mc() {
+        let src = "
+            start:
+                addi a0, zero, 10
+                li a1, 0
+            loop: add a1, a1, a0    # labels can share a line
+                addi a0, a0, -1
+                bnez a0, loop
+                lw t0, 8(sp)
+                sw t0, -4(s0)
+                lui t1, 0x12345
+                jal ra, done
+                fadd.d fa0, fa1, fa2
+                fmadd.s ft0, ft1, ft2, ft3, rtz
+                fcvt.w.s a0, fa0, rtz
+                fcvt.d.w fa0, a0
+                amoadd.w.aqrl a0, a1, (a2)
+                lr.w.aq t0, (a1)
+                csrrw a0, mstatus, a1
+                csrrsi zero, mtvec, 3
+                fence rw, w
+                fence.i
+                srai a0, a0, 31
+                rori a0, a1, 7
+            done:
+                jalr zero, 0(ra)
+        ";
+        // llvm-mc -triple=riscv32 -mattr=+m,+a,+f,+d,+zbb,-relax -filetype=obj
+        let expected = [
+            0x00a0_0513,
+            0x0000_0593,
+            0x00a5_85b3,
+            0xfff5_0513,
+            0xfe05_1ce3,
+            0x0081_2283,
+            0xfe54_2e23,
+            0x1234_5337,
+            0x0340_00ef,
+            0x02c5_f553,
+            0x1820_9043,
+            0xc005_1553,
+            0xd205_0553,
+            0x06b6_252f,
+            0x1405_a2af,
+            0x3005_9573,
+            0x3051_e073,
+            0x0310_000f,
+            0x0000_100f,
+            0x41f5_5513,
+            0x6075_d513,
+            0x0000_8067,
+        ];
+        assert_eq!(assemble(src, 0x1000), Ok(expected.to_vec()));
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn expands_pseudo_instructions() {
+        let decoded = |src| {
+            let words = assemble(src, 0x1000).unwrap();
+            words.into_iter().map(decode_inst).collect::<Vec<_>>()
+        };
+        assert_eq!(decoded("li a0, -2048"), [Addi { rd: 10, rs1: 0, imm: -2048 }]);
+        assert_eq!(decoded("li a0, 0x12345000"), [Lui { rd: 10, imm: 0x12345000 }]);
+        assert_eq!(
+            decoded("li a0, 0xDEADBEEF"),
+            [Lui { rd: 10, imm: 0xDEADC000u32 as i32 }, Addi { rd: 10, rs1: 10, imm: -0x111 }]
+        );
+        assert_eq!(
+            decoded("la t0, data\n nop\n data: .word 7"),
+            [Auipc { rd: 5, imm: 0 }, Addi { rd: 5, rs1: 5, imm: 12 }, Addi { rd: 0, rs1: 0, imm: 0 }, Ill]
+        );
+        assert_eq!(decoded("back: bgt a0, a1, back"), [Blt { rs1: 11, rs2: 10, imm: 0 }]);
+        assert_eq!(decoded("j 2048"), [Jal { rd: 0, imm: 2048 }]);
+        assert_eq!(decoded("ret"), [Jalr { rd: 0, rs1: 1, imm: 0 }]);
+        assert_eq!(decoded("csrr a0, mepc"), [Csrrs { rd: 10, rs1: 0, csr: 0x341 }]);
+        assert_eq!(decoded("fneg.d fa0, fs0"), [FsgnjnD { rd: 10, rs1: 8, rs2: 8 }]);
+    }
+
+    #[test]
+    fn reports_errors_by_line() {
+        let error = |src| assemble(src, 0).unwrap_err();
+        assert_eq!(error("nop\nj nowhere").line, 2);
+        assert_eq!(
+            error("addi a0, a0, 2048").msg,
+            "2048 is outside -2048..=2047"
+        );
+        assert_eq!(error("add a0, a1, x32").msg, "bad register x32");
+        assert_eq!(error("add a0, a1").msg, "expected 3 operands, found 2");
+        assert_eq!(error("frob a0").msg, "unknown instruction frob");
+        assert_eq!(error("a: nop\na: nop").msg, "label a defined twice");
+        assert_eq!(
+            error("add.aq a0, a1, a2").msg,
+            "add takes no ordering suffix"
+        );
+        assert_eq!(
+            error("beq a0, a1, 4096").msg,
+            "target 4096 out of range or misaligned"
+        );
+    }
+
+    #[test]
+    fn runs_in_both_models() {
+        let src = "
+                li a0, 0
+                li t0, 10
+            loop:
+                add a0, a0, t0
+                addi t0, t0, -1
+                bnez t0, loop
+                li a7, 93       # exit with the sum
+                ecall
+        ";
+        let (mem, info) = QueueMem::with_program(0x1000, &assemble(src, 0x1000).unwrap());
+        let mut cosim = CoSim::new(mem, &info, CoreConfig::default());
+        cosim.run().unwrap();
+        assert_eq!(cosim.core().exit_code(), 55);
+    }
+}
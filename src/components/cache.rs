@@ -1,87 +1,1360 @@
-#[derive(Debug)]
+use super::component::Component;
+use super::memory::{AmoOp, MemType, Memory, MemoryTransaction, BLOCK_SIZE, BLOCK_SIZE_U};
+use super::replacement::{Replacement, ReplacementPolicy};
+use crate::instructions::MemWidth;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+type MemTransaction = Rc<RefCell<MemoryTransaction>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CacheTransaction {
     Busy,
     ReadStarted,
-    ReadDone(u32),
+    /// Value read, zero extended. A read-modify-write completes with the word's old value.
+    ReadDone(u64),
     WriteStarted,
     WriteDone,
+    /// Access to an address the memory behind the cache cannot serve
+    Fault,
 }
 
-/// Trait for Caches to implement reade and write operations.
+pub type Transaction = Rc<RefCell<CacheTransaction>>;
+
+/// Trait for caches to implement read and write operations.
 pub trait Cache {
     /// Loads a byte from cache
-    fn read_b(&mut self, addr: u32) -> &Transaction;
-    /// Loads a half-word from storage
-    fn read_h(&mut self, addr: u32) -> &Transaction;
+    fn read_b(&mut self, addr: u32) -> Transaction;
+    /// Loads a half-word from cache
+    fn read_h(&mut self, addr: u32) -> Transaction;
     /// Loads a word from cache
-    fn read_w(&mut self, addr: u32) -> &Transaction;
+    fn read_w(&mut self, addr: u32) -> Transaction;
+    /// Loads a double-word from cache, for FP loads
+    fn read_d(&mut self, addr: u32) -> Transaction;
 
     /// Stores a byte to cache
-    fn write_b(&mut self, addr: u32, val: u32) -> &Transaction;
+    fn write_b(&mut self, addr: u32, val: u32) -> Transaction;
     /// Stores a half-word to cache
-    fn write_h(&mut self, addr: u32, val: u32) -> &Transaction;
+    fn write_h(&mut self, addr: u32, val: u32) -> Transaction;
     /// Stores a word to cache
-    fn write_w(&mut self, addr: u32, val: u32) -> &Transaction;
-    /// Flush  line
-    fn flush_line(&mut self, addr: u32) -> &Transaction;
+    fn write_w(&mut self, addr: u32, val: u32) -> Transaction;
+    /// Stores a double-word to cache, for FP stores
+    fn write_d(&mut self, addr: u32, val: u64) -> Transaction;
+
+    /// Atomically replaces the word at `addr` with `op` applied to it and `val`, completing
+    /// with the old value. Same contract as `Memory::read_modify_write`.
+    fn read_modify_write(&mut self, addr: u32, op: AmoOp, val: u32) -> Transaction;
+
+    /// Flush line
+    // Nothing flushes single lines or whole caches yet, `fence.i` only needs `clean`
+    #[allow(dead_code)]
+    fn flush_line(&mut self, addr: u32) -> Transaction;
     /// Flush whole cache
-    fn flush(&mut self) -> &Transaction;
-
-}
-
-// fn read_byte(&mut self, addr: u32) -> u32 {
-//     if addr < self.elf_mem.len() as u32 {
-//         self.elf_mem[addr as usize] as u32
-//     } else {
-//         *self.stack.entry(addr).or_default() as u32
-//     }
-// }
-// fn write_byte(&mut self, addr: u32, val: u32) {
-//     if addr < self.elf_mem.len() as u32 {
-//         self.elf_mem[addr as usize] = val as u8;
-//     } else {
-//         self.stack.insert(addr, val as u8);
-//     }
-// }
-
-// fn load_b(&mut self, addr: u32) -> u32 {
-//     log::debug!("Memory: Loading byte from 0x{:#08x}", addr);
-//     self.read_byte(addr)
-// }
-// fn load_h(&mut self, addr: u32) -> u32 {
-//     log::debug!("Memory: Loading half-word from 0x{:#08x}", addr);
-//     self.read_byte(addr) | (self.read_byte(addr) << 8)
-// }
-// fn load_w(&mut self, addr: u32) -> u32 {
-//     log::debug!("Memory: Loading word from 0x{:#08x}", addr);
-//     let result = self.read_byte(addr)
-//         | (self.read_byte(addr + 1) << 8)
-//         | (self.read_byte(addr + 2) << 16)
-//         | (self.read_byte(addr + 3) << 24);
-//     log::debug!("Memory: Loading word from 0x{:#08x} 0x{:#08x}", addr,  self.read_byte(addr));
-//     result
-
-// }
-
-// fn store_b(&mut self, addr: u32, val: u32) {
-//     log::debug!("Memory: Writing byte 0x{:02x} to 0x{:08x}", addr, val);
-//     self.write_byte(addr, val)
-// }
-// fn store_h(&mut self, addr: u32, val: u32) {
-//     log::debug!("Memory: Writing half-word 0x{:04x} to 0x{:08x}", addr, val);
-//     let b0: u32 = val & 0xFF;
-//     let b1: u32 = (val & 0xFF00) >> 8;
-//     self.write_byte(addr, b1);
-//     self.write_byte(addr + 1, b0);
-// }
-// fn store_w(&mut self, addr: u32, val: u32) {
-//     log::debug!("Memory: Writing word 0x{:08x} to 0x{:08x}", addr, val);
-//     let b0: u32 = val & 0xFF;
-//     let b1: u32 = (val & 0xFF00) >> 8;
-//     let b2: u32 = (val & 0xFF0000) >> 16;
-//     let b3: u32 = (val & 0xFF000000) >> 24;
-//     self.write_byte(addr, b3);
-//     self.write_byte(addr + 1, b2);
-//     self.write_byte(addr + 2, b1);
-//     self.write_byte(addr + 3, b0);
-// }
\ No newline at end of file
+    #[allow(dead_code)]
+    fn flush(&mut self) -> Transaction;
+    /// Writes back every dirty line, keeping them cached
+    fn clean(&mut self) -> Transaction;
+
+    /// Loads `width` bytes from cache
+    fn read(&mut self, addr: u32, width: MemWidth) -> Transaction {
+        match width {
+            MemWidth::Byte => self.read_b(addr),
+            MemWidth::Half => self.read_h(addr),
+            MemWidth::Word => self.read_w(addr),
+            MemWidth::Double => self.read_d(addr),
+        }
+    }
+    /// Stores the low `width` bytes of `val` to cache
+    fn write(&mut self, addr: u32, width: MemWidth, val: u64) -> Transaction {
+        match width {
+            MemWidth::Byte => self.write_b(addr, val as u32),
+            MemWidth::Half => self.write_h(addr, val as u32),
+            MemWidth::Word => self.write_w(addr, val as u32),
+            MemWidth::Double => self.write_d(addr, val),
+        }
+    }
+}
+
+/// When a cache's writes reach memory
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Lines are marked dirty and written back when they are evicted or flushed
+    WriteBack,
+    /// Every write goes on to memory, through the write buffer if there is one
+    WriteThrough,
+}
+
+/// What a cache does with a write to a block it doesn't hold
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMiss {
+    /// Fill the line, then write it as on a hit
+    Allocate,
+    /// Write around the cache. Memory only takes whole blocks, so the block is still read,
+    /// but only to merge the write in before writing it back.
+    NoAllocate,
+}
+
+/// How a cache's contents relate to those of the caches in front of it
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inclusion {
+    /// Holds every block they do, dropping a block from them when it evicts it
+    Inclusive,
+    /// Holds no block they do: a block they read moves up out of it, and it keeps what
+    /// they evict, clean or dirty
+    Exclusive,
+    /// Neither inclusive nor exclusive: fills come through it and only dirty lines are
+    /// written back to it
+    Nine,
+}
+
+impl fmt::Display for WritePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WritePolicy::WriteBack => write!(f, "write-back"),
+            WritePolicy::WriteThrough => write!(f, "write-through"),
+        }
+    }
+}
+
+impl fmt::Display for WriteMiss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteMiss::Allocate => write!(f, "allocate"),
+            WriteMiss::NoAllocate => write!(f, "no-allocate"),
+        }
+    }
+}
+
+impl fmt::Display for Inclusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inclusion::Inclusive => write!(f, "inclusive"),
+            Inclusion::Exclusive => write!(f, "exclusive"),
+            Inclusion::Nine => write!(f, "nine"),
+        }
+    }
+}
+
+/// Geometry and timing of a cache. Lines are `BLOCK_SIZE` bytes, the unit memory transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Capacity in bytes
+    pub size: u32,
+    /// Lines per set
+    pub ways: u32,
+    /// Cycles a lookup takes, which is all a hit costs
+    pub hit_latency: u32,
+    /// Misses to different blocks that can be outstanding at once
+    pub mshrs: u32,
+    /// When writes reach memory
+    pub write_policy: WritePolicy,
+    /// Whether a write miss brings the block in
+    pub write_miss: WriteMiss,
+    /// Entries of the coalescing write buffer that writes going on to memory complete in,
+    /// 0 to have them wait for memory
+    pub write_buffer: u32,
+    /// Which blocks held by the caches in front of this one it also holds
+    pub inclusion: Inclusion,
+    /// Which line of a full set a fill replaces
+    pub replacement: Replacement,
+    /// Seed for policies that make random choices
+    pub seed: u64,
+}
+
+impl CacheConfig {
+    pub fn sets(&self) -> u32 {
+        self.size / (self.ways * BLOCK_SIZE)
+    }
+
+    /// Checks the size splits into a power-of-two number of sets of `ways` lines
+    pub fn validate(&self) -> Result<(), String> {
+        let set_size = self.ways.checked_mul(BLOCK_SIZE).filter(|&size| size > 0);
+        match set_size {
+            None => Err(format!("cache must have 1 to {} ways", u32::MAX / BLOCK_SIZE)),
+            Some(set_size) if !self.size.is_multiple_of(set_size) || self.size == 0 => Err(format!(
+                "cache size {} is not a multiple of {} ways of {}-byte lines",
+                self.size, self.ways, BLOCK_SIZE
+            )),
+            Some(_) if !self.sets().is_power_of_two() => Err(format!(
+                "cache size {} gives {} sets, which is not a power of two",
+                self.size,
+                self.sets()
+            )),
+            Some(_) if self.hit_latency == 0 => Err("cache hit latency must be at least 1".to_string()),
+            Some(_) if self.mshrs == 0 => Err("cache needs at least 1 MSHR".to_string()),
+            Some(_) if self.replacement == Replacement::TreePlru && !self.ways.is_power_of_two() => {
+                Err(format!("tree-PLRU needs a power-of-two number of ways, not {}", self.ways))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// Access counts of a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Valid lines replaced to make room for a fill
+    pub evictions: u64,
+    /// Misses to a block already being filled, which wait on its MSHR
+    pub mshr_merges: u64,
+    /// Misses that had to wait because every MSHR was in use
+    pub mshr_full: u64,
+    /// Evicted lines that had to be written back
+    pub dirty_evictions: u64,
+    /// Writes merged into one already waiting in the write buffer
+    pub writes_coalesced: u64,
+    /// Lines dropped because an inclusive cache behind this one evicted them
+    pub back_invalidations: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that missed, 0 if there were none
+    pub fn miss_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.misses as f64 / lookups as f64,
+        }
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.2}% miss rate), {} evictions ({} dirty), {} merged misses, \
+             {} MSHR full stalls, {} coalesced writes, {} back-invalidations",
+            self.hits,
+            self.misses,
+            100.0 * self.miss_rate(),
+            self.evictions,
+            self.dirty_evictions,
+            self.mshr_merges,
+            self.mshr_full,
+            self.writes_coalesced,
+            self.back_invalidations
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    valid: bool,
+    /// Written since it was filled, so memory is stale
+    dirty: bool,
+    /// Address of the block held
+    block: u32,
+    data: [u8; BLOCK_SIZE_U],
+}
+
+/// What an access asks of the cache
+#[derive(Debug, Clone, Copy)]
+enum Request {
+    Read(MemWidth),
+    Write(MemWidth, u64),
+    ReadBlock,
+    WriteBlock([u8; BLOCK_SIZE_U]),
+    Rmw(AmoOp, u32),
+    /// Write back the dirty lines of one block, or of the whole cache, dropping them all
+    /// unless `keep` is set
+    Flush { scope: Option<u32>, keep: bool },
+}
+
+/// Handle an access completes through, depending on which trait it came in through
+#[derive(Debug)]
+enum Reply {
+    Cache(Transaction),
+    Memory(MemTransaction),
+}
+
+/// Result of a finished access
+enum Done {
+    /// Value read, or old value of a read-modify-write
+    Value(u64),
+    Block([u8; BLOCK_SIZE_U]),
+    Write,
+    Fault,
+}
+
+impl Reply {
+    fn complete(&self, done: Done) {
+        match self {
+            Reply::Cache(t) => {
+                *t.borrow_mut() = match done {
+                    Done::Value(val) => CacheTransaction::ReadDone(val),
+                    Done::Write => CacheTransaction::WriteDone,
+                    Done::Fault => CacheTransaction::Fault,
+                    Done::Block(_) => unreachable!("block reads only come in through Memory"),
+                }
+            }
+            Reply::Memory(t) => {
+                *t.borrow_mut() = match done {
+                    Done::Value(old) => MemoryTransaction::RmwDone(old as u32),
+                    Done::Block(data) => MemoryTransaction::ReadDone(data),
+                    Done::Write => MemoryTransaction::WriteDone,
+                    Done::Fault => MemoryTransaction::Fault,
+                }
+            }
+        }
+    }
+}
+
+/// How far an access has got. Transactions to memory are `None` until memory accepts them.
+#[derive(Debug)]
+enum Stage {
+    /// Looking the block up, with the cycles left until the lookup is done
+    Lookup(u32),
+    /// Missed, waiting for an MSHR to free up. `stalled` once it has found every MSHR in use.
+    Miss { stalled: bool },
+    /// Line updated, writing the block as it then was through to memory
+    WriteThrough([u8; BLOCK_SIZE_U], Option<MemTransaction>),
+    /// Read-modify-write passed on to memory
+    Rmw(Option<MemTransaction>),
+    /// Flushed, waiting for the write-backs to reach memory
+    Drain,
+    /// Instruction fetch of a block written since the last `fence_i` that isn't kept here,
+    /// passed on to memory without being allocated
+    Around(Option<MemTransaction>),
+}
+
+/// Block write on its way to memory, from the write buffer or a dirty line written back
+#[derive(Debug)]
+struct PendingWrite {
+    block: u32,
+    mem_type: MemType,
+    data: [u8; BLOCK_SIZE_U],
+    /// `None` until memory accepts it, after which no more writes are merged in
+    transaction: Option<MemTransaction>,
+}
+
+/// Miss status holding register, tracking a block being filled from memory and the
+/// accesses waiting for it
+#[derive(Debug)]
+struct Mshr {
+    block: u32,
+    mem_type: MemType,
+    fill: Option<MemTransaction>,
+    /// Accesses that missed on the block, in the order they missed
+    targets: Vec<Access>,
+}
+
+#[derive(Debug)]
+struct Access {
+    addr: u32,
+    /// Side of memory fills and writes go to
+    mem_type: MemType,
+    request: Request,
+    reply: Reply,
+    stage: Stage,
+}
+
+/// Non-blocking set-associative cache with configurable replacement and write policies.
+/// Each set holds `ways` lines of `BLOCK_SIZE` bytes, filled with `Memory::read_block` from
+/// the memory below, which is shared with the other caches in front of it.
+///
+/// A write-back cache marks the lines it writes dirty and writes them back with
+/// `Memory::write_block` when they are evicted or flushed. A write-through cache writes the
+/// whole line on to memory, completing the write once memory has it or, with a write buffer,
+/// once it is in the buffer, where later writes to the block are merged in until memory takes
+/// it. Write misses fill the line first, or with `WriteMiss::NoAllocate` go around the cache.
+/// Nothing reads a block from memory while a write of it is still queued.
+///
+/// One access is accepted a cycle and looked up for `hit_latency` cycles. A miss takes an
+/// MSHR and the fill completes every access that missed on the block since, while hits to
+/// other blocks carry on around it. Accesses are only turned away as `Busy` while every MSHR
+/// is in use, or when one has already been accepted that cycle.
+///
+/// The core's loads and stores come in through `Cache`. `Memory` takes whole blocks, so the
+/// cache can stand in for memory in front of instruction fetch or another cache, and `M` can
+/// be another cache, to build up a hierarchy. A cache shared by both sides keeps what
+/// instruction fetch saw of the blocks written on the data side since the last `fence_i`,
+/// as memory does, and an inclusive one lists the blocks it evicts for whoever owns the
+/// caches in front of it to `back_invalidate`.
+#[derive(Debug)]
+pub struct SetAssocCache<M: Memory + ?Sized> {
+    /// Name the cache is reported under, such as "L1D"
+    name: &'static str,
+    config: CacheConfig,
+    mem: Rc<RefCell<M>>,
+    /// Side of memory that accesses made through `Cache` go to
+    mem_type: MemType,
+    /// `config.ways` lines per set, one set after another
+    lines: Vec<Line>,
+    /// Accesses being looked up, waiting for an MSHR or writing through
+    accesses: Vec<Access>,
+    /// Outstanding misses, at most `config.mshrs`
+    mshrs: Vec<Mshr>,
+    /// Block writes on their way to memory, in the order they are to reach it
+    write_queue: Vec<PendingWrite>,
+    /// Blocks written through `Memory` on the data side since the last `fence_i`, with what
+    /// instruction fetch still sees of them: the line as it was, or `None` if it wasn't here
+    /// and the memory below has it
+    fetch_view: HashMap<u32, Option<[u8; BLOCK_SIZE_U]>>,
+    /// Blocks evicted by an inclusive cache that the caches in front of it haven't dropped yet
+    back_invalidations: Vec<u32>,
+    /// Evicted clean lines are written back too, for an exclusive cache below to keep
+    write_back_clean: bool,
+    /// An access was accepted this cycle
+    port_used: bool,
+    policy: Box<dyn ReplacementPolicy>,
+    stats: CacheStats,
+}
+
+impl Request {
+    /// Whether the access changes the block
+    fn writes(&self) -> bool {
+        matches!(self, Request::Write(..) | Request::WriteBlock(_) | Request::Rmw(..))
+    }
+}
+
+impl<M: Memory + ?Sized> SetAssocCache<M> {
+    /// Builds an empty cache in front of `mem`. `config` must pass `CacheConfig::validate`.
+    pub fn new(name: &'static str, config: CacheConfig, mem: Rc<RefCell<M>>, mem_type: MemType) -> Self {
+        let empty = Line {
+            valid: false,
+            dirty: false,
+            block: 0,
+            data: [0; BLOCK_SIZE_U],
+        };
+        let (sets, ways) = (config.sets() as usize, config.ways as usize);
+        Self {
+            name,
+            config,
+            mem,
+            mem_type,
+            lines: vec![empty; (config.sets() * config.ways) as usize],
+            accesses: Vec::new(),
+            mshrs: Vec::new(),
+            write_queue: Vec::new(),
+            fetch_view: HashMap::new(),
+            back_invalidations: Vec::new(),
+            write_back_clean: false,
+            port_used: false,
+            policy: config.replacement.build(sets, ways, config.seed),
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+    /// Counts particular to the replacement policy
+    pub fn policy_counters(&self) -> Vec<(&'static str, u64)> {
+        self.policy.counters()
+    }
+
+    /// Has the cache write back the clean lines it evicts as well as the dirty ones, as an
+    /// exclusive cache behind it only gets blocks back that way
+    pub fn write_back_clean_victims(&mut self) {
+        self.write_back_clean = true;
+    }
+
+    /// Blocks evicted since the last call that the caches in front of an inclusive cache
+    /// must drop to keep it inclusive
+    pub fn take_back_invalidations(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.back_invalidations)
+    }
+
+    /// Drops `block` because the inclusive cache behind this one evicted it, writing it
+    /// back first if it is dirty
+    pub fn back_invalidate(&mut self, block: u32) {
+        if let Some(idx) = self.find(block) {
+            log::debug!("{} dropping back-invalidated 0x{:08x}", self.name, block);
+            if self.lines[idx].dirty {
+                self.write_back(idx);
+            }
+            self.drop_line(idx);
+            self.stats.back_invalidations += 1;
+        }
+    }
+
+    /// Index of the line holding `block`, if it is cached
+    fn find(&self, block: u32) -> Option<usize> {
+        let ways = self.config.ways as usize;
+        let set = ((block / BLOCK_SIZE) % self.config.sets()) as usize;
+        (set * ways..(set + 1) * ways).find(|&i| self.lines[i].valid && self.lines[i].block == block)
+    }
+
+    fn touch(&mut self, idx: usize) {
+        let ways = self.config.ways as usize;
+        self.policy.touch(idx / ways, idx % ways);
+    }
+
+    /// Whether a miss of `request` brings the block into the cache
+    fn allocates(&self, request: Request) -> bool {
+        match request {
+            Request::Write(..) | Request::WriteBlock(_) => self.config.write_miss == WriteMiss::Allocate,
+            Request::ReadBlock => self.config.inclusion != Inclusion::Exclusive,
+            _ => true,
+        }
+    }
+
+    /// Puts `data` in the set `block` maps to, replacing an invalid line if there is one
+    /// and the policy's choice otherwise, which is written back if it is dirty. Returns the
+    /// line's index.
+    fn install(&mut self, block: u32, data: [u8; BLOCK_SIZE_U]) -> usize {
+        let ways = self.config.ways as usize;
+        let set = ((block / BLOCK_SIZE) % self.config.sets()) as usize;
+        let way = (0..ways)
+            .find(|&way| !self.lines[set * ways + way].valid)
+            .unwrap_or_else(|| self.policy.victim(set));
+        let victim = set * ways + way;
+        if self.lines[victim].valid {
+            log::debug!(
+                "{} evicted 0x{:08x} for 0x{:08x}",
+                self.name,
+                self.lines[victim].block,
+                block
+            );
+            self.stats.evictions += 1;
+            if self.lines[victim].dirty {
+                self.stats.dirty_evictions += 1;
+                self.write_back(victim);
+            } else if self.write_back_clean {
+                self.write_back(victim);
+            }
+            self.drop_line(victim);
+        }
+        self.lines[victim] = Line {
+            valid: true,
+            dirty: false,
+            block,
+            data,
+        };
+        self.policy.insert(set, way);
+        victim
+    }
+
+    /// Queues the line at `idx` to be written to memory. Write-backs aren't limited by the
+    /// write buffer's size, as the fills making them can't wait for room.
+    fn write_back(&mut self, idx: usize) {
+        let line = self.lines[idx];
+        log::debug!("{} writing back 0x{:08x}", self.name, line.block);
+        self.write_queue.push(PendingWrite {
+            block: line.block,
+            mem_type: self.mem_type,
+            data: line.data,
+            transaction: None,
+        });
+        self.lines[idx].dirty = false;
+    }
+
+    /// Invalidates the line at `idx`, which the caches in front must drop too if this one
+    /// is inclusive
+    fn drop_line(&mut self, idx: usize) {
+        self.lines[idx].valid = false;
+        if self.config.inclusion == Inclusion::Inclusive {
+            self.back_invalidations.push(self.lines[idx].block);
+        }
+    }
+
+    /// Drops the line holding `block`, if any
+    fn invalidate(&mut self, block: u32) {
+        if let Some(idx) = self.find(block) {
+            self.drop_line(idx);
+        }
+    }
+
+    /// Writes back the dirty lines holding `block`, or every block if it is `None`, and
+    /// drops them unless `keep` is set
+    fn flush_lines(&mut self, scope: Option<u32>, keep: bool) {
+        for idx in 0..self.lines.len() {
+            let line = self.lines[idx];
+            if line.valid && scope.is_none_or(|block| line.block == block) {
+                if line.dirty {
+                    self.write_back(idx);
+                }
+                if !keep {
+                    self.drop_line(idx);
+                }
+            }
+        }
+    }
+
+    /// Records what instruction fetch sees of `block` before its first data side write
+    /// since the last `fence_i`
+    fn save_fetch_view(&mut self, block: u32) {
+        if !self.fetch_view.contains_key(&block) {
+            let old = self.find(block).map(|idx| self.lines[idx].data);
+            self.fetch_view.insert(block, old);
+        }
+    }
+
+    /// Whether a write of `block` is still on its way to memory
+    fn writing(&self, block: u32) -> bool {
+        self.write_queue.iter().any(|write| write.block == block)
+    }
+
+    /// Puts a write of `data` to `block` in the write buffer, merging it into the last write
+    /// of the block if memory hasn't taken that yet. Returns false if the buffer is full.
+    fn buffer(&mut self, block: u32, mem_type: MemType, data: [u8; BLOCK_SIZE_U]) -> bool {
+        if let Some(write) = self.write_queue.iter_mut().rev().find(|write| write.block == block) {
+            // The line is only written in place, so its newest state has every earlier write
+            if write.transaction.is_none() {
+                write.data = data;
+                self.stats.writes_coalesced += 1;
+                return true;
+            }
+        }
+        if self.write_queue.len() >= self.config.write_buffer as usize {
+            return false;
+        }
+        self.write_queue.push(PendingWrite {
+            block,
+            mem_type,
+            data,
+            transaction: None,
+        });
+        true
+    }
+
+    /// Accepts an access if the port is free. Hits are serviced even with every MSHR in use,
+    /// and a miss waits for one to free up.
+    fn start(&mut self, addr: u32, mem_type: MemType, request: Request, reply: Reply) -> bool {
+        if self.port_used {
+            log::debug!("{} is busy, access to 0x{:08x} will be ignored", self.name, addr);
+            return false;
+        }
+        self.port_used = true;
+        self.accesses.push(Access {
+            addr,
+            mem_type,
+            request,
+            reply,
+            stage: Stage::Lookup(self.config.hit_latency),
+        });
+        true
+    }
+
+    fn start_cache(&mut self, addr: u32, request: Request) -> Transaction {
+        let started = match request {
+            Request::Read(_) | Request::Rmw(..) => CacheTransaction::ReadStarted,
+            _ => CacheTransaction::WriteStarted,
+        };
+        let transaction = Rc::new(RefCell::new(started));
+        let reply = Reply::Cache(Rc::clone(&transaction));
+        if !self.start(addr, self.mem_type, request, reply) {
+            *transaction.borrow_mut() = CacheTransaction::Busy;
+        }
+        transaction
+    }
+
+    fn start_memory(&mut self, addr: u32, mem_type: MemType, request: Request) -> MemTransaction {
+        let started = match request {
+            Request::WriteBlock(data) => MemoryTransaction::WriteStarted(data),
+            Request::Rmw(op, val) => MemoryTransaction::RmwStarted(op, val),
+            _ => MemoryTransaction::ReadStarted,
+        };
+        let transaction = Rc::new(RefCell::new(started));
+        let reply = Reply::Memory(Rc::clone(&transaction));
+        if !self.start(addr, mem_type, request, reply) {
+            *transaction.borrow_mut() = MemoryTransaction::Busy;
+        }
+        transaction
+    }
+
+    /// Polls the memory transaction of a stage, starting it with `start` if memory has
+    /// not accepted it yet. Returns its state once it is in flight.
+    fn poll(
+        &mut self,
+        pending: &mut Option<MemTransaction>,
+        start: impl FnOnce(&mut M) -> MemTransaction,
+    ) -> MemoryTransaction {
+        let transaction = match pending.take() {
+            Some(t) => t,
+            None => start(&mut self.mem.borrow_mut()),
+        };
+        let mt = *transaction.borrow();
+        if !matches!(mt, MemoryTransaction::Busy) {
+            *pending = Some(transaction);
+        }
+        mt
+    }
+
+    /// Carries out `request` on the block `data` holds, returning what it finished with
+    fn apply(addr: u32, request: Request, data: &mut [u8; BLOCK_SIZE_U]) -> Done {
+        let offset = (addr % BLOCK_SIZE) as usize;
+        match request {
+            Request::Read(width) => {
+                let len = width.bytes() as usize;
+                let mut raw = [0; 8];
+                raw[..len].copy_from_slice(&data[offset..offset + len]);
+                Done::Value(u64::from_le_bytes(raw))
+            }
+            Request::ReadBlock => Done::Block(*data),
+            Request::Write(width, val) => {
+                let len = width.bytes() as usize;
+                data[offset..offset + len].copy_from_slice(&val.to_le_bytes()[..len]);
+                Done::Write
+            }
+            Request::WriteBlock(block) => {
+                *data = block;
+                Done::Write
+            }
+            Request::Rmw(op, val) => {
+                let mut raw = [0; 4];
+                raw.copy_from_slice(&data[offset..offset + 4]);
+                let old = u32::from_le_bytes(raw);
+                data[offset..offset + 4].copy_from_slice(&op.apply(old, val).to_le_bytes());
+                Done::Value(old as u64)
+            }
+            Request::Flush { .. } => unreachable!("flushes don't touch the lines' data"),
+        }
+    }
+
+    /// Carries out the access on the line at `idx`. Returns what it finished with, or
+    /// `None` if it has moved on to writing through.
+    fn perform(&mut self, idx: usize, access: &mut Access) -> Option<Done> {
+        let line = &mut self.lines[idx];
+        let done = Self::apply(access.addr, access.request, &mut line.data);
+        if !access.request.writes() {
+            return Some(done);
+        }
+        match self.config.write_policy {
+            WritePolicy::WriteBack => {
+                line.dirty = true;
+                Some(done)
+            }
+            WritePolicy::WriteThrough => {
+                access.stage = Stage::WriteThrough(line.data, None);
+                None
+            }
+        }
+    }
+
+    /// Carries out the access on the line at `idx`, completing it or returning it if it has
+    /// moved on to writing through
+    fn finish(&mut self, idx: usize, mut access: Access) -> Option<Access> {
+        match self.perform(idx, &mut access) {
+            Some(done) => {
+                access.reply.complete(done);
+                None
+            }
+            None => Some(access),
+        }
+    }
+
+    /// Advances an access by a cycle. Returns it while it is still with the pipeline, and
+    /// nothing once it has completed or is waiting on an MSHR.
+    fn step(&mut self, mut access: Access) -> Option<Access> {
+        let (addr, mem_type) = (access.addr, access.mem_type);
+        let block = addr - addr % BLOCK_SIZE;
+        loop {
+            match access.stage {
+                Stage::Lookup(remaining) if remaining > 1 => {
+                    access.stage = Stage::Lookup(remaining - 1);
+                    return Some(access);
+                }
+                Stage::Lookup(_) => {
+                    let from_fetch = matches!(mem_type, MemType::IMem);
+                    if from_fetch && self.fetch_view.contains_key(&block) {
+                        match (access.request, self.fetch_view[&block]) {
+                            (Request::ReadBlock, Some(data)) => {
+                                self.stats.hits += 1;
+                                access.reply.complete(Done::Block(data));
+                            }
+                            (Request::ReadBlock, None) => {
+                                self.stats.misses += 1;
+                                access.stage = Stage::Around(None);
+                                continue;
+                            }
+                            // A clean line evicted in front, which is older than the block
+                            // here now
+                            _ => access.reply.complete(Done::Write),
+                        }
+                        return None;
+                    }
+                    if !from_fetch && access.request.writes() && matches!(access.reply, Reply::Memory(_)) {
+                        self.save_fetch_view(block);
+                    }
+                    match access.request {
+                        Request::Flush { scope, keep } => {
+                            self.flush_lines(scope, keep);
+                            access.stage = Stage::Drain;
+                            continue;
+                        }
+                        // Lines are never newer than memory, so the word can be changed there
+                        Request::Rmw(..) if self.config.write_policy == WritePolicy::WriteThrough => {
+                            access.stage = Stage::Rmw(None);
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    let pending = self.mshrs.iter().any(|mshr| mshr.block == block);
+                    let idx = match self.find(block) {
+                        // The block moves up out of an exclusive cache, and memory is left to
+                        // keep it if it was dirty
+                        Some(idx)
+                            if self.config.inclusion == Inclusion::Exclusive
+                                && matches!(access.request, Request::ReadBlock) =>
+                        {
+                            self.stats.hits += 1;
+                            if self.lines[idx].dirty {
+                                self.write_back(idx);
+                            }
+                            self.lines[idx].valid = false;
+                            access.reply.complete(Done::Block(self.lines[idx].data));
+                            return None;
+                        }
+                        Some(idx) => {
+                            self.stats.hits += 1;
+                            log::debug!("{} hit on 0x{:08x}", self.name, addr);
+                            self.touch(idx);
+                            idx
+                        }
+                        // A whole block write doesn't need the old contents
+                        None if matches!(access.request, Request::WriteBlock(_)) && !pending => {
+                            self.stats.misses += 1;
+                            if !self.allocates(access.request) {
+                                let Request::WriteBlock(data) = access.request else { unreachable!() };
+                                access.stage = Stage::WriteThrough(data, None);
+                                continue;
+                            }
+                            self.install(block, [0; BLOCK_SIZE_U])
+                        }
+                        None => {
+                            self.stats.misses += 1;
+                            log::debug!("{} miss on 0x{:08x}", self.name, addr);
+                            access.stage = Stage::Miss { stalled: false };
+                            continue;
+                        }
+                    };
+                    access = self.finish(idx, access)?;
+                }
+                Stage::Miss { stalled } => {
+                    // Filled by an earlier miss while this one waited for an MSHR
+                    if let Some(idx) = self.find(block) {
+                        access = self.finish(idx, access)?;
+                        continue;
+                    }
+                    if let Some(mshr) = self.mshrs.iter_mut().find(|mshr| mshr.block == block) {
+                        log::debug!("{} merged miss on 0x{:08x}", self.name, addr);
+                        self.stats.mshr_merges += 1;
+                        mshr.targets.push(access);
+                        return None;
+                    }
+                    if self.mshrs.len() >= self.config.mshrs as usize {
+                        if !stalled {
+                            log::debug!("{} has no free MSHR for 0x{:08x}", self.name, addr);
+                            self.stats.mshr_full += 1;
+                            access.stage = Stage::Miss { stalled: true };
+                        }
+                        return Some(access);
+                    }
+                    // Memory would give back the block from before the queued writes
+                    if self.writing(block) {
+                        return Some(access);
+                    }
+                    let mut fill = None;
+                    self.poll(&mut fill, |mem| mem.read_block(block, mem_type));
+                    self.mshrs.push(Mshr {
+                        block,
+                        mem_type,
+                        fill,
+                        targets: vec![access],
+                    });
+                    return None;
+                }
+                Stage::WriteThrough(data, _) if self.config.write_buffer > 0 => {
+                    if self.buffer(block, mem_type, data) {
+                        access.reply.complete(Done::Write);
+                        return None;
+                    }
+                    return Some(access);
+                }
+                Stage::WriteThrough(data, ref mut pending) => {
+                    let mut pending = pending.take();
+                    let mt = self.poll(&mut pending, |mem| mem.write_block(block, data, mem_type));
+                    if let MemoryTransaction::WriteDone = mt {
+                        access.reply.complete(Done::Write);
+                        return None;
+                    }
+                    access.stage = Stage::WriteThrough(data, pending);
+                    return Some(access);
+                }
+                Stage::Rmw(ref mut pending) => {
+                    let Request::Rmw(op, val) = access.request else {
+                        unreachable!("only read-modify-writes reach Stage::Rmw")
+                    };
+                    let mut pending = pending.take();
+                    if pending.is_none() {
+                        // Wait for a fill of the block so it can't bring back the old word,
+                        // and for buffered writes so they can't put it back in memory
+                        if self.mshrs.iter().any(|mshr| mshr.block == block) || self.writing(block) {
+                            return Some(access);
+                        }
+                        // Nothing here is newer than memory, so dropping the line is
+                        // enough to keep it from going stale
+                        self.invalidate(block);
+                    }
+                    let mt = self.poll(&mut pending, |mem| mem.read_modify_write(addr, op, val));
+                    if let MemoryTransaction::RmwDone(old) = mt {
+                        access.reply.complete(Done::Value(old as u64));
+                        return None;
+                    }
+                    access.stage = Stage::Rmw(pending);
+                    return Some(access);
+                }
+                Stage::Drain => {
+                    let Request::Flush { scope, .. } = access.request else {
+                        unreachable!("only flushes reach Stage::Drain")
+                    };
+                    if self.write_queue.iter().any(|write| scope.is_none_or(|block| write.block == block)) {
+                        return Some(access);
+                    }
+                    access.reply.complete(Done::Write);
+                    return None;
+                }
+                Stage::Around(ref mut pending) => {
+                    let mut pending = pending.take();
+                    match self.poll(&mut pending, |mem| mem.read_block(block, mem_type)) {
+                        MemoryTransaction::ReadDone(data) => access.reply.complete(Done::Block(data)),
+                        MemoryTransaction::Fault => access.reply.complete(Done::Fault),
+                        _ => {
+                            access.stage = Stage::Around(pending);
+                            return Some(access);
+                        }
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Polls the fill of MSHR `i`, starting it if memory hasn't accepted it yet. Once the
+    /// block arrives it is installed and every access waiting on it carried out, unless none
+    /// of them allocate. Then reads are served from the block and writes merged into it and
+    /// written on, in the order they missed. Returns whether the MSHR is free again.
+    fn step_mshr(&mut self, i: usize) -> bool {
+        let (block, mem_type) = (self.mshrs[i].block, self.mshrs[i].mem_type);
+        let mut fill = self.mshrs[i].fill.take();
+        let mt = self.poll(&mut fill, |mem| mem.read_block(block, mem_type));
+        self.mshrs[i].fill = fill;
+        match mt {
+            MemoryTransaction::ReadDone(mut data) => {
+                let targets = std::mem::take(&mut self.mshrs[i].targets);
+                if targets.iter().any(|access| self.allocates(access.request)) {
+                    let idx = self.install(block, data);
+                    for access in targets {
+                        if let Some(access) = self.finish(idx, access) {
+                            self.accesses.push(access);
+                        }
+                    }
+                } else {
+                    for mut access in targets {
+                        let done = Self::apply(access.addr, access.request, &mut data);
+                        if access.request.writes() {
+                            access.stage = Stage::WriteThrough(data, None);
+                            self.accesses.push(access);
+                        } else {
+                            access.reply.complete(done);
+                        }
+                    }
+                }
+                true
+            }
+            MemoryTransaction::Fault => {
+                for access in std::mem::take(&mut self.mshrs[i].targets) {
+                    access.reply.complete(Done::Fault);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Hands the queued writes to memory in order, stopping at the first it turns away, and
+    /// drops the ones it has finished
+    fn drain(&mut self) {
+        let mut i = 0;
+        while i < self.write_queue.len() {
+            let write = &mut self.write_queue[i];
+            let (block, data, mem_type) = (write.block, write.data, write.mem_type);
+            let mut pending = write.transaction.take();
+            let mt = self.poll(&mut pending, |mem| mem.write_block(block, data, mem_type));
+            self.write_queue[i].transaction = pending;
+            match mt {
+                MemoryTransaction::Busy => break,
+                MemoryTransaction::WriteDone => {
+                    self.write_queue.remove(i);
+                }
+                MemoryTransaction::Fault => {
+                    log::warn!("{} could not write 0x{:08x} back to memory", self.name, block);
+                    self.write_queue.remove(i);
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    /// Drops every line
+    fn invalidate_all(&mut self) {
+        for idx in 0..self.lines.len() {
+            if self.lines[idx].valid {
+                self.drop_line(idx);
+            }
+        }
+    }
+}
+
+impl<M: Memory + ?Sized> Cache for SetAssocCache<M> {
+    fn read_b(&mut self, addr: u32) -> Transaction {
+        self.start_cache(addr, Request::Read(MemWidth::Byte))
+    }
+    fn read_h(&mut self, addr: u32) -> Transaction {
+        self.start_cache(addr, Request::Read(MemWidth::Half))
+    }
+    fn read_w(&mut self, addr: u32) -> Transaction {
+        self.start_cache(addr, Request::Read(MemWidth::Word))
+    }
+    fn read_d(&mut self, addr: u32) -> Transaction {
+        self.start_cache(addr, Request::Read(MemWidth::Double))
+    }
+
+    fn write_b(&mut self, addr: u32, val: u32) -> Transaction {
+        self.start_cache(addr, Request::Write(MemWidth::Byte, val as u64))
+    }
+    fn write_h(&mut self, addr: u32, val: u32) -> Transaction {
+        self.start_cache(addr, Request::Write(MemWidth::Half, val as u64))
+    }
+    fn write_w(&mut self, addr: u32, val: u32) -> Transaction {
+        self.start_cache(addr, Request::Write(MemWidth::Word, val as u64))
+    }
+    fn write_d(&mut self, addr: u32, val: u64) -> Transaction {
+        self.start_cache(addr, Request::Write(MemWidth::Double, val))
+    }
+
+    fn read_modify_write(&mut self, addr: u32, op: AmoOp, val: u32) -> Transaction {
+        self.start_cache(addr, Request::Rmw(op, val))
+    }
+
+    /// Completes once the line's write-back, if it was dirty, has reached memory
+    fn flush_line(&mut self, addr: u32) -> Transaction {
+        let block = addr - addr % BLOCK_SIZE;
+        self.start_cache(block, Request::Flush { scope: Some(block), keep: false })
+    }
+    fn flush(&mut self) -> Transaction {
+        self.start_cache(0, Request::Flush { scope: None, keep: false })
+    }
+    fn clean(&mut self) -> Transaction {
+        self.start_cache(0, Request::Flush { scope: None, keep: true })
+    }
+}
+
+impl<M: Memory + ?Sized> Memory for SetAssocCache<M> {
+    fn read_block(&mut self, addr: u32, mem_type: MemType) -> MemTransaction {
+        self.start_memory(addr, mem_type, Request::ReadBlock)
+    }
+    fn write_block(&mut self, addr: u32, val: [u8; BLOCK_SIZE_U], mem_type: MemType) -> MemTransaction {
+        self.start_memory(addr, mem_type, Request::WriteBlock(val))
+    }
+    fn read_modify_write(&mut self, addr: u32, op: AmoOp, val: u32) -> MemTransaction {
+        self.start_memory(addr, MemType::DMem, Request::Rmw(op, val))
+    }
+
+    /// An instruction cache may hold blocks from before the writes, so it drops everything,
+    /// and a shared one lets fetch see the written blocks. Doesn't write anything back, so
+    /// write-back caches must be cleaned first, nearest the core first.
+    fn fence_i(&mut self) {
+        self.fetch_view.clear();
+        if let MemType::IMem = self.mem_type {
+            self.invalidate_all();
+        }
+        self.mem.borrow_mut().fence_i();
+    }
+
+    /// Reads the newest copy of the byte, from a line, a queued write or memory
+    fn backdoor_read(&mut self, addr: u32) -> u8 {
+        let (block, offset) = (addr - addr % BLOCK_SIZE, (addr % BLOCK_SIZE) as usize);
+        if let Some(idx) = self.find(block) {
+            return self.lines[idx].data[offset];
+        }
+        match self.write_queue.iter().rev().find(|write| write.block == block) {
+            Some(write) => write.data[offset],
+            None => self.mem.borrow_mut().backdoor_read(addr),
+        }
+    }
+    /// Writes every copy of the byte, so queued writes can't put back the old value
+    fn backdoor_write(&mut self, addr: u32, val: u8) {
+        let (block, offset) = (addr - addr % BLOCK_SIZE, (addr % BLOCK_SIZE) as usize);
+        self.save_fetch_view(block);
+        if let Some(idx) = self.find(block) {
+            self.lines[idx].data[offset] = val;
+        }
+        for write in self.write_queue.iter_mut().filter(|write| write.block == block) {
+            write.data[offset] = val;
+            if let Some(transaction) = &write.transaction {
+                if let MemoryTransaction::WriteStarted(data) = &mut *transaction.borrow_mut() {
+                    data[offset] = val;
+                }
+            }
+        }
+        self.mem.borrow_mut().backdoor_write(addr, val);
+    }
+}
+
+impl<M: Memory + ?Sized> Component for SetAssocCache<M> {
+    /// Advances the fills and accesses in flight. Memory is shared, so whoever owns it
+    /// cycles it.
+    fn cycle(&mut self) {
+        self.port_used = false;
+        self.drain();
+        // Fills first, so lookups finishing this cycle see the blocks they bring in
+        let mut i = 0;
+        while i < self.mshrs.len() {
+            if self.step_mshr(i) {
+                self.mshrs.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        for access in std::mem::take(&mut self.accesses) {
+            if let Some(access) = self.step(access) {
+                self.accesses.push(access);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::memory::QueueMem;
+
+    const CONFIG: CacheConfig = CacheConfig {
+        size: 4 * BLOCK_SIZE,
+        ways: 2,
+        hit_latency: 1,
+        mshrs: 2,
+        write_policy: WritePolicy::WriteThrough,
+        write_miss: WriteMiss::Allocate,
+        write_buffer: 0,
+        inclusion: Inclusion::Nine,
+        replacement: Replacement::Lru,
+        seed: 1,
+    };
+
+    fn tick(mem: &Rc<RefCell<QueueMem>>, cache: &mut SetAssocCache<QueueMem>) {
+        mem.borrow_mut().cycle();
+        cache.cycle();
+    }
+
+    /// Cycles memory and the cache until `transaction` is done, returning the cycles taken
+    fn wait(mem: &Rc<RefCell<QueueMem>>, cache: &mut SetAssocCache<QueueMem>, transaction: &Transaction) -> u32 {
+        let mut cycles = 0;
+        while matches!(
+            *transaction.borrow(),
+            CacheTransaction::ReadStarted | CacheTransaction::WriteStarted
+        ) {
+            tick(mem, cache);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn validates_geometry() {
+        assert!(CONFIG.validate().is_ok());
+        assert_eq!(CONFIG.sets(), 2);
+        assert!(CacheConfig { ways: 0, ..CONFIG }.validate().is_err());
+        assert!(CacheConfig { size: 3 * BLOCK_SIZE, ways: 1, ..CONFIG }.validate().is_err());
+        assert!(CacheConfig { size: 6 * BLOCK_SIZE, ..CONFIG }.validate().is_err());
+        assert!(CacheConfig { hit_latency: 0, ..CONFIG }.validate().is_err());
+        assert!(CacheConfig { mshrs: 0, ..CONFIG }.validate().is_err());
+        let plru = CacheConfig {
+            replacement: Replacement::TreePlru,
+            ..CONFIG
+        };
+        assert!(plru.validate().is_ok());
+        assert!(CacheConfig { size: 6 * BLOCK_SIZE, ways: 3, ..plru }.validate().is_err());
+    }
+
+    #[test]
+    fn hits_misses_and_evictions() {
+        let mem = Rc::new(RefCell::new(QueueMem::empty()));
+        let mut cache = SetAssocCache::new("L1D", CONFIG, Rc::clone(&mem), MemType::DMem);
+
+        // Miss, fill, then the write goes through to memory
+        let t = cache.write_w(0x1004, 0xDEAD_BEEF);
+        assert!(wait(&mem, &mut cache, &t) > 100);
+        assert_eq!(mem.borrow_mut().backdoor_read(0x1005), 0xBE);
+        let t = cache.read_h(0x1006);
+        assert_eq!(wait(&mem, &mut cache, &t), 1);
+        assert_eq!(*t.borrow(), CacheTransaction::ReadDone(0xDEAD));
+
+        // 0x1000, 0x1080 and 0x1100 all map to set 0 of 2 ways
+        for addr in [0x1080, 0x1000, 0x1100] {
+            let t = cache.read_b(addr);
+            wait(&mem, &mut cache, &t);
+        }
+        assert_eq!(
+            *cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 3,
+                evictions: 1,
+                ..CacheStats::default()
+            }
+        );
+        // 0x1080 was least recently used, so it went
+        assert!(cache.find(0x1000).is_some());
+        assert!(cache.find(0x1080).is_none());
+
+        // One access at a time
+        let t = cache.read_w(0x1004);
+        assert_eq!(*cache.read_w(0x1000).borrow(), CacheTransaction::Busy);
+        wait(&mem, &mut cache, &t);
+        assert_eq!(*t.borrow(), CacheTransaction::ReadDone(0xDEAD_BEEF));
+    }
+
+    #[test]
+    fn hits_under_misses() {
+        let mem = Rc::new(RefCell::new(QueueMem::empty()));
+        let mut cache = SetAssocCache::new("L1D", CONFIG, Rc::clone(&mem), MemType::DMem);
+        let t = cache.read_w(0x1000);
+        wait(&mem, &mut cache, &t);
+
+        let miss = cache.read_w(0x2000);
+        tick(&mem, &mut cache);
+        // Same block, waits on the first miss's MSHR
+        let merged = cache.read_w(0x2008);
+        tick(&mem, &mut cache);
+        let hit = cache.read_w(0x1000);
+        tick(&mem, &mut cache);
+        assert_eq!(*hit.borrow(), CacheTransaction::ReadDone(0));
+        assert_eq!(*miss.borrow(), CacheTransaction::ReadStarted);
+
+        // A second block takes the other MSHR, then there are none left. Hits still
+        // complete, while another miss waits for an MSHR.
+        let other = cache.read_w(0x3000);
+        tick(&mem, &mut cache);
+        tick(&mem, &mut cache);
+        let hit = cache.read_w(0x1004);
+        tick(&mem, &mut cache);
+        assert_eq!(*hit.borrow(), CacheTransaction::ReadDone(0));
+        assert_eq!(*miss.borrow(), CacheTransaction::ReadStarted);
+        assert_eq!(*other.borrow(), CacheTransaction::ReadStarted);
+        let waiting = cache.read_w(0x4000);
+        tick(&mem, &mut cache);
+        tick(&mem, &mut cache);
+        assert_eq!(cache.stats().mshr_full, 1);
+
+        wait(&mem, &mut cache, &miss);
+        assert_eq!(*merged.borrow(), CacheTransaction::ReadDone(0));
+        wait(&mem, &mut cache, &other);
+        wait(&mem, &mut cache, &waiting);
+        assert_eq!(
+            *cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 5,
+                // All four blocks map to set 0
+                evictions: 2,
+                mshr_merges: 1,
+                mshr_full: 1,
+                ..CacheStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn write_back_and_flush() {
+        let mem = Rc::new(RefCell::new(QueueMem::empty()));
+        let config = CacheConfig {
+            write_policy: WritePolicy::WriteBack,
+            ..CONFIG
+        };
+        let mut cache = SetAssocCache::new("L1D", config, Rc::clone(&mem), MemType::DMem);
+
+        let t = cache.write_w(0x1000, 0xDEAD_BEEF);
+        wait(&mem, &mut cache, &t);
+        assert_eq!(mem.borrow_mut().backdoor_read(0x1000), 0);
+        assert_eq!(cache.backdoor_read(0x1000), 0xEF);
+
+        // Pushing 0x1000 out of set 0 writes it back
+        for addr in [0x1080, 0x1100] {
+            let t = cache.read_w(addr);
+            wait(&mem, &mut cache, &t);
+        }
+        assert_eq!(cache.stats().dirty_evictions, 1);
+        assert_eq!(cache.backdoor_read(0x1000), 0xEF);
+        for _ in 0..100 {
+            tick(&mem, &mut cache);
+        }
+        assert_eq!(mem.borrow_mut().backdoor_read(0x1000), 0xEF);
+
+        // A flush completes once the dirty line has reached memory
+        let t = cache.write_b(0x1101, 0x42);
+        wait(&mem, &mut cache, &t);
+        let t = cache.flush();
+        wait(&mem, &mut cache, &t);
+        assert_eq!(*t.borrow(), CacheTransaction::WriteDone);
+        assert_eq!(mem.borrow_mut().backdoor_read(0x1101), 0x42);
+        assert!(cache.find(0x1100).is_none());
+        assert_eq!(cache.stats().dirty_evictions, 1);
+    }
+
+    #[test]
+    fn write_buffer_coalesces() {
+        let mem = Rc::new(RefCell::new(QueueMem::empty()));
+        let config = CacheConfig {
+            write_buffer: 1,
+            ..CONFIG
+        };
+        let mut cache = SetAssocCache::new("L1D", config, Rc::clone(&mem), MemType::DMem);
+        let t = cache.read_w(0x1000);
+        wait(&mem, &mut cache, &t);
+
+        // With memory's queue full the buffered write can't leave, so the rest merge into it
+        let _busy = [0x4000, 0x4040].map(|addr| mem.borrow_mut().read_block(addr, MemType::DMem));
+        for i in 0..4 {
+            let t = cache.write_w(0x1000 + 4 * i, i + 1);
+            assert_eq!(wait(&mem, &mut cache, &t), 1);
+        }
+        assert_eq!(cache.stats().writes_coalesced, 3);
+        assert_eq!(mem.borrow_mut().backdoor_read(0x100C), 0);
+
+        // Full, so a write to another block waits for memory to take the first
+        let t = cache.write_w(0x1040, 5);
+        assert!(wait(&mem, &mut cache, &t) > 1);
+        for _ in 0..200 {
+            tick(&mem, &mut cache);
+        }
+        assert_eq!(mem.borrow_mut().backdoor_read(0x100C), 4);
+        assert_eq!(mem.borrow_mut().backdoor_read(0x1040), 5);
+    }
+
+    #[test]
+    fn no_allocate_writes_around() {
+        let mem = Rc::new(RefCell::new(QueueMem::empty()));
+        let config = CacheConfig {
+            write_policy: WritePolicy::WriteBack,
+            write_miss: WriteMiss::NoAllocate,
+            ..CONFIG
+        };
+        let mut cache = SetAssocCache::new("L1D", config, Rc::clone(&mem), MemType::DMem);
+
+        let t = cache.write_h(0x1002, 0xBEEF);
+        wait(&mem, &mut cache, &t);
+        assert!(cache.find(0x1000).is_none());
+        assert_eq!(mem.borrow_mut().backdoor_read(0x1003), 0xBE);
+
+        // Reads still allocate, and write hits stay in the line
+        let t = cache.read_w(0x1000);
+        wait(&mem, &mut cache, &t);
+        assert_eq!(*t.borrow(), CacheTransaction::ReadDone(0xBEEF_0000));
+        let t = cache.write_b(0x1000, 1);
+        assert_eq!(wait(&mem, &mut cache, &t), 1);
+        assert_eq!(mem.borrow_mut().backdoor_read(0x1000), 0);
+        assert_eq!(cache.stats().misses, 2);
+    }
+}
@@ -0,0 +1,751 @@
+use super::cache::{Cache, CacheConfig, CacheTransaction, Inclusion, WriteMiss, WritePolicy};
+use super::component::Component;
+use super::hierarchy::{Hierarchy, Level, LevelCache};
+use super::memory::{AmoOp, ElfInfo, MemoryMap, BLOCK_SIZE};
+use super::ports::Ports;
+use super::replacement::Replacement;
+use crate::compressed::inst_len;
+use crate::csr::{self, Counters, CsrFile};
+use crate::disasm::Symbols;
+use crate::error::SimError;
+use crate::exec::{self, Effect, MemWrite, Retired};
+use crate::fpu::{FpFmt, FpRegFile};
+use crate::functional::{RETURN_ADDR, STACK_START};
+use crate::htif::Htif;
+use crate::instructions::{decode_inst, FuClass, Instruction, MemWidth};
+use crate::spike::CommitWriter;
+use crate::syscall::{SyscallResult, Syscalls};
+use crate::trap::{self, Exception};
+
+/// Data access waiting on memory
+#[derive(Debug, Clone, Copy)]
+enum MemAccess {
+    Load {
+        rd: u32,
+        addr: u32,
+        width: MemWidth,
+        signed: bool,
+        /// Destination is an FP register
+        fp: bool,
+    },
+    Store {
+        addr: u32,
+        width: MemWidth,
+        val: u64,
+    },
+    /// AMOs and successful store-conditionals, done as one read-modify-write transaction
+    Rmw {
+        rd: u32,
+        addr: u32,
+        op: AmoOp,
+        val: u32,
+        /// Store-conditional, which writes 0 to rd rather than the old value
+        conditional: bool,
+    },
+    /// Clean of the data cache and each shared level, so a fence.i can make stores
+    /// visible to instruction fetch
+    FenceI,
+}
+
+#[derive(Debug)]
+enum State {
+    /// Waiting for the block holding the PC to be fetched
+    Fetch,
+    /// Waiting on a multi-cycle functional unit
+    Execute {
+        word: u32,
+        inst: Instruction,
+        next_pc: u32,
+        /// Register write to make once the result is ready
+        effect: Effect,
+        fflags: u8,
+        /// Cycles left before the result is ready
+        remaining: u32,
+    },
+    /// Waiting on a data access for the instruction being executed
+    Memory {
+        word: u32,
+        inst: Instruction,
+        next_pc: u32,
+        access: MemAccess,
+    },
+    Halted,
+}
+
+/// Timing parameters of the core
+#[derive(Debug, Clone, Copy)]
+pub struct CoreConfig {
+    /// Cycles a multiply takes to execute
+    pub mul_latency: u32,
+    /// Cycles a divide or remainder takes to execute
+    pub div_latency: u32,
+    /// Cycles a bit count, byte reverse or `orc.b` takes to execute
+    pub bitmanip_latency: u32,
+    /// Cycles a carry-less multiply takes to execute
+    pub clmul_latency: u32,
+    /// Cycles an FP add, compare, conversion or move takes to execute
+    pub fp_add_latency: u32,
+    /// Cycles an FP multiply or fused multiply-add takes to execute
+    pub fp_mul_latency: u32,
+    /// Cycles an FP divide or square root takes to execute
+    pub fp_div_latency: u32,
+    /// L1 instruction cache
+    pub icache: CacheConfig,
+    /// L1 data cache
+    pub dcache: CacheConfig,
+    /// Unified L2 behind the L1s, if there is one
+    pub l2: Option<CacheConfig>,
+    /// Unified L3 behind the L2, if there is one
+    pub l3: Option<CacheConfig>,
+}
+
+impl Default for CoreConfig {
+    fn default() -> Self {
+        Self {
+            mul_latency: 3,
+            div_latency: 20,
+            bitmanip_latency: 1,
+            clmul_latency: 2,
+            fp_add_latency: 3,
+            fp_mul_latency: 4,
+            fp_div_latency: 20,
+            icache: CacheConfig {
+                size: 16 * 1024,
+                ways: 4,
+                hit_latency: 1,
+                mshrs: 2,
+                write_policy: WritePolicy::WriteBack,
+                write_miss: WriteMiss::Allocate,
+                write_buffer: 0,
+                inclusion: Inclusion::Nine,
+                replacement: Replacement::Lru,
+                seed: 1,
+            },
+            dcache: CacheConfig {
+                size: 16 * 1024,
+                ways: 4,
+                hit_latency: 1,
+                mshrs: 4,
+                write_policy: WritePolicy::WriteBack,
+                write_miss: WriteMiss::Allocate,
+                write_buffer: 0,
+                inclusion: Inclusion::Nine,
+                replacement: Replacement::Lru,
+                seed: 1,
+            },
+            l2: None,
+            l3: None,
+        }
+    }
+}
+
+impl CoreConfig {
+    /// L2 used when one is asked for, as there is none by default
+    pub const L2: CacheConfig = CacheConfig {
+        size: 256 * 1024,
+        ways: 8,
+        hit_latency: 10,
+        mshrs: 8,
+        write_policy: WritePolicy::WriteBack,
+        write_miss: WriteMiss::Allocate,
+        write_buffer: 0,
+        inclusion: Inclusion::Nine,
+        replacement: Replacement::Lru,
+        seed: 1,
+    };
+    /// L3 used when one is asked for, as there is none by default
+    pub const L3: CacheConfig = CacheConfig {
+        size: 2 * 1024 * 1024,
+        ways: 16,
+        hit_latency: 30,
+        mshrs: 16,
+        write_policy: WritePolicy::WriteBack,
+        write_miss: WriteMiss::Allocate,
+        write_buffer: 0,
+        inclusion: Inclusion::Nine,
+        replacement: Replacement::Lru,
+        seed: 1,
+    };
+
+    /// Names and configs of the shared levels, nearest the core first
+    pub fn shared_levels(&self) -> Vec<(&'static str, CacheConfig)> {
+        [("L2", self.l2), ("L3", self.l3)]
+            .into_iter()
+            .filter_map(|(name, config)| config.map(|config| (name, config)))
+            .collect()
+    }
+
+    /// Execute latency in cycles of an instruction on the given functional unit
+    pub fn latency(&self, class: FuClass) -> u32 {
+        match class {
+            FuClass::Alu => 1,
+            FuClass::Mul => self.mul_latency,
+            FuClass::Div => self.div_latency,
+            FuClass::Bitmanip => self.bitmanip_latency,
+            FuClass::Clmul => self.clmul_latency,
+            FuClass::FpAdd => self.fp_add_latency,
+            FuClass::FpMul => self.fp_mul_latency,
+            FuClass::FpDiv => self.fp_div_latency,
+            FuClass::System => 1,
+        }
+    }
+}
+
+/// Simple in-order core that executes one instruction at a time. Instruction
+/// fetch goes through an L1 instruction cache on `MemType::IMem` and loads/stores
+/// through an L1 data cache on `MemType::DMem`, both in front of the same hierarchy.
+/// Data accesses must be naturally aligned so they never cross a block. Instructions
+/// only need 2-byte alignment, so a 32-bit one at the end of a block takes two fetches.
+#[derive(Debug)]
+pub struct TimingCore<M: Level> {
+    config: CoreConfig,
+    regs: [u32; 32],
+    /// FP register file along with `fcsr`
+    fregs: FpRegFile,
+    csrs: CsrFile,
+    pc: u32,
+    /// Addresses data accesses may touch under the trap model
+    map: MemoryMap,
+    /// L1s and the hierarchy behind them, cycled by the core
+    ports: Ports<M>,
+    /// Block reserved by the last `lr.w`, cleared by any `sc.w`
+    reservation: Option<u32>,
+    state: State,
+    /// Instructions retired since the last `take_retired`
+    retired_queue: Vec<Retired>,
+    cycles: u64,
+    retired: u64,
+    /// Error that stopped the core
+    error: Option<SimError>,
+    syscalls: Syscalls,
+    htif: Option<Htif>,
+    /// Status the program exited with, or a0 if it returned to `RETURN_ADDR`
+    exit_code: i32,
+    /// ELF symbols, for the instruction trace
+    symbols: Symbols,
+    /// Spike-format log of retired instructions, for `--trace-file`
+    commit_log: Option<CommitWriter>,
+}
+
+impl<M: Level> TimingCore<M> {
+    pub fn new(mem: M, info: &ElfInfo, config: CoreConfig) -> Self {
+        let mut regs = [0; 32];
+        regs[1] = RETURN_ADDR;
+        regs[2] = STACK_START;
+        Self {
+            config,
+            regs,
+            fregs: FpRegFile::new(),
+            csrs: CsrFile::new(),
+            pc: info.entry,
+            map: info.map.clone(),
+            ports: Ports::new(mem, &config),
+            reservation: None,
+            state: State::Fetch,
+            retired_queue: Vec::new(),
+            cycles: 0,
+            retired: 0,
+            error: None,
+            syscalls: Syscalls::new(info.brk),
+            htif: info.tohost.map(|tohost| Htif::new(tohost, info.fromhost)),
+            exit_code: 0,
+            symbols: info.symbols.clone(),
+            commit_log: None,
+        }
+    }
+
+    pub fn regs(&self) -> &[u32; 32] {
+        &self.regs
+    }
+    pub fn halted(&self) -> bool {
+        matches!(self.state, State::Halted)
+    }
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+    pub fn icache(&self) -> &LevelCache {
+        self.ports.icache()
+    }
+    pub fn dcache(&self) -> &LevelCache {
+        self.ports.dcache()
+    }
+    pub fn hierarchy(&self) -> &Hierarchy<M> {
+        self.ports.hierarchy()
+    }
+
+    /// Returns the instructions retired since the last call
+    pub fn take_retired(&mut self) -> Vec<Retired> {
+        std::mem::take(&mut self.retired_queue)
+    }
+
+    /// Returns the error that halted the core, if any
+    pub fn take_error(&mut self) -> Option<SimError> {
+        self.error.take()
+    }
+
+    /// Writes every retired instruction to `log` from now on
+    pub fn set_commit_log(&mut self, log: CommitWriter) {
+        self.commit_log = Some(log);
+    }
+
+    /// Enables the trap model, see `CsrFile::handles_traps`
+    pub fn set_traps(&mut self, enabled: bool) {
+        self.csrs.set_traps(enabled);
+    }
+
+    /// Runs until the program returns to `RETURN_ADDR`
+    pub fn run(&mut self) -> Result<(), SimError> {
+        while !self.halted() {
+            self.cycle();
+            self.retired_queue.clear();
+        }
+        if let Some(e) = self.take_error() {
+            return Err(e);
+        }
+        if let Some(log) = &mut self.commit_log {
+            log.flush().map_err(SimError::CommitLog)?;
+        }
+        Ok(())
+    }
+
+    /// Stops the core with an error
+    fn fail(&mut self, error: SimError) {
+        log::debug!("Core halted at 0x{:08x}: {}", self.pc, error);
+        self.error = Some(error);
+        self.state = State::Halted;
+    }
+
+    fn fetch(&mut self) {
+        let pc = self.pc;
+        if !pc.is_multiple_of(2) {
+            return self.raise(Exception::InstructionMisaligned { addr: pc }, 0, Instruction::Ill);
+        }
+        let word = match self.ports.fetch(pc, None) {
+            Ok(Some(word)) => word,
+            Ok(None) => return,
+            Err((exception, word)) => return self.raise(exception, word, Instruction::Ill),
+        };
+        let len = inst_len(word as u16);
+        let inst = decode_inst(word);
+        log::trace!(
+            "0x{:08x}: 0x{:08x} {}",
+            pc,
+            word,
+            inst.disasm(pc, Some(&self.symbols))
+        );
+
+        let outcome = exec::execute(&inst, self.pc, len, &self.regs, &self.fregs);
+        if let Some(exception) = trap::misaligned(&outcome.effect) {
+            return self.raise(exception, word, inst);
+        }
+        if self.csrs.handles_traps() {
+            if let Some(exception) = trap::access_fault(&outcome.effect, &self.map) {
+                return self.raise(exception, word, inst);
+            }
+        }
+        match outcome.effect {
+            Effect::None => self.retire(outcome.next_pc, Retired::new(pc, word, inst)),
+            Effect::WriteReg { .. } | Effect::WriteFreg { .. } => {
+                let latency = self.config.latency(inst.fu_class());
+                if latency > 1 {
+                    self.state = State::Execute {
+                        word,
+                        inst,
+                        next_pc: outcome.next_pc,
+                        effect: outcome.effect,
+                        fflags: outcome.fflags,
+                        remaining: latency - 1,
+                    };
+                } else {
+                    self.complete(word, inst, outcome.next_pc, outcome.effect, outcome.fflags)
+                }
+            }
+            Effect::Load {
+                rd,
+                addr,
+                width,
+                signed,
+            } => {
+                self.state = State::Memory {
+                    word,
+                    inst,
+                    next_pc: outcome.next_pc,
+                    access: MemAccess::Load {
+                        rd,
+                        addr,
+                        width,
+                        signed,
+                        fp: false,
+                    },
+                }
+            }
+            Effect::LoadFp { rd, addr, width } => {
+                self.state = State::Memory {
+                    word,
+                    inst,
+                    next_pc: outcome.next_pc,
+                    access: MemAccess::Load {
+                        rd,
+                        addr,
+                        width,
+                        signed: false,
+                        fp: true,
+                    },
+                }
+            }
+            Effect::Store { addr, width, val } => {
+                self.state = State::Memory {
+                    word,
+                    inst,
+                    next_pc: outcome.next_pc,
+                    access: MemAccess::Store { addr, width, val },
+                }
+            }
+            Effect::LoadReserved { rd, addr } => {
+                self.reservation = Some(addr / BLOCK_SIZE);
+                self.state = State::Memory {
+                    word,
+                    inst,
+                    next_pc: outcome.next_pc,
+                    access: MemAccess::Load {
+                        rd,
+                        addr,
+                        width: MemWidth::Word,
+                        signed: true,
+                        fp: false,
+                    },
+                }
+            }
+            Effect::StoreConditional { rd, addr, val } => {
+                if self.reservation.take() == Some(addr / BLOCK_SIZE) {
+                    self.state = State::Memory {
+                        word,
+                        inst,
+                        next_pc: outcome.next_pc,
+                        access: MemAccess::Rmw {
+                            rd,
+                            addr,
+                            op: AmoOp::Swap,
+                            val,
+                            conditional: true,
+                        },
+                    }
+                } else {
+                    // Failed, nothing is written so there is no memory access
+                    let reg_write = self.set_reg(rd, 1);
+                    let record = Retired {
+                        reg_write,
+                        ..Retired::new(pc, word, inst)
+                    };
+                    self.retire(outcome.next_pc, record)
+                }
+            }
+            Effect::Amo { rd, addr, op, val } => {
+                self.state = State::Memory {
+                    word,
+                    inst,
+                    next_pc: outcome.next_pc,
+                    access: MemAccess::Rmw {
+                        rd,
+                        addr,
+                        op,
+                        val,
+                        conditional: false,
+                    },
+                }
+            }
+            Effect::Ecall if self.csrs.handles_traps() => self.raise(Exception::Ecall, word, inst),
+            Effect::Ecall => {
+                match self
+                    .syscalls
+                    .call(pc, &self.regs, self.ports.dcache_mut(), self.retired)
+                {
+                    Ok(SyscallResult::Return(val)) => {
+                        let reg_write = self.set_reg(10, val);
+                        let record = Retired {
+                            reg_write,
+                            ..Retired::new(pc, word, inst)
+                        };
+                        self.retire(outcome.next_pc, record)
+                    }
+                    Ok(SyscallResult::Exit(status)) => {
+                        let code = self.htif.map_or(status, |htif| htif.exit_code(status));
+                        self.retire(outcome.next_pc, Retired::new(pc, word, inst));
+                        log::info!(
+                            "Program exited with {} after {} instructions in {} cycles",
+                            code,
+                            self.retired,
+                            self.cycles
+                        );
+                        self.exit_code = code;
+                        self.state = State::Halted;
+                    }
+                    Err(e) => self.fail(e),
+                }
+            }
+            Effect::Ebreak => self.raise(Exception::Breakpoint, word, inst),
+            Effect::Mret => {
+                let next_pc = self.csrs.mret();
+                self.retire(next_pc, Retired::new(pc, word, inst))
+            }
+            Effect::FenceI => {
+                self.state = State::Memory {
+                    word,
+                    inst,
+                    next_pc: outcome.next_pc,
+                    access: MemAccess::FenceI,
+                }
+            }
+            Effect::Csr { rd, csr, op, val } => {
+                // CSR accesses serialize. Only one instruction is ever in flight here,
+                // so everything older has retired and the counters are exact.
+                let counters = Counters {
+                    cycle: self.cycles,
+                    instret: self.retired,
+                };
+                match self.csrs.access(csr, op, val, &mut self.fregs, counters) {
+                    Some(access) => {
+                        let reg_write = self.set_reg(rd, access.old);
+                        let csr_write = access.written.map(|new| (csr, new));
+                        let record = Retired {
+                            reg_write,
+                            csr_write,
+                            ..Retired::new(pc, word, inst)
+                        };
+                        self.retire(outcome.next_pc, record)
+                    }
+                    None => self.raise(Exception::Illegal { word }, word, inst),
+                }
+            }
+            Effect::Illegal => self.raise(Exception::Illegal { word }, word, inst),
+        }
+    }
+
+    fn execute(&mut self) {
+        let State::Execute {
+            word,
+            inst,
+            next_pc,
+            effect,
+            fflags,
+            ref mut remaining,
+        } = self.state
+        else {
+            unreachable!("execute stage should only run in State::Execute")
+        };
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.complete(word, inst, next_pc, effect, fflags);
+        }
+    }
+
+    /// Writes back the result of an executed instruction, accrues its FP flags and retires it
+    fn complete(&mut self, word: u32, inst: Instruction, next_pc: u32, effect: Effect, fflags: u8) {
+        let csr_write = (fflags != 0).then(|| {
+            self.fregs.raise(fflags);
+            (csr::FFLAGS, self.fregs.fflags() as u32)
+        });
+        match effect {
+            Effect::WriteReg { rd, val } => {
+                let reg_write = self.set_reg(rd, val);
+                let record = Retired {
+                    reg_write,
+                    csr_write,
+                    ..Retired::new(self.pc, word, inst)
+                };
+                self.retire(next_pc, record)
+            }
+            Effect::WriteFreg { rd, val } => {
+                self.fregs.set_raw(rd, val);
+                let record = Retired {
+                    freg_write: Some((rd, val)),
+                    csr_write,
+                    ..Retired::new(self.pc, word, inst)
+                };
+                self.retire(next_pc, record)
+            }
+            _ => unreachable!("only register writes go through the execute stage"),
+        }
+    }
+
+    fn memory(&mut self) {
+        let State::Memory {
+            word,
+            inst,
+            next_pc,
+            access,
+        } = self.state
+        else {
+            unreachable!("memory stage should only run in State::Memory")
+        };
+        match access {
+            MemAccess::Load {
+                rd,
+                addr,
+                width,
+                signed,
+                fp,
+            } => {
+                if let Some(CacheTransaction::ReadDone(raw)) =
+                    self.ports.poll_data(|dcache| dcache.read(addr, width))
+                {
+                    if fp {
+                        let val = match width {
+                            MemWidth::Word => FpRegFile::boxed(raw, FpFmt::S),
+                            _ => raw,
+                        };
+                        self.fregs.set_raw(rd, val);
+                        let record = Retired {
+                            freg_write: Some((rd, val)),
+                            mem_read: Some(addr),
+                            ..Retired::new(self.pc, word, inst)
+                        };
+                        self.retire(next_pc, record);
+                    } else {
+                        let val = width.extend(raw as u32, signed);
+                        let reg_write = self.set_reg(rd, val);
+                        let record = Retired {
+                            reg_write,
+                            mem_read: Some(addr),
+                            ..Retired::new(self.pc, word, inst)
+                        };
+                        self.retire(next_pc, record);
+                    }
+                }
+            }
+            MemAccess::Store { addr, width, val } => {
+                if let Some(CacheTransaction::WriteDone) =
+                    self.ports.poll_data(|dcache| dcache.write(addr, width, val))
+                {
+                    let store = MemWrite { addr, width, val };
+                    let record = Retired {
+                        mem_write: Some(store),
+                        ..Retired::new(self.pc, word, inst)
+                    };
+                    self.retire(next_pc, record);
+                    self.check_tohost(&store);
+                }
+            }
+            MemAccess::Rmw {
+                rd,
+                addr,
+                op,
+                val,
+                conditional,
+            } => {
+                if let Some(CacheTransaction::ReadDone(old)) =
+                    self.ports.poll_data(|dcache| Cache::read_modify_write(dcache, addr, op, val))
+                {
+                    let old = old as u32;
+                    let reg_write = self.set_reg(rd, if conditional { 0 } else { old });
+                    let width = MemWidth::Word;
+                    let store = MemWrite { addr, width, val: op.apply(old, val) as u64 };
+                    // A store-conditional only writes
+                    let mem_read = (!conditional).then_some(addr);
+                    let record = Retired {
+                        reg_write,
+                        mem_read,
+                        mem_write: Some(store),
+                        ..Retired::new(self.pc, word, inst)
+                    };
+                    self.retire(next_pc, record);
+                    self.check_tohost(&store);
+                }
+            }
+            MemAccess::FenceI => {
+                if self.ports.fence_i() {
+                    self.retire(next_pc, Retired::new(self.pc, word, inst));
+                }
+            }
+        }
+    }
+
+    /// Halts if a retired store signalled an exit through HTIF `tohost`
+    fn check_tohost(&mut self, store: &MemWrite) {
+        if let Some(code) = self.htif.and_then(|htif| htif.check(store, self.ports.dcache_mut())) {
+            log::info!(
+                "Program signalled exit {} through tohost after {} instructions in {} cycles",
+                code,
+                self.retired,
+                self.cycles
+            );
+            self.exit_code = code;
+            self.state = State::Halted;
+        }
+    }
+
+    fn set_reg(&mut self, idx: u32, val: u32) -> Option<(u32, u32)> {
+        if idx != 0 {
+            self.regs[idx as usize] = val;
+            Some((idx, val))
+        } else {
+            None
+        }
+    }
+
+    /// Takes an exception raised by the instruction at the PC. Exceptions are taken at
+    /// commit, in place of retiring the instruction: everything older has retired and
+    /// nothing younger has executed, so the trap is precise. Without the trap model the
+    /// core stops instead, as it does if the handler itself cannot be fetched.
+    fn raise(&mut self, exception: Exception, word: u32, inst: Instruction) {
+        let pc = self.pc;
+        let unfetchable_handler = matches!(exception, Exception::InstructionFault { .. })
+            && pc == self.csrs.trap_vector();
+        if !self.csrs.handles_traps() || unfetchable_handler {
+            return self.fail(exception.into_error(pc, word));
+        }
+        log::debug!("0x{:08x}: {:?} trapped", pc, exception);
+        self.retired_queue.push(Retired {
+            trap: Some(exception),
+            ..Retired::new(pc, word, inst)
+        });
+        self.pc = self.csrs.trap(pc, exception.cause(), exception.tval(pc));
+        self.state = State::Fetch;
+    }
+
+    fn retire(&mut self, next_pc: u32, record: Retired) {
+        let logged = match &mut self.commit_log {
+            Some(log) => log.write(&record),
+            None => Ok(()),
+        };
+        self.retired_queue.push(record);
+        self.retired += 1;
+        self.pc = next_pc;
+        self.state = if self.pc == RETURN_ADDR {
+            log::info!(
+                "Program returned after {} instructions in {} cycles",
+                self.retired,
+                self.cycles
+            );
+            self.exit_code = self.regs[10] as i32;
+            State::Halted
+        } else {
+            State::Fetch
+        };
+        if let Err(e) = logged {
+            self.fail(SimError::CommitLog(e));
+        }
+    }
+}
+
+impl<M: Level> Component for TimingCore<M> {
+    fn cycle(&mut self) {
+        if self.halted() {
+            return;
+        }
+        self.ports.cycle();
+        self.cycles += 1;
+        match self.state {
+            State::Fetch => self.fetch(),
+            State::Execute { .. } => self.execute(),
+            State::Memory { .. } => self.memory(),
+            State::Halted => {}
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use super::cache::{CacheConfig, Inclusion, SetAssocCache};
+use super::component::Component;
+use super::memory::{MemType, Memory};
+use std::cell::{Ref, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+/// Anything a cache can sit in front of: memory, or another cache. Lets the levels of a
+/// hierarchy be chosen at runtime.
+pub trait Level: Memory + Component + fmt::Debug + 'static {}
+
+impl<T: Memory + Component + fmt::Debug + 'static> Level for T {}
+
+/// Cache in front of whichever level comes next
+pub type LevelCache = SetAssocCache<dyn Level>;
+
+/// Memory and the unified caches shared by a core's L1s, nearest the core first. Each
+/// cache sits in front of the next through `Memory`, the last in front of memory itself.
+#[derive(Debug)]
+pub struct Hierarchy<M: Level> {
+    mem: Rc<RefCell<M>>,
+    levels: Vec<Rc<RefCell<LevelCache>>>,
+}
+
+impl<M: Level> Hierarchy<M> {
+    /// Builds `levels` in front of `mem`, nearest the core first. Their configs must pass
+    /// `CacheConfig::validate`.
+    pub fn new(mem: M, levels: &[(&'static str, CacheConfig)]) -> Self {
+        let mem = Rc::new(RefCell::new(mem));
+        let mut below: Rc<RefCell<dyn Level>> = Rc::clone(&mem) as _;
+        let mut built = Vec::new();
+        for &(name, config) in levels.iter().rev() {
+            let mut level = SetAssocCache::new(name, config, below, MemType::DMem);
+            let next_exclusive = built
+                .last()
+                .is_some_and(|next: &Rc<RefCell<LevelCache>>| next.borrow().config().inclusion == Inclusion::Exclusive);
+            if next_exclusive {
+                level.write_back_clean_victims();
+            }
+            let level = Rc::new(RefCell::new(level));
+            below = Rc::clone(&level) as _;
+            built.push(level);
+        }
+        built.reverse();
+        Self { mem, levels: built }
+    }
+
+    /// Builds a private L1 in front of the first level
+    pub fn l1(&self, name: &'static str, config: CacheConfig, mem_type: MemType) -> LevelCache {
+        let below: Rc<RefCell<dyn Level>> = match self.levels.first() {
+            Some(level) => Rc::clone(level) as _,
+            None => Rc::clone(&self.mem) as _,
+        };
+        let mut l1 = SetAssocCache::new(name, config, below, mem_type);
+        let first_exclusive = self
+            .levels
+            .first()
+            .is_some_and(|level| level.borrow().config().inclusion == Inclusion::Exclusive);
+        if first_exclusive {
+            l1.write_back_clean_victims();
+        }
+        l1
+    }
+
+    /// Shared levels, nearest the core first
+    pub fn levels(&self) -> Vec<Ref<'_, LevelCache>> {
+        self.levels.iter().map(|level| level.borrow()).collect()
+    }
+
+    /// Shared level `i`, for accesses such as the cleaning before a `fence.i` that go to
+    /// one level rather than through the L1s
+    pub fn level(&self, i: usize) -> Rc<RefCell<LevelCache>> {
+        Rc::clone(&self.levels[i])
+    }
+
+    /// Has the caches in front of each inclusive level drop the blocks it evicted, starting
+    /// from memory, so blocks a level drops because of the one behind it are passed on up too
+    pub fn back_invalidate(&self, l1s: [&mut LevelCache; 2]) {
+        let [icache, dcache] = l1s;
+        for (i, level) in self.levels.iter().enumerate().rev() {
+            let blocks = level.borrow_mut().take_back_invalidations();
+            for block in blocks {
+                for upper in &self.levels[..i] {
+                    upper.borrow_mut().back_invalidate(block);
+                }
+                icache.back_invalidate(block);
+                dcache.back_invalidate(block);
+            }
+        }
+    }
+}
+
+impl<M: Level> Component for Hierarchy<M> {
+    /// Cycles memory, then the levels from the one nearest memory
+    fn cycle(&mut self) {
+        self.mem.borrow_mut().cycle();
+        for level in self.levels.iter().rev() {
+            level.borrow_mut().cycle();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::cache::{Cache, CacheTransaction, WriteMiss, WritePolicy};
+    use crate::components::memory::{MemoryTransaction, QueueMem, BLOCK_SIZE};
+    use crate::components::replacement::Replacement;
+
+    /// 2 sets of 2 ways
+    const SMALL: CacheConfig = CacheConfig {
+        size: 4 * BLOCK_SIZE,
+        ways: 2,
+        hit_latency: 1,
+        mshrs: 2,
+        write_policy: WritePolicy::WriteBack,
+        write_miss: WriteMiss::Allocate,
+        write_buffer: 0,
+        inclusion: Inclusion::Nine,
+        replacement: Replacement::Lru,
+        seed: 1,
+    };
+
+    struct System {
+        hierarchy: Hierarchy<QueueMem>,
+        icache: LevelCache,
+        dcache: LevelCache,
+    }
+
+    impl System {
+        /// L1s of `SMALL` in front of an L2 twice their size
+        fn new(inclusion: Inclusion) -> Self {
+            let l2 = CacheConfig {
+                size: 8 * BLOCK_SIZE,
+                inclusion,
+                ..SMALL
+            };
+            let hierarchy = Hierarchy::new(QueueMem::empty(), &[("L2", l2)]);
+            let icache = hierarchy.l1("L1I", SMALL, MemType::IMem);
+            let dcache = hierarchy.l1("L1D", SMALL, MemType::DMem);
+            Self {
+                hierarchy,
+                icache,
+                dcache,
+            }
+        }
+
+        fn tick(&mut self) {
+            self.hierarchy.cycle();
+            self.hierarchy.back_invalidate([&mut self.icache, &mut self.dcache]);
+            self.icache.cycle();
+            self.dcache.cycle();
+        }
+
+        /// Reads a word through the L1D, returning it and the cycles it took
+        fn load(&mut self, addr: u32) -> (u64, u32) {
+            let t = self.dcache.read_w(addr);
+            let mut cycles = 0;
+            while *t.borrow() == CacheTransaction::ReadStarted {
+                self.tick();
+                cycles += 1;
+            }
+            let CacheTransaction::ReadDone(val) = *t.borrow() else {
+                panic!("load of 0x{:08x} failed", addr)
+            };
+            (val, cycles)
+        }
+
+        fn store(&mut self, addr: u32, val: u32) {
+            let t = self.dcache.write_w(addr, val);
+            while *t.borrow() == CacheTransaction::WriteStarted {
+                self.tick();
+            }
+        }
+
+        fn l2(&self) -> Ref<'_, LevelCache> {
+            self.hierarchy.levels().remove(0)
+        }
+    }
+
+    #[test]
+    fn misses_go_through_each_level() {
+        let mut system = System::new(Inclusion::Nine);
+        let (_, cold) = system.load(0x1000);
+        assert!(cold > 50);
+        // Evicted from the L1D by two more blocks in set 0, but the L2 still has it
+        system.load(0x1080);
+        system.load(0x1100);
+        let (_, l2_hit) = system.load(0x1000);
+        assert!(l2_hit < 10, "{} cycles", l2_hit);
+        assert_eq!(system.l2().stats().hits, 1);
+        assert_eq!(system.l2().stats().misses, 3);
+        assert_eq!(system.dcache.stats().misses, 4);
+    }
+
+    #[test]
+    fn inclusive_back_invalidates() {
+        let mut system = System::new(Inclusion::Inclusive);
+        system.store(0x1000, 7);
+        // 0x1000, 0x1100 and 0x1200 map to set 0 of both levels. The L2 never sees the
+        // L1D's hit on 0x1000, so it evicts 0x1000 for 0x1200 while the L1D still uses it.
+        for addr in [0x1100, 0x1000, 0x1200] {
+            system.load(addr);
+        }
+        for _ in 0..10 {
+            system.tick();
+        }
+        // The dirty copy dropped from the L1D comes back down, evicting 0x1100 from the L2
+        // and so from the L1D too, leaving room for 0x1200 without an L1D eviction
+        assert_eq!(system.dcache.stats().back_invalidations, 2);
+        assert_eq!(system.dcache.stats().evictions, 0);
+        let (val, cycles) = system.load(0x1000);
+        assert_eq!(val, 7);
+        assert!(cycles < 10, "{} cycles", cycles);
+        assert_eq!(system.l2().stats().hits, 1);
+    }
+
+    #[test]
+    fn exclusive_moves_blocks() {
+        let mut system = System::new(Inclusion::Exclusive);
+        system.store(0x1000, 7);
+        // Fills go around the L2, then the L1D's victims land in it
+        assert_eq!(system.l2().stats().evictions, 0);
+        system.load(0x1080);
+        system.load(0x1100);
+        let (val, cycles) = system.load(0x1000);
+        assert_eq!(val, 7);
+        assert!(cycles < 10, "{} cycles", cycles);
+        // Moved back up, so it misses in the L2 next time
+        system.load(0x1180);
+        system.load(0x1200);
+        assert_eq!(system.l2().stats().hits, 1);
+        assert_eq!(system.load(0x1000).0, 7);
+        assert_eq!(system.l2().stats().hits, 2);
+    }
+
+    #[test]
+    fn fetch_sees_writes_after_clean_and_fence() {
+        let mut system = System::new(Inclusion::Nine);
+        system.store(0x1000, 7);
+        // Written back into the L2 as 0x1000 leaves the L1D
+        system.load(0x1080);
+        system.load(0x1100);
+        for _ in 0..10 {
+            system.tick();
+        }
+        assert_eq!(system.l2().stats().hits, 1);
+        let fetch = |system: &mut System| {
+            let t = system.icache.read_block(0x1000, MemType::IMem);
+            loop {
+                system.tick();
+                if let MemoryTransaction::ReadDone(data) = *t.borrow() {
+                    return data[0];
+                }
+            }
+        };
+        assert_eq!(fetch(&mut system), 0);
+
+        let level = system.hierarchy.level(0);
+        loop {
+            let t = level.borrow_mut().clean();
+            while *t.borrow() == CacheTransaction::WriteStarted {
+                system.tick();
+            }
+            if *t.borrow() == CacheTransaction::WriteDone {
+                break;
+            }
+            system.tick();
+        }
+        system.icache.fence_i();
+        assert_eq!(fetch(&mut system), 7);
+    }
+}
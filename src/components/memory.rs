@@ -1,10 +1,11 @@
 use super::component::Component;
-use elf::{endian::LittleEndian, section::SectionHeader, ElfBytes};
-use log;
-use std::borrow::{Borrow, BorrowMut};
+use crate::disasm::Symbols;
+use crate::error::SimError;
+use crate::functional::{STACK_SIZE, STACK_START};
+use elf::{abi, endian::AnyEndian, file::Class, ElfBytes};
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ops::{Deref, DerefMut};
+use std::ops::Range;
 use std::path::PathBuf;
 use std::rc::Rc;
 
@@ -15,16 +16,56 @@ const IMEM_TRANSACTIONS: u32 = 2;
 const IMEM_TRANSACTIONS_U: usize = IMEM_TRANSACTIONS as usize;
 
 const ACCESS_CYCLES: u32 = 50;
-const BLOCK_SIZE: u32 = 1 << 6;
-const BLOCK_SIZE_U: usize = BLOCK_SIZE as usize;
+pub const BLOCK_SIZE: u32 = 1 << 6;
+pub const BLOCK_SIZE_U: usize = BLOCK_SIZE as usize;
 
 #[derive(Debug, Clone, Copy)]
 pub enum MemoryTransaction {
+    /// The port's queue was full and the request was not taken. This is backpressure rather
+    /// than a fault: nothing happened, and the requester retries on a later cycle once a
+    /// queued transaction completes.
     Busy,
     ReadStarted,
     ReadDone([u8; BLOCK_SIZE_U]),
     WriteStarted([u8; BLOCK_SIZE_U]),
     WriteDone,
+    /// Atomic read-modify-write of the word at the entry's address
+    RmwStarted(AmoOp, u32),
+    /// Read-modify-write done, holding the word's old value
+    RmwDone(u32),
+    /// Access to an address memory cannot serve
+    Fault,
+}
+
+/// Operation of an atomic read-modify-write, combining the old value in memory with the operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmoOp {
+    Swap,
+    Add,
+    Xor,
+    And,
+    Or,
+    Min,
+    Max,
+    Minu,
+    Maxu,
+}
+
+impl AmoOp {
+    /// Value written back to memory
+    pub fn apply(self, old: u32, val: u32) -> u32 {
+        match self {
+            AmoOp::Swap => val,
+            AmoOp::Add => old.wrapping_add(val),
+            AmoOp::Xor => old ^ val,
+            AmoOp::And => old & val,
+            AmoOp::Or => old | val,
+            AmoOp::Min => (old as i32).min(val as i32) as u32,
+            AmoOp::Max => (old as i32).max(val as i32) as u32,
+            AmoOp::Minu => old.min(val),
+            AmoOp::Maxu => old.max(val),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -33,6 +74,9 @@ pub enum MemType {
     DMem,
 }
 
+/// Block-granular memory with a bounded queue of transactions per port. Requests made while
+/// a port's queue is full complete immediately as `MemoryTransaction::Busy` and must be
+/// reissued; faults the run cannot recover from are reported as `MemoryTransaction::Fault`.
 pub trait Memory {
     fn read_block(&mut self, addr: u32, mem_type: MemType) -> Rc<RefCell<MemoryTransaction>>;
     fn write_block(
@@ -41,21 +85,98 @@ pub trait Memory {
         val: [u8; BLOCK_SIZE_U],
         mem_type: MemType,
     ) -> Rc<RefCell<MemoryTransaction>>;
+    /// Atomically replaces the aligned word at `addr` in DMem with `op` applied to it and
+    /// `val`, completing with the old value. The read and write are one transaction so no
+    /// other access to the block can come between them; anything caching the block must
+    /// write it back before and drop it (or hold it locked) until the transaction is done.
+    fn read_modify_write(
+        &mut self,
+        addr: u32,
+        op: AmoOp,
+        val: u32,
+    ) -> Rc<RefCell<MemoryTransaction>>;
+
+    /// Makes every write made through DMem so far visible to IMem, for `fence.i`.
+    /// Until then fetches may see the instruction stream as it was before the writes.
+    fn fence_i(&mut self);
+
+    /// Reads a byte immediately with no timing, used for functional accesses such as syscalls
+    fn backdoor_read(&mut self, addr: u32) -> u8;
+    /// Writes a byte immediately with no timing, used for functional accesses such as syscalls
+    fn backdoor_write(&mut self, addr: u32, val: u8);
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct QueueEntry {
     cycle_counter: u32,
     addr: u32,
     transaction: Rc<RefCell<MemoryTransaction>>,
 }
 
-#[derive(Debug)]
+/// Information about the loaded program
+#[derive(Debug, Clone)]
+pub struct ElfInfo {
+    /// Entry point the PC starts at
+    pub entry: u32,
+    /// Initial program break, the end of the highest loaded segment
+    pub brk: u32,
+    /// Address of the HTIF `tohost` symbol, if the program has one
+    pub tohost: Option<u32>,
+    /// Address of the HTIF `fromhost` symbol, if the program has one
+    pub fromhost: Option<u32>,
+    /// Function, object and label symbols, for disassembly
+    pub symbols: Symbols,
+    /// Addresses data accesses may touch under the trap model
+    pub map: MemoryMap,
+}
+
+/// Address ranges a program may access under the trap model: its loaded segments and the
+/// stack below `STACK_START`. Accesses anywhere else raise access faults. Without the trap
+/// model data memory is sparse and every address can be read and written.
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+    /// Ends are 64-bit so a segment can end at the top of the address space
+    regions: Vec<Range<u64>>,
+}
+
+impl MemoryMap {
+    fn new(segments: &[Segment]) -> Self {
+        let mut regions: Vec<Range<u64>> = segments.iter().map(Segment::range).collect();
+        regions.push((STACK_START - STACK_SIZE).into()..STACK_START.into());
+        Self { regions }
+    }
+
+    /// Whether all `size` bytes from `addr` are mapped
+    pub fn contains(&self, addr: u32, size: u32) -> bool {
+        let last = addr.wrapping_add(size - 1);
+        [addr, last]
+            .iter()
+            .all(|&byte| self.regions.iter().any(|region| region.contains(&byte.into())))
+    }
+}
+
+/// Loadable segment of the ELF, placed at its linked virtual address
+#[derive(Debug, Clone)]
+struct Segment {
+    base: u32,
+    /// File contents followed by zero fill up to the segment's memory size
+    data: Vec<u8>,
+}
+
+impl Segment {
+    /// Addresses the segment covers, which may run up to the top of the address space
+    fn range(&self) -> Range<u64> {
+        let base = u64::from(self.base);
+        base..base + self.data.len() as u64
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct QueueMem {
-    /// Memory copied in from ELF file
-    elf_mem: Vec<u8>,
+    /// Memory copied in from the ELF file's loadable segments
+    elf_mem: Vec<Segment>,
 
-    /// Stack region of memory, starting at 0x40000000 and decreasing
+    /// Any memory outside the ELF segments, including the stack starting at 0x40000000 and decreasing
     stack: HashMap<u32, u8>,
 
     /// Queue of transactions for imem
@@ -63,54 +184,266 @@ pub struct QueueMem {
 
     /// Queue of transactions for dmem
     dmem_queue: Vec<QueueEntry>,
+
+    /// Contents as of the last `fence_i` of the program blocks written since, keyed by block
+    /// address. IMem reads these, so writes only become visible to fetch after a `fence.i`.
+    imem_stale: HashMap<u32, [u8; BLOCK_SIZE_U]>,
+
+    /// IMem reads outside the ELF segments succeed rather than fault
+    fetch_anywhere: bool,
 }
 impl QueueMem {
-    /// Construct a new Memory object by loading elf file into elf_mem and creating empry stack
-    pub fn load_elf(elf_path: PathBuf) -> (Self, u32) {
-        // Opening elf file
-        let file_data = std::fs::read(elf_path).expect("Could not read file.");
-        let slice = file_data.as_slice();
-        let elf_file =
-            ElfBytes::<LittleEndian>::minimal_parse(slice).expect("ELF file should be parsable");
-        // Finding text section to get offset
-        let text_header: SectionHeader = elf_file
-            .section_header_by_name(".text")
-            .expect("Section table should be parseable")
-            .expect("file should have a .text section");
+    /// Construct a new Memory object by loading the ELF's PT_LOAD segments at their virtual addresses
+    /// and creating an empty stack.
+    pub fn load_elf(elf_path: PathBuf) -> Result<(Self, ElfInfo), SimError> {
+        let file_data = std::fs::read(elf_path)?;
+        Self::from_elf_bytes(&file_data)
+    }
+
+    /// Construct a new Memory object from the contents of an ELF file, see `load_elf`.
+    /// Only little-endian 32-bit RISC-V executables are accepted.
+    pub fn from_elf_bytes(file_data: &[u8]) -> Result<(Self, ElfInfo), SimError> {
+        let elf_file = ElfBytes::<AnyEndian>::minimal_parse(file_data)?;
+        if elf_file.ehdr.class != Class::ELF32 {
+            return Err(SimError::UnsupportedMachine(
+                "only 32-bit ELF files are supported".to_string(),
+            ));
+        }
+        if elf_file.ehdr.endianness == AnyEndian::Big {
+            return Err(SimError::UnsupportedMachine(
+                "only little-endian ELF files are supported".to_string(),
+            ));
+        }
+        if elf_file.ehdr.e_type != abi::ET_EXEC {
+            return Err(SimError::UnsupportedMachine(format!(
+                "ELF type {} is not an executable ({})",
+                elf_file.ehdr.e_type,
+                abi::ET_EXEC
+            )));
+        }
+        if elf_file.ehdr.e_machine != abi::EM_RISCV {
+            return Err(SimError::UnsupportedMachine(format!(
+                "ELF machine {} is not RISC-V ({})",
+                elf_file.ehdr.e_machine,
+                abi::EM_RISCV
+            )));
+        }
 
+        let mut elf_mem = Vec::new();
+        let segments = elf_file
+            .segments()
+            .ok_or_else(|| SimError::BadElf("no program header table".to_string()))?;
+        for phdr in segments.iter().filter(|phdr| phdr.p_type == abi::PT_LOAD) {
+            if phdr.p_memsz < phdr.p_filesz {
+                return Err(SimError::BadElf(format!(
+                    "segment at 0x{:08x} has {} bytes in the file but only {} in memory",
+                    phdr.p_vaddr, phdr.p_filesz, phdr.p_memsz
+                )));
+            }
+            if phdr.p_vaddr.checked_add(phdr.p_memsz).is_none_or(|end| end > 1 << 32) {
+                return Err(SimError::BadElf(format!(
+                    "segment at 0x{:08x} of {} bytes runs past the end of the address space",
+                    phdr.p_vaddr, phdr.p_memsz
+                )));
+            }
+            let mut data = elf_file.segment_data(&phdr)?.to_vec();
+            data.resize(phdr.p_memsz as usize, 0);
+            log::debug!(
+                "Loaded segment at 0x{:08x}, {} bytes from file, {} bytes in memory",
+                phdr.p_vaddr,
+                phdr.p_filesz,
+                phdr.p_memsz
+            );
+            elf_mem.push(Segment {
+                base: phdr.p_vaddr as u32,
+                data,
+            });
+        }
+
+        if elf_mem.is_empty() {
+            return Err(SimError::BadElf("no loadable segments".to_string()));
+        }
+
+        let (mut tohost, mut fromhost) = (None, None);
+        let mut symbols = Vec::new();
+        if let Some((symtab, strtab)) = elf_file.symbol_table()? {
+            for sym in symtab.iter() {
+                let name = strtab.get(sym.st_name as usize)?;
+                match name {
+                    "tohost" => tohost = Some(sym.st_value as u32),
+                    "fromhost" => fromhost = Some(sym.st_value as u32),
+                    _ => {}
+                }
+                // Skip section and file symbols, mapping symbols like `$x` and local `.L` labels
+                let named = !name.is_empty() && !name.starts_with('$') && !name.starts_with(".L");
+                let kind = sym.st_symtype();
+                let code_or_data = matches!(kind, abi::STT_NOTYPE | abi::STT_OBJECT | abi::STT_FUNC);
+                if named && code_or_data && !sym.is_undefined() {
+                    symbols.push((sym.st_value as u32, kind != abi::STT_FUNC, name.to_string()));
+                }
+            }
+        }
+        // Functions go first so they win over labels at the same address
+        symbols.sort_by_key(|&(addr, not_func, _)| (addr, not_func));
+        let symbols = Symbols::new(symbols.into_iter().map(|(addr, _, name)| (addr, name)).collect());
+        if let Some(addr) = tohost {
+            log::info!("Found HTIF tohost at 0x{:08x}", addr);
+        }
+
+        // A segment ending at the top of the address space leaves no room for a heap
+        let brk = elf_mem
+            .iter()
+            .map(|seg| u32::try_from(seg.range().end).unwrap_or(u32::MAX))
+            .max()
+            .unwrap_or(0);
+        let map = MemoryMap::new(&elf_mem);
+
+        Ok((
+            Self {
+                elf_mem,
+                stack: HashMap::new(),
+                imem_queue: Vec::new(),
+                dmem_queue: Vec::new(),
+                imem_stale: HashMap::new(),
+                fetch_anywhere: false,
+            },
+            ElfInfo {
+                entry: elf_file.ehdr.e_entry as u32,
+                brk,
+                tohost,
+                fromhost,
+                symbols,
+                map,
+            },
+        ))
+    }
+
+    /// Construct a memory holding a single segment of `words` at `base`, for running
+    /// assembled programs without an ELF file. The program starts at `base`.
+    #[cfg(test)]
+    pub fn with_program(base: u32, words: &[u32]) -> (Self, ElfInfo) {
+        let data: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let brk = base + data.len() as u32;
+        let elf_mem = vec![Segment { base, data }];
+        let map = MemoryMap::new(&elf_mem);
         (
             Self {
-                elf_mem: file_data,
+                elf_mem,
                 stack: HashMap::new(),
                 imem_queue: Vec::new(),
                 dmem_queue: Vec::new(),
+                imem_stale: HashMap::new(),
+                fetch_anywhere: false,
+            },
+            ElfInfo {
+                entry: base,
+                brk,
+                tohost: None,
+                fromhost: None,
+                symbols: Symbols::default(),
+                map,
             },
-            text_header.sh_offset as u32,
         )
     }
 
-    fn read_byte(elf_mem: &mut Vec<u8>, mut stack: &mut HashMap<u32, u8>, addr: u32) -> u8 {
-        if addr < elf_mem.len() as u32 {
-            elf_mem[addr as usize] as u8
-        } else {
-            *stack.entry(addr).or_default() as u8
+    /// Construct a memory with nothing loaded, where every address reads as zero and can
+    /// be fetched from. For trace-driven runs, which only need the timing of accesses.
+    pub fn empty() -> Self {
+        Self {
+            elf_mem: Vec::new(),
+            stack: HashMap::new(),
+            imem_queue: Vec::new(),
+            dmem_queue: Vec::new(),
+            imem_stale: HashMap::new(),
+            fetch_anywhere: true,
         }
     }
-    fn write_byte(elf_mem: &mut Vec<u8>, mut stack: &mut HashMap<u32, u8>, addr: u32, val: u8) {
-        if addr < elf_mem.len() as u32 {
-            elf_mem[addr as usize] = val as u8;
-        } else {
-            stack.insert(addr, val as u8);
+
+    /// Reads a byte as instruction fetch sees it, immediately with no timing
+    pub fn backdoor_fetch(&mut self, addr: u32) -> u8 {
+        QueueMem::fetch_byte(&mut self.elf_mem, &mut self.stack, &self.imem_stale, addr)
+    }
+
+    /// Whether an address falls inside one of the ELF segments
+    pub fn is_mapped(&self, addr: u32) -> bool {
+        self.elf_mem.iter().any(|seg| seg.range().contains(&addr.into()))
+    }
+
+    fn read_byte(elf_mem: &mut [Segment], stack: &mut HashMap<u32, u8>, addr: u32) -> u8 {
+        match QueueMem::segment_byte(elf_mem, addr) {
+            Some(byte) => *byte,
+            None => *stack.entry(addr).or_default(),
+        }
+    }
+    fn write_byte(elf_mem: &mut [Segment], stack: &mut HashMap<u32, u8>, addr: u32, val: u8) {
+        match QueueMem::segment_byte(elf_mem, addr) {
+            Some(byte) => *byte = val,
+            None => {
+                stack.insert(addr, val);
+            }
         }
     }
+    /// Writes a byte on the data side. Only program memory can be fetched from, so the first
+    /// write to a block of it since the last `fence_i` saves the block for IMem to keep reading.
+    fn write_data_byte(
+        elf_mem: &mut [Segment],
+        stack: &mut HashMap<u32, u8>,
+        imem_stale: &mut HashMap<u32, [u8; BLOCK_SIZE_U]>,
+        addr: u32,
+        val: u8,
+    ) {
+        if QueueMem::segment_byte(elf_mem, addr).is_some() {
+            let block = addr - addr % BLOCK_SIZE;
+            imem_stale.entry(block).or_insert_with(|| {
+                std::array::from_fn(|i| {
+                    QueueMem::segment_byte(elf_mem, block + i as u32).map_or(0, |byte| *byte)
+                })
+            });
+        }
+        QueueMem::write_byte(elf_mem, stack, addr, val)
+    }
+    /// Reads a byte on the instruction side
+    fn fetch_byte(
+        elf_mem: &mut [Segment],
+        stack: &mut HashMap<u32, u8>,
+        imem_stale: &HashMap<u32, [u8; BLOCK_SIZE_U]>,
+        addr: u32,
+    ) -> u8 {
+        match imem_stale.get(&(addr - addr % BLOCK_SIZE)) {
+            Some(block) => block[(addr % BLOCK_SIZE) as usize],
+            None => QueueMem::read_byte(elf_mem, stack, addr),
+        }
+    }
+    fn segment_byte(elf_mem: &mut [Segment], addr: u32) -> Option<&mut u8> {
+        elf_mem.iter_mut().find_map(|seg| {
+            addr.checked_sub(seg.base)
+                .and_then(|offset| seg.data.get_mut(offset as usize))
+        })
+    }
 }
 
 impl Memory for QueueMem {
+    fn backdoor_read(&mut self, addr: u32) -> u8 {
+        QueueMem::read_byte(&mut self.elf_mem, &mut self.stack, addr)
+    }
+    fn backdoor_write(&mut self, addr: u32, val: u8) {
+        QueueMem::write_data_byte(&mut self.elf_mem, &mut self.stack, &mut self.imem_stale, addr, val)
+    }
+
+    fn fence_i(&mut self) {
+        log::debug!("fence.i made {} written blocks visible to IMem", self.imem_stale.len());
+        self.imem_stale.clear();
+    }
+
     fn read_block(&mut self, addr: u32, mem_type: MemType) -> Rc<RefCell<MemoryTransaction>> {
         let block_start = addr - addr % BLOCK_SIZE;
         match mem_type {
             MemType::IMem => {
-                if self.imem_queue.len() < IMEM_TRANSACTIONS_U {
+                if !self.fetch_anywhere && !self.is_mapped(addr) {
+                    log::debug!("Read from 0x{:08x} in IMem is outside the program", addr);
+
+                    Rc::new(RefCell::new(MemoryTransaction::Fault))
+                } else if self.imem_queue.len() < IMEM_TRANSACTIONS_U {
                     let transaction = Rc::new(RefCell::new(MemoryTransaction::ReadStarted));
                     self.imem_queue.push(QueueEntry {
                         cycle_counter: 0,
@@ -121,7 +454,7 @@ impl Memory for QueueMem {
 
                     Rc::clone(&transaction)
                 } else {
-                    log::debug!("Memory unit is busy, transaction will be ignored");
+                    log::debug!("Memory unit is busy, transaction must be retried");
 
                     Rc::new(RefCell::new(MemoryTransaction::Busy))
                 }
@@ -138,7 +471,7 @@ impl Memory for QueueMem {
 
                     Rc::clone(&transaction)
                 } else {
-                    log::debug!("Memory unit is busy, transaction will be ignored");
+                    log::debug!("Memory unit is busy, transaction must be retried");
 
                     Rc::new(RefCell::new(MemoryTransaction::Busy))
                 }
@@ -146,6 +479,29 @@ impl Memory for QueueMem {
         }
     }
 
+    fn read_modify_write(
+        &mut self,
+        addr: u32,
+        op: AmoOp,
+        val: u32,
+    ) -> Rc<RefCell<MemoryTransaction>> {
+        if self.dmem_queue.len() < DMEM_TRANSACTIONS_U {
+            let transaction = Rc::new(RefCell::new(MemoryTransaction::RmwStarted(op, val)));
+            self.dmem_queue.push(QueueEntry {
+                cycle_counter: 0,
+                transaction: Rc::clone(&transaction),
+                addr,
+            });
+            log::debug!("Read-modify-write of 0x{:08x} in DMem queued in Memory", addr);
+
+            Rc::clone(&transaction)
+        } else {
+            log::debug!("Memory unit is busy, transaction must be retried");
+
+            Rc::new(RefCell::new(MemoryTransaction::Busy))
+        }
+    }
+
     fn write_block(
         &mut self,
         addr: u32,
@@ -166,7 +522,7 @@ impl Memory for QueueMem {
 
                     Rc::clone(&transaction)
                 } else {
-                    log::debug!("Memory unit is busy, transaction will be ignored");
+                    log::debug!("Memory unit is busy, transaction must be retried");
 
                     Rc::new(RefCell::new(MemoryTransaction::Busy))
                 }
@@ -183,7 +539,7 @@ impl Memory for QueueMem {
 
                     Rc::clone(&transaction)
                 } else {
-                    log::debug!("Memory unit is busy, transaction will be ignored");
+                    log::debug!("Memory unit is busy, transaction must be retried");
 
                     Rc::new(RefCell::new(MemoryTransaction::Busy))
                 }
@@ -219,14 +575,31 @@ impl Component for QueueMem {
                         let qe: &mut Vec<QueueEntry> = &mut self.dmem_queue;
 
                         for j in 0..BLOCK_SIZE {
-                            QueueMem::write_byte(&mut self.elf_mem, &mut self.stack, qe[i].addr + j, data[j as usize]);
+                            QueueMem::write_data_byte(&mut self.elf_mem, &mut self.stack, &mut self.imem_stale, qe[i].addr + j, data[j as usize]);
                         }
                         *(*transaction).borrow_mut() = MemoryTransaction::WriteDone;
                         log::debug!("Write to 0x{:08x} in DMem completed", qe[i].addr);
                         qe.remove(0);
 
                     }
-                    _ => unreachable!("MemoryTransaction should only be ReadStarted or Writestarted when in queues")
+                    MemoryTransaction::RmwStarted(op, val) => {
+                        let qe: &mut Vec<QueueEntry> = &mut self.dmem_queue;
+
+                        let addr = qe[i].addr;
+                        let mut bytes = [0; 4];
+                        for (j, byte) in bytes.iter_mut().enumerate() {
+                            *byte = QueueMem::read_byte(&mut self.elf_mem, &mut self.stack, addr + j as u32);
+                        }
+                        let old = u32::from_le_bytes(bytes);
+                        for (j, byte) in op.apply(old, val).to_le_bytes().into_iter().enumerate() {
+                            QueueMem::write_data_byte(&mut self.elf_mem, &mut self.stack, &mut self.imem_stale, addr + j as u32, byte);
+                        }
+                        *(*transaction).borrow_mut() = MemoryTransaction::RmwDone(old);
+                        log::debug!("Read-modify-write of 0x{:08x} in DMem completed", addr);
+                        qe.remove(0);
+
+                    }
+                    _ => unreachable!("MemoryTransaction should only be ReadStarted, WriteStarted or RmwStarted when in queues")
                 };
             } else {
                 let qe = &mut self.dmem_queue[i];
@@ -246,7 +619,7 @@ impl Component for QueueMem {
                         let mut result: [u8; BLOCK_SIZE_U] = [0;64];
 
                         for j in 0..BLOCK_SIZE {
-                            result[j as usize] = QueueMem::read_byte(&mut self.elf_mem, &mut self.stack, qe[i].addr + j);
+                            result[j as usize] = QueueMem::fetch_byte(&mut self.elf_mem, &mut self.stack, &self.imem_stale, qe[i].addr + j);
                         }
                         *(*transaction).borrow_mut() = MemoryTransaction::ReadDone(result);
 
@@ -260,7 +633,11 @@ impl Component for QueueMem {
                         for j in 0..BLOCK_SIZE {
                             QueueMem::write_byte(&mut self.elf_mem, &mut self.stack, qe[i].addr + j, data[j as usize]);
                         }
-                        
+                        // Writes on the instruction side are visible to fetch straight away
+                        if let Some(stale) = self.imem_stale.get_mut(&qe[i].addr) {
+                            *stale = data;
+                        }
+
                         *(*transaction).borrow_mut() = MemoryTransaction::WriteDone;
                         log::debug!("Write to 0x{:08x} in IMem completed", qe[i].addr);
                         qe.remove(0);
@@ -276,3 +653,217 @@ impl Component for QueueMem {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amo_ops_signedness() {
+        let neg = -5i32 as u32;
+        assert_eq!(AmoOp::Swap.apply(1, 2), 2);
+        assert_eq!(AmoOp::Add.apply(u32::MAX, 2), 1);
+        assert_eq!(AmoOp::Xor.apply(0b1100, 0b1010), 0b0110);
+        assert_eq!(AmoOp::And.apply(0b1100, 0b1010), 0b1000);
+        assert_eq!(AmoOp::Or.apply(0b1100, 0b1010), 0b1110);
+        assert_eq!(AmoOp::Min.apply(neg, 3), neg);
+        assert_eq!(AmoOp::Max.apply(neg, 3), 3);
+        assert_eq!(AmoOp::Minu.apply(neg, 3), 3);
+        assert_eq!(AmoOp::Maxu.apply(neg, 3), neg);
+    }
+
+    #[test]
+    fn stores_reach_fetch_after_fence_i() {
+        let mut mem = QueueMem {
+            elf_mem: vec![Segment {
+                base: 0x1000,
+                data: vec![0x13; 2 * BLOCK_SIZE_U],
+            }],
+            stack: HashMap::new(),
+            imem_queue: Vec::new(),
+            dmem_queue: Vec::new(),
+            imem_stale: HashMap::new(),
+            fetch_anywhere: false,
+        };
+        mem.backdoor_write(0x1041, 0xAA);
+        assert_eq!(mem.backdoor_read(0x1041), 0xAA);
+        assert_eq!(mem.backdoor_fetch(0x1041), 0x13);
+        // The rest of the block is unchanged either way, the other block is not saved
+        assert_eq!(mem.backdoor_fetch(0x107F), 0x13);
+        assert_eq!(mem.imem_stale.len(), 1);
+        // Stack memory can't be fetched from so it is never saved
+        mem.backdoor_write(0x3FFF_FFFC, 1);
+        assert_eq!(mem.imem_stale.len(), 1);
+
+        mem.fence_i();
+        assert_eq!(mem.backdoor_fetch(0x1041), 0xAA);
+        assert!(mem.imem_stale.is_empty());
+    }
+
+    /// Header fields of a hand-built ELF image, with one PT_LOAD program header per segment
+    struct Image {
+        class64: bool,
+        big_endian: bool,
+        e_type: u16,
+        machine: u16,
+        entry: u32,
+        /// `(p_vaddr, file contents, p_memsz)`, contents laid out back to back after the headers
+        segments: Vec<(u32, Vec<u8>, u32)>,
+    }
+
+    impl Image {
+        fn riscv(entry: u32, segments: Vec<(u32, Vec<u8>, u32)>) -> Self {
+            Image {
+                class64: false,
+                big_endian: false,
+                e_type: abi::ET_EXEC,
+                machine: abi::EM_RISCV,
+                entry,
+                segments,
+            }
+        }
+
+        /// Program headers are only written for 32-bit images
+        fn bytes(&self) -> Vec<u8> {
+            let mut out = vec![0x7F, b'E', b'L', b'F'];
+            out.push(if self.class64 { 2 } else { 1 });
+            out.push(if self.big_endian { 2 } else { 1 });
+            out.push(1);
+            out.resize(16, 0);
+            let big_endian = self.big_endian;
+            let half = |out: &mut Vec<u8>, val: u16| {
+                out.extend(if big_endian { val.to_be_bytes() } else { val.to_le_bytes() })
+            };
+            let word = |out: &mut Vec<u8>, val: u32| {
+                out.extend(if big_endian { val.to_be_bytes() } else { val.to_le_bytes() })
+            };
+            half(&mut out, self.e_type);
+            half(&mut out, self.machine);
+            word(&mut out, 1);
+            if self.class64 {
+                // e_entry, e_phoff and e_shoff, then the sizes of a 64-bit header and entries
+                out.extend([0; 24]);
+                word(&mut out, 0);
+                for val in [64, 56, 0, 64, 0, 0] {
+                    half(&mut out, val);
+                }
+                return out;
+            }
+            let phnum = self.segments.len() as u32;
+            word(&mut out, self.entry);
+            word(&mut out, if phnum > 0 { 52 } else { 0 });
+            word(&mut out, 0);
+            word(&mut out, 0);
+            for val in [52, 32, phnum as u16, 40, 0, 0] {
+                half(&mut out, val);
+            }
+            let mut offset = 52 + 32 * phnum;
+            for (vaddr, data, memsz) in &self.segments {
+                for val in [abi::PT_LOAD, offset, *vaddr, *vaddr, data.len() as u32, *memsz, 7, 4] {
+                    word(&mut out, val);
+                }
+                offset += data.len() as u32;
+            }
+            for (_, data, _) in &self.segments {
+                out.extend(data);
+            }
+            out
+        }
+    }
+
+    #[test]
+    fn loads_segments_at_their_virtual_addresses() {
+        // The .bss segment's file contents are followed by the text's, which must not leak
+        // into its zero fill
+        let image = Image::riscv(
+            0x8000_0004,
+            vec![
+                (0x8000_2000, vec![5, 6], 8),
+                (0x8000_0000, vec![1, 2, 3, 4, 0x13, 0, 0, 0], 8),
+            ],
+        );
+        let (mut mem, info) = QueueMem::from_elf_bytes(&image.bytes()).unwrap();
+        assert_eq!(info.entry, 0x8000_0004);
+        assert_eq!(info.brk, 0x8000_2008);
+        assert_eq!(info.tohost, None);
+        let text: Vec<u8> = (0x8000_0000..0x8000_0008).map(|a| mem.backdoor_read(a)).collect();
+        assert_eq!(text, [1, 2, 3, 4, 0x13, 0, 0, 0]);
+        let data: Vec<u8> = (0x8000_2000..0x8000_2008).map(|a| mem.backdoor_read(a)).collect();
+        assert_eq!(data, [5, 6, 0, 0, 0, 0, 0, 0]);
+        assert!(mem.is_mapped(0x8000_0007) && mem.is_mapped(0x8000_2007));
+        assert!(!mem.is_mapped(0x8000_0008) && !mem.is_mapped(0x8000_2008));
+        // Nothing is placed at the file offsets
+        assert!(!mem.is_mapped(52));
+    }
+
+    #[test]
+    fn segments_may_end_at_the_top_of_the_address_space() {
+        let image = Image::riscv(0xFFFF_F000, vec![(0xFFFF_F000, vec![0x13, 0, 0, 0], 0x1000)]);
+        let (mem, info) = QueueMem::from_elf_bytes(&image.bytes()).unwrap();
+        assert!(mem.is_mapped(0xFFFF_F000) && mem.is_mapped(0xFFFF_FFFF));
+        assert!(!mem.is_mapped(0));
+        assert!(info.map.contains(0xFFFF_FFFC, 4));
+        assert!(!info.map.contains(0xFFFF_FFFE, 4));
+        assert_eq!(info.brk, u32::MAX);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_rv32_executables() {
+        let unsupported = |image: Image| {
+            matches!(QueueMem::from_elf_bytes(&image.bytes()), Err(SimError::UnsupportedMachine(_)))
+        };
+        let segments = || vec![(0x1000, vec![0x13, 0, 0, 0], 4)];
+        assert!(unsupported(Image {
+            class64: true,
+            ..Image::riscv(0x1000, Vec::new())
+        }));
+        assert!(unsupported(Image {
+            big_endian: true,
+            ..Image::riscv(0x1000, segments())
+        }));
+        assert!(unsupported(Image {
+            machine: abi::EM_ARM,
+            ..Image::riscv(0x1000, segments())
+        }));
+        for e_type in [abi::ET_REL, abi::ET_DYN, abi::ET_CORE] {
+            assert!(unsupported(Image {
+                e_type,
+                ..Image::riscv(0x1000, segments())
+            }));
+        }
+
+        let bad = |bytes: &[u8]| {
+            matches!(QueueMem::from_elf_bytes(bytes), Err(SimError::BadElf(_)))
+        };
+        assert!(bad(&Image::riscv(0x1000, Vec::new()).bytes()));
+        // Wrapping past 4 GiB, and file contents that don't fit in the segment
+        assert!(bad(&Image::riscv(0xFFFF_F000, vec![(0xFFFF_F000, vec![0; 4], 0x2000)]).bytes()));
+        assert!(bad(&Image::riscv(0x1000, vec![(0x1000, vec![0; 8], 4)]).bytes()));
+        assert!(bad(b"\x7fELF"));
+        assert!(bad(&[0; 64]));
+    }
+
+    #[test]
+    fn full_queues_turn_requests_away_until_one_completes() {
+        let (mut mem, _) = QueueMem::with_program(0x1000, &[0x13; 32]);
+        let first = mem.read_block(0x1000, MemType::DMem);
+        let second = mem.write_block(0x1040, [0; BLOCK_SIZE_U], MemType::DMem);
+        let busy = mem.read_block(0x1000, MemType::DMem);
+        assert!(matches!(*busy.borrow(), MemoryTransaction::Busy));
+        assert!(matches!(
+            *mem.read_modify_write(0x1000, AmoOp::Add, 1).borrow(),
+            MemoryTransaction::Busy
+        ));
+        // The ports queue separately
+        let fetch = mem.read_block(0x1000, MemType::IMem);
+        assert!(matches!(*fetch.borrow(), MemoryTransaction::ReadStarted));
+
+        for _ in 0..=ACCESS_CYCLES {
+            mem.cycle();
+        }
+        assert!(matches!(*first.borrow(), MemoryTransaction::ReadDone(_)));
+        assert!(matches!(*second.borrow(), MemoryTransaction::WriteDone));
+        let retry = mem.read_block(0x1000, MemType::DMem);
+        assert!(matches!(*retry.borrow(), MemoryTransaction::ReadStarted));
+    }
+}
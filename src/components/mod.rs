@@ -1,2 +1,8 @@
+pub mod cache;
+pub mod component;
+pub mod core;
+pub mod hierarchy;
 pub mod memory;
-pub mod component;
\ No newline at end of file
+pub mod ports;
+pub mod replacement;
+pub mod trace_core;
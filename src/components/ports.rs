@@ -0,0 +1,208 @@
+use super::cache::{self, Cache, CacheTransaction};
+use super::component::Component;
+use super::core::CoreConfig;
+use super::hierarchy::{Hierarchy, Level, LevelCache};
+use super::memory::{Memory, MemoryTransaction, MemType, BLOCK_SIZE, BLOCK_SIZE_U};
+use crate::compressed::inst_len;
+use crate::trap::Exception;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Transaction = Rc<RefCell<MemoryTransaction>>;
+
+/// A core's side of the memory system: the L1 instruction cache it fetches through on
+/// `MemType::IMem`, the L1 data cache it loads and stores through on `MemType::DMem`, and
+/// the hierarchy behind both. At most one fetch and one data access are outstanding.
+#[derive(Debug)]
+pub struct Ports<M: Level> {
+    /// Shared caches and memory behind the L1s
+    hierarchy: Hierarchy<M>,
+    icache: LevelCache,
+    dcache: LevelCache,
+    /// Outstanding instruction fetch
+    transaction: Option<Transaction>,
+    /// Outstanding data access
+    data_transaction: Option<cache::Transaction>,
+    /// Last block fetched from IMem, sequential fetches within it are free
+    fetch_buffer: Option<(u32, [u8; BLOCK_SIZE_U])>,
+    /// Lower half of a 32-bit instruction straddling a block boundary, kept while
+    /// the block holding its upper half is fetched
+    straddle: Option<u16>,
+    /// Caches cleaned so far for the `fence.i` in progress, the data cache first and then
+    /// each shared level
+    cleaned: usize,
+}
+
+impl<M: Level> Ports<M> {
+    pub fn new(mem: M, config: &CoreConfig) -> Self {
+        let hierarchy = Hierarchy::new(mem, &config.shared_levels());
+        Self {
+            icache: hierarchy.l1("L1I", config.icache, MemType::IMem),
+            dcache: hierarchy.l1("L1D", config.dcache, MemType::DMem),
+            hierarchy,
+            transaction: None,
+            data_transaction: None,
+            fetch_buffer: None,
+            straddle: None,
+            cleaned: 0,
+        }
+    }
+
+    pub fn icache(&self) -> &LevelCache {
+        &self.icache
+    }
+    pub fn dcache(&self) -> &LevelCache {
+        &self.dcache
+    }
+    /// The data cache, for accesses such as syscalls that go around the data port
+    pub fn dcache_mut(&mut self) -> &mut LevelCache {
+        &mut self.dcache
+    }
+    pub fn hierarchy(&self) -> &Hierarchy<M> {
+        &self.hierarchy
+    }
+
+    /// Polls the outstanding fetch, starting it with `start` if there is none.
+    /// Returns the finished transaction and clears it, or `None` while it is in flight.
+    fn poll(
+        &mut self,
+        start: impl FnOnce(&mut LevelCache) -> Transaction,
+    ) -> Option<MemoryTransaction> {
+        let transaction = match self.transaction.take() {
+            Some(t) => t,
+            None => start(&mut self.icache),
+        };
+        let mt = *transaction.borrow();
+        match mt {
+            // Dropped by memory, retry next cycle
+            MemoryTransaction::Busy => None,
+            MemoryTransaction::ReadStarted
+            | MemoryTransaction::WriteStarted(_)
+            | MemoryTransaction::RmwStarted(..) => {
+                self.transaction = Some(transaction);
+                None
+            }
+            MemoryTransaction::ReadDone(_)
+            | MemoryTransaction::WriteDone
+            | MemoryTransaction::RmwDone(_)
+            | MemoryTransaction::Fault => Some(mt),
+        }
+    }
+
+    /// Polls the outstanding data access like `poll`
+    pub fn poll_data(
+        &mut self,
+        start: impl FnOnce(&mut LevelCache) -> cache::Transaction,
+    ) -> Option<CacheTransaction> {
+        let transaction = match self.data_transaction.take() {
+            Some(t) => t,
+            None => start(&mut self.dcache),
+        };
+        let ct = *transaction.borrow();
+        match ct {
+            // Dropped by the cache, retry next cycle
+            CacheTransaction::Busy => None,
+            CacheTransaction::ReadStarted | CacheTransaction::WriteStarted => {
+                self.data_transaction = Some(transaction);
+                None
+            }
+            CacheTransaction::ReadDone(_) | CacheTransaction::WriteDone | CacheTransaction::Fault => {
+                Some(ct)
+            }
+        }
+    }
+
+    /// Reads the 16-bit parcel at `addr` from the fetch buffer, fetching its block through
+    /// IMem first if it is not there. Returns `None` while the fetch is in flight.
+    fn fetch_parcel(&mut self, addr: u32) -> Result<Option<u16>, Exception> {
+        let block = addr - addr % BLOCK_SIZE;
+        match self.fetch_buffer {
+            Some((base, data)) if base == block => {
+                let offset = (addr - block) as usize;
+                Ok(Some(u16::from_le_bytes([data[offset], data[offset + 1]])))
+            }
+            _ => {
+                match self.poll(|mem| mem.read_block(addr, MemType::IMem)) {
+                    Some(MemoryTransaction::ReadDone(data)) => {
+                        self.fetch_buffer = Some((block, data))
+                    }
+                    Some(MemoryTransaction::Fault) => {
+                        return Err(Exception::InstructionFault { addr })
+                    }
+                    _ => {}
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Fetches the instruction at the 2-byte aligned `pc`, taking two fetches for a 32-bit
+    /// one whose upper half is in the next block. Returns `None` while a fetch is in flight.
+    /// A fault comes with as much of the word as was fetched before it.
+    ///
+    /// `known` is the word when it is known up front, as when replaying a trace. Its length
+    /// decides whether a second fetch is needed, rather than the bytes in memory.
+    pub fn fetch(&mut self, pc: u32, known: Option<u32>) -> Result<Option<u32>, (Exception, u32)> {
+        let low = match self.straddle.take() {
+            Some(low) => low,
+            None => match self.fetch_parcel(pc) {
+                Ok(Some(low)) => known.map_or(low, |word| word as u16),
+                Ok(None) => return Ok(None),
+                Err(exception) => return Err((exception, 0)),
+            },
+        };
+        if inst_len(low) == 2 {
+            return Ok(Some(known.unwrap_or(low as u32)));
+        }
+        let addr = pc.wrapping_add(2);
+        match self.fetch_parcel(addr) {
+            Ok(Some(high)) => Ok(Some(known.unwrap_or(low as u32 | (high as u32) << 16))),
+            Ok(None) => {
+                // Upper half is in the next block, hold on to the lower half while it is fetched
+                if addr.is_multiple_of(BLOCK_SIZE) {
+                    self.straddle = Some(low);
+                }
+                Ok(None)
+            }
+            Err(exception) => Err((exception, low as u32)),
+        }
+    }
+
+    /// Takes a `fence.i` a step further, cleaning the data cache and then each shared level
+    /// in turn. Returns true once all of them are clean, having invalidated the instruction
+    /// cache and dropped the fetch buffer.
+    pub fn fence_i(&mut self) -> bool {
+        let cleaned = match self.cleaned {
+            0 => self.poll_data(|dcache| dcache.clean()),
+            level => {
+                let shared = self.hierarchy.level(level - 1);
+                self.poll_data(move |_| shared.borrow_mut().clean())
+            }
+        };
+        if !matches!(cleaned, Some(CacheTransaction::WriteDone)) {
+            return false;
+        }
+        if self.cleaned < self.hierarchy.levels().len() {
+            self.cleaned += 1;
+            return false;
+        }
+        // Older stores have all reached memory now, so once it has made them visible to
+        // IMem the only stale instruction bytes left are the ones already fetched or in
+        // the cache
+        self.cleaned = 0;
+        self.icache.fence_i();
+        self.fetch_buffer = None;
+        self.straddle = None;
+        true
+    }
+}
+
+impl<M: Level> Component for Ports<M> {
+    /// Cycles the hierarchy, then the L1s
+    fn cycle(&mut self) {
+        self.hierarchy.cycle();
+        self.hierarchy.back_invalidate([&mut self.icache, &mut self.dcache]);
+        self.icache.cycle();
+        self.dcache.cycle();
+    }
+}
@@ -0,0 +1,346 @@
+use std::fmt;
+
+/// Chooses which line of a set a cache evicts. The cache tells the policy about every hit
+/// and fill, and only asks for a victim once every way of the set holds a valid line.
+pub trait ReplacementPolicy: fmt::Debug {
+    /// Records a hit on `way` of `set`
+    fn touch(&mut self, set: usize, way: usize);
+    /// Records a new line filled into `way` of `set`
+    fn insert(&mut self, set: usize, way: usize);
+    /// Picks the way of a full `set` to evict
+    fn victim(&mut self, set: usize) -> usize;
+    /// Counts of events particular to the policy, for the run stats
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        Vec::new()
+    }
+}
+
+/// Replacement policies a cache can be configured with
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// Least recently used
+    Lru,
+    /// Tree pseudo-LRU, needs a power-of-two number of ways
+    TreePlru,
+    /// Uniformly random, from the configured seed
+    Random,
+    /// First in, first out, ignoring hits
+    Fifo,
+    /// Static re-reference interval prediction
+    Srrip,
+    /// Bimodal re-reference interval prediction, mostly inserting at distant re-reference
+    Brrip,
+}
+
+impl Replacement {
+    /// Builds the policy for a cache of `sets` sets of `ways` lines
+    pub fn build(self, sets: usize, ways: usize, seed: u64) -> Box<dyn ReplacementPolicy> {
+        match self {
+            Replacement::Lru => Box::new(Lru::new(sets, ways, false)),
+            Replacement::Fifo => Box::new(Lru::new(sets, ways, true)),
+            Replacement::TreePlru => Box::new(TreePlru::new(sets, ways)),
+            Replacement::Random => Box::new(Random {
+                ways,
+                rng: Rng::new(seed),
+            }),
+            Replacement::Srrip => Box::new(Rrip::new(sets, ways, None)),
+            Replacement::Brrip => Box::new(Rrip::new(sets, ways, Some(Rng::new(seed)))),
+        }
+    }
+}
+
+impl fmt::Display for Replacement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Replacement::Lru => "lru",
+            Replacement::TreePlru => "tree-plru",
+            Replacement::Random => "random",
+            Replacement::Fifo => "fifo",
+            Replacement::Srrip => "srrip",
+            Replacement::Brrip => "brrip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Small xorshift generator, so runs with the same seed pick the same victims
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero, and nearby seeds should give unrelated streams
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// LRU, or FIFO when only fills count as a use
+#[derive(Debug)]
+struct Lru {
+    ways: usize,
+    /// Value of `clock` at each line's last use
+    stamps: Vec<u64>,
+    clock: u64,
+    fifo: bool,
+}
+
+impl Lru {
+    fn new(sets: usize, ways: usize, fifo: bool) -> Self {
+        Self {
+            ways,
+            stamps: vec![0; sets * ways],
+            clock: 0,
+            fifo,
+        }
+    }
+
+    fn stamp(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.stamps[set * self.ways + way] = self.clock;
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn touch(&mut self, set: usize, way: usize) {
+        if !self.fifo {
+            self.stamp(set, way);
+        }
+    }
+    fn insert(&mut self, set: usize, way: usize) {
+        self.stamp(set, way);
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let stamps = &self.stamps[set * self.ways..(set + 1) * self.ways];
+        (0..self.ways)
+            .min_by_key(|&way| stamps[way])
+            .expect("sets have at least one way")
+    }
+}
+
+/// Binary tree over the ways of each set, each node pointing at the half used less recently
+#[derive(Debug)]
+struct TreePlru {
+    ways: usize,
+    /// `ways - 1` nodes per set, heap ordered. Set means the right half is the older one.
+    nodes: Vec<bool>,
+}
+
+impl TreePlru {
+    fn new(sets: usize, ways: usize) -> Self {
+        assert!(ways.is_power_of_two(), "tree-PLRU needs a power-of-two number of ways");
+        Self {
+            ways,
+            nodes: vec![false; sets * (ways - 1)],
+        }
+    }
+}
+
+impl ReplacementPolicy for TreePlru {
+    fn touch(&mut self, set: usize, way: usize) {
+        let nodes = &mut self.nodes[set * (self.ways - 1)..(set + 1) * (self.ways - 1)];
+        let (mut node, mut lo, mut hi) = (0, 0, self.ways);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            // Point away from the half just used
+            nodes[node] = way < mid;
+            if way < mid {
+                (node, hi) = (2 * node + 1, mid);
+            } else {
+                (node, lo) = (2 * node + 2, mid);
+            }
+        }
+    }
+    fn insert(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let nodes = &self.nodes[set * (self.ways - 1)..(set + 1) * (self.ways - 1)];
+        let (mut node, mut lo, mut hi) = (0, 0, self.ways);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if nodes[node] {
+                (node, lo) = (2 * node + 2, mid);
+            } else {
+                (node, hi) = (2 * node + 1, mid);
+            }
+        }
+        lo
+    }
+}
+
+#[derive(Debug)]
+struct Random {
+    ways: usize,
+    rng: Rng,
+}
+
+impl ReplacementPolicy for Random {
+    fn touch(&mut self, _set: usize, _way: usize) {}
+    fn insert(&mut self, _set: usize, _way: usize) {}
+    fn victim(&mut self, _set: usize) -> usize {
+        self.rng.below(self.ways)
+    }
+}
+
+/// Re-reference prediction value of a line expected to be reused furthest in the future
+const DISTANT: u8 = 3;
+/// Out of how many BRRIP fills one is inserted at long rather than distant re-reference
+const BRRIP_LONG_ONE_IN: usize = 32;
+
+/// SRRIP, or BRRIP when it has a generator for its bimodal insertion, with 2-bit
+/// re-reference prediction values. Hits predict near re-reference, the victim is a line
+/// predicted distant, ageing the whole set until there is one.
+#[derive(Debug)]
+struct Rrip {
+    ways: usize,
+    rrpv: Vec<u8>,
+    bimodal: Option<Rng>,
+    /// Times a set was aged because no line in it was predicted distant
+    ageings: u64,
+    /// Fills inserted at long rather than distant re-reference
+    long_inserts: u64,
+}
+
+impl Rrip {
+    fn new(sets: usize, ways: usize, bimodal: Option<Rng>) -> Self {
+        Self {
+            ways,
+            rrpv: vec![DISTANT; sets * ways],
+            bimodal,
+            ageings: 0,
+            long_inserts: 0,
+        }
+    }
+}
+
+impl ReplacementPolicy for Rrip {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.rrpv[set * self.ways + way] = 0;
+    }
+    fn insert(&mut self, set: usize, way: usize) {
+        let long = match &mut self.bimodal {
+            Some(rng) => rng.below(BRRIP_LONG_ONE_IN) == 0,
+            None => true,
+        };
+        if long {
+            self.long_inserts += 1;
+        }
+        self.rrpv[set * self.ways + way] = if long { DISTANT - 1 } else { DISTANT };
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let rrpv = &mut self.rrpv[set * self.ways..(set + 1) * self.ways];
+        loop {
+            if let Some(way) = rrpv.iter().position(|&v| v == DISTANT) {
+                return way;
+            }
+            for v in rrpv.iter_mut() {
+                *v += 1;
+            }
+            self.ageings += 1;
+        }
+    }
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![("rrip_ageings", self.ageings), ("rrip_long_inserts", self.long_inserts)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Misses of a single fully associative set of `ways` lines over `blocks`
+    fn misses(replacement: Replacement, ways: usize, blocks: &[usize]) -> usize {
+        let mut policy = replacement.build(1, ways, 1);
+        let mut lines: Vec<Option<usize>> = vec![None; ways];
+        let mut misses = 0;
+        for &block in blocks {
+            if let Some(way) = lines.iter().position(|&line| line == Some(block)) {
+                policy.touch(0, way);
+                continue;
+            }
+            misses += 1;
+            let way = match lines.iter().position(Option::is_none) {
+                Some(way) => way,
+                None => policy.victim(0),
+            };
+            lines[way] = Some(block);
+            policy.insert(0, way);
+        }
+        misses
+    }
+
+    /// Victims a single set of `ways` lines picks over `n` misses, each refilling the victim
+    fn victims(replacement: Replacement, ways: usize, seed: u64, n: usize) -> Vec<usize> {
+        let mut policy = replacement.build(1, ways, seed);
+        for way in 0..ways {
+            policy.insert(0, way);
+        }
+        (0..n)
+            .map(|_| {
+                let way = policy.victim(0);
+                policy.insert(0, way);
+                way
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lru_fifo_and_plru_orders() {
+        // 0 is reused before 4 comes in, so LRU keeps it and FIFO doesn't
+        let pattern = [0, 1, 2, 3, 0, 4, 0];
+        assert_eq!(misses(Replacement::Lru, 4, &pattern), 5);
+        assert_eq!(misses(Replacement::Fifo, 4, &pattern), 6);
+        assert_eq!(misses(Replacement::TreePlru, 4, &pattern), 5);
+
+        let mut plru = Replacement::TreePlru.build(1, 4, 0);
+        for way in [0, 1, 2, 3] {
+            plru.insert(0, way);
+        }
+        assert_eq!(plru.victim(0), 0);
+        plru.touch(0, 0);
+        // The right pair is older now, and 2 was used before 3
+        assert_eq!(plru.victim(0), 2);
+    }
+
+    #[test]
+    fn rrip_resists_scans() {
+        // A hot pair reused between scans of blocks used once. LRU lets each scan flush
+        // the pair, SRRIP keeps it because scan blocks never get promoted.
+        let mut pattern = Vec::new();
+        for round in 0..16 {
+            pattern.extend([0, 1, 0, 1]);
+            pattern.extend((0..4).map(|i| 100 + 4 * round + i));
+        }
+        let lru = misses(Replacement::Lru, 4, &pattern);
+        let srrip = misses(Replacement::Srrip, 4, &pattern);
+        assert!(srrip < lru, "srrip {} lru {}", srrip, lru);
+    }
+
+    #[test]
+    fn random_and_brrip_follow_the_seed() {
+        let pattern: Vec<usize> = (0..200).map(|i| (i * 7) % 13).collect();
+        for replacement in [Replacement::Random, Replacement::Brrip] {
+            let first = misses(replacement, 4, &pattern);
+            assert_eq!(misses(replacement, 4, &pattern), first);
+            assert!(first < pattern.len());
+            let victims_1 = victims(replacement, 8, 1, 64);
+            assert_eq!(victims(replacement, 8, 1, 64), victims_1);
+            assert_ne!(victims(replacement, 8, 2, 64), victims_1, "{} ignores the seed", replacement);
+        }
+        let mut a = Rng::new(1);
+        let mut b = Rng::new(2);
+        assert_ne!(a.next(), b.next());
+    }
+}
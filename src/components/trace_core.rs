@@ -0,0 +1,327 @@
+use super::cache::{Cache, CacheTransaction};
+use super::component::Component;
+use super::core::CoreConfig;
+use super::hierarchy::{Hierarchy, Level, LevelCache};
+use super::memory::AmoOp;
+use super::ports::Ports;
+use crate::error::SimError;
+use crate::instructions::{decode_inst, Instruction, MemKind, MemWidth, Reg};
+use crate::spike::Commit;
+
+/// Data access waiting on memory, at the address the trace recorded
+#[derive(Debug, Clone, Copy)]
+enum MemAccess {
+    Load {
+        addr: u32,
+        width: MemWidth,
+    },
+    Store {
+        addr: u32,
+        width: MemWidth,
+        val: u64,
+    },
+    /// AMOs and store-conditionals, done as one read-modify-write transaction that
+    /// swaps in the value the trace says was written
+    Rmw {
+        addr: u32,
+        val: u32,
+    },
+    /// Clean of the data cache and each shared level ahead of a fence.i
+    FenceI,
+}
+
+#[derive(Debug)]
+enum State {
+    /// Waiting for the block holding the PC to be fetched
+    Fetch,
+    /// Waiting on a multi-cycle functional unit
+    Execute {
+        /// Cycles left before the result is ready
+        remaining: u32,
+    },
+    /// Waiting on a data access for the instruction being replayed
+    Memory(MemAccess),
+    Halted,
+}
+
+/// Replays a commit log through the memory system with the timing of `TimingCore`:
+/// each instruction is fetched through the L1 instruction cache, waits out its functional
+/// unit's latency and makes its data access through the L1 data cache, both in front of
+/// the same hierarchy as the core's. Nothing is executed, the
+/// instruction words, data addresses and store values all come from the trace, so a
+/// program's access pattern can be studied without being able to run it here.
+pub struct TraceCore<M: Level, I: Iterator<Item = Result<Commit, SimError>>> {
+    config: CoreConfig,
+    /// L1s and the hierarchy behind them, cycled by the core
+    ports: Ports<M>,
+    commits: I,
+    /// Instruction being replayed
+    commit: Option<Commit>,
+    /// Integer registers as of the last replayed write
+    regs: [u32; 32],
+    state: State,
+    cycles: u64,
+    retired: u64,
+    /// Error that stopped the replay
+    error: Option<SimError>,
+}
+
+impl<M: Level, I: Iterator<Item = Result<Commit, SimError>>> TraceCore<M, I> {
+    pub fn new(mem: M, commits: I, config: CoreConfig) -> Self {
+        let mut core = Self {
+            config,
+            ports: Ports::new(mem, &config),
+            commits,
+            commit: None,
+            regs: [0; 32],
+            state: State::Fetch,
+            cycles: 0,
+            retired: 0,
+            error: None,
+        };
+        core.advance();
+        core
+    }
+
+    pub fn regs(&self) -> &[u32; 32] {
+        &self.regs
+    }
+    pub fn icache(&self) -> &LevelCache {
+        self.ports.icache()
+    }
+    pub fn dcache(&self) -> &LevelCache {
+        self.ports.dcache()
+    }
+    pub fn hierarchy(&self) -> &Hierarchy<M> {
+        self.ports.hierarchy()
+    }
+    pub fn halted(&self) -> bool {
+        matches!(self.state, State::Halted)
+    }
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    /// Runs until the trace is exhausted
+    pub fn run(&mut self) -> Result<(), SimError> {
+        while !self.halted() {
+            self.cycle();
+        }
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves on to the next record of the trace, halting at the end of it
+    fn advance(&mut self) {
+        self.commit = None;
+        match self.commits.next() {
+            Some(Ok(commit)) => {
+                self.commit = Some(commit);
+                self.state = State::Fetch;
+            }
+            Some(Err(e)) => self.fail(e),
+            None => {
+                log::info!(
+                    "Trace ended after {} instructions in {} cycles",
+                    self.retired,
+                    self.cycles
+                );
+                self.state = State::Halted;
+            }
+        }
+    }
+
+    fn fail(&mut self, error: SimError) {
+        log::debug!("Trace replay halted after {} instructions: {}", self.retired, error);
+        self.error = Some(error);
+        self.state = State::Halted;
+    }
+
+    fn fetch(&mut self) {
+        let commit = self.commit.as_ref().expect("fetching with no trace record");
+        let (pc, word) = (commit.pc, commit.word);
+        // The trace supplies the word, the fetch only has to take as long as the core's
+        match self.ports.fetch(pc, Some(word)) {
+            Ok(Some(word)) => self.issue(word),
+            Ok(None) => {}
+            Err((exception, _)) => self.fail(exception.into_error(pc, word)),
+        }
+    }
+
+    /// Sends a fetched instruction to memory or its functional unit
+    fn issue(&mut self, word: u32) {
+        let inst = decode_inst(word);
+        let commit = self.commit.as_ref().expect("issuing with no trace record");
+        // An access the trace has no address for, such as a failed `sc.w`, isn't made
+        let access = inst.mem_op().and_then(|op| match op.kind {
+            MemKind::Load => commit.loads.first().map(|&addr| MemAccess::Load {
+                addr,
+                width: op.width,
+            }),
+            MemKind::Store if !matches!(inst, Instruction::ScW { .. }) => {
+                commit.stores.first().map(|&(addr, val)| MemAccess::Store {
+                    addr,
+                    width: op.width,
+                    val,
+                })
+            }
+            MemKind::Store | MemKind::Amo => commit
+                .stores
+                .first()
+                .map(|&(addr, val)| MemAccess::Rmw { addr, val: val as u32 }),
+        });
+        let access = match inst {
+            Instruction::FenceI => Some(MemAccess::FenceI),
+            _ => access,
+        };
+        match access {
+            Some(access) => self.state = State::Memory(access),
+            None => match self.config.latency(inst.fu_class()) {
+                1 => self.retire(),
+                latency => self.state = State::Execute { remaining: latency - 1 },
+            },
+        }
+    }
+
+    fn execute(&mut self) {
+        let State::Execute { ref mut remaining } = self.state else {
+            unreachable!("execute stage should only run in State::Execute")
+        };
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.retire();
+        }
+    }
+
+    fn memory(&mut self) {
+        let State::Memory(access) = self.state else {
+            unreachable!("memory stage should only run in State::Memory")
+        };
+        match access {
+            MemAccess::Load { addr, width } => {
+                if let Some(CacheTransaction::ReadDone(_)) =
+                    self.ports.poll_data(|dcache| dcache.read(addr, width))
+                {
+                    self.retire();
+                }
+            }
+            MemAccess::Store { addr, width, val } => {
+                if let Some(CacheTransaction::WriteDone) =
+                    self.ports.poll_data(|dcache| dcache.write(addr, width, val))
+                {
+                    self.retire();
+                }
+            }
+            MemAccess::Rmw { addr, val } => {
+                if let Some(CacheTransaction::ReadDone(_)) =
+                    self.ports.poll_data(|dcache| Cache::read_modify_write(dcache, addr, AmoOp::Swap, val))
+                {
+                    self.retire();
+                }
+            }
+            MemAccess::FenceI => {
+                if self.ports.fence_i() {
+                    self.retire();
+                }
+            }
+        }
+    }
+
+    fn retire(&mut self) {
+        let commit = self.commit.as_ref().expect("retiring with no trace record");
+        for &(reg, val) in &commit.reg_writes {
+            if let Reg::X(rd) = reg {
+                self.regs[rd as usize] = val as u32;
+            }
+        }
+        self.retired += 1;
+        self.advance();
+    }
+}
+
+impl<M: Level, I: Iterator<Item = Result<Commit, SimError>>> Component
+    for TraceCore<M, I>
+{
+    fn cycle(&mut self) {
+        if self.halted() {
+            return;
+        }
+        self.ports.cycle();
+        self.cycles += 1;
+        match self.state {
+            State::Fetch => self.fetch(),
+            State::Execute { .. } => self.execute(),
+            State::Memory(_) => self.memory(),
+            State::Halted => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::components::core::TimingCore;
+    use crate::components::memory::QueueMem;
+    use crate::spike::CommitLog;
+
+    #[test]
+    fn replay_matches_timing_core() {
+        let program = assemble(
+            "
+            lui a0, 2
+            li a1, 5
+            sw a1, 0(a0)
+            lw a2, 0(a0)
+            mul a3, a2, a1
+            amoadd.w a4, a1, (a0)
+            ret
+            ",
+            0x1000,
+        )
+        .unwrap();
+        let (mem, info) = QueueMem::with_program(0x1000, &program);
+        let mut core = TimingCore::new(mem, &info, CoreConfig::default());
+        core.run().unwrap();
+
+        let writes = [
+            " x10 0x00002000",
+            " x11 0x00000005",
+            " mem 0x00002000 0x00000005",
+            " x12 0x00000005 mem 0x00002000",
+            " x13 0x00000019",
+            " x14 0x00000005 mem 0x00002000 mem 0x00002000 0x0000000a",
+            "",
+        ];
+        let log: String = program
+            .iter()
+            .zip(writes)
+            .enumerate()
+            .map(|(i, (word, writes))| {
+                format!("core   0: 3 0x{:08x} (0x{:08x}){}\n", 0x1000 + 4 * i, word, writes)
+            })
+            .collect();
+        let commits = CommitLog::new(log.as_bytes());
+        let mut replay = TraceCore::new(QueueMem::empty(), commits, CoreConfig::default());
+        replay.run().unwrap();
+
+        assert_eq!(replay.retired(), core.retired());
+        assert_eq!(replay.cycles(), core.cycles());
+        assert_eq!(replay.regs()[10..15], core.regs()[10..15]);
+    }
+
+    #[test]
+    fn misaligned_accesses_stop_the_replay() {
+        // sw to the last three bytes of a line and the first of the next
+        let log = "core   0: 3 0x00001000 (0x00a52023) mem 0x0000203d 0x00000000\n";
+        let commits = CommitLog::new(log.as_bytes());
+        let mut replay = TraceCore::new(QueueMem::empty(), commits, CoreConfig::default());
+        assert!(matches!(replay.run(), Err(SimError::BadTrace { line: 1, .. })));
+        assert_eq!(replay.retired(), 0);
+    }
+}
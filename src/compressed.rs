@@ -0,0 +1,371 @@
+use crate::instructions::Instruction;
+
+/// Length in bytes of the instruction whose low 16 bits are `low`
+pub fn inst_len(low: u16) -> u32 {
+    if low & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Register from a 3-bit compressed register field, which addresses x8-x15 (or f8-f15)
+fn creg(field: u16) -> u32 {
+    (field & 0x7) as u32 + 8
+}
+
+/// Extracts `inst[hi:lo]`
+fn bits(inst: u16, hi: u32, lo: u32) -> u32 {
+    ((inst as u32) >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+/// Sign extends the low `width` bits of `val`
+fn sext(val: u32, width: u32) -> i32 {
+    ((val << (32 - width)) as i32) >> (32 - width)
+}
+
+/// imm[5] = inst[12], imm[4:0] = inst[6:2], sign extended
+fn ci_imm(inst: u16) -> i32 {
+    sext((bits(inst, 12, 12) << 5) | bits(inst, 6, 2), 6)
+}
+/// offset[11|4|9:8|10|6|7|3:1|5] = inst[12:2]
+fn cj_imm(inst: u16) -> i32 {
+    let imm = (bits(inst, 12, 12) << 11)
+        | (bits(inst, 11, 11) << 4)
+        | (bits(inst, 10, 9) << 8)
+        | (bits(inst, 8, 8) << 10)
+        | (bits(inst, 7, 7) << 6)
+        | (bits(inst, 6, 6) << 7)
+        | (bits(inst, 5, 3) << 1)
+        | (bits(inst, 2, 2) << 5);
+    sext(imm, 12)
+}
+/// offset[8|4:3] = inst[12:10], offset[7:6|2:1|5] = inst[6:2]
+fn cb_imm(inst: u16) -> i32 {
+    let imm = (bits(inst, 12, 12) << 8)
+        | (bits(inst, 11, 10) << 3)
+        | (bits(inst, 6, 5) << 6)
+        | (bits(inst, 4, 3) << 1)
+        | (bits(inst, 2, 2) << 5);
+    sext(imm, 9)
+}
+/// Word load/store offset, uimm[5:3] = inst[12:10], uimm[2|6] = inst[6:5]
+fn cl_word_imm(inst: u16) -> i32 {
+    ((bits(inst, 12, 10) << 3) | (bits(inst, 6, 6) << 2) | (bits(inst, 5, 5) << 6)) as i32
+}
+/// Doubleword load/store offset, uimm[5:3] = inst[12:10], uimm[7:6] = inst[6:5]
+fn cl_double_imm(inst: u16) -> i32 {
+    ((bits(inst, 12, 10) << 3) | (bits(inst, 6, 5) << 6)) as i32
+}
+/// Word stack load offset, uimm[5] = inst[12], uimm[4:2|7:6] = inst[6:2]
+fn ci_lwsp_imm(inst: u16) -> i32 {
+    ((bits(inst, 12, 12) << 5) | (bits(inst, 6, 4) << 2) | (bits(inst, 3, 2) << 6)) as i32
+}
+/// Doubleword stack load offset, uimm[5] = inst[12], uimm[4:3|8:6] = inst[6:2]
+fn ci_ldsp_imm(inst: u16) -> i32 {
+    ((bits(inst, 12, 12) << 5) | (bits(inst, 6, 5) << 3) | (bits(inst, 4, 2) << 6)) as i32
+}
+/// Word stack store offset, uimm[5:2|7:6] = inst[12:7]
+fn css_swsp_imm(inst: u16) -> i32 {
+    ((bits(inst, 12, 9) << 2) | (bits(inst, 8, 7) << 6)) as i32
+}
+/// Doubleword stack store offset, uimm[5:3|8:6] = inst[12:7]
+fn css_sdsp_imm(inst: u16) -> i32 {
+    ((bits(inst, 12, 10) << 3) | (bits(inst, 9, 7) << 6)) as i32
+}
+
+/// Expands a 16-bit RVC instruction into the equivalent 32-bit instruction.
+/// Reserved encodings and RV64/RV128-only ones decode to `Instruction::Ill`.
+pub fn expand_compressed(inst: u16) -> Instruction {
+    let funct3 = bits(inst, 15, 13);
+    // Full register fields
+    let rd = bits(inst, 11, 7);
+    let rs2 = bits(inst, 6, 2);
+    // Compressed register fields
+    let rd_c = creg(inst >> 2);
+    let rs1_c = creg(inst >> 7);
+
+    match (inst & 0b11, funct3) {
+        // Quadrant 0
+        (0b00, 0b000) => {
+            // nzuimm[5:4|9:6|2|3] = inst[12:5]
+            let imm = (bits(inst, 12, 11) << 4)
+                | (bits(inst, 10, 7) << 6)
+                | (bits(inst, 6, 6) << 2)
+                | (bits(inst, 5, 5) << 3);
+            match imm {
+                0 => Instruction::Ill,
+                _ => Instruction::Addi {
+                    rd: rd_c,
+                    rs1: 2,
+                    imm: imm as i32,
+                },
+            }
+        }
+        (0b00, 0b001) => Instruction::Fld {
+            rd: rd_c,
+            rs1: rs1_c,
+            imm: cl_double_imm(inst),
+        },
+        (0b00, 0b010) => Instruction::Lw {
+            rd: rd_c,
+            rs1: rs1_c,
+            imm: cl_word_imm(inst),
+        },
+        (0b00, 0b011) => Instruction::Flw {
+            rd: rd_c,
+            rs1: rs1_c,
+            imm: cl_word_imm(inst),
+        },
+        (0b00, 0b101) => Instruction::Fsd {
+            rs1: rs1_c,
+            rs2: rd_c,
+            imm: cl_double_imm(inst),
+        },
+        (0b00, 0b110) => Instruction::Sw {
+            rs1: rs1_c,
+            rs2: rd_c,
+            imm: cl_word_imm(inst),
+        },
+        (0b00, 0b111) => Instruction::Fsw {
+            rs1: rs1_c,
+            rs2: rd_c,
+            imm: cl_word_imm(inst),
+        },
+
+        // Quadrant 1
+        (0b01, 0b000) => Instruction::Addi {
+            rd,
+            rs1: rd,
+            imm: ci_imm(inst),
+        },
+        (0b01, 0b001) => Instruction::Jal {
+            rd: 1,
+            imm: cj_imm(inst),
+        },
+        (0b01, 0b010) => Instruction::Addi {
+            rd,
+            rs1: 0,
+            imm: ci_imm(inst),
+        },
+        (0b01, 0b011) if rd == 2 => {
+            // nzimm[9] = inst[12], nzimm[4|6|8:7|5] = inst[6:2]
+            let imm = (bits(inst, 12, 12) << 9)
+                | (bits(inst, 6, 6) << 4)
+                | (bits(inst, 5, 5) << 6)
+                | (bits(inst, 4, 3) << 7)
+                | (bits(inst, 2, 2) << 5);
+            match imm {
+                0 => Instruction::Ill,
+                _ => Instruction::Addi {
+                    rd: 2,
+                    rs1: 2,
+                    imm: sext(imm, 10),
+                },
+            }
+        }
+        (0b01, 0b011) => match ci_imm(inst) {
+            0 => Instruction::Ill,
+            imm => Instruction::Lui { rd, imm: imm << 12 },
+        },
+        (0b01, 0b100) => {
+            let shamt = bits(inst, 6, 2);
+            match (bits(inst, 11, 10), bits(inst, 12, 12), bits(inst, 6, 5)) {
+                // shamt[5] must be zero on RV32
+                (0b00, 0, _) => Instruction::Srli {
+                    rd: rs1_c,
+                    rs1: rs1_c,
+                    shamt,
+                },
+                (0b01, 0, _) => Instruction::Srai {
+                    rd: rs1_c,
+                    rs1: rs1_c,
+                    shamt,
+                },
+                (0b10, _, _) => Instruction::Andi {
+                    rd: rs1_c,
+                    rs1: rs1_c,
+                    imm: ci_imm(inst),
+                },
+                (0b11, 0, 0b00) => Instruction::Sub {
+                    rd: rs1_c,
+                    rs1: rs1_c,
+                    rs2: rd_c,
+                },
+                (0b11, 0, 0b01) => Instruction::Xor {
+                    rd: rs1_c,
+                    rs1: rs1_c,
+                    rs2: rd_c,
+                },
+                (0b11, 0, 0b10) => Instruction::Or {
+                    rd: rs1_c,
+                    rs1: rs1_c,
+                    rs2: rd_c,
+                },
+                (0b11, 0, 0b11) => Instruction::And {
+                    rd: rs1_c,
+                    rs1: rs1_c,
+                    rs2: rd_c,
+                },
+                _ => Instruction::Ill,
+            }
+        }
+        (0b01, 0b101) => Instruction::Jal {
+            rd: 0,
+            imm: cj_imm(inst),
+        },
+        (0b01, 0b110) => Instruction::Beq {
+            rs1: rs1_c,
+            rs2: 0,
+            imm: cb_imm(inst),
+        },
+        (0b01, 0b111) => Instruction::Bne {
+            rs1: rs1_c,
+            rs2: 0,
+            imm: cb_imm(inst),
+        },
+
+        // Quadrant 2
+        (0b10, 0b000) if bits(inst, 12, 12) == 0 => Instruction::Slli {
+            rd,
+            rs1: rd,
+            shamt: rs2,
+        },
+        (0b10, 0b001) => Instruction::Fld {
+            rd,
+            rs1: 2,
+            imm: ci_ldsp_imm(inst),
+        },
+        (0b10, 0b010) if rd != 0 => Instruction::Lw {
+            rd,
+            rs1: 2,
+            imm: ci_lwsp_imm(inst),
+        },
+        (0b10, 0b011) => Instruction::Flw {
+            rd,
+            rs1: 2,
+            imm: ci_lwsp_imm(inst),
+        },
+        (0b10, 0b100) => match (bits(inst, 12, 12), rd, rs2) {
+            (0, 0, 0) => Instruction::Ill,
+            (0, rs1, 0) => Instruction::Jalr { rd: 0, rs1, imm: 0 },
+            (0, rd, rs2) => Instruction::Add { rd, rs1: 0, rs2 },
+            (1, 0, 0) => Instruction::Ebreak,
+            (1, rs1, 0) => Instruction::Jalr { rd: 1, rs1, imm: 0 },
+            (_, rd, rs2) => Instruction::Add { rd, rs1: rd, rs2 },
+        },
+        (0b10, 0b101) => Instruction::Fsd {
+            rs1: 2,
+            rs2,
+            imm: css_sdsp_imm(inst),
+        },
+        (0b10, 0b110) => Instruction::Sw {
+            rs1: 2,
+            rs2,
+            imm: css_swsp_imm(inst),
+        },
+        (0b10, 0b111) => Instruction::Fsw {
+            rs1: 2,
+            rs2,
+            imm: css_swsp_imm(inst),
+        },
+
+        _ => Instruction::Ill,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    /// Encodings produced by `llvm-mc -triple=riscv32 -mattr=+c,+f,+d -show-encoding`
+    /// from the uncompressed instruction in the comment
+    const KNOWN_ENCODINGS: &[(u16, Instruction)] = &[
+        (0x0040, Addi { rd: 8, rs1: 2, imm: 4 }), // addi x8, x2, 4
+        (0x1ffc, Addi { rd: 15, rs1: 2, imm: 1020 }), // addi x15, x2, 1020
+        (0x2080, Fld { rd: 8, rs1: 9, imm: 0 }), // fld f8, 0(x9)
+        (0x3ffc, Fld { rd: 15, rs1: 15, imm: 248 }), // fld f15, 248(x15)
+        (0x40c0, Lw { rd: 8, rs1: 9, imm: 4 }), // lw x8, 4(x9)
+        (0x5c7c, Lw { rd: 15, rs1: 8, imm: 124 }), // lw x15, 124(x8)
+        (0x6124, Flw { rd: 9, rs1: 10, imm: 64 }), // flw f9, 64(x10)
+        (0xa588, Fsd { rs1: 11, rs2: 10, imm: 8 }), // fsd f10, 8(x11)
+        (0xc004, Sw { rs1: 8, rs2: 9, imm: 0 }), // sw x9, 0(x8)
+        (0xdff8, Sw { rs1: 15, rs2: 14, imm: 124 }), // sw x14, 124(x15)
+        (0xe2d0, Fsw { rs1: 13, rs2: 12, imm: 4 }), // fsw f12, 4(x13)
+        (0x0001, Addi { rd: 0, rs1: 0, imm: 0 }), // addi x0, x0, 0
+        (0x1281, Addi { rd: 5, rs1: 5, imm: -32 }), // addi x5, x5, -32
+        (0x0ffd, Addi { rd: 31, rs1: 31, imm: 31 }), // addi x31, x31, 31
+        (0x2009, Jal { rd: 1, imm: 2 }), // jal x1, 2
+        (0x3001, Jal { rd: 1, imm: -2048 }), // jal x1, -2048
+        (0x2ffd, Jal { rd: 1, imm: 2046 }), // jal x1, 2046
+        (0x557d, Addi { rd: 10, rs1: 0, imm: -1 }), // addi x10, x0, -1
+        (0x457d, Addi { rd: 10, rs1: 0, imm: 31 }), // addi x10, x0, 31
+        (0x0141, Addi { rd: 2, rs1: 2, imm: 16 }), // addi x2, x2, 16
+        (0x7101, Addi { rd: 2, rs1: 2, imm: -512 }), // addi x2, x2, -512
+        (0x617d, Addi { rd: 2, rs1: 2, imm: 496 }), // addi x2, x2, 496
+        (0x6505, Lui { rd: 10, imm: 4096 }), // lui x10, 1
+        (0x7501, Lui { rd: 10, imm: -131072 }), // lui x10, 0xfffe0
+        (0x61fd, Lui { rd: 3, imm: 126976 }), // lui x3, 31
+        (0x8005, Srli { rd: 8, rs1: 8, shamt: 1 }), // srli x8, x8, 1
+        (0x83fd, Srli { rd: 15, rs1: 15, shamt: 31 }), // srli x15, x15, 31
+        (0x849d, Srai { rd: 9, rs1: 9, shamt: 7 }), // srai x9, x9, 7
+        (0x997d, Andi { rd: 10, rs1: 10, imm: -1 }), // andi x10, x10, -1
+        (0x89bd, Andi { rd: 11, rs1: 11, imm: 15 }), // andi x11, x11, 15
+        (0x8c05, Sub { rd: 8, rs1: 8, rs2: 9 }), // sub x8, x8, x9
+        (0x8e35, Xor { rd: 12, rs1: 12, rs2: 13 }), // xor x12, x12, x13
+        (0x8f5d, Or { rd: 14, rs1: 14, rs2: 15 }), // or x14, x14, x15
+        (0x8c7d, And { rd: 8, rs1: 8, rs2: 15 }), // and x8, x8, x15
+        (0xbff5, Jal { rd: 0, imm: -4 }), // jal x0, -4
+        (0xa6e5, Jal { rd: 0, imm: 1000 }), // jal x0, 1000
+        (0xd001, Beq { rs1: 8, rs2: 0, imm: -256 }), // beq x8, x0, -256
+        (0xccfd, Beq { rs1: 9, rs2: 0, imm: 254 }), // beq x9, x0, 254
+        (0xe399, Bne { rs1: 15, rs2: 0, imm: 6 }), // bne x15, x0, 6
+        (0x0086, Slli { rd: 1, rs1: 1, shamt: 1 }), // slli x1, x1, 1
+        (0x0ffe, Slli { rd: 31, rs1: 31, shamt: 31 }), // slli x31, x31, 31
+        (0x30fe, Fld { rd: 1, rs1: 2, imm: 504 }), // fld f1, 504(x2)
+        (0x50fe, Lw { rd: 1, rs1: 2, imm: 252 }), // lw x1, 252(x2)
+        (0x4f82, Lw { rd: 31, rs1: 2, imm: 0 }), // lw x31, 0(x2)
+        (0x6f92, Flw { rd: 31, rs1: 2, imm: 4 }), // flw f31, 4(x2)
+        (0x8082, Jalr { rd: 0, rs1: 1, imm: 0 }), // jalr x0, 0(x1)
+        (0x852e, Add { rd: 10, rs1: 0, rs2: 11 }), // add x10, x0, x11
+        (0x9002, Ebreak), // ebreak
+        (0x9282, Jalr { rd: 1, rs1: 5, imm: 0 }), // jalr x1, 0(x5)
+        (0x952e, Add { rd: 10, rs1: 10, rs2: 11 }), // add x10, x10, x11
+        (0xbf86, Fsd { rs1: 2, rs2: 1, imm: 504 }), // fsd f1, 504(x2)
+        (0xdf86, Sw { rs1: 2, rs2: 1, imm: 252 }), // sw x1, 252(x2)
+        (0xe47e, Fsw { rs1: 2, rs2: 31, imm: 8 }), // fsw f31, 8(x2)
+    ];
+
+    #[test]
+    fn expands_known_encodings() {
+        for &(half, expected) in KNOWN_ENCODINGS {
+            assert_eq!(inst_len(half), 2);
+            assert_eq!(expand_compressed(half), expected, "expanding 0x{:04x}", half);
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_encodings() {
+        for half in [
+            0x0000, // all zeros
+            0x8000, // quadrant 0 funct3 0b100
+            0x6101, // c.addi16sp with a zero immediate
+            0x6501, // c.lui with a zero immediate
+            0x8002, // c.jr x0
+            0x4002, // c.lwsp into x0
+            0x9005, // c.srli with shamt[5] set
+            0x1082, // c.slli with shamt[5] set
+            0x9C05, // c.subw, RV64 only
+        ] {
+            assert_eq!(expand_compressed(half), Ill, "expanding 0x{:04x}", half);
+        }
+    }
+
+    #[test]
+    fn lengths_from_low_bits() {
+        assert_eq!(inst_len(0x0513), 4); // low half of addi a0, zero, 0
+        assert_eq!(inst_len(0x4505), 2); // c.li a0, 1
+        assert_eq!(inst_len(0x0001), 2); // c.nop
+    }
+}
@@ -0,0 +1,260 @@
+use crate::components::component::Component;
+use crate::components::core::{CoreConfig, TimingCore};
+use crate::components::memory::{ElfInfo, QueueMem};
+use crate::csr;
+use crate::disasm::Symbols;
+use crate::error::SimError;
+use crate::exec::Retired;
+use crate::functional::FunctionalSim;
+use crate::spike::CommitWriter;
+use std::fmt;
+
+/// First point where the timing core and the golden model disagree
+#[derive(Debug)]
+pub struct Mismatch {
+    /// Index of the record that differs, counting trapped instructions
+    pub index: u64,
+    pub cycle: u64,
+    pub expected: Retired,
+    pub actual: Retired,
+    /// Golden register file after executing `expected`
+    pub golden_regs: [u32; 32],
+    /// Timing core register file after retiring `actual`
+    pub core_regs: [u32; 32],
+    /// ELF symbols, for disassembling the two records
+    pub symbols: Symbols,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Co-simulation mismatch on instruction {} (cycle {})",
+            self.index, self.cycle
+        )?;
+        let (e, a) = (&self.expected, &self.actual);
+        if e.pc != a.pc {
+            writeln!(f, "  pc:        expected 0x{:08x}, got 0x{:08x}", e.pc, a.pc)?;
+        }
+        if e.word != a.word {
+            writeln!(f, "  inst:      expected 0x{:08x}, got 0x{:08x}", e.word, a.word)?;
+        }
+        if e.reg_write != a.reg_write {
+            writeln!(
+                f,
+                "  reg write: expected {:?}, got {:?}",
+                e.reg_write, a.reg_write
+            )?;
+        }
+        if e.freg_write != a.freg_write {
+            writeln!(
+                f,
+                "  freg write: expected {:?}, got {:?}",
+                e.freg_write, a.freg_write
+            )?;
+        }
+        if e.csr_write != a.csr_write {
+            writeln!(
+                f,
+                "  csr write: expected {:?}, got {:?}",
+                e.csr_write, a.csr_write
+            )?;
+        }
+        if e.trap != a.trap {
+            writeln!(f, "  trap:      expected {:?}, got {:?}", e.trap, a.trap)?;
+        }
+        if e.mem_read != a.mem_read {
+            writeln!(
+                f,
+                "  mem read:  expected {:?}, got {:?}",
+                e.mem_read, a.mem_read
+            )?;
+        }
+        if e.mem_write != a.mem_write {
+            writeln!(
+                f,
+                "  mem write: expected {:?}, got {:?}",
+                e.mem_write, a.mem_write
+            )?;
+        }
+        let symbols = Some(&self.symbols);
+        writeln!(f, "  golden: 0x{:08x} {}", e.pc, e.inst.disasm(e.pc, symbols))?;
+        writeln!(f, "  core:   0x{:08x} {}", a.pc, a.inst.disasm(a.pc, symbols))?;
+        for i in 0..32 {
+            let marker = if self.golden_regs[i] != self.core_regs[i] {
+                " <"
+            } else {
+                ""
+            };
+            writeln!(
+                f,
+                "  x{:<2} golden 0x{:08x} core 0x{:08x}{}",
+                i, self.golden_regs[i], self.core_regs[i], marker
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the timing core and the functional model in lockstep, checking every
+/// retired instruction of the core against the golden model.
+pub struct CoSim {
+    core: TimingCore<QueueMem>,
+    golden: FunctionalSim,
+    /// Records compared so far, retired or trapped
+    checked: u64,
+    symbols: Symbols,
+    /// Spike-format log of the core's retired instructions, for `--trace-file`
+    commit_log: Option<CommitWriter>,
+}
+
+impl CoSim {
+    /// Both models get their own copy of the same program image.
+    /// Only the timing core prints program output.
+    pub fn new(mem: QueueMem, info: &ElfInfo, config: CoreConfig) -> Self {
+        let mut golden = FunctionalSim::new(mem.clone(), info);
+        golden.syscalls().set_quiet(true);
+        Self {
+            core: TimingCore::new(mem, info, config),
+            golden,
+            checked: 0,
+            symbols: info.symbols.clone(),
+            commit_log: None,
+        }
+    }
+
+    pub fn core(&self) -> &TimingCore<QueueMem> {
+        &self.core
+    }
+
+    /// Writes every instruction the core retires to `log` from now on, including
+    /// one that diverges
+    pub fn set_commit_log(&mut self, log: CommitWriter) {
+        self.commit_log = Some(log);
+    }
+
+    /// Enables the trap model in both models, see `CsrFile::handles_traps`
+    pub fn set_traps(&mut self, enabled: bool) {
+        self.core.set_traps(enabled);
+        self.golden.set_traps(enabled);
+    }
+
+    /// Runs until the core halts or diverges from the golden model
+    pub fn run(&mut self) -> Result<(), SimError> {
+        while !self.core.halted() {
+            self.core.cycle();
+            for actual in self.core.take_retired() {
+                if let Some(log) = &mut self.commit_log {
+                    log.write(&actual).map_err(SimError::CommitLog)?;
+                }
+                let mut expected = self.golden.step()?;
+                self.adopt_counter_read(&mut expected, &actual);
+                if expected != actual {
+                    return Err(SimError::Mismatch(Box::new(Mismatch {
+                        index: self.checked,
+                        cycle: self.core.cycles(),
+                        expected,
+                        actual,
+                        golden_regs: *self.golden.regs(),
+                        core_regs: *self.core.regs(),
+                        symbols: self.symbols.clone(),
+                    })));
+                }
+                self.checked += 1;
+            }
+        }
+        if let Some(e) = self.core.take_error() {
+            return Err(e);
+        }
+        if let Some(log) = &mut self.commit_log {
+            log.flush().map_err(SimError::CommitLog)?;
+        }
+        log::info!(
+            "Co-simulation matched for {} instructions",
+            self.golden.retired()
+        );
+        Ok(())
+    }
+
+    /// Cycle and time counters differ between the models by design, so a read of
+    /// one takes the core's value in the golden model too
+    fn adopt_counter_read(&mut self, expected: &mut Retired, actual: &Retired) {
+        let Some(csr) = actual.inst.csr() else {
+            return;
+        };
+        if !csr::timing_dependent(csr) || expected.inst != actual.inst {
+            return;
+        }
+        if let (Some((rd, _)), Some((core_rd, val))) = (expected.reg_write, actual.reg_write) {
+            if rd == core_rd {
+                self.golden.override_reg(rd, val);
+                expected.reg_write = actual.reg_write;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn reports_the_first_divergence() {
+        let src = "
+                li a1, 3
+                add a0, a1, a2
+                li a7, 93
+                ecall
+        ";
+        let (mem, info) = QueueMem::with_program(0x1000, &assemble(src, 0x1000).unwrap());
+        let mut cosim = CoSim::new(mem, &info, CoreConfig::default());
+        // As if the core had lost a write to a2
+        cosim.golden.override_reg(12, 4);
+        let Err(SimError::Mismatch(mismatch)) = cosim.run() else {
+            panic!("planted divergence not reported")
+        };
+        assert_eq!((mismatch.index, mismatch.expected.pc), (1, 0x1004));
+        assert_eq!(mismatch.expected.reg_write, Some((10, 7)));
+        assert_eq!(mismatch.actual.reg_write, Some((10, 3)));
+        let report = mismatch.to_string();
+        for line in [
+            "reg write: expected Some((10, 7)), got Some((10, 3))",
+            "golden: 0x00001004 add a0, a1, a2",
+            "x10 golden 0x00000007 core 0x00000003 <",
+            "x12 golden 0x00000004 core 0x00000000 <",
+        ] {
+            assert!(report.contains(line), "{:?} missing from\n{}", line, report);
+        }
+        assert!(!report.contains("x11 golden 0x00000003 core 0x00000003 <"));
+    }
+
+    #[test]
+    fn both_models_take_the_same_traps() {
+        let src = "
+                la t0, handler
+                csrw mtvec, t0
+                li t1, 0x20000000
+                lw a0, 0(t1)            # load access fault
+                amoor.w a0, a0, (t1)    # store/AMO access fault
+                lw a0, 2(sp)            # misaligned
+                sw t1, -4(sp)
+                ecall
+                lw a0, -4(sp)
+                ret
+            handler:
+                csrr t5, mcause
+                add s1, s1, t5
+                csrr t5, mepc
+                addi t5, t5, 4
+                csrw mepc, t5
+                mret
+        ";
+        let (mem, info) = QueueMem::with_program(0x1000, &assemble(src, 0x1000).unwrap());
+        let mut cosim = CoSim::new(mem, &info, CoreConfig::default());
+        cosim.set_traps(true);
+        cosim.run().unwrap();
+        assert_eq!(cosim.core().exit_code(), 0x2000_0000);
+        assert_eq!(cosim.core().regs()[9], 5 + 7 + 4 + 11);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::fpu::FpRegFile;
+
+// Floating point
+pub const FFLAGS: u32 = 0x001;
+pub const FRM: u32 = 0x002;
+pub const FCSR: u32 = 0x003;
+
+// Unprivileged counters and their upper halves
+pub const CYCLE: u32 = 0xC00;
+pub const TIME: u32 = 0xC01;
+pub const INSTRET: u32 = 0xC02;
+pub const CYCLEH: u32 = 0xC80;
+pub const TIMEH: u32 = 0xC81;
+pub const INSTRETH: u32 = 0xC82;
+
+// Machine information
+pub const MVENDORID: u32 = 0xF11;
+pub const MARCHID: u32 = 0xF12;
+pub const MIMPID: u32 = 0xF13;
+pub const MHARTID: u32 = 0xF14;
+
+// Machine trap setup and handling
+pub const MSTATUS: u32 = 0x300;
+pub const MISA: u32 = 0x301;
+pub const MIE: u32 = 0x304;
+pub const MTVEC: u32 = 0x305;
+pub const MSCRATCH: u32 = 0x340;
+pub const MEPC: u32 = 0x341;
+pub const MCAUSE: u32 = 0x342;
+pub const MTVAL: u32 = 0x343;
+pub const MIP: u32 = 0x344;
+
+// Machine counters
+pub const MCYCLE: u32 = 0xB00;
+pub const MINSTRET: u32 = 0xB02;
+pub const MCYCLEH: u32 = 0xB80;
+pub const MINSTRETH: u32 = 0xB82;
+pub const MCOUNTINHIBIT: u32 = 0x320;
+
+/// mstatus.MIE, machine interrupt enable
+pub const MSTATUS_MIE: u32 = 1 << 3;
+/// mstatus.MPIE, interrupt enable before the last trap
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+/// mstatus.MPP, privilege before the last trap. Only M-mode exists so it is always 0b11.
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+/// mstatus.FS, floating point unit state
+pub const MSTATUS_FS: u32 = 0b11 << 13;
+
+/// RV32 with the extensions the simulator implements
+const MISA_VAL: u32 =
+    (1 << 30) | ext(b'I') | ext(b'M') | ext(b'A') | ext(b'F') | ext(b'D') | ext(b'C') | ext(b'B');
+
+const fn ext(letter: u8) -> u32 {
+    1 << (letter - b'A')
+}
+
+/// Counter values owned by the model doing the access
+#[derive(Debug, Clone, Copy)]
+pub struct Counters {
+    pub cycle: u64,
+    pub instret: u64,
+}
+
+/// Operation of a CSR instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsrOp {
+    /// `csrrs`/`csrrc` with a zero source, which do not write
+    Read,
+    Write,
+    Set,
+    Clear,
+}
+
+/// Result of a CSR instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsrAccess {
+    /// Value read, written to rd
+    pub old: u32,
+    /// Value the CSR holds afterwards, if it was written
+    pub written: Option<u32>,
+}
+
+/// Cycle and time counters depend on the timing of the model reading them
+pub fn timing_dependent(csr: u32) -> bool {
+    matches!(csr, CYCLE | TIME | CYCLEH | TIMEH | MCYCLE | MCYCLEH)
+}
+
+/// Assembler names of the CSRs the CSR file implements
+const NAMES: &[(u32, &str)] = &[
+    (FFLAGS, "fflags"),
+    (FRM, "frm"),
+    (FCSR, "fcsr"),
+    (CYCLE, "cycle"),
+    (TIME, "time"),
+    (INSTRET, "instret"),
+    (CYCLEH, "cycleh"),
+    (TIMEH, "timeh"),
+    (INSTRETH, "instreth"),
+    (MVENDORID, "mvendorid"),
+    (MARCHID, "marchid"),
+    (MIMPID, "mimpid"),
+    (MHARTID, "mhartid"),
+    (MSTATUS, "mstatus"),
+    (MISA, "misa"),
+    (MIE, "mie"),
+    (MTVEC, "mtvec"),
+    (MSCRATCH, "mscratch"),
+    (MEPC, "mepc"),
+    (MCAUSE, "mcause"),
+    (MTVAL, "mtval"),
+    (MIP, "mip"),
+    (MCYCLE, "mcycle"),
+    (MINSTRET, "minstret"),
+    (MCYCLEH, "mcycleh"),
+    (MINSTRETH, "minstreth"),
+    (MCOUNTINHIBIT, "mcountinhibit"),
+];
+
+/// Assembler name of a CSR
+pub fn name(csr: u32) -> Option<&'static str> {
+    NAMES.iter().find(|&&(num, _)| num == csr).map(|&(_, name)| name)
+}
+
+/// CSR number for an assembler name
+#[cfg(test)]
+pub fn from_name(name: &str) -> Option<u32> {
+    NAMES.iter().find(|&&(_, n)| n == name).map(|&(num, _)| num)
+}
+
+/// Machine-mode CSR file. The FP CSRs live in `FpRegFile` and the counters are
+/// derived from the counts of the model accessing them, so both are passed in.
+#[derive(Debug, Clone, Default)]
+pub struct CsrFile {
+    mstatus: u32,
+    mtvec: u32,
+    mscratch: u32,
+    mepc: u32,
+    mcause: u32,
+    mtval: u32,
+    /// Added to the model's cycle count, set when `mcycle` is written
+    cycle_offset: u64,
+    /// Added to the model's retired count, set when `minstret` is written
+    instret_offset: u64,
+    /// Trap model enabled, see `handles_traps`
+    traps: bool,
+}
+
+impl CsrFile {
+    pub fn new() -> Self {
+        Self {
+            mstatus: MSTATUS_MPP,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(test)]
+    pub fn mstatus(&self) -> u32 {
+        self.mstatus
+    }
+    pub fn set_mstatus(&mut self, val: u32) {
+        self.mstatus = (val & (MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_FS)) | MSTATUS_MPP;
+    }
+    #[cfg(test)]
+    pub fn mtvec(&self) -> u32 {
+        self.mtvec
+    }
+    #[cfg(test)]
+    pub fn mepc(&self) -> u32 {
+        self.mepc
+    }
+    pub fn set_mepc(&mut self, val: u32) {
+        self.mepc = val & !1;
+    }
+    #[cfg(test)]
+    pub fn mcause(&self) -> u32 {
+        self.mcause
+    }
+    #[cfg(test)]
+    pub fn mtval(&self) -> u32 {
+        self.mtval
+    }
+
+    /// Exceptions, `ecall` included, trap to `mtvec` instead of stopping the simulation or
+    /// being serviced by syscall emulation. Bare-metal programs with their own handlers
+    /// need this, the trap is taken even if no handler has been installed yet.
+    pub fn handles_traps(&self) -> bool {
+        self.traps
+    }
+    pub fn set_traps(&mut self, enabled: bool) {
+        self.traps = enabled;
+    }
+
+    /// Address exceptions trap to
+    pub fn trap_vector(&self) -> u32 {
+        self.mtvec & !0b11
+    }
+
+    /// Enters the trap handler for an exception raised at `pc`, returning the handler address.
+    /// Only interrupts use vectored mode, exceptions always go to the base.
+    pub fn trap(&mut self, pc: u32, cause: u32, tval: u32) -> u32 {
+        self.set_mepc(pc);
+        self.mcause = cause;
+        self.mtval = tval;
+        let mpie = if self.mstatus & MSTATUS_MIE != 0 {
+            MSTATUS_MPIE
+        } else {
+            0
+        };
+        self.set_mstatus((self.mstatus & !(MSTATUS_MIE | MSTATUS_MPIE)) | mpie);
+        self.trap_vector()
+    }
+
+    /// Returns from the trap handler, returning the address to resume at
+    pub fn mret(&mut self) -> u32 {
+        let mie = if self.mstatus & MSTATUS_MPIE != 0 {
+            MSTATUS_MIE
+        } else {
+            0
+        };
+        self.set_mstatus((self.mstatus & !MSTATUS_MIE) | mie | MSTATUS_MPIE);
+        self.mepc
+    }
+
+    /// Performs a CSR instruction. Returns `None` if the CSR does not exist or a
+    /// read-only CSR would be written, which is an illegal instruction.
+    pub fn access(
+        &mut self,
+        csr: u32,
+        op: CsrOp,
+        val: u32,
+        fregs: &mut FpRegFile,
+        counters: Counters,
+    ) -> Option<CsrAccess> {
+        let old = self.read(csr, fregs, counters)?;
+        let new = match op {
+            CsrOp::Read => return Some(CsrAccess { old, written: None }),
+            CsrOp::Write => val,
+            CsrOp::Set => old | val,
+            CsrOp::Clear => old & !val,
+        };
+        // csr[11:10] == 0b11 marks the read-only CSRs
+        if csr >> 10 == 0b11 {
+            return None;
+        }
+        self.write(csr, new, fregs, counters);
+        let written = self.read(csr, fregs, counters);
+        Some(CsrAccess { old, written })
+    }
+
+    fn read(&self, csr: u32, fregs: &FpRegFile, counters: Counters) -> Option<u32> {
+        let cycle = counters.cycle.wrapping_add(self.cycle_offset);
+        let instret = counters.instret.wrapping_add(self.instret_offset);
+        let val = match csr {
+            FFLAGS => fregs.fflags() as u32,
+            FRM => fregs.frm(),
+            FCSR => fregs.fcsr(),
+            // mtime ticks once per core cycle
+            CYCLE | TIME | MCYCLE => cycle as u32,
+            CYCLEH | TIMEH | MCYCLEH => (cycle >> 32) as u32,
+            INSTRET | MINSTRET => instret as u32,
+            INSTRETH | MINSTRETH => (instret >> 32) as u32,
+            MVENDORID | MARCHID | MIMPID | MHARTID => 0,
+            MSTATUS => self.mstatus,
+            MISA => MISA_VAL,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            // No interrupts and no programmable event counters, so these read as zero
+            MIE | MIP | MCOUNTINHIBIT => 0,
+            0xC03..=0xC1F | 0xC83..=0xC9F | 0xB03..=0xB1F | 0xB83..=0xB9F | 0x323..=0x33F => 0,
+            _ => return None,
+        };
+        Some(val)
+    }
+
+    fn write(&mut self, csr: u32, val: u32, fregs: &mut FpRegFile, counters: Counters) {
+        let cycle = counters.cycle.wrapping_add(self.cycle_offset);
+        let instret = counters.instret.wrapping_add(self.instret_offset);
+        let low = |old: u64| (old & !0xFFFF_FFFF) | val as u64;
+        let high = |old: u64| (old & 0xFFFF_FFFF) | (val as u64) << 32;
+        match csr {
+            FFLAGS => fregs.set_fflags(val as u8),
+            FRM => fregs.set_frm(val),
+            FCSR => fregs.set_fcsr(val),
+            MCYCLE => self.cycle_offset = low(cycle).wrapping_sub(counters.cycle),
+            MCYCLEH => self.cycle_offset = high(cycle).wrapping_sub(counters.cycle),
+            MINSTRET => self.instret_offset = low(instret).wrapping_sub(counters.instret),
+            MINSTRETH => self.instret_offset = high(instret).wrapping_sub(counters.instret),
+            MSTATUS => self.set_mstatus(val),
+            // Only direct and vectored modes exist
+            MTVEC => self.mtvec = val & !0b10,
+            MSCRATCH => self.mscratch = val,
+            MEPC => self.set_mepc(val),
+            MCAUSE => self.mcause = val,
+            MTVAL => self.mtval = val,
+            // WARL fields with nothing implemented behind them
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(cycle: u64, instret: u64) -> Counters {
+        Counters { cycle, instret }
+    }
+
+    #[test]
+    fn read_only_csrs_reject_writes_but_not_reads() {
+        let mut csrs = CsrFile::new();
+        let mut fregs = FpRegFile::new();
+        let now = counters(100, 40);
+        for csr in [CYCLE, INSTRETH, MHARTID, MVENDORID] {
+            assert!(csrs.access(csr, CsrOp::Read, 0, &mut fregs, now).is_some());
+            assert_eq!(csrs.access(csr, CsrOp::Write, 0, &mut fregs, now), None);
+            assert_eq!(csrs.access(csr, CsrOp::Set, 1, &mut fregs, now), None);
+        }
+        assert_eq!(csrs.access(0x7C0, CsrOp::Read, 0, &mut fregs, now), None);
+        let misa = csrs.access(MISA, CsrOp::Write, 0, &mut fregs, now).unwrap();
+        assert_eq!(misa.old, MISA_VAL);
+        assert_eq!(misa.written, Some(MISA_VAL));
+    }
+
+    #[test]
+    fn counters_follow_the_model_and_keep_writes() {
+        let mut csrs = CsrFile::new();
+        let mut fregs = FpRegFile::new();
+        let read = |csrs: &mut CsrFile, fregs: &mut FpRegFile, csr, now| {
+            csrs.access(csr, CsrOp::Read, 0, fregs, now).unwrap().old
+        };
+        let now = counters(0x1_0000_0005, 3);
+        assert_eq!(read(&mut csrs, &mut fregs, CYCLE, now), 5);
+        assert_eq!(read(&mut csrs, &mut fregs, CYCLEH, now), 1);
+        assert_eq!(read(&mut csrs, &mut fregs, TIME, now), 5);
+        assert_eq!(read(&mut csrs, &mut fregs, INSTRET, now), 3);
+        csrs.access(MINSTRET, CsrOp::Write, 100, &mut fregs, now);
+        csrs.access(MCYCLEH, CsrOp::Write, 0, &mut fregs, now);
+        let later = counters(0x1_0000_0015, 13);
+        assert_eq!(read(&mut csrs, &mut fregs, INSTRET, later), 110);
+        assert_eq!(read(&mut csrs, &mut fregs, MCYCLE, later), 0x15);
+        assert_eq!(read(&mut csrs, &mut fregs, CYCLEH, later), 0);
+    }
+
+    #[test]
+    fn warl_fields_and_fp_csrs() {
+        let mut csrs = CsrFile::new();
+        let mut fregs = FpRegFile::new();
+        let now = counters(0, 0);
+        let access = csrs.access(MSTATUS, CsrOp::Write, 0, &mut fregs, now).unwrap();
+        assert_eq!(access.written, Some(MSTATUS_MPP));
+        csrs.access(MSTATUS, CsrOp::Set, u32::MAX, &mut fregs, now);
+        assert_eq!(csrs.mstatus(), MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP | MSTATUS_FS);
+        csrs.access(MEPC, CsrOp::Write, 0x1003, &mut fregs, now);
+        assert_eq!(csrs.mepc(), 0x1002);
+        csrs.access(MTVEC, CsrOp::Write, 0x2003, &mut fregs, now);
+        assert_eq!(csrs.mtvec(), 0x2001);
+
+        csrs.access(FCSR, CsrOp::Write, 0xFF, &mut fregs, now);
+        assert_eq!(fregs.frm(), 0b111);
+        assert_eq!(fregs.fflags(), 0x1F);
+        let access = csrs.access(FFLAGS, CsrOp::Clear, 0x01, &mut fregs, now).unwrap();
+        assert_eq!(access.old, 0x1F);
+        assert_eq!(access.written, Some(0x1E));
+        let access = csrs.access(FRM, CsrOp::Write, 1, &mut fregs, now).unwrap();
+        assert_eq!(access.old, 0b111);
+        assert_eq!(fregs.fcsr(), 0x3E);
+    }
+
+    #[test]
+    fn trap_entry_and_mret() {
+        let mut csrs = CsrFile::new();
+        let mut fregs = FpRegFile::new();
+        let now = counters(0, 0);
+        assert!(!csrs.handles_traps());
+        csrs.set_traps(true);
+        assert!(csrs.handles_traps());
+        // Before a handler is installed traps go to address 0
+        assert_eq!(csrs.trap(0x1230, 11, 0), 0);
+        assert_eq!(csrs.mret(), 0x1230);
+
+        csrs.access(MTVEC, CsrOp::Write, 0x8001, &mut fregs, now);
+        csrs.access(MSTATUS, CsrOp::Set, MSTATUS_MIE, &mut fregs, now);
+
+        assert_eq!(csrs.trap(0x1234, 2, 0xFFFF_FFFF), 0x8000);
+        assert_eq!((csrs.mepc(), csrs.mcause(), csrs.mtval()), (0x1234, 2, 0xFFFF_FFFF));
+        assert_eq!(csrs.mstatus(), MSTATUS_MPIE | MSTATUS_MPP);
+
+        assert_eq!(csrs.mret(), 0x1234);
+        assert_eq!(csrs.mstatus(), MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+    }
+}
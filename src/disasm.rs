@@ -0,0 +1,635 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::csr;
+use crate::instructions::Instruction::{self, *};
+use crate::instructions::{FREG_NAMES, REG_NAMES};
+
+/// Code and data symbols of the loaded program, sorted by address
+#[derive(Debug, Clone, Default)]
+pub struct Symbols(Rc<Vec<(u32, String)>>);
+
+impl Symbols {
+    /// Builds the table from `(address, name)` pairs in any order. Where several symbols share
+    /// an address the first one given is kept.
+    pub fn new(mut symbols: Vec<(u32, String)>) -> Self {
+        symbols.sort_by_key(|&(addr, _)| addr);
+        symbols.dedup_by_key(|&mut (addr, _)| addr);
+        Self(Rc::new(symbols))
+    }
+
+    /// Nearest symbol at or below `addr` and the offset of `addr` from it
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        let idx = self.0.partition_point(|&(sym, _)| sym <= addr).checked_sub(1)?;
+        let (sym, ref name) = self.0[idx];
+        Some((name, addr - sym))
+    }
+}
+
+/// Instruction printed at a known address, so jump and branch targets are shown as
+/// `symbol+0x24`, or as absolute addresses where no symbol covers them
+pub struct Disasm<'a> {
+    inst: &'a Instruction,
+    pc: u32,
+    symbols: Option<&'a Symbols>,
+}
+
+impl Instruction {
+    pub fn disasm<'a>(&'a self, pc: u32, symbols: Option<&'a Symbols>) -> Disasm<'a> {
+        Disasm {
+            inst: self,
+            pc,
+            symbols,
+        }
+    }
+
+    /// Assembler mnemonic, without any atomic ordering suffix
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Lb { .. } => "lb",
+            Lh { .. } => "lh",
+            Lw { .. } => "lw",
+            Lbu { .. } => "lbu",
+            Lhu { .. } => "lhu",
+            Sb { .. } => "sb",
+            Sh { .. } => "sh",
+            Sw { .. } => "sw",
+            Sll { .. } => "sll",
+            Slli { .. } => "slli",
+            Srl { .. } => "srl",
+            Srli { .. } => "srli",
+            Sra { .. } => "sra",
+            Srai { .. } => "srai",
+            Add { .. } => "add",
+            Addi { .. } => "addi",
+            Sub { .. } => "sub",
+            Lui { .. } => "lui",
+            Auipc { .. } => "auipc",
+            Xor { .. } => "xor",
+            Xori { .. } => "xori",
+            Or { .. } => "or",
+            Ori { .. } => "ori",
+            And { .. } => "and",
+            Andi { .. } => "andi",
+            Slt { .. } => "slt",
+            Slti { .. } => "slti",
+            Sltu { .. } => "sltu",
+            Sltiu { .. } => "sltiu",
+            Beq { .. } => "beq",
+            Bne { .. } => "bne",
+            Blt { .. } => "blt",
+            Bge { .. } => "bge",
+            Bltu { .. } => "bltu",
+            Bgeu { .. } => "bgeu",
+            Jal { .. } => "jal",
+            Jalr { .. } => "jalr",
+            Mul { .. } => "mul",
+            Mulh { .. } => "mulh",
+            Mulhsu { .. } => "mulhsu",
+            Mulhu { .. } => "mulhu",
+            Div { .. } => "div",
+            Divu { .. } => "divu",
+            Rem { .. } => "rem",
+            Remu { .. } => "remu",
+            Sh1add { .. } => "sh1add",
+            Sh2add { .. } => "sh2add",
+            Sh3add { .. } => "sh3add",
+            Andn { .. } => "andn",
+            Orn { .. } => "orn",
+            Xnor { .. } => "xnor",
+            Clz { .. } => "clz",
+            Ctz { .. } => "ctz",
+            Cpop { .. } => "cpop",
+            Max { .. } => "max",
+            Maxu { .. } => "maxu",
+            Min { .. } => "min",
+            Minu { .. } => "minu",
+            SextB { .. } => "sext.b",
+            SextH { .. } => "sext.h",
+            ZextH { .. } => "zext.h",
+            Rol { .. } => "rol",
+            Ror { .. } => "ror",
+            Rori { .. } => "rori",
+            OrcB { .. } => "orc.b",
+            Rev8 { .. } => "rev8",
+            Bclr { .. } => "bclr",
+            Bclri { .. } => "bclri",
+            Bext { .. } => "bext",
+            Bexti { .. } => "bexti",
+            Binv { .. } => "binv",
+            Binvi { .. } => "binvi",
+            Bset { .. } => "bset",
+            Bseti { .. } => "bseti",
+            Clmul { .. } => "clmul",
+            Clmulh { .. } => "clmulh",
+            Clmulr { .. } => "clmulr",
+            LrW { .. } => "lr.w",
+            ScW { .. } => "sc.w",
+            AmoswapW { .. } => "amoswap.w",
+            AmoaddW { .. } => "amoadd.w",
+            AmoxorW { .. } => "amoxor.w",
+            AmoandW { .. } => "amoand.w",
+            AmoorW { .. } => "amoor.w",
+            AmominW { .. } => "amomin.w",
+            AmomaxW { .. } => "amomax.w",
+            AmominuW { .. } => "amominu.w",
+            AmomaxuW { .. } => "amomaxu.w",
+            Flw { .. } => "flw",
+            Fld { .. } => "fld",
+            Fsw { .. } => "fsw",
+            Fsd { .. } => "fsd",
+            FmaddS { .. } => "fmadd.s",
+            FmsubS { .. } => "fmsub.s",
+            FnmsubS { .. } => "fnmsub.s",
+            FnmaddS { .. } => "fnmadd.s",
+            FmaddD { .. } => "fmadd.d",
+            FmsubD { .. } => "fmsub.d",
+            FnmsubD { .. } => "fnmsub.d",
+            FnmaddD { .. } => "fnmadd.d",
+            FaddS { .. } => "fadd.s",
+            FsubS { .. } => "fsub.s",
+            FmulS { .. } => "fmul.s",
+            FdivS { .. } => "fdiv.s",
+            FsqrtS { .. } => "fsqrt.s",
+            FaddD { .. } => "fadd.d",
+            FsubD { .. } => "fsub.d",
+            FmulD { .. } => "fmul.d",
+            FdivD { .. } => "fdiv.d",
+            FsqrtD { .. } => "fsqrt.d",
+            FsgnjS { .. } => "fsgnj.s",
+            FsgnjnS { .. } => "fsgnjn.s",
+            FsgnjxS { .. } => "fsgnjx.s",
+            FminS { .. } => "fmin.s",
+            FmaxS { .. } => "fmax.s",
+            FsgnjD { .. } => "fsgnj.d",
+            FsgnjnD { .. } => "fsgnjn.d",
+            FsgnjxD { .. } => "fsgnjx.d",
+            FminD { .. } => "fmin.d",
+            FmaxD { .. } => "fmax.d",
+            FeqS { .. } => "feq.s",
+            FltS { .. } => "flt.s",
+            FleS { .. } => "fle.s",
+            FclassS { .. } => "fclass.s",
+            FeqD { .. } => "feq.d",
+            FltD { .. } => "flt.d",
+            FleD { .. } => "fle.d",
+            FclassD { .. } => "fclass.d",
+            FcvtWS { .. } => "fcvt.w.s",
+            FcvtWuS { .. } => "fcvt.wu.s",
+            FcvtSW { .. } => "fcvt.s.w",
+            FcvtSWu { .. } => "fcvt.s.wu",
+            FcvtWD { .. } => "fcvt.w.d",
+            FcvtWuD { .. } => "fcvt.wu.d",
+            FcvtDW { .. } => "fcvt.d.w",
+            FcvtDWu { .. } => "fcvt.d.wu",
+            FcvtSD { .. } => "fcvt.s.d",
+            FcvtDS { .. } => "fcvt.d.s",
+            FmvXW { .. } => "fmv.x.w",
+            FmvWX { .. } => "fmv.w.x",
+            Fence { .. } => "fence",
+            FenceI => "fence.i",
+            Ecall => "ecall",
+            Ebreak => "ebreak",
+            Mret => "mret",
+            Csrrw { .. } => "csrrw",
+            Csrrs { .. } => "csrrs",
+            Csrrc { .. } => "csrrc",
+            Csrrwi { .. } => "csrrwi",
+            Csrrsi { .. } => "csrrsi",
+            Csrrci { .. } => "csrrci",
+            Ill => "unimp",
+        }
+    }
+
+    /// Writes the instruction with `target` formatting jump and branch offsets
+    fn write(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        target: &dyn Fn(&mut fmt::Formatter<'_>, i32) -> fmt::Result,
+    ) -> fmt::Result {
+        let x = |reg: u32| REG_NAMES[reg as usize];
+        let fr = |reg: u32| FREG_NAMES[reg as usize];
+        let name = self.mnemonic();
+        match *self {
+            // Pseudo-instructions, as objdump shows them
+            Addi { rd: 0, rs1: 0, imm: 0 } => write!(f, "nop"),
+            Addi { rd, rs1: 0, imm } => write!(f, "li {}, {}", x(rd), imm),
+            Addi { rd, rs1, imm: 0 } => write!(f, "mv {}, {}", x(rd), x(rs1)),
+            Xori { rd, rs1, imm: -1 } => write!(f, "not {}, {}", x(rd), x(rs1)),
+            Sub { rd, rs1: 0, rs2 } => write!(f, "neg {}, {}", x(rd), x(rs2)),
+            Sltiu { rd, rs1, imm: 1 } => write!(f, "seqz {}, {}", x(rd), x(rs1)),
+            Sltu { rd, rs1: 0, rs2 } => write!(f, "snez {}, {}", x(rd), x(rs2)),
+            Slt { rd, rs1, rs2: 0 } => write!(f, "sltz {}, {}", x(rd), x(rs1)),
+            Slt { rd, rs1: 0, rs2 } => write!(f, "sgtz {}, {}", x(rd), x(rs2)),
+            Beq { rs1, rs2: 0, imm } => pseudo_branch(f, "beqz", x(rs1), imm, target),
+            Bne { rs1, rs2: 0, imm } => pseudo_branch(f, "bnez", x(rs1), imm, target),
+            Bge { rs1: 0, rs2, imm } => pseudo_branch(f, "blez", x(rs2), imm, target),
+            Bge { rs1, rs2: 0, imm } => pseudo_branch(f, "bgez", x(rs1), imm, target),
+            Blt { rs1, rs2: 0, imm } => pseudo_branch(f, "bltz", x(rs1), imm, target),
+            Blt { rs1: 0, rs2, imm } => pseudo_branch(f, "bgtz", x(rs2), imm, target),
+            Jal { rd: 0, imm } => {
+                write!(f, "j ")?;
+                target(f, imm)
+            }
+            Jal { rd: 1, imm } => {
+                write!(f, "jal ")?;
+                target(f, imm)
+            }
+            Jalr { rd: 0, rs1: 1, imm: 0 } => write!(f, "ret"),
+            Jalr { rd: 0, rs1, imm: 0 } => write!(f, "jr {}", x(rs1)),
+            Jalr { rd: 1, rs1, imm: 0 } => write!(f, "jalr {}", x(rs1)),
+            Csrrs { rd, rs1: 0, csr: csr::CYCLE } => write!(f, "rdcycle {}", x(rd)),
+            Csrrs { rd, rs1: 0, csr: csr::TIME } => write!(f, "rdtime {}", x(rd)),
+            Csrrs { rd, rs1: 0, csr: csr::INSTRET } => write!(f, "rdinstret {}", x(rd)),
+            Csrrs { rd, rs1: 0, csr } => write!(f, "csrr {}, {}", x(rd), CsrName(csr)),
+            Csrrw { rd: 0, rs1, csr } => write!(f, "csrw {}, {}", CsrName(csr), x(rs1)),
+            Csrrs { rd: 0, rs1, csr } => write!(f, "csrs {}, {}", CsrName(csr), x(rs1)),
+            Csrrc { rd: 0, rs1, csr } => write!(f, "csrc {}, {}", CsrName(csr), x(rs1)),
+            Csrrwi { rd: 0, uimm, csr } => write!(f, "csrwi {}, {}", CsrName(csr), uimm),
+            Csrrsi { rd: 0, uimm, csr } => write!(f, "csrsi {}, {}", CsrName(csr), uimm),
+            Csrrci { rd: 0, uimm, csr } => write!(f, "csrci {}, {}", CsrName(csr), uimm),
+            FsgnjS { rd, rs1, rs2 } if rs1 == rs2 => write!(f, "fmv.s {}, {}", fr(rd), fr(rs1)),
+            FsgnjnS { rd, rs1, rs2 } if rs1 == rs2 => write!(f, "fneg.s {}, {}", fr(rd), fr(rs1)),
+            FsgnjxS { rd, rs1, rs2 } if rs1 == rs2 => write!(f, "fabs.s {}, {}", fr(rd), fr(rs1)),
+            FsgnjD { rd, rs1, rs2 } if rs1 == rs2 => write!(f, "fmv.d {}, {}", fr(rd), fr(rs1)),
+            FsgnjnD { rd, rs1, rs2 } if rs1 == rs2 => write!(f, "fneg.d {}, {}", fr(rd), fr(rs1)),
+            FsgnjxD { rd, rs1, rs2 } if rs1 == rs2 => write!(f, "fabs.d {}, {}", fr(rd), fr(rs1)),
+            Fence { pred: 0xF, succ: 0xF, fm: 0 } => write!(f, "fence"),
+            Fence { pred: 0x3, succ: 0x3, fm: 0x8 } => write!(f, "fence.tso"),
+            Fence { pred: 0x1, succ: 0x0, fm: 0 } => write!(f, "pause"),
+
+            // Integer register operands
+            Lb { rd, rs1, imm }
+            | Lh { rd, rs1, imm }
+            | Lw { rd, rs1, imm }
+            | Lbu { rd, rs1, imm }
+            | Lhu { rd, rs1, imm }
+            | Jalr { rd, rs1, imm } => write!(f, "{} {}, {}({})", name, x(rd), imm, x(rs1)),
+            Sb { rs1, rs2, imm } | Sh { rs1, rs2, imm } | Sw { rs1, rs2, imm } => {
+                write!(f, "{} {}, {}({})", name, x(rs2), imm, x(rs1))
+            }
+            Addi { rd, rs1, imm }
+            | Xori { rd, rs1, imm }
+            | Ori { rd, rs1, imm }
+            | Andi { rd, rs1, imm }
+            | Slti { rd, rs1, imm }
+            | Sltiu { rd, rs1, imm } => write!(f, "{} {}, {}, {}", name, x(rd), x(rs1), imm),
+            Slli { rd, rs1, shamt }
+            | Srli { rd, rs1, shamt }
+            | Srai { rd, rs1, shamt }
+            | Rori { rd, rs1, shamt }
+            | Bclri { rd, rs1, shamt }
+            | Bexti { rd, rs1, shamt }
+            | Binvi { rd, rs1, shamt }
+            | Bseti { rd, rs1, shamt } => write!(f, "{} {}, {}, {}", name, x(rd), x(rs1), shamt),
+            Lui { rd, imm } | Auipc { rd, imm } => {
+                write!(f, "{} {}, 0x{:x}", name, x(rd), imm as u32 >> 12)
+            }
+            Beq { rs1, rs2, imm }
+            | Bne { rs1, rs2, imm }
+            | Blt { rs1, rs2, imm }
+            | Bge { rs1, rs2, imm }
+            | Bltu { rs1, rs2, imm }
+            | Bgeu { rs1, rs2, imm } => {
+                write!(f, "{} {}, {}, ", name, x(rs1), x(rs2))?;
+                target(f, imm)
+            }
+            Jal { rd, imm } => {
+                write!(f, "{} {}, ", name, x(rd))?;
+                target(f, imm)
+            }
+            Clz { rd, rs1 }
+            | Ctz { rd, rs1 }
+            | Cpop { rd, rs1 }
+            | SextB { rd, rs1 }
+            | SextH { rd, rs1 }
+            | ZextH { rd, rs1 }
+            | OrcB { rd, rs1 }
+            | Rev8 { rd, rs1 } => write!(f, "{} {}, {}", name, x(rd), x(rs1)),
+            Sll { rd, rs1, rs2 }
+            | Srl { rd, rs1, rs2 }
+            | Sra { rd, rs1, rs2 }
+            | Add { rd, rs1, rs2 }
+            | Sub { rd, rs1, rs2 }
+            | Xor { rd, rs1, rs2 }
+            | Or { rd, rs1, rs2 }
+            | And { rd, rs1, rs2 }
+            | Slt { rd, rs1, rs2 }
+            | Sltu { rd, rs1, rs2 }
+            | Mul { rd, rs1, rs2 }
+            | Mulh { rd, rs1, rs2 }
+            | Mulhsu { rd, rs1, rs2 }
+            | Mulhu { rd, rs1, rs2 }
+            | Div { rd, rs1, rs2 }
+            | Divu { rd, rs1, rs2 }
+            | Rem { rd, rs1, rs2 }
+            | Remu { rd, rs1, rs2 }
+            | Sh1add { rd, rs1, rs2 }
+            | Sh2add { rd, rs1, rs2 }
+            | Sh3add { rd, rs1, rs2 }
+            | Andn { rd, rs1, rs2 }
+            | Orn { rd, rs1, rs2 }
+            | Xnor { rd, rs1, rs2 }
+            | Max { rd, rs1, rs2 }
+            | Maxu { rd, rs1, rs2 }
+            | Min { rd, rs1, rs2 }
+            | Minu { rd, rs1, rs2 }
+            | Rol { rd, rs1, rs2 }
+            | Ror { rd, rs1, rs2 }
+            | Bclr { rd, rs1, rs2 }
+            | Bext { rd, rs1, rs2 }
+            | Binv { rd, rs1, rs2 }
+            | Bset { rd, rs1, rs2 }
+            | Clmul { rd, rs1, rs2 }
+            | Clmulh { rd, rs1, rs2 }
+            | Clmulr { rd, rs1, rs2 } => write!(f, "{} {}, {}, {}", name, x(rd), x(rs1), x(rs2)),
+
+            // Atomics
+            LrW { rd, rs1, aq, rl } => {
+                write!(f, "{}{} {}, ({})", name, Ordering(aq, rl), x(rd), x(rs1))
+            }
+            ScW { rd, rs1, rs2, aq, rl }
+            | AmoswapW { rd, rs1, rs2, aq, rl }
+            | AmoaddW { rd, rs1, rs2, aq, rl }
+            | AmoxorW { rd, rs1, rs2, aq, rl }
+            | AmoandW { rd, rs1, rs2, aq, rl }
+            | AmoorW { rd, rs1, rs2, aq, rl }
+            | AmominW { rd, rs1, rs2, aq, rl }
+            | AmomaxW { rd, rs1, rs2, aq, rl }
+            | AmominuW { rd, rs1, rs2, aq, rl }
+            | AmomaxuW { rd, rs1, rs2, aq, rl } => write!(
+                f,
+                "{}{} {}, {}, ({})",
+                name,
+                Ordering(aq, rl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+
+            // FP register operands, the rounding mode is left out when it is the default
+            Flw { rd, rs1, imm } | Fld { rd, rs1, imm } => {
+                write!(f, "{} {}, {}({})", name, fr(rd), imm, x(rs1))
+            }
+            Fsw { rs1, rs2, imm } | Fsd { rs1, rs2, imm } => {
+                write!(f, "{} {}, {}({})", name, fr(rs2), imm, x(rs1))
+            }
+            FmaddS { rd, rs1, rs2, rs3, rm }
+            | FmsubS { rd, rs1, rs2, rs3, rm }
+            | FnmsubS { rd, rs1, rs2, rs3, rm }
+            | FnmaddS { rd, rs1, rs2, rs3, rm }
+            | FmaddD { rd, rs1, rs2, rs3, rm }
+            | FmsubD { rd, rs1, rs2, rs3, rm }
+            | FnmsubD { rd, rs1, rs2, rs3, rm }
+            | FnmaddD { rd, rs1, rs2, rs3, rm } => write!(
+                f,
+                "{} {}, {}, {}, {}{}",
+                name,
+                fr(rd),
+                fr(rs1),
+                fr(rs2),
+                fr(rs3),
+                RoundingMode(rm, DYN)
+            ),
+            FaddS { rd, rs1, rs2, rm }
+            | FsubS { rd, rs1, rs2, rm }
+            | FmulS { rd, rs1, rs2, rm }
+            | FdivS { rd, rs1, rs2, rm }
+            | FaddD { rd, rs1, rs2, rm }
+            | FsubD { rd, rs1, rs2, rm }
+            | FmulD { rd, rs1, rs2, rm }
+            | FdivD { rd, rs1, rs2, rm } => write!(
+                f,
+                "{} {}, {}, {}{}",
+                name,
+                fr(rd),
+                fr(rs1),
+                fr(rs2),
+                RoundingMode(rm, DYN)
+            ),
+            FsqrtS { rd, rs1, rm } | FsqrtD { rd, rs1, rm } | FcvtSD { rd, rs1, rm } => {
+                write!(f, "{} {}, {}{}", name, fr(rd), fr(rs1), RoundingMode(rm, DYN))
+            }
+            FcvtDS { rd, rs1, rm } => {
+                write!(f, "{} {}, {}{}", name, fr(rd), fr(rs1), RoundingMode(rm, RNE))
+            }
+            FsgnjS { rd, rs1, rs2 }
+            | FsgnjnS { rd, rs1, rs2 }
+            | FsgnjxS { rd, rs1, rs2 }
+            | FminS { rd, rs1, rs2 }
+            | FmaxS { rd, rs1, rs2 }
+            | FsgnjD { rd, rs1, rs2 }
+            | FsgnjnD { rd, rs1, rs2 }
+            | FsgnjxD { rd, rs1, rs2 }
+            | FminD { rd, rs1, rs2 }
+            | FmaxD { rd, rs1, rs2 } => {
+                write!(f, "{} {}, {}, {}", name, fr(rd), fr(rs1), fr(rs2))
+            }
+            FeqS { rd, rs1, rs2 }
+            | FltS { rd, rs1, rs2 }
+            | FleS { rd, rs1, rs2 }
+            | FeqD { rd, rs1, rs2 }
+            | FltD { rd, rs1, rs2 }
+            | FleD { rd, rs1, rs2 } => write!(f, "{} {}, {}, {}", name, x(rd), fr(rs1), fr(rs2)),
+            FclassS { rd, rs1 } | FclassD { rd, rs1 } | FmvXW { rd, rs1 } => {
+                write!(f, "{} {}, {}", name, x(rd), fr(rs1))
+            }
+            FmvWX { rd, rs1 } => write!(f, "{} {}, {}", name, fr(rd), x(rs1)),
+            FcvtWS { rd, rs1, rm }
+            | FcvtWuS { rd, rs1, rm }
+            | FcvtWD { rd, rs1, rm }
+            | FcvtWuD { rd, rs1, rm } => {
+                write!(f, "{} {}, {}{}", name, x(rd), fr(rs1), RoundingMode(rm, DYN))
+            }
+            FcvtSW { rd, rs1, rm } | FcvtSWu { rd, rs1, rm } => {
+                write!(f, "{} {}, {}{}", name, fr(rd), x(rs1), RoundingMode(rm, DYN))
+            }
+            FcvtDW { rd, rs1, rm } | FcvtDWu { rd, rs1, rm } => {
+                write!(f, "{} {}, {}{}", name, fr(rd), x(rs1), RoundingMode(rm, RNE))
+            }
+
+            // System
+            Fence { pred, succ, .. } => {
+                write!(f, "{} {}, {}", name, FenceSet(pred), FenceSet(succ))
+            }
+            Csrrw { rd, rs1, csr } | Csrrs { rd, rs1, csr } | Csrrc { rd, rs1, csr } => {
+                write!(f, "{} {}, {}, {}", name, x(rd), CsrName(csr), x(rs1))
+            }
+            Csrrwi { rd, uimm, csr } | Csrrsi { rd, uimm, csr } | Csrrci { rd, uimm, csr } => {
+                write!(f, "{} {}, {}, {}", name, x(rd), CsrName(csr), uimm)
+            }
+            FenceI | Ecall | Ebreak | Mret | Ill => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Rounding modes `asm` fills in when the operand is left out
+const RNE: u32 = 0;
+const DYN: u32 = 7;
+
+/// `, rm` operand, empty when `rm` is the instruction's default
+struct RoundingMode(u32, u32);
+
+impl fmt::Display for RoundingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let RoundingMode(rm, default) = *self;
+        match rm {
+            _ if rm == default => Ok(()),
+            0 => write!(f, ", rne"),
+            1 => write!(f, ", rtz"),
+            2 => write!(f, ", rdn"),
+            3 => write!(f, ", rup"),
+            4 => write!(f, ", rmm"),
+            7 => write!(f, ", dyn"),
+            // Reserved encodings
+            _ => write!(f, ", {}", rm),
+        }
+    }
+}
+
+/// `.aq`/`.rl`/`.aqrl` suffix of an atomic
+struct Ordering(bool, bool);
+
+impl fmt::Display for Ordering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0, self.1) {
+            (false, false) => Ok(()),
+            (true, false) => write!(f, ".aq"),
+            (false, true) => write!(f, ".rl"),
+            (true, true) => write!(f, ".aqrl"),
+        }
+    }
+}
+
+/// IORW set of a fence, `0` when empty
+struct FenceSet(u32);
+
+impl fmt::Display for FenceSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "0");
+        }
+        for (bit, c) in [(8, 'i'), (4, 'o'), (2, 'r'), (1, 'w')] {
+            if self.0 & bit != 0 {
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// CSR by name, or by number if it is not one the CSR file implements
+struct CsrName(u32);
+
+impl fmt::Display for CsrName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match csr::name(self.0) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "0x{:x}", self.0),
+        }
+    }
+}
+
+fn pseudo_branch(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    reg: &str,
+    imm: i32,
+    target: &dyn Fn(&mut fmt::Formatter<'_>, i32) -> fmt::Result,
+) -> fmt::Result {
+    write!(f, "{} {}, ", name, reg)?;
+    target(f, imm)
+}
+
+/// Assembly with jump and branch targets as offsets from the instruction, which
+/// `asm::assemble` reads back to the same instruction
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &|f, offset| write!(f, "{}", offset))
+    }
+}
+
+impl fmt::Display for Disasm<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inst.write(f, &|f, offset| {
+            let addr = self.pc.wrapping_add(offset as u32);
+            match self.symbols.and_then(|symbols| symbols.lookup(addr)) {
+                Some((name, 0)) => write!(f, "{}", name),
+                Some((name, offset)) => write!(f, "{}+0x{:x}", name, offset),
+                None => write!(f, "0x{:x}", addr),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::encode::encode;
+    use crate::instructions::decode_inst;
+    use proptest::prelude::*;
+
+    fn text(word: u32) -> String {
+        decode_inst(word).to_string()
+    }
+
+    #[test]
+    fn objdump_style() {
+        assert_eq!(text(0x00a5_85b3), "add a1, a1, a0");
+        assert_eq!(text(0x0081_2283), "lw t0, 8(sp)");
+        assert_eq!(text(0xfe54_2e23), "sw t0, -4(s0)");
+        assert_eq!(text(0x1234_5337), "lui t1, 0x12345");
+        assert_eq!(text(0x02c5_f553), "fadd.d fa0, fa1, fa2");
+        assert_eq!(text(0x1820_9043), "fmadd.s ft0, ft1, ft2, ft3, rtz");
+        assert_eq!(text(0xd205_0553), "fcvt.d.w fa0, a0");
+        assert_eq!(text(0x06b6_252f), "amoadd.w.aqrl a0, a1, (a2)");
+        assert_eq!(text(0x3005_9573), "csrrw a0, mstatus, a1");
+        assert_eq!(text(0x7c00_2573), "csrr a0, 0x7c0");
+        assert_eq!(text(0x0310_000f), "fence rw, w");
+        assert_eq!(text(0x0000_0000), "unimp");
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        assert_eq!(text(0x0000_0013), "nop");
+        assert_eq!(text(0xfff0_0513), "li a0, -1");
+        assert_eq!(text(0x0005_8513), "mv a0, a1");
+        assert_eq!(text(0x0000_8067), "ret");
+        assert_eq!(text(0x0000_0067), "jr zero");
+        assert_eq!(text(0x0240_006f), "j 36");
+        assert_eq!(text(0xfe05_1ce3), "bnez a0, -8");
+        assert_eq!(text(0xc000_2573), "rdcycle a0");
+        assert_eq!(text(0x22a5_0553), "fmv.d fa0, fa0");
+        assert_eq!(text(0x4501), "li a0, 0"); // c.li
+    }
+
+    #[test]
+    fn targets_use_symbols() {
+        let symbols = Symbols::new(vec![(0x1000, "main".to_string()), (0x2000, "exit".to_string())]);
+        let beq = decode_inst(0x0205_0263); // beq a0, zero, 36
+        assert_eq!(beq.to_string(), "beqz a0, 36");
+        assert_eq!(beq.disasm(0x1000, Some(&symbols)).to_string(), "beqz a0, main+0x24");
+        assert_eq!(beq.disasm(0x1FDC, Some(&symbols)).to_string(), "beqz a0, exit");
+        assert_eq!(beq.disasm(0x0F00, Some(&symbols)).to_string(), "beqz a0, 0xf24");
+        assert_eq!(beq.disasm(0x0F00, None).to_string(), "beqz a0, 0xf24");
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20_000))]
+
+        #[test]
+        fn reassembles(word in any::<u32>(), opcode in 0u32..32) {
+            // Pick the major opcode separately so most words decode to something
+            let inst = decode_inst(word & !0x7F | opcode << 2 | 0b11);
+            // fence has no syntax for the reserved fm values
+            prop_assume!(match inst {
+                Fence { fm: 0, .. } | Fence { pred: 0x3, succ: 0x3, fm: 0x8 } => true,
+                Fence { .. } => false,
+                _ => true,
+            });
+            let text = inst.to_string();
+            prop_assert_eq!(assemble(&text, 0), Ok(vec![encode(&inst)]), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::instructions::Instruction;
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+/// rs3 = inst[31:27], fmt = inst[26:25]
+fn r4_type(rs3: u32, fmt: u32, rs2: u32, rs1: u32, rm: u32, rd: u32, opcode: u32) -> u32 {
+    r_type(rs3 << 2 | fmt, rs2, rs1, rm, rd, opcode)
+}
+/// imm[11:0] = inst[31:20]
+fn i_type(imm: i32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (imm as u32) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+/// imm[11:5] = inst[31:25], imm[4:0] = inst[11:7]
+fn s_type(imm: i32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    (imm >> 5 & 0x7F) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1F) << 7 | opcode
+}
+/// imm[12|10:5] = inst[31:25], imm[4:1|11] = inst[11:7]
+fn b_type(imm: i32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    (imm >> 12 & 1) << 31
+        | (imm >> 5 & 0x3F) << 25
+        | rs2 << 20
+        | rs1 << 15
+        | funct3 << 12
+        | (imm >> 1 & 0xF) << 8
+        | (imm >> 11 & 1) << 7
+        | opcode
+}
+/// imm[31:12] = inst[31:12], taken already shifted into place
+fn u_type(imm: i32, rd: u32, opcode: u32) -> u32 {
+    (imm as u32 & 0xFFFF_F000) | rd << 7 | opcode
+}
+/// imm[20|10:1|11|19:12] = inst[31:12]
+fn j_type(imm: i32, rd: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    (imm >> 20 & 1) << 31
+        | (imm >> 1 & 0x3FF) << 21
+        | (imm >> 11 & 1) << 20
+        | (imm >> 12 & 0xFF) << 12
+        | rd << 7
+        | opcode
+}
+/// Atomics put funct5 followed by the aq and rl bits in funct7
+fn amo(funct5: u32, aq: bool, rl: bool, rs2: u32, rs1: u32, rd: u32) -> u32 {
+    r_type(
+        funct5 << 2 | (aq as u32) << 1 | rl as u32,
+        rs2,
+        rs1,
+        0b010,
+        rd,
+        0b0101111,
+    )
+}
+
+/// Encodes an instruction as its 32-bit machine code, the inverse of `decode_inst`.
+/// Fields are expected to be in range, as `decode_inst` produces them; anything wider is
+/// truncated into its slot. Compressed instructions decode to their 32-bit equivalents so
+/// everything encodes to 32 bits, and `Ill` encodes to the all-zeros illegal instruction.
+pub fn encode(inst: &Instruction) -> u32 {
+    use Instruction::*;
+    match *inst {
+        // Loads
+        Lb { rd, rs1, imm } => i_type(imm, rs1, 0b000, rd, 0b0000011),
+        Lh { rd, rs1, imm } => i_type(imm, rs1, 0b001, rd, 0b0000011),
+        Lw { rd, rs1, imm } => i_type(imm, rs1, 0b010, rd, 0b0000011),
+        Lbu { rd, rs1, imm } => i_type(imm, rs1, 0b100, rd, 0b0000011),
+        Lhu { rd, rs1, imm } => i_type(imm, rs1, 0b101, rd, 0b0000011),
+
+        // Stores
+        Sb { rs1, rs2, imm } => s_type(imm, rs2, rs1, 0b000, 0b0100011),
+        Sh { rs1, rs2, imm } => s_type(imm, rs2, rs1, 0b001, 0b0100011),
+        Sw { rs1, rs2, imm } => s_type(imm, rs2, rs1, 0b010, 0b0100011),
+
+        // Shifts
+        Sll { rd, rs1, rs2 } => r_type(0b0000000, rs2, rs1, 0b001, rd, 0b0110011),
+        Slli { rd, rs1, shamt } => r_type(0b0000000, shamt, rs1, 0b001, rd, 0b0010011),
+        Srl { rd, rs1, rs2 } => r_type(0b0000000, rs2, rs1, 0b101, rd, 0b0110011),
+        Srli { rd, rs1, shamt } => r_type(0b0000000, shamt, rs1, 0b101, rd, 0b0010011),
+        Sra { rd, rs1, rs2 } => r_type(0b0100000, rs2, rs1, 0b101, rd, 0b0110011),
+        Srai { rd, rs1, shamt } => r_type(0b0100000, shamt, rs1, 0b101, rd, 0b0010011),
+
+        // Arithmetic
+        Add { rd, rs1, rs2 } => r_type(0b0000000, rs2, rs1, 0b000, rd, 0b0110011),
+        Addi { rd, rs1, imm } => i_type(imm, rs1, 0b000, rd, 0b0010011),
+        Sub { rd, rs1, rs2 } => r_type(0b0100000, rs2, rs1, 0b000, rd, 0b0110011),
+        Lui { rd, imm } => u_type(imm, rd, 0b0110111),
+        Auipc { rd, imm } => u_type(imm, rd, 0b0010111),
+
+        // Logical
+        Xor { rd, rs1, rs2 } => r_type(0b0000000, rs2, rs1, 0b100, rd, 0b0110011),
+        Xori { rd, rs1, imm } => i_type(imm, rs1, 0b100, rd, 0b0010011),
+        Or { rd, rs1, rs2 } => r_type(0b0000000, rs2, rs1, 0b110, rd, 0b0110011),
+        Ori { rd, rs1, imm } => i_type(imm, rs1, 0b110, rd, 0b0010011),
+        And { rd, rs1, rs2 } => r_type(0b0000000, rs2, rs1, 0b111, rd, 0b0110011),
+        Andi { rd, rs1, imm } => i_type(imm, rs1, 0b111, rd, 0b0010011),
+
+        // Compare
+        Slt { rd, rs1, rs2 } => r_type(0b0000000, rs2, rs1, 0b010, rd, 0b0110011),
+        Slti { rd, rs1, imm } => i_type(imm, rs1, 0b010, rd, 0b0010011),
+        Sltu { rd, rs1, rs2 } => r_type(0b0000000, rs2, rs1, 0b011, rd, 0b0110011),
+        Sltiu { rd, rs1, imm } => i_type(imm, rs1, 0b011, rd, 0b0010011),
+
+        // Branches
+        Beq { rs1, rs2, imm } => b_type(imm, rs2, rs1, 0b000, 0b1100011),
+        Bne { rs1, rs2, imm } => b_type(imm, rs2, rs1, 0b001, 0b1100011),
+        Blt { rs1, rs2, imm } => b_type(imm, rs2, rs1, 0b100, 0b1100011),
+        Bge { rs1, rs2, imm } => b_type(imm, rs2, rs1, 0b101, 0b1100011),
+        Bltu { rs1, rs2, imm } => b_type(imm, rs2, rs1, 0b110, 0b1100011),
+        Bgeu { rs1, rs2, imm } => b_type(imm, rs2, rs1, 0b111, 0b1100011),
+
+        // Jumps
+        Jal { rd, imm } => j_type(imm, rd, 0b1101111),
+        Jalr { rd, rs1, imm } => i_type(imm, rs1, 0b000, rd, 0b1100111),
+
+        // Multiply and divide
+        Mul { rd, rs1, rs2 } => r_type(0b0000001, rs2, rs1, 0b000, rd, 0b0110011),
+        Mulh { rd, rs1, rs2 } => r_type(0b0000001, rs2, rs1, 0b001, rd, 0b0110011),
+        Mulhsu { rd, rs1, rs2 } => r_type(0b0000001, rs2, rs1, 0b010, rd, 0b0110011),
+        Mulhu { rd, rs1, rs2 } => r_type(0b0000001, rs2, rs1, 0b011, rd, 0b0110011),
+        Div { rd, rs1, rs2 } => r_type(0b0000001, rs2, rs1, 0b100, rd, 0b0110011),
+        Divu { rd, rs1, rs2 } => r_type(0b0000001, rs2, rs1, 0b101, rd, 0b0110011),
+        Rem { rd, rs1, rs2 } => r_type(0b0000001, rs2, rs1, 0b110, rd, 0b0110011),
+        Remu { rd, rs1, rs2 } => r_type(0b0000001, rs2, rs1, 0b111, rd, 0b0110011),
+
+        // Zba
+        Sh1add { rd, rs1, rs2 } => r_type(0b0010000, rs2, rs1, 0b010, rd, 0b0110011),
+        Sh2add { rd, rs1, rs2 } => r_type(0b0010000, rs2, rs1, 0b100, rd, 0b0110011),
+        Sh3add { rd, rs1, rs2 } => r_type(0b0010000, rs2, rs1, 0b110, rd, 0b0110011),
+
+        // Zbb, the unary ops put their selector in rs2
+        Andn { rd, rs1, rs2 } => r_type(0b0100000, rs2, rs1, 0b111, rd, 0b0110011),
+        Orn { rd, rs1, rs2 } => r_type(0b0100000, rs2, rs1, 0b110, rd, 0b0110011),
+        Xnor { rd, rs1, rs2 } => r_type(0b0100000, rs2, rs1, 0b100, rd, 0b0110011),
+        Clz { rd, rs1 } => r_type(0b0110000, 0b00000, rs1, 0b001, rd, 0b0010011),
+        Ctz { rd, rs1 } => r_type(0b0110000, 0b00001, rs1, 0b001, rd, 0b0010011),
+        Cpop { rd, rs1 } => r_type(0b0110000, 0b00010, rs1, 0b001, rd, 0b0010011),
+        Max { rd, rs1, rs2 } => r_type(0b0000101, rs2, rs1, 0b110, rd, 0b0110011),
+        Maxu { rd, rs1, rs2 } => r_type(0b0000101, rs2, rs1, 0b111, rd, 0b0110011),
+        Min { rd, rs1, rs2 } => r_type(0b0000101, rs2, rs1, 0b100, rd, 0b0110011),
+        Minu { rd, rs1, rs2 } => r_type(0b0000101, rs2, rs1, 0b101, rd, 0b0110011),
+        SextB { rd, rs1 } => r_type(0b0110000, 0b00100, rs1, 0b001, rd, 0b0010011),
+        SextH { rd, rs1 } => r_type(0b0110000, 0b00101, rs1, 0b001, rd, 0b0010011),
+        ZextH { rd, rs1 } => r_type(0b0000100, 0b00000, rs1, 0b100, rd, 0b0110011),
+        Rol { rd, rs1, rs2 } => r_type(0b0110000, rs2, rs1, 0b001, rd, 0b0110011),
+        Ror { rd, rs1, rs2 } => r_type(0b0110000, rs2, rs1, 0b101, rd, 0b0110011),
+        Rori { rd, rs1, shamt } => r_type(0b0110000, shamt, rs1, 0b101, rd, 0b0010011),
+        OrcB { rd, rs1 } => r_type(0b0010100, 0b00111, rs1, 0b101, rd, 0b0010011),
+        Rev8 { rd, rs1 } => r_type(0b0110100, 0b11000, rs1, 0b101, rd, 0b0010011),
+
+        // Zbs
+        Bclr { rd, rs1, rs2 } => r_type(0b0100100, rs2, rs1, 0b001, rd, 0b0110011),
+        Bclri { rd, rs1, shamt } => r_type(0b0100100, shamt, rs1, 0b001, rd, 0b0010011),
+        Bext { rd, rs1, rs2 } => r_type(0b0100100, rs2, rs1, 0b101, rd, 0b0110011),
+        Bexti { rd, rs1, shamt } => r_type(0b0100100, shamt, rs1, 0b101, rd, 0b0010011),
+        Binv { rd, rs1, rs2 } => r_type(0b0110100, rs2, rs1, 0b001, rd, 0b0110011),
+        Binvi { rd, rs1, shamt } => r_type(0b0110100, shamt, rs1, 0b001, rd, 0b0010011),
+        Bset { rd, rs1, rs2 } => r_type(0b0010100, rs2, rs1, 0b001, rd, 0b0110011),
+        Bseti { rd, rs1, shamt } => r_type(0b0010100, shamt, rs1, 0b001, rd, 0b0010011),
+
+        // Zbc
+        Clmul { rd, rs1, rs2 } => r_type(0b0000101, rs2, rs1, 0b001, rd, 0b0110011),
+        Clmulh { rd, rs1, rs2 } => r_type(0b0000101, rs2, rs1, 0b011, rd, 0b0110011),
+        Clmulr { rd, rs1, rs2 } => r_type(0b0000101, rs2, rs1, 0b010, rd, 0b0110011),
+
+        // Atomics
+        LrW { rd, rs1, aq, rl } => amo(0b00010, aq, rl, 0, rs1, rd),
+        ScW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b00011, aq, rl, rs2, rs1, rd),
+        AmoswapW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b00001, aq, rl, rs2, rs1, rd),
+        AmoaddW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b00000, aq, rl, rs2, rs1, rd),
+        AmoxorW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b00100, aq, rl, rs2, rs1, rd),
+        AmoandW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b01100, aq, rl, rs2, rs1, rd),
+        AmoorW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b01000, aq, rl, rs2, rs1, rd),
+        AmominW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b10000, aq, rl, rs2, rs1, rd),
+        AmomaxW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b10100, aq, rl, rs2, rs1, rd),
+        AmominuW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b11000, aq, rl, rs2, rs1, rd),
+        AmomaxuW {
+            rd,
+            rs1,
+            rs2,
+            aq,
+            rl,
+        } => amo(0b11100, aq, rl, rs2, rs1, rd),
+
+        // FP loads and stores
+        Flw { rd, rs1, imm } => i_type(imm, rs1, 0b010, rd, 0b0000111),
+        Fld { rd, rs1, imm } => i_type(imm, rs1, 0b011, rd, 0b0000111),
+        Fsw { rs1, rs2, imm } => s_type(imm, rs2, rs1, 0b010, 0b0100111),
+        Fsd { rs1, rs2, imm } => s_type(imm, rs2, rs1, 0b011, 0b0100111),
+
+        // Fused multiply-add
+        FmaddS {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => r4_type(rs3, 0b00, rs2, rs1, rm, rd, 0b1000011),
+        FmsubS {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => r4_type(rs3, 0b00, rs2, rs1, rm, rd, 0b1000111),
+        FnmsubS {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => r4_type(rs3, 0b00, rs2, rs1, rm, rd, 0b1001011),
+        FnmaddS {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => r4_type(rs3, 0b00, rs2, rs1, rm, rd, 0b1001111),
+        FmaddD {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => r4_type(rs3, 0b01, rs2, rs1, rm, rd, 0b1000011),
+        FmsubD {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => r4_type(rs3, 0b01, rs2, rs1, rm, rd, 0b1000111),
+        FnmsubD {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => r4_type(rs3, 0b01, rs2, rs1, rm, rd, 0b1001011),
+        FnmaddD {
+            rd,
+            rs1,
+            rs2,
+            rs3,
+            rm,
+        } => r4_type(rs3, 0b01, rs2, rs1, rm, rd, 0b1001111),
+
+        // FP arithmetic, funct3 is the rounding mode where the op rounds
+        FaddS { rd, rs1, rs2, rm } => r_type(0b0000000, rs2, rs1, rm, rd, 0b1010011),
+        FsubS { rd, rs1, rs2, rm } => r_type(0b0000100, rs2, rs1, rm, rd, 0b1010011),
+        FmulS { rd, rs1, rs2, rm } => r_type(0b0001000, rs2, rs1, rm, rd, 0b1010011),
+        FdivS { rd, rs1, rs2, rm } => r_type(0b0001100, rs2, rs1, rm, rd, 0b1010011),
+        FsqrtS { rd, rs1, rm } => r_type(0b0101100, 0b00000, rs1, rm, rd, 0b1010011),
+        FaddD { rd, rs1, rs2, rm } => r_type(0b0000001, rs2, rs1, rm, rd, 0b1010011),
+        FsubD { rd, rs1, rs2, rm } => r_type(0b0000101, rs2, rs1, rm, rd, 0b1010011),
+        FmulD { rd, rs1, rs2, rm } => r_type(0b0001001, rs2, rs1, rm, rd, 0b1010011),
+        FdivD { rd, rs1, rs2, rm } => r_type(0b0001101, rs2, rs1, rm, rd, 0b1010011),
+        FsqrtD { rd, rs1, rm } => r_type(0b0101101, 0b00000, rs1, rm, rd, 0b1010011),
+
+        // FP sign injection and min/max
+        FsgnjS { rd, rs1, rs2 } => r_type(0b0010000, rs2, rs1, 0b000, rd, 0b1010011),
+        FsgnjnS { rd, rs1, rs2 } => r_type(0b0010000, rs2, rs1, 0b001, rd, 0b1010011),
+        FsgnjxS { rd, rs1, rs2 } => r_type(0b0010000, rs2, rs1, 0b010, rd, 0b1010011),
+        FminS { rd, rs1, rs2 } => r_type(0b0010100, rs2, rs1, 0b000, rd, 0b1010011),
+        FmaxS { rd, rs1, rs2 } => r_type(0b0010100, rs2, rs1, 0b001, rd, 0b1010011),
+        FsgnjD { rd, rs1, rs2 } => r_type(0b0010001, rs2, rs1, 0b000, rd, 0b1010011),
+        FsgnjnD { rd, rs1, rs2 } => r_type(0b0010001, rs2, rs1, 0b001, rd, 0b1010011),
+        FsgnjxD { rd, rs1, rs2 } => r_type(0b0010001, rs2, rs1, 0b010, rd, 0b1010011),
+        FminD { rd, rs1, rs2 } => r_type(0b0010101, rs2, rs1, 0b000, rd, 0b1010011),
+        FmaxD { rd, rs1, rs2 } => r_type(0b0010101, rs2, rs1, 0b001, rd, 0b1010011),
+
+        // FP compare and classify
+        FeqS { rd, rs1, rs2 } => r_type(0b1010000, rs2, rs1, 0b010, rd, 0b1010011),
+        FltS { rd, rs1, rs2 } => r_type(0b1010000, rs2, rs1, 0b001, rd, 0b1010011),
+        FleS { rd, rs1, rs2 } => r_type(0b1010000, rs2, rs1, 0b000, rd, 0b1010011),
+        FclassS { rd, rs1 } => r_type(0b1110000, 0b00000, rs1, 0b001, rd, 0b1010011),
+        FeqD { rd, rs1, rs2 } => r_type(0b1010001, rs2, rs1, 0b010, rd, 0b1010011),
+        FltD { rd, rs1, rs2 } => r_type(0b1010001, rs2, rs1, 0b001, rd, 0b1010011),
+        FleD { rd, rs1, rs2 } => r_type(0b1010001, rs2, rs1, 0b000, rd, 0b1010011),
+        FclassD { rd, rs1 } => r_type(0b1110001, 0b00000, rs1, 0b001, rd, 0b1010011),
+
+        // FP conversions and moves
+        FcvtWS { rd, rs1, rm } => r_type(0b1100000, 0b00000, rs1, rm, rd, 0b1010011),
+        FcvtWuS { rd, rs1, rm } => r_type(0b1100000, 0b00001, rs1, rm, rd, 0b1010011),
+        FcvtSW { rd, rs1, rm } => r_type(0b1101000, 0b00000, rs1, rm, rd, 0b1010011),
+        FcvtSWu { rd, rs1, rm } => r_type(0b1101000, 0b00001, rs1, rm, rd, 0b1010011),
+        FcvtWD { rd, rs1, rm } => r_type(0b1100001, 0b00000, rs1, rm, rd, 0b1010011),
+        FcvtWuD { rd, rs1, rm } => r_type(0b1100001, 0b00001, rs1, rm, rd, 0b1010011),
+        FcvtDW { rd, rs1, rm } => r_type(0b1101001, 0b00000, rs1, rm, rd, 0b1010011),
+        FcvtDWu { rd, rs1, rm } => r_type(0b1101001, 0b00001, rs1, rm, rd, 0b1010011),
+        FcvtSD { rd, rs1, rm } => r_type(0b0100000, 0b00001, rs1, rm, rd, 0b1010011),
+        FcvtDS { rd, rs1, rm } => r_type(0b0100001, 0b00000, rs1, rm, rd, 0b1010011),
+        FmvXW { rd, rs1 } => r_type(0b1110000, 0b00000, rs1, 0b000, rd, 0b1010011),
+        FmvWX { rd, rs1 } => r_type(0b1111000, 0b00000, rs1, 0b000, rd, 0b1010011),
+
+        // Sync
+        Fence { pred, succ, fm } => fm << 28 | pred << 24 | succ << 20 | 0b0001111,
+        FenceI => i_type(0, 0, 0b001, 0, 0b0001111),
+
+        // System
+        Ecall => 0x0000_0073,
+        Ebreak => 0x0010_0073,
+        Mret => 0x3020_0073,
+
+        // CSRs, the immediate forms put uimm in rs1
+        Csrrw { rd, rs1, csr } => i_type(csr as i32, rs1, 0b001, rd, 0b1110011),
+        Csrrs { rd, rs1, csr } => i_type(csr as i32, rs1, 0b010, rd, 0b1110011),
+        Csrrc { rd, rs1, csr } => i_type(csr as i32, rs1, 0b011, rd, 0b1110011),
+        Csrrwi { rd, uimm, csr } => i_type(csr as i32, uimm, 0b101, rd, 0b1110011),
+        Csrrsi { rd, uimm, csr } => i_type(csr as i32, uimm, 0b110, rd, 0b1110011),
+        Csrrci { rd, uimm, csr } => i_type(csr as i32, uimm, 0b111, rd, 0b1110011),
+
+        Ill => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::decode_inst;
+    use proptest::prelude::*;
+
+    /// Major opcodes `decode_inst` accepts, so generated words mostly decode to something
+    const OPCODES: &[u32] = &[
+        0b0000011, 0b0000111, 0b0001111, 0b0010011, 0b0010111, 0b0100011, 0b0100111, 0b0101111,
+        0b0110011, 0b0110111, 0b1000011, 0b1000111, 0b1001011, 0b1001111, 0b1010011, 0b1100011,
+        0b1100111, 0b1101111, 0b1110011,
+    ];
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20_000))]
+
+        #[test]
+        fn round_trips_through_decode(
+            word in any::<u32>(),
+            opcode in proptest::sample::select(OPCODES),
+        ) {
+            let inst = decode_inst(word & !0x7F | opcode);
+            prop_assert_eq!(decode_inst(encode(&inst)), inst);
+        }
+
+        #[test]
+        fn round_trips_compressed(parcel in any::<u16>()) {
+            let inst = decode_inst(parcel as u32);
+            prop_assert_eq!(decode_inst(encode(&inst)), inst);
+        }
+    }
+
+    #[test]
+    fn canonical_encodings_are_reproduced() {
+        // Fields decode ignores come back as zero, anything else is bit for bit
+        for word in [
+            0x0000_0013,
+            0xfff5_0513,
+            0x8000_00ef,
+            0xfe00_0ee3,
+            0x0000_100f,
+            0x3052_b073,
+        ] {
+            assert_eq!(encode(&decode_inst(word)), word, "encoding 0x{:08x}", word);
+        }
+        assert_eq!(encode(&decode_inst(0x0000_808f)), 0x0000_000f); // fence with rd and rs1 set
+    }
+}
@@ -0,0 +1,145 @@
+use crate::cosim::Mismatch;
+use std::fmt;
+
+/// Errors that stop a simulation
+#[derive(Debug)]
+pub enum SimError {
+    /// Input file could not be read
+    Io(std::io::Error),
+    /// ELF file could not be parsed or is missing something the loader needs
+    BadElf(String),
+    /// ELF file is not a little-endian 32-bit RISC-V executable
+    UnsupportedMachine(String),
+    /// Access to an address with nothing mapped at it. For a fetch `word` holds whatever
+    /// of the instruction was fetched before the fault.
+    AddressFault { pc: u32, addr: u32, word: u32 },
+    /// Access not aligned to its size
+    MisalignedAccess { pc: u32, addr: u32, size: u32 },
+    IllegalInstruction { pc: u32, word: u32 },
+    /// `ecall` with a syscall number the emulation does not service
+    UnsupportedSyscall { pc: u32, num: u32 },
+    /// `ebreak` executed
+    Breakpoint { pc: u32 },
+    /// Timing core diverged from the golden model
+    Mismatch(Box<Mismatch>),
+    /// Commit log could not be read or has a line that isn't a Spike RV32 commit record
+    BadTrace { line: usize, msg: String },
+    /// Commit log given with `--trace-file` could not be written
+    CommitLog(std::io::Error),
+    /// Command line options describe a machine that can't be built
+    BadConfig(String),
+    /// Stats file, `stats.txt` or the one given with `--stats-file`, could not be written
+    StatsFile(std::io::Error),
+}
+
+impl SimError {
+    /// Process exit code for this error, distinct per kind so batch runs can classify failures.
+    /// Kept above 110 so they do not collide with program exit codes, riscv-tests test numbers
+    /// or the 101 a Rust panic exits with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SimError::Io(_) => 111,
+            SimError::BadElf(_) => 112,
+            SimError::UnsupportedMachine(_) => 113,
+            SimError::AddressFault { .. } => 114,
+            SimError::MisalignedAccess { .. } => 115,
+            SimError::IllegalInstruction { .. } => 116,
+            SimError::Mismatch(_) => 117,
+            SimError::UnsupportedSyscall { .. } => 118,
+            SimError::Breakpoint { .. } => 119,
+            SimError::BadTrace { .. } => 120,
+            SimError::CommitLog(_) => 121,
+            SimError::BadConfig(_) => 122,
+            SimError::StatsFile(_) => 123,
+        }
+    }
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::Io(e) => write!(f, "could not read input file: {}", e),
+            SimError::BadElf(msg) => write!(f, "bad ELF file: {}", msg),
+            SimError::UnsupportedMachine(msg) => write!(f, "unsupported ELF file: {}", msg),
+            SimError::AddressFault { pc, addr, word } => write!(
+                f,
+                "address fault at 0x{:08x} (0x{:08x}) accessing 0x{:08x}",
+                pc, word, addr
+            ),
+            SimError::MisalignedAccess { pc, addr, size } => write!(
+                f,
+                "misaligned {} byte access at 0x{:08x} to 0x{:08x}",
+                size, pc, addr
+            ),
+            SimError::IllegalInstruction { pc, word } => {
+                write!(f, "illegal instruction 0x{:08x} at 0x{:08x}", word, pc)
+            }
+            SimError::UnsupportedSyscall { pc, num } => {
+                write!(f, "unsupported syscall {} at 0x{:08x}", num, pc)
+            }
+            SimError::Breakpoint { pc } => write!(f, "breakpoint at 0x{:08x}", pc),
+            SimError::Mismatch(mismatch) => write!(f, "{}", mismatch),
+            SimError::BadTrace { line, msg } => write!(f, "bad commit log line {}: {}", line, msg),
+            SimError::CommitLog(e) => write!(f, "could not write commit log: {}", e),
+            SimError::BadConfig(msg) => write!(f, "invalid configuration: {}", msg),
+            SimError::StatsFile(e) => write!(f, "could not write stats file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+impl From<std::io::Error> for SimError {
+    fn from(e: std::io::Error) -> Self {
+        SimError::Io(e)
+    }
+}
+
+impl From<elf::ParseError> for SimError {
+    fn from(e: elf::ParseError) -> Self {
+        SimError::BadElf(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::Symbols;
+    use crate::exec::Retired;
+    use crate::instructions::decode_inst;
+
+    #[test]
+    fn each_kind_has_its_exit_code() {
+        let io = || std::io::Error::other("denied");
+        let nop = Retired::new(0x1000, 0x13, decode_inst(0x13));
+        let mismatch = Mismatch {
+            index: 0,
+            cycle: 0,
+            expected: nop,
+            actual: nop,
+            golden_regs: [0; 32],
+            core_regs: [0; 32],
+            symbols: Symbols::default(),
+        };
+        let errors = [
+            (SimError::Io(io()), 111),
+            (SimError::BadElf(String::new()), 112),
+            (SimError::UnsupportedMachine(String::new()), 113),
+            (SimError::AddressFault { pc: 0, addr: 0, word: 0 }, 114),
+            (SimError::MisalignedAccess { pc: 0, addr: 1, size: 4 }, 115),
+            (SimError::IllegalInstruction { pc: 0, word: 0 }, 116),
+            (SimError::Mismatch(Box::new(mismatch)), 117),
+            (SimError::UnsupportedSyscall { pc: 0, num: 1 }, 118),
+            (SimError::Breakpoint { pc: 0 }, 119),
+            (SimError::BadTrace { line: 1, msg: String::new() }, 120),
+            (SimError::CommitLog(io()), 121),
+            (SimError::BadConfig(String::new()), 122),
+            (SimError::StatsFile(io()), 123),
+        ];
+        for (error, code) in &errors {
+            assert_eq!(error.exit_code(), *code, "{:?}", error);
+        }
+        // Clear of the codes programs and riscv-tests exit with and of a panic's 101
+        assert!(errors.iter().all(|(error, _)| error.exit_code() > 110));
+    }
+}
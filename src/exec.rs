@@ -0,0 +1,608 @@
+use crate::components::memory::AmoOp;
+use crate::csr::CsrOp;
+use crate::fpu::{self, Compare, FpFmt, FpRegFile, RoundingMode, SignInject};
+use crate::instructions::{Instruction, MemWidth};
+use crate::trap::Exception;
+
+/// Architectural effect of an instruction, applied by whichever model is executing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    None,
+    WriteReg {
+        rd: u32,
+        val: u32,
+    },
+    /// Write to an FP register, singles already NaN-boxed
+    WriteFreg {
+        rd: u32,
+        val: u64,
+    },
+    Load {
+        rd: u32,
+        addr: u32,
+        width: MemWidth,
+        signed: bool,
+    },
+    /// Load into an FP register, word loads are NaN-boxed
+    LoadFp {
+        rd: u32,
+        addr: u32,
+        width: MemWidth,
+    },
+    Store {
+        addr: u32,
+        width: MemWidth,
+        val: u64,
+    },
+    /// Word load that also registers a reservation on its address
+    LoadReserved {
+        rd: u32,
+        addr: u32,
+    },
+    /// Word store made only if the reservation still holds, rd gets 0 on success and 1 on failure
+    StoreConditional {
+        rd: u32,
+        addr: u32,
+        val: u32,
+    },
+    /// Atomic read-modify-write of a word, the old value goes to rd
+    Amo {
+        rd: u32,
+        addr: u32,
+        op: AmoOp,
+        val: u32,
+    },
+    /// Environment call, serviced by syscall emulation
+    Ecall,
+    Ebreak,
+    /// Return from a trap, the model takes the next PC from `mepc`
+    Mret,
+    /// Makes earlier stores visible to instruction fetch, dropping anything already fetched
+    FenceI,
+    /// CSR read-modify-write, performed by the model that owns the CSR file.
+    /// The old value goes to rd.
+    Csr {
+        rd: u32,
+        csr: u32,
+        op: CsrOp,
+        val: u32,
+    },
+    Illegal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub effect: Effect,
+    pub next_pc: u32,
+    /// FP exception flags to accrue into `fflags` when the instruction retires
+    pub fflags: u8,
+}
+
+/// Store performed by a retired instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemWrite {
+    pub addr: u32,
+    pub width: MemWidth,
+    pub val: u64,
+}
+
+/// Record of a retired instruction, used to compare models against each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retired {
+    pub pc: u32,
+    pub word: u32,
+    pub inst: Instruction,
+    /// Destination register and the value written to it
+    pub reg_write: Option<(u32, u32)>,
+    /// Destination FP register and the raw 64-bit value written to it
+    pub freg_write: Option<(u32, u64)>,
+    /// CSR written and the value it holds afterwards, `fflags` when an FP instruction
+    /// raises exception flags
+    pub csr_write: Option<(u32, u32)>,
+    /// Address read by a load, AMO or `lr.w`
+    pub mem_read: Option<u32>,
+    pub mem_write: Option<MemWrite>,
+    /// Exception the instruction raised instead of retiring, the model has entered the handler
+    pub trap: Option<Exception>,
+}
+
+impl Retired {
+    /// Record of an instruction with no writes
+    pub fn new(pc: u32, word: u32, inst: Instruction) -> Self {
+        Self {
+            pc,
+            word,
+            inst,
+            reg_write: None,
+            freg_write: None,
+            csr_write: None,
+            mem_read: None,
+            mem_write: None,
+            trap: None,
+        }
+    }
+}
+
+/// Computes the effect and next PC of an instruction given the current register files.
+/// `len` is the instruction's size in bytes, 2 if it was compressed.
+pub fn execute(
+    inst: &Instruction,
+    pc: u32,
+    len: u32,
+    regs: &[u32; 32],
+    fregs: &FpRegFile,
+) -> Outcome {
+    let reg = |idx: u32| regs[idx as usize];
+    let addr = |rs1: u32, imm: i32| reg(rs1).wrapping_add(imm as u32);
+    let write = |rd: u32, val: u32| Effect::WriteReg { rd, val };
+    let load = |rd: u32, rs1: u32, imm: i32, width: MemWidth, signed: bool| Effect::Load {
+        rd,
+        addr: addr(rs1, imm),
+        width,
+        signed,
+    };
+    let store = |rs1: u32, rs2: u32, imm: i32, width: MemWidth| Effect::Store {
+        addr: addr(rs1, imm),
+        width,
+        val: reg(rs2) as u64,
+    };
+    // `src` is the source register or immediate, csrrs/csrrc only read if it is zero
+    let csr_op = |rd: u32, csr: u32, op: CsrOp, src: u32, val: u32| {
+        let op = if src == 0 && op != CsrOp::Write { CsrOp::Read } else { op };
+        Effect::Csr { rd, csr, op, val }
+    };
+    let amo = |rd: u32, rs1: u32, rs2: u32, op: AmoOp| Effect::Amo {
+        rd,
+        addr: reg(rs1),
+        op,
+        val: reg(rs2),
+    };
+    let branch = |taken: bool, imm: i32| {
+        if taken {
+            pc.wrapping_add(imm as u32)
+        } else {
+            pc.wrapping_add(len)
+        }
+    };
+
+    let mut next_pc = pc.wrapping_add(len);
+    let mut fflags = 0;
+    let effect = match *inst {
+        // Loads
+        Instruction::Lb { rd, rs1, imm } => load(rd, rs1, imm, MemWidth::Byte, true),
+        Instruction::Lh { rd, rs1, imm } => load(rd, rs1, imm, MemWidth::Half, true),
+        Instruction::Lw { rd, rs1, imm } => load(rd, rs1, imm, MemWidth::Word, true),
+        Instruction::Lbu { rd, rs1, imm } => load(rd, rs1, imm, MemWidth::Byte, false),
+        Instruction::Lhu { rd, rs1, imm } => load(rd, rs1, imm, MemWidth::Half, false),
+
+        // Stores
+        Instruction::Sb { rs1, rs2, imm } => store(rs1, rs2, imm, MemWidth::Byte),
+        Instruction::Sh { rs1, rs2, imm } => store(rs1, rs2, imm, MemWidth::Half),
+        Instruction::Sw { rs1, rs2, imm } => store(rs1, rs2, imm, MemWidth::Word),
+
+        // Shifts
+        Instruction::Sll { rd, rs1, rs2 } => write(rd, reg(rs1) << (reg(rs2) & 0x1F)),
+        Instruction::Slli { rd, rs1, shamt } => write(rd, reg(rs1) << shamt),
+        Instruction::Srl { rd, rs1, rs2 } => write(rd, reg(rs1) >> (reg(rs2) & 0x1F)),
+        Instruction::Srli { rd, rs1, shamt } => write(rd, reg(rs1) >> shamt),
+        Instruction::Sra { rd, rs1, rs2 } => {
+            write(rd, ((reg(rs1) as i32) >> (reg(rs2) & 0x1F)) as u32)
+        }
+        Instruction::Srai { rd, rs1, shamt } => write(rd, ((reg(rs1) as i32) >> shamt) as u32),
+
+        // Arithmetic
+        Instruction::Add { rd, rs1, rs2 } => write(rd, reg(rs1).wrapping_add(reg(rs2))),
+        Instruction::Addi { rd, rs1, imm } => write(rd, reg(rs1).wrapping_add(imm as u32)),
+        Instruction::Sub { rd, rs1, rs2 } => write(rd, reg(rs1).wrapping_sub(reg(rs2))),
+        Instruction::Lui { rd, imm } => write(rd, imm as u32),
+        Instruction::Auipc { rd, imm } => write(rd, pc.wrapping_add(imm as u32)),
+
+        // Logical
+        Instruction::Xor { rd, rs1, rs2 } => write(rd, reg(rs1) ^ reg(rs2)),
+        Instruction::Xori { rd, rs1, imm } => write(rd, reg(rs1) ^ imm as u32),
+        Instruction::Or { rd, rs1, rs2 } => write(rd, reg(rs1) | reg(rs2)),
+        Instruction::Ori { rd, rs1, imm } => write(rd, reg(rs1) | imm as u32),
+        Instruction::And { rd, rs1, rs2 } => write(rd, reg(rs1) & reg(rs2)),
+        Instruction::Andi { rd, rs1, imm } => write(rd, reg(rs1) & imm as u32),
+
+        // Compare
+        Instruction::Slt { rd, rs1, rs2 } => {
+            write(rd, ((reg(rs1) as i32) < (reg(rs2) as i32)) as u32)
+        }
+        Instruction::Slti { rd, rs1, imm } => write(rd, ((reg(rs1) as i32) < imm) as u32),
+        Instruction::Sltu { rd, rs1, rs2 } => write(rd, (reg(rs1) < reg(rs2)) as u32),
+        Instruction::Sltiu { rd, rs1, imm } => write(rd, (reg(rs1) < imm as u32) as u32),
+
+        // Branches
+        Instruction::Beq { rs1, rs2, imm } => {
+            next_pc = branch(reg(rs1) == reg(rs2), imm);
+            Effect::None
+        }
+        Instruction::Bne { rs1, rs2, imm } => {
+            next_pc = branch(reg(rs1) != reg(rs2), imm);
+            Effect::None
+        }
+        Instruction::Blt { rs1, rs2, imm } => {
+            next_pc = branch((reg(rs1) as i32) < (reg(rs2) as i32), imm);
+            Effect::None
+        }
+        Instruction::Bge { rs1, rs2, imm } => {
+            next_pc = branch((reg(rs1) as i32) >= (reg(rs2) as i32), imm);
+            Effect::None
+        }
+        Instruction::Bltu { rs1, rs2, imm } => {
+            next_pc = branch(reg(rs1) < reg(rs2), imm);
+            Effect::None
+        }
+        Instruction::Bgeu { rs1, rs2, imm } => {
+            next_pc = branch(reg(rs1) >= reg(rs2), imm);
+            Effect::None
+        }
+
+        // Jumps
+        Instruction::Jal { rd, imm } => {
+            next_pc = pc.wrapping_add(imm as u32);
+            write(rd, pc.wrapping_add(len))
+        }
+        Instruction::Jalr { rd, rs1, imm } => {
+            next_pc = addr(rs1, imm) & !1;
+            write(rd, pc.wrapping_add(len))
+        }
+
+        // Multiply
+        Instruction::Mul { rd, rs1, rs2 } => write(rd, reg(rs1).wrapping_mul(reg(rs2))),
+        Instruction::Mulh { rd, rs1, rs2 } => {
+            let product = reg(rs1) as i32 as i64 * reg(rs2) as i32 as i64;
+            write(rd, (product >> 32) as u32)
+        }
+        Instruction::Mulhsu { rd, rs1, rs2 } => {
+            let product = reg(rs1) as i32 as i64 * reg(rs2) as i64;
+            write(rd, (product >> 32) as u32)
+        }
+        Instruction::Mulhu { rd, rs1, rs2 } => {
+            let product = reg(rs1) as u64 * reg(rs2) as u64;
+            write(rd, (product >> 32) as u32)
+        }
+
+        // Divide, division by zero and overflow give the results the spec defines rather than trapping
+        Instruction::Div { rd, rs1, rs2 } => {
+            let (a, b) = (reg(rs1) as i32, reg(rs2) as i32);
+            write(rd, if b == 0 { u32::MAX } else { a.wrapping_div(b) as u32 })
+        }
+        Instruction::Divu { rd, rs1, rs2 } => {
+            write(rd, reg(rs1).checked_div(reg(rs2)).unwrap_or(u32::MAX))
+        }
+        Instruction::Rem { rd, rs1, rs2 } => {
+            let (a, b) = (reg(rs1) as i32, reg(rs2) as i32);
+            write(rd, if b == 0 { a as u32 } else { a.wrapping_rem(b) as u32 })
+        }
+        Instruction::Remu { rd, rs1, rs2 } => {
+            write(rd, reg(rs1).checked_rem(reg(rs2)).unwrap_or(reg(rs1)))
+        }
+
+        // Address generation
+        Instruction::Sh1add { rd, rs1, rs2 } => write(rd, (reg(rs1) << 1).wrapping_add(reg(rs2))),
+        Instruction::Sh2add { rd, rs1, rs2 } => write(rd, (reg(rs1) << 2).wrapping_add(reg(rs2))),
+        Instruction::Sh3add { rd, rs1, rs2 } => write(rd, (reg(rs1) << 3).wrapping_add(reg(rs2))),
+
+        // Basic bit manipulation
+        Instruction::Andn { rd, rs1, rs2 } => write(rd, reg(rs1) & !reg(rs2)),
+        Instruction::Orn { rd, rs1, rs2 } => write(rd, reg(rs1) | !reg(rs2)),
+        Instruction::Xnor { rd, rs1, rs2 } => write(rd, !(reg(rs1) ^ reg(rs2))),
+        Instruction::Clz { rd, rs1 } => write(rd, reg(rs1).leading_zeros()),
+        Instruction::Ctz { rd, rs1 } => write(rd, reg(rs1).trailing_zeros()),
+        Instruction::Cpop { rd, rs1 } => write(rd, reg(rs1).count_ones()),
+        Instruction::Max { rd, rs1, rs2 } => {
+            write(rd, (reg(rs1) as i32).max(reg(rs2) as i32) as u32)
+        }
+        Instruction::Maxu { rd, rs1, rs2 } => write(rd, reg(rs1).max(reg(rs2))),
+        Instruction::Min { rd, rs1, rs2 } => {
+            write(rd, (reg(rs1) as i32).min(reg(rs2) as i32) as u32)
+        }
+        Instruction::Minu { rd, rs1, rs2 } => write(rd, reg(rs1).min(reg(rs2))),
+        Instruction::SextB { rd, rs1 } => write(rd, reg(rs1) as i8 as u32),
+        Instruction::SextH { rd, rs1 } => write(rd, reg(rs1) as i16 as u32),
+        Instruction::ZextH { rd, rs1 } => write(rd, reg(rs1) as u16 as u32),
+        Instruction::Rol { rd, rs1, rs2 } => write(rd, reg(rs1).rotate_left(reg(rs2) & 0x1F)),
+        Instruction::Ror { rd, rs1, rs2 } => write(rd, reg(rs1).rotate_right(reg(rs2) & 0x1F)),
+        Instruction::Rori { rd, rs1, shamt } => write(rd, reg(rs1).rotate_right(shamt)),
+        Instruction::OrcB { rd, rs1 } => {
+            let bytes = reg(rs1).to_le_bytes().map(|b| if b != 0 { 0xFF } else { 0 });
+            write(rd, u32::from_le_bytes(bytes))
+        }
+        Instruction::Rev8 { rd, rs1 } => write(rd, reg(rs1).swap_bytes()),
+
+        // Single-bit operations
+        Instruction::Bclr { rd, rs1, rs2 } => write(rd, reg(rs1) & !(1 << (reg(rs2) & 0x1F))),
+        Instruction::Bclri { rd, rs1, shamt } => write(rd, reg(rs1) & !(1 << shamt)),
+        Instruction::Bext { rd, rs1, rs2 } => write(rd, (reg(rs1) >> (reg(rs2) & 0x1F)) & 1),
+        Instruction::Bexti { rd, rs1, shamt } => write(rd, (reg(rs1) >> shamt) & 1),
+        Instruction::Binv { rd, rs1, rs2 } => write(rd, reg(rs1) ^ (1 << (reg(rs2) & 0x1F))),
+        Instruction::Binvi { rd, rs1, shamt } => write(rd, reg(rs1) ^ (1 << shamt)),
+        Instruction::Bset { rd, rs1, rs2 } => write(rd, reg(rs1) | (1 << (reg(rs2) & 0x1F))),
+        Instruction::Bseti { rd, rs1, shamt } => write(rd, reg(rs1) | (1 << shamt)),
+
+        // Carry-less multiply, the three variants take different slices of the 64-bit product
+        Instruction::Clmul { rd, rs1, rs2 } => write(rd, clmul(reg(rs1), reg(rs2)) as u32),
+        Instruction::Clmulh { rd, rs1, rs2 } => write(rd, (clmul(reg(rs1), reg(rs2)) >> 32) as u32),
+        Instruction::Clmulr { rd, rs1, rs2 } => write(rd, (clmul(reg(rs1), reg(rs2)) >> 31) as u32),
+
+        // Atomics, ordering bits need nothing as the models perform accesses in program order
+        Instruction::LrW { rd, rs1, .. } => Effect::LoadReserved { rd, addr: reg(rs1) },
+        Instruction::ScW { rd, rs1, rs2, .. } => Effect::StoreConditional {
+            rd,
+            addr: reg(rs1),
+            val: reg(rs2),
+        },
+        Instruction::AmoswapW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::Swap),
+        Instruction::AmoaddW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::Add),
+        Instruction::AmoxorW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::Xor),
+        Instruction::AmoandW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::And),
+        Instruction::AmoorW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::Or),
+        Instruction::AmominW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::Min),
+        Instruction::AmomaxW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::Max),
+        Instruction::AmominuW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::Minu),
+        Instruction::AmomaxuW { rd, rs1, rs2, .. } => amo(rd, rs1, rs2, AmoOp::Maxu),
+
+        // Sync. Memory accesses are performed in program order, so fence has nothing to do
+        Instruction::Fence { .. } => Effect::None,
+        Instruction::FenceI => Effect::FenceI,
+
+        // System
+        Instruction::Ecall => Effect::Ecall,
+        Instruction::Ebreak => Effect::Ebreak,
+        Instruction::Mret => Effect::Mret,
+
+        // Control and status registers
+        Instruction::Csrrw { rd, rs1, csr } => csr_op(rd, csr, CsrOp::Write, rs1, reg(rs1)),
+        Instruction::Csrrs { rd, rs1, csr } => csr_op(rd, csr, CsrOp::Set, rs1, reg(rs1)),
+        Instruction::Csrrc { rd, rs1, csr } => csr_op(rd, csr, CsrOp::Clear, rs1, reg(rs1)),
+        Instruction::Csrrwi { rd, uimm, csr } => csr_op(rd, csr, CsrOp::Write, uimm, uimm),
+        Instruction::Csrrsi { rd, uimm, csr } => csr_op(rd, csr, CsrOp::Set, uimm, uimm),
+        Instruction::Csrrci { rd, uimm, csr } => csr_op(rd, csr, CsrOp::Clear, uimm, uimm),
+
+        Instruction::Ill => Effect::Illegal,
+
+        // Floating point
+        _ => {
+            let (effect, flags) = execute_fp(inst, regs, fregs);
+            fflags = flags;
+            effect
+        }
+    };
+    Outcome {
+        effect,
+        next_pc,
+        fflags,
+    }
+}
+
+/// Full 64-bit carry-less product of two words
+fn clmul(a: u32, b: u32) -> u64 {
+    (0..32)
+        .filter(|i| (b >> i) & 1 != 0)
+        .fold(0, |acc, i| acc ^ ((a as u64) << i))
+}
+
+/// Effect and exception flags of an F or D extension instruction
+fn execute_fp(inst: &Instruction, regs: &[u32; 32], fregs: &FpRegFile) -> (Effect, u8) {
+    use FpFmt::{D, S};
+    let reg = |idx: u32| regs[idx as usize];
+    let freg = |idx: u32, fmt: FpFmt| fregs.read(idx, fmt);
+    let addr = |rs1: u32, imm: i32| reg(rs1).wrapping_add(imm as u32);
+    let write = |rd: u32, (val, flags): (u32, u8)| (Effect::WriteReg { rd, val }, flags);
+    let fwrite = |rd: u32, fmt: FpFmt, (val, flags): (u64, u8)| {
+        let val = FpRegFile::boxed(val, fmt);
+        (Effect::WriteFreg { rd, val }, flags)
+    };
+    // Resolves the rounding mode, reserved modes make the instruction illegal
+    let rounded = |rm: u32, op: &dyn Fn(RoundingMode) -> (Effect, u8)| {
+        match RoundingMode::from_rm(rm, fregs.frm()) {
+            Some(rm) => op(rm),
+            None => (Effect::Illegal, 0),
+        }
+    };
+    let fma = |rd, rs1, rs2, rs3, rm, fmt, negate_product, negate_addend| {
+        rounded(rm, &|rm| {
+            let (a, b, c) = (freg(rs1, fmt), freg(rs2, fmt), freg(rs3, fmt));
+            fwrite(rd, fmt, fpu::fma(a, b, c, negate_product, negate_addend, fmt, rm))
+        })
+    };
+    type BinaryOp = fn(u64, u64, FpFmt, RoundingMode) -> (u64, u8);
+    let binary = |rd, rs1, rs2, rm, fmt, op: BinaryOp| {
+        rounded(rm, &|rm| fwrite(rd, fmt, op(freg(rs1, fmt), freg(rs2, fmt), fmt, rm)))
+    };
+    let sign_inject = |rd, rs1, rs2, fmt, op| {
+        fwrite(rd, fmt, (fpu::sign_inject(freg(rs1, fmt), freg(rs2, fmt), fmt, op), 0))
+    };
+    let min_max = |rd, rs1, rs2, fmt, max| {
+        fwrite(rd, fmt, fpu::min_max(freg(rs1, fmt), freg(rs2, fmt), fmt, max))
+    };
+    let compare = |rd, rs1, rs2, fmt, op| {
+        let (result, flags) = fpu::compare(freg(rs1, fmt), freg(rs2, fmt), fmt, op);
+        write(rd, (result as u32, flags))
+    };
+    let to_int = |rd, rs1, rm, fmt, signed| {
+        rounded(rm, &|rm| write(rd, fpu::to_int(freg(rs1, fmt), fmt, signed, rm)))
+    };
+    let from_int = |rd, rs1, rm, fmt, signed| {
+        rounded(rm, &|rm| fwrite(rd, fmt, fpu::from_int(reg(rs1), signed, fmt, rm)))
+    };
+
+    match *inst {
+        // Loads and stores, stores write the raw register bits
+        Instruction::Flw { rd, rs1, imm } => {
+            let width = MemWidth::Word;
+            (Effect::LoadFp { rd, addr: addr(rs1, imm), width }, 0)
+        }
+        Instruction::Fld { rd, rs1, imm } => {
+            let width = MemWidth::Double;
+            (Effect::LoadFp { rd, addr: addr(rs1, imm), width }, 0)
+        }
+        Instruction::Fsw { rs1, rs2, imm } => {
+            let val = fregs.raw(rs2) & 0xFFFF_FFFF;
+            let width = MemWidth::Word;
+            (Effect::Store { addr: addr(rs1, imm), width, val }, 0)
+        }
+        Instruction::Fsd { rs1, rs2, imm } => {
+            let val = fregs.raw(rs2);
+            let width = MemWidth::Double;
+            (Effect::Store { addr: addr(rs1, imm), width, val }, 0)
+        }
+
+        // Fused multiply-add
+        Instruction::FmaddS { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, S, false, false),
+        Instruction::FmsubS { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, S, false, true),
+        Instruction::FnmsubS { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, S, true, false),
+        Instruction::FnmaddS { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, S, true, true),
+        Instruction::FmaddD { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, D, false, false),
+        Instruction::FmsubD { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, D, false, true),
+        Instruction::FnmsubD { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, D, true, false),
+        Instruction::FnmaddD { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, D, true, true),
+
+        // Arithmetic
+        Instruction::FaddS { rd, rs1, rs2, rm } => binary(rd, rs1, rs2, rm, S, fpu::add),
+        Instruction::FsubS { rd, rs1, rs2, rm } => binary(rd, rs1, rs2, rm, S, fpu::sub),
+        Instruction::FmulS { rd, rs1, rs2, rm } => binary(rd, rs1, rs2, rm, S, fpu::mul),
+        Instruction::FdivS { rd, rs1, rs2, rm } => binary(rd, rs1, rs2, rm, S, fpu::div),
+        Instruction::FsqrtS { rd, rs1, rm } => {
+            rounded(rm, &|rm| fwrite(rd, S, fpu::sqrt(freg(rs1, S), S, rm)))
+        }
+        Instruction::FaddD { rd, rs1, rs2, rm } => binary(rd, rs1, rs2, rm, D, fpu::add),
+        Instruction::FsubD { rd, rs1, rs2, rm } => binary(rd, rs1, rs2, rm, D, fpu::sub),
+        Instruction::FmulD { rd, rs1, rs2, rm } => binary(rd, rs1, rs2, rm, D, fpu::mul),
+        Instruction::FdivD { rd, rs1, rs2, rm } => binary(rd, rs1, rs2, rm, D, fpu::div),
+        Instruction::FsqrtD { rd, rs1, rm } => {
+            rounded(rm, &|rm| fwrite(rd, D, fpu::sqrt(freg(rs1, D), D, rm)))
+        }
+
+        // Sign injection and min/max
+        Instruction::FsgnjS { rd, rs1, rs2 } => sign_inject(rd, rs1, rs2, S, SignInject::Copy),
+        Instruction::FsgnjnS { rd, rs1, rs2 } => sign_inject(rd, rs1, rs2, S, SignInject::Negate),
+        Instruction::FsgnjxS { rd, rs1, rs2 } => sign_inject(rd, rs1, rs2, S, SignInject::Xor),
+        Instruction::FminS { rd, rs1, rs2 } => min_max(rd, rs1, rs2, S, false),
+        Instruction::FmaxS { rd, rs1, rs2 } => min_max(rd, rs1, rs2, S, true),
+        Instruction::FsgnjD { rd, rs1, rs2 } => sign_inject(rd, rs1, rs2, D, SignInject::Copy),
+        Instruction::FsgnjnD { rd, rs1, rs2 } => sign_inject(rd, rs1, rs2, D, SignInject::Negate),
+        Instruction::FsgnjxD { rd, rs1, rs2 } => sign_inject(rd, rs1, rs2, D, SignInject::Xor),
+        Instruction::FminD { rd, rs1, rs2 } => min_max(rd, rs1, rs2, D, false),
+        Instruction::FmaxD { rd, rs1, rs2 } => min_max(rd, rs1, rs2, D, true),
+
+        // Compare and classify
+        Instruction::FeqS { rd, rs1, rs2 } => compare(rd, rs1, rs2, S, Compare::Eq),
+        Instruction::FltS { rd, rs1, rs2 } => compare(rd, rs1, rs2, S, Compare::Lt),
+        Instruction::FleS { rd, rs1, rs2 } => compare(rd, rs1, rs2, S, Compare::Le),
+        Instruction::FclassS { rd, rs1 } => write(rd, (fpu::classify(freg(rs1, S), S), 0)),
+        Instruction::FeqD { rd, rs1, rs2 } => compare(rd, rs1, rs2, D, Compare::Eq),
+        Instruction::FltD { rd, rs1, rs2 } => compare(rd, rs1, rs2, D, Compare::Lt),
+        Instruction::FleD { rd, rs1, rs2 } => compare(rd, rs1, rs2, D, Compare::Le),
+        Instruction::FclassD { rd, rs1 } => write(rd, (fpu::classify(freg(rs1, D), D), 0)),
+
+        // Conversions and moves, moves copy raw bits without unboxing
+        Instruction::FcvtWS { rd, rs1, rm } => to_int(rd, rs1, rm, S, true),
+        Instruction::FcvtWuS { rd, rs1, rm } => to_int(rd, rs1, rm, S, false),
+        Instruction::FcvtSW { rd, rs1, rm } => from_int(rd, rs1, rm, S, true),
+        Instruction::FcvtSWu { rd, rs1, rm } => from_int(rd, rs1, rm, S, false),
+        Instruction::FcvtWD { rd, rs1, rm } => to_int(rd, rs1, rm, D, true),
+        Instruction::FcvtWuD { rd, rs1, rm } => to_int(rd, rs1, rm, D, false),
+        Instruction::FcvtDW { rd, rs1, rm } => from_int(rd, rs1, rm, D, true),
+        Instruction::FcvtDWu { rd, rs1, rm } => from_int(rd, rs1, rm, D, false),
+        Instruction::FcvtSD { rd, rs1, rm } => {
+            rounded(rm, &|rm| fwrite(rd, S, fpu::convert(freg(rs1, D), D, S, rm)))
+        }
+        Instruction::FcvtDS { rd, rs1, rm } => {
+            rounded(rm, &|rm| fwrite(rd, D, fpu::convert(freg(rs1, S), S, D, rm)))
+        }
+        Instruction::FmvXW { rd, rs1 } => write(rd, (fregs.raw(rs1) as u32, 0)),
+        Instruction::FmvWX { rd, rs1 } => fwrite(rd, S, (reg(rs1) as u64, 0)),
+
+        _ => unreachable!("{} is not a floating point instruction", inst),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_r(inst: fn(u32, u32, u32) -> Instruction, a: u32, b: u32) -> u32 {
+        let mut regs = [0; 32];
+        regs[1] = a;
+        regs[2] = b;
+        match execute(&inst(3, 1, 2), 0, 4, &regs, &FpRegFile::new()).effect {
+            Effect::WriteReg { rd: 3, val } => val,
+            effect => panic!("unexpected effect {:?}", effect),
+        }
+    }
+
+    #[test]
+    fn multiply_high_halves() {
+        let mulh = |rd, rs1, rs2| Instruction::Mulh { rd, rs1, rs2 };
+        let mulhsu = |rd, rs1, rs2| Instruction::Mulhsu { rd, rs1, rs2 };
+        let mulhu = |rd, rs1, rs2| Instruction::Mulhu { rd, rs1, rs2 };
+        assert_eq!(run_r(mulh, -1i32 as u32, -1i32 as u32), 0);
+        assert_eq!(run_r(mulh, i32::MIN as u32, i32::MIN as u32), 0x4000_0000);
+        assert_eq!(run_r(mulhsu, -1i32 as u32, u32::MAX), u32::MAX);
+        assert_eq!(run_r(mulhu, u32::MAX, u32::MAX), 0xFFFF_FFFE);
+    }
+
+    #[test]
+    fn divide_by_zero_and_overflow() {
+        let div = |rd, rs1, rs2| Instruction::Div { rd, rs1, rs2 };
+        let divu = |rd, rs1, rs2| Instruction::Divu { rd, rs1, rs2 };
+        let rem = |rd, rs1, rs2| Instruction::Rem { rd, rs1, rs2 };
+        let remu = |rd, rs1, rs2| Instruction::Remu { rd, rs1, rs2 };
+        assert_eq!(run_r(div, 7, 0), u32::MAX);
+        assert_eq!(run_r(divu, 7, 0), u32::MAX);
+        assert_eq!(run_r(rem, 7, 0), 7);
+        assert_eq!(run_r(remu, 7, 0), 7);
+        assert_eq!(run_r(div, i32::MIN as u32, -1i32 as u32), i32::MIN as u32);
+        assert_eq!(run_r(rem, i32::MIN as u32, -1i32 as u32), 0);
+        assert_eq!(run_r(div, -7i32 as u32, 2), -3i32 as u32);
+        assert_eq!(run_r(rem, -7i32 as u32, 2), -1i32 as u32);
+    }
+
+    #[test]
+    fn carry_less_multiply() {
+        let clmul = |rd, rs1, rs2| Instruction::Clmul { rd, rs1, rs2 };
+        let clmulh = |rd, rs1, rs2| Instruction::Clmulh { rd, rs1, rs2 };
+        let clmulr = |rd, rs1, rs2| Instruction::Clmulr { rd, rs1, rs2 };
+        for (a, b, low, high, reversed) in [
+            (0x1234_5678, 0x9ABC_DEF0, 0x5CD2_5A80, 0x0886_0E94, 0x110C_1D28),
+            (u32::MAX, u32::MAX, 0x5555_5555, 0x5555_5555, 0xAAAA_AAAA),
+            (0x8000_0001, 0x3, 0x8000_0003, 0x1, 0x3),
+        ] {
+            assert_eq!(run_r(clmul, a, b), low);
+            assert_eq!(run_r(clmulh, a, b), high);
+            assert_eq!(run_r(clmulr, a, b), reversed);
+        }
+    }
+
+    #[test]
+    fn bit_manipulation() {
+        let clz = |rd, rs1, _| Instruction::Clz { rd, rs1 };
+        let ctz = |rd, rs1, _| Instruction::Ctz { rd, rs1 };
+        let cpop = |rd, rs1, _| Instruction::Cpop { rd, rs1 };
+        let orc_b = |rd, rs1, _| Instruction::OrcB { rd, rs1 };
+        let rev8 = |rd, rs1, _| Instruction::Rev8 { rd, rs1 };
+        let sext_b = |rd, rs1, _| Instruction::SextB { rd, rs1 };
+        let min = |rd, rs1, rs2| Instruction::Min { rd, rs1, rs2 };
+        let maxu = |rd, rs1, rs2| Instruction::Maxu { rd, rs1, rs2 };
+        let ror = |rd, rs1, rs2| Instruction::Ror { rd, rs1, rs2 };
+        let sh3add = |rd, rs1, rs2| Instruction::Sh3add { rd, rs1, rs2 };
+        let bext = |rd, rs1, rs2| Instruction::Bext { rd, rs1, rs2 };
+        let binv = |rd, rs1, rs2| Instruction::Binv { rd, rs1, rs2 };
+        assert_eq!(run_r(clz, 0, 0), 32);
+        assert_eq!(run_r(clz, 0x0001_0000, 0), 15);
+        assert_eq!(run_r(ctz, 0, 0), 32);
+        assert_eq!(run_r(cpop, 0xF0F0_0001, 0), 9);
+        assert_eq!(run_r(orc_b, 0x0100_8000, 0), 0xFF00_FF00);
+        assert_eq!(run_r(rev8, 0x1234_5678, 0), 0x7856_3412);
+        assert_eq!(run_r(sext_b, 0x1234_5680, 0), 0xFFFF_FF80);
+        assert_eq!(run_r(min, -1i32 as u32, 1), -1i32 as u32);
+        assert_eq!(run_r(maxu, -1i32 as u32, 1), u32::MAX);
+        assert_eq!(run_r(ror, 0x0000_0001, 33), 0x8000_0000);
+        assert_eq!(run_r(sh3add, 0x10, 0x3), 0x83);
+        assert_eq!(run_r(bext, 0x8000_0000, 63), 1);
+        assert_eq!(run_r(binv, 0x8000_0000, 31), 0);
+    }
+}
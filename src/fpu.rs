@@ -0,0 +1,938 @@
+//! IEEE-754 binary32/binary64 arithmetic for the F and D extensions.
+//!
+//! Values are handled as raw bits so results are exact to the spec, including
+//! all five rounding modes, the accrued exception flags and RISC-V's canonical
+//! NaN. Finite values are unpacked into an integer significand and exponent,
+//! operated on exactly (or with a sticky bit far below the rounding point) and
+//! rounded once when packed.
+
+/// Invalid operation
+pub const FLAG_NV: u8 = 0x10;
+/// Divide by zero
+pub const FLAG_DZ: u8 = 0x08;
+/// Overflow
+pub const FLAG_OF: u8 = 0x04;
+/// Underflow
+pub const FLAG_UF: u8 = 0x02;
+/// Inexact
+pub const FLAG_NX: u8 = 0x01;
+
+/// Rounding mode field value selecting the dynamic rounding mode in `frm`
+pub const RM_DYN: u32 = 0b111;
+
+/// IEEE-754 rounding mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round to nearest, ties to even
+    Rne,
+    /// Round towards zero
+    Rtz,
+    /// Round down, towards negative infinity
+    Rdn,
+    /// Round up, towards positive infinity
+    Rup,
+    /// Round to nearest, ties to max magnitude
+    Rmm,
+}
+
+impl RoundingMode {
+    /// Resolves an instruction's rm field against `frm`, `None` if the mode is reserved
+    pub fn from_rm(rm: u32, frm: u32) -> Option<Self> {
+        let rm = if rm == RM_DYN { frm } else { rm };
+        match rm {
+            0b000 => Some(RoundingMode::Rne),
+            0b001 => Some(RoundingMode::Rtz),
+            0b010 => Some(RoundingMode::Rdn),
+            0b011 => Some(RoundingMode::Rup),
+            0b100 => Some(RoundingMode::Rmm),
+            _ => None,
+        }
+    }
+}
+
+/// Floating point format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FpFmt {
+    /// binary32
+    S,
+    /// binary64
+    D,
+}
+
+impl FpFmt {
+    fn mant_bits(self) -> u32 {
+        match self {
+            FpFmt::S => 23,
+            FpFmt::D => 52,
+        }
+    }
+    fn exp_bits(self) -> u32 {
+        match self {
+            FpFmt::S => 8,
+            FpFmt::D => 11,
+        }
+    }
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits() - 1)) - 1
+    }
+    fn max_exp(self) -> i32 {
+        (1 << self.exp_bits()) - 1
+    }
+    fn sign_bit(self) -> u64 {
+        1 << (self.mant_bits() + self.exp_bits())
+    }
+    fn mant_mask(self) -> u64 {
+        (1 << self.mant_bits()) - 1
+    }
+    /// Quiet NaN with no payload that RISC-V returns from every arithmetic op
+    pub fn canonical_nan(self) -> u64 {
+        match self {
+            FpFmt::S => 0x7FC0_0000,
+            FpFmt::D => 0x7FF8_0000_0000_0000,
+        }
+    }
+    fn infinity(self, sign: bool) -> u64 {
+        self.pack_raw(sign, self.max_exp() as u64, 0)
+    }
+    fn max_finite(self, sign: bool) -> u64 {
+        self.pack_raw(sign, self.max_exp() as u64 - 1, self.mant_mask())
+    }
+    fn zero(self, sign: bool) -> u64 {
+        self.pack_raw(sign, 0, 0)
+    }
+    fn pack_raw(self, sign: bool, exp: u64, mant: u64) -> u64 {
+        (if sign { self.sign_bit() } else { 0 }) | (exp << self.mant_bits()) | mant
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Zero,
+    /// Value is `sig * 2^exp`
+    Finite {
+        exp: i32,
+        sig: u128,
+    },
+    Inf,
+    Nan {
+        signaling: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Unpacked {
+    sign: bool,
+    kind: Kind,
+}
+
+fn unpack(bits: u64, fmt: FpFmt) -> Unpacked {
+    let sign = bits & fmt.sign_bit() != 0;
+    let exp = ((bits >> fmt.mant_bits()) & fmt.max_exp() as u64) as i32;
+    let mant = bits & fmt.mant_mask();
+    let kind = if exp == fmt.max_exp() {
+        if mant == 0 {
+            Kind::Inf
+        } else {
+            Kind::Nan {
+                signaling: mant >> (fmt.mant_bits() - 1) == 0,
+            }
+        }
+    } else if exp == 0 {
+        if mant == 0 {
+            Kind::Zero
+        } else {
+            Kind::Finite {
+                exp: 1 - fmt.bias() - fmt.mant_bits() as i32,
+                sig: mant as u128,
+            }
+        }
+    } else {
+        Kind::Finite {
+            exp: exp - fmt.bias() - fmt.mant_bits() as i32,
+            sig: (mant | (1 << fmt.mant_bits())) as u128,
+        }
+    };
+    Unpacked { sign, kind }
+}
+
+/// Shifts right, ORing any bits shifted out into the LSB
+fn shift_right_jam(sig: u128, shift: u32) -> u128 {
+    if shift == 0 {
+        sig
+    } else if shift >= 128 {
+        (sig != 0) as u128
+    } else {
+        (sig >> shift) | ((sig & ((1 << shift) - 1)) != 0) as u128
+    }
+}
+
+/// Moves the MSB of a nonzero significand to `bit`, returning the adjusted exponent.
+/// Shifting right jams, so callers keep the LSB well below the rounding point.
+fn normalize(sig: u128, exp: i32, bit: u32) -> (u128, i32) {
+    let msb = 127 - sig.leading_zeros();
+    if msb < bit {
+        (sig << (bit - msb), exp - (bit - msb) as i32)
+    } else {
+        (shift_right_jam(sig, msb - bit), exp + (msb - bit) as i32)
+    }
+}
+
+/// Whether to round a truncated magnitude up given the bits below it
+fn round_up(rm: RoundingMode, sign: bool, kept_odd: bool, rem: u128, half: u128) -> bool {
+    match rm {
+        RoundingMode::Rne => rem > half || (rem == half && kept_odd),
+        RoundingMode::Rtz => false,
+        RoundingMode::Rdn => rem != 0 && sign,
+        RoundingMode::Rup => rem != 0 && !sign,
+        RoundingMode::Rmm => rem >= half,
+    }
+}
+
+/// Rounds `sig * 2^exp` to the format. The LSB of `sig` may be a sticky bit.
+fn round_pack(sign: bool, exp: i32, sig: u128, fmt: FpFmt, rm: RoundingMode) -> (u64, u8) {
+    if sig == 0 {
+        return (fmt.zero(sign), 0);
+    }
+    const TOP: u32 = 125;
+    let (sig, exp) = normalize(sig, exp, TOP);
+    let mant_bits = fmt.mant_bits();
+    // Biased exponent of the value if it were normal
+    let biased = exp + TOP as i32 + fmt.bias();
+    let normal_shift = TOP - mant_bits;
+    let shift = if biased >= 1 {
+        normal_shift
+    } else {
+        (normal_shift as i64 + (1 - biased) as i64).min(127) as u32
+    };
+
+    let mut flags = 0;
+    let kept = sig >> shift;
+    let rem = sig & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    let kept = kept + round_up(rm, sign, kept & 1 == 1, rem, half) as u128;
+    if rem != 0 {
+        flags |= FLAG_NX;
+    }
+
+    if biased < 1 {
+        // Tininess is detected after rounding, as if the exponent range were unbounded
+        let n_kept = sig >> normal_shift;
+        let n_rem = sig & ((1 << normal_shift) - 1);
+        let n_kept =
+            n_kept + round_up(rm, sign, n_kept & 1 == 1, n_rem, 1 << (normal_shift - 1)) as u128;
+        let tiny = n_kept < (2 << mant_bits) || biased < 0;
+        if tiny && rem != 0 {
+            flags |= FLAG_UF;
+        }
+        // A carry out of the subnormal mantissa lands in the exponent field as 1
+        return (fmt.pack_raw(sign, 0, 0) + kept as u64, flags);
+    }
+
+    let bits = ((biased as u64 - 1) << mant_bits) + kept as u64;
+    if (bits >> mant_bits) >= fmt.max_exp() as u64 {
+        flags |= FLAG_OF | FLAG_NX;
+        let to_inf = match rm {
+            RoundingMode::Rne | RoundingMode::Rmm => true,
+            RoundingMode::Rtz => false,
+            RoundingMode::Rdn => sign,
+            RoundingMode::Rup => !sign,
+        };
+        let bits = if to_inf {
+            fmt.infinity(sign)
+        } else {
+            fmt.max_finite(sign)
+        };
+        return (bits, flags);
+    }
+    (bits | if sign { fmt.sign_bit() } else { 0 }, flags)
+}
+
+/// Result for ops with a NaN input, raising invalid if any input is signaling
+fn propagate_nan(inputs: &[Unpacked], fmt: FpFmt) -> (u64, u8) {
+    let signaling = inputs
+        .iter()
+        .any(|u| matches!(u.kind, Kind::Nan { signaling: true }));
+    (fmt.canonical_nan(), if signaling { FLAG_NV } else { 0 })
+}
+
+fn is_nan(u: &Unpacked) -> bool {
+    matches!(u.kind, Kind::Nan { .. })
+}
+
+/// Exact sum of two finite or zero values, rounded once
+fn add_finite(
+    a: (bool, i32, u128),
+    b: (bool, i32, u128),
+    fmt: FpFmt,
+    rm: RoundingMode,
+) -> (u64, u8) {
+    let ((sa, ea, siga), (sb, eb, sigb)) = (a, b);
+    if siga == 0 && sigb == 0 {
+        let sign = if sa == sb {
+            sa
+        } else {
+            rm == RoundingMode::Rdn
+        };
+        return (fmt.zero(sign), 0);
+    }
+    if siga == 0 {
+        return round_pack(sb, eb, sigb, fmt, rm);
+    }
+    if sigb == 0 {
+        return round_pack(sa, ea, siga, fmt, rm);
+    }
+    // Leave room for a carry, then align the smaller operand to the larger
+    let (siga, ea) = normalize(siga, ea, 124);
+    let (sigb, eb) = normalize(sigb, eb, 124);
+    let ((sa, ea, siga), (sb, eb, sigb)) = if ea >= eb {
+        ((sa, ea, siga), (sb, eb, sigb))
+    } else {
+        ((sb, eb, sigb), (sa, ea, siga))
+    };
+    let sigb = shift_right_jam(sigb, (ea - eb).min(128) as u32);
+    if sa == sb {
+        round_pack(sa, ea, siga + sigb, fmt, rm)
+    } else if siga >= sigb {
+        if siga == sigb {
+            return (fmt.zero(rm == RoundingMode::Rdn), 0);
+        }
+        round_pack(sa, ea, siga - sigb, fmt, rm)
+    } else {
+        round_pack(sb, ea, sigb - siga, fmt, rm)
+    }
+}
+
+fn finite_parts(u: &Unpacked) -> (bool, i32, u128) {
+    match u.kind {
+        Kind::Finite { exp, sig } => (u.sign, exp, sig),
+        _ => (u.sign, 0, 0),
+    }
+}
+
+pub fn add(a: u64, b: u64, fmt: FpFmt, rm: RoundingMode) -> (u64, u8) {
+    let (ua, ub) = (unpack(a, fmt), unpack(b, fmt));
+    if is_nan(&ua) || is_nan(&ub) {
+        return propagate_nan(&[ua, ub], fmt);
+    }
+    match (ua.kind, ub.kind) {
+        (Kind::Inf, Kind::Inf) if ua.sign != ub.sign => (fmt.canonical_nan(), FLAG_NV),
+        (Kind::Inf, _) => (fmt.infinity(ua.sign), 0),
+        (_, Kind::Inf) => (fmt.infinity(ub.sign), 0),
+        _ => add_finite(finite_parts(&ua), finite_parts(&ub), fmt, rm),
+    }
+}
+
+pub fn sub(a: u64, b: u64, fmt: FpFmt, rm: RoundingMode) -> (u64, u8) {
+    let ub = unpack(b, fmt);
+    if is_nan(&ub) {
+        return add(a, b, fmt, rm);
+    }
+    add(a, b ^ fmt.sign_bit(), fmt, rm)
+}
+
+pub fn mul(a: u64, b: u64, fmt: FpFmt, rm: RoundingMode) -> (u64, u8) {
+    let (ua, ub) = (unpack(a, fmt), unpack(b, fmt));
+    if is_nan(&ua) || is_nan(&ub) {
+        return propagate_nan(&[ua, ub], fmt);
+    }
+    let sign = ua.sign != ub.sign;
+    match (ua.kind, ub.kind) {
+        (Kind::Inf, Kind::Zero) | (Kind::Zero, Kind::Inf) => (fmt.canonical_nan(), FLAG_NV),
+        (Kind::Inf, _) | (_, Kind::Inf) => (fmt.infinity(sign), 0),
+        (Kind::Zero, _) | (_, Kind::Zero) => (fmt.zero(sign), 0),
+        (Kind::Finite { exp: ea, sig: siga }, Kind::Finite { exp: eb, sig: sigb }) => {
+            round_pack(sign, ea + eb, siga * sigb, fmt, rm)
+        }
+        _ => unreachable!("NaNs are handled above"),
+    }
+}
+
+pub fn div(a: u64, b: u64, fmt: FpFmt, rm: RoundingMode) -> (u64, u8) {
+    let (ua, ub) = (unpack(a, fmt), unpack(b, fmt));
+    if is_nan(&ua) || is_nan(&ub) {
+        return propagate_nan(&[ua, ub], fmt);
+    }
+    let sign = ua.sign != ub.sign;
+    match (ua.kind, ub.kind) {
+        (Kind::Inf, Kind::Inf) | (Kind::Zero, Kind::Zero) => (fmt.canonical_nan(), FLAG_NV),
+        (Kind::Inf, _) => (fmt.infinity(sign), 0),
+        (_, Kind::Inf) => (fmt.zero(sign), 0),
+        (Kind::Zero, _) => (fmt.zero(sign), 0),
+        (_, Kind::Zero) => (fmt.infinity(sign), FLAG_DZ),
+        (Kind::Finite { exp: ea, sig: siga }, Kind::Finite { exp: eb, sig: sigb }) => {
+            let (num, ea) = normalize(siga, ea, 126);
+            let q = num / sigb;
+            let sticky = (num % sigb != 0) as u128;
+            round_pack(sign, ea - eb, q | sticky, fmt, rm)
+        }
+        _ => unreachable!("NaNs are handled above"),
+    }
+}
+
+/// Integer square root, returning the root and whether it was inexact
+fn isqrt(n: u128) -> (u128, bool) {
+    let mut root: u128 = 0;
+    let mut rem = n;
+    let mut bit: u128 = 1 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    (root, rem != 0)
+}
+
+pub fn sqrt(a: u64, fmt: FpFmt, rm: RoundingMode) -> (u64, u8) {
+    let ua = unpack(a, fmt);
+    match ua.kind {
+        Kind::Nan { .. } => propagate_nan(&[ua], fmt),
+        Kind::Zero => (a, 0),
+        _ if ua.sign => (fmt.canonical_nan(), FLAG_NV),
+        Kind::Inf => (a, 0),
+        Kind::Finite { exp, sig } => {
+            // Make the exponent even so it halves exactly, with plenty of bits for the root
+            let (sig, exp) = normalize(sig, exp, 124);
+            let (sig, exp) = if exp % 2 != 0 {
+                (sig << 1, exp - 1)
+            } else {
+                (sig, exp)
+            };
+            let (root, inexact) = isqrt(sig);
+            round_pack(false, exp / 2, root | inexact as u128, fmt, rm)
+        }
+    }
+}
+
+/// Computes `(a * b) + c` with a single rounding, negating the product and/or addend
+pub fn fma(
+    a: u64,
+    b: u64,
+    c: u64,
+    negate_product: bool,
+    negate_addend: bool,
+    fmt: FpFmt,
+    rm: RoundingMode,
+) -> (u64, u8) {
+    let (ua, ub, uc) = (unpack(a, fmt), unpack(b, fmt), unpack(c, fmt));
+    let inf_times_zero = matches!(
+        (ua.kind, ub.kind),
+        (Kind::Inf, Kind::Zero) | (Kind::Zero, Kind::Inf)
+    );
+    if is_nan(&ua) || is_nan(&ub) || is_nan(&uc) {
+        let (bits, flags) = propagate_nan(&[ua, ub, uc], fmt);
+        return (bits, flags | if inf_times_zero { FLAG_NV } else { 0 });
+    }
+    if inf_times_zero {
+        return (fmt.canonical_nan(), FLAG_NV);
+    }
+    let product_sign = (ua.sign != ub.sign) != negate_product;
+    let addend_sign = uc.sign != negate_addend;
+    let product_inf = matches!(ua.kind, Kind::Inf) || matches!(ub.kind, Kind::Inf);
+    match (product_inf, uc.kind) {
+        (true, Kind::Inf) if product_sign != addend_sign => (fmt.canonical_nan(), FLAG_NV),
+        (true, _) => (fmt.infinity(product_sign), 0),
+        (false, Kind::Inf) => (fmt.infinity(addend_sign), 0),
+        _ => {
+            let product = match (ua.kind, ub.kind) {
+                (Kind::Finite { exp: ea, sig: siga }, Kind::Finite { exp: eb, sig: sigb }) => {
+                    (product_sign, ea + eb, siga * sigb)
+                }
+                _ => (product_sign, 0, 0),
+            };
+            let (_, ec, sigc) = finite_parts(&uc);
+            add_finite(product, (addend_sign, ec, sigc), fmt, rm)
+        }
+    }
+}
+
+/// Min/max as defined by RISC-V: a single NaN input returns the other operand and -0 < +0
+pub fn min_max(a: u64, b: u64, fmt: FpFmt, max: bool) -> (u64, u8) {
+    let (ua, ub) = (unpack(a, fmt), unpack(b, fmt));
+    let flags = if matches!(ua.kind, Kind::Nan { signaling: true })
+        || matches!(ub.kind, Kind::Nan { signaling: true })
+    {
+        FLAG_NV
+    } else {
+        0
+    };
+    let bits = match (is_nan(&ua), is_nan(&ub)) {
+        (true, true) => fmt.canonical_nan(),
+        (true, false) => b,
+        (false, true) => a,
+        (false, false) => {
+            let a_less = total_less(a, b, fmt);
+            if a_less != max {
+                a
+            } else {
+                b
+            }
+        }
+    };
+    (bits, flags)
+}
+
+/// Ordering of two non-NaN values where -0 is less than +0
+fn total_less(a: u64, b: u64, fmt: FpFmt) -> bool {
+    let sign = fmt.sign_bit();
+    match (a & sign != 0, b & sign != 0) {
+        (true, false) => true,
+        (false, true) => false,
+        (false, false) => a < b,
+        (true, true) => a > b,
+    }
+}
+
+/// Comparison for `feq`, `flt` and `fle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Lt,
+    Le,
+}
+
+pub fn compare(a: u64, b: u64, fmt: FpFmt, op: Compare) -> (bool, u8) {
+    let (ua, ub) = (unpack(a, fmt), unpack(b, fmt));
+    if is_nan(&ua) || is_nan(&ub) {
+        // feq is a quiet comparison, flt and fle signal on any NaN
+        let signaling = matches!(ua.kind, Kind::Nan { signaling: true })
+            || matches!(ub.kind, Kind::Nan { signaling: true });
+        let flags = if op != Compare::Eq || signaling {
+            FLAG_NV
+        } else {
+            0
+        };
+        return (false, flags);
+    }
+    let both_zero = ua.kind == Kind::Zero && ub.kind == Kind::Zero;
+    let eq = a == b || both_zero;
+    let result = match op {
+        Compare::Eq => eq,
+        Compare::Lt => !eq && total_less(a, b, fmt),
+        Compare::Le => eq || total_less(a, b, fmt),
+    };
+    (result, 0)
+}
+
+/// 10-bit mask from `fclass`
+pub fn classify(a: u64, fmt: FpFmt) -> u32 {
+    let ua = unpack(a, fmt);
+    let subnormal = (a >> fmt.mant_bits()) & fmt.max_exp() as u64 == 0;
+    let bit = match (ua.kind, ua.sign) {
+        (Kind::Inf, true) => 0,
+        (Kind::Finite { .. }, true) if !subnormal => 1,
+        (Kind::Finite { .. }, true) => 2,
+        (Kind::Zero, true) => 3,
+        (Kind::Zero, false) => 4,
+        (Kind::Finite { .. }, false) if subnormal => 5,
+        (Kind::Finite { .. }, false) => 6,
+        (Kind::Inf, false) => 7,
+        (Kind::Nan { signaling: true }, _) => 8,
+        (Kind::Nan { signaling: false }, _) => 9,
+    };
+    1 << bit
+}
+
+/// Converts to a 32-bit integer, saturating and raising invalid when out of range
+pub fn to_int(a: u64, fmt: FpFmt, signed: bool, rm: RoundingMode) -> (u32, u8) {
+    let ua = unpack(a, fmt);
+    let (max, min) = if signed {
+        (i32::MAX as u32, i32::MIN as u32)
+    } else {
+        (u32::MAX, 0)
+    };
+    let (sign, exp, sig) = match ua.kind {
+        Kind::Nan { .. } => return (max, FLAG_NV),
+        Kind::Inf => return (if ua.sign { min } else { max }, FLAG_NV),
+        Kind::Zero => return (0, 0),
+        Kind::Finite { exp, sig } => (ua.sign, exp, sig),
+    };
+    // Magnitude rounded to an integer, capped well above the 32-bit range
+    let (mag, inexact) = if exp >= 0 {
+        (if exp > 40 { 1 << 40 } else { sig << exp }, false)
+    } else {
+        let shift = (-exp) as u32;
+        let (kept, rem, half) = if shift >= 128 {
+            (0, sig, u128::MAX)
+        } else {
+            (sig >> shift, sig & ((1 << shift) - 1), 1 << (shift - 1))
+        };
+        let kept = kept + round_up(rm, sign, kept & 1 == 1, rem, half) as u128;
+        (kept, rem != 0)
+    };
+    let in_range = if signed {
+        if sign {
+            mag <= 1 << 31
+        } else {
+            mag < 1 << 31
+        }
+    } else {
+        !sign || mag == 0
+    } && mag <= u32::MAX as u128;
+    if !in_range {
+        return (if sign { min } else { max }, FLAG_NV);
+    }
+    let val = if sign {
+        (mag as u32).wrapping_neg()
+    } else {
+        mag as u32
+    };
+    (val, if inexact { FLAG_NX } else { 0 })
+}
+
+/// Converts a 32-bit integer to floating point
+pub fn from_int(val: u32, signed: bool, fmt: FpFmt, rm: RoundingMode) -> (u64, u8) {
+    let sign = signed && (val as i32) < 0;
+    let mag = if sign {
+        (val as i32).unsigned_abs()
+    } else {
+        val
+    };
+    round_pack(sign, 0, mag as u128, fmt, rm)
+}
+
+/// Converts between formats, rounding when narrowing
+pub fn convert(a: u64, from: FpFmt, to: FpFmt, rm: RoundingMode) -> (u64, u8) {
+    let ua = unpack(a, from);
+    match ua.kind {
+        Kind::Nan { .. } => propagate_nan(&[ua], to),
+        Kind::Inf => (to.infinity(ua.sign), 0),
+        Kind::Zero => (to.zero(ua.sign), 0),
+        Kind::Finite { exp, sig } => round_pack(ua.sign, exp, sig, to, rm),
+    }
+}
+
+/// Sign injection for `fsgnj`, `fsgnjn` and `fsgnjx`, operating on raw bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignInject {
+    Copy,
+    Negate,
+    Xor,
+}
+
+pub fn sign_inject(a: u64, b: u64, fmt: FpFmt, op: SignInject) -> u64 {
+    let sign = fmt.sign_bit();
+    let new_sign = match op {
+        SignInject::Copy => b & sign,
+        SignInject::Negate => !b & sign,
+        SignInject::Xor => (a ^ b) & sign,
+    };
+    (a & !sign) | new_sign
+}
+
+/// Floating point register file, 64 bits wide to hold D values. Single precision
+/// values are NaN-boxed in the upper 32 bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FpRegFile {
+    regs: [u64; 32],
+    /// Dynamic rounding mode
+    frm: u32,
+    /// Accrued exception flags
+    fflags: u8,
+}
+
+impl Default for FpRegFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FpRegFile {
+    pub fn new() -> Self {
+        Self {
+            regs: [0; 32],
+            frm: 0,
+            fflags: 0,
+        }
+    }
+
+    /// Raw 64-bit contents of a register
+    pub fn raw(&self, idx: u32) -> u64 {
+        self.regs[idx as usize]
+    }
+    pub fn set_raw(&mut self, idx: u32, val: u64) {
+        self.regs[idx as usize] = val;
+    }
+
+    /// Reads a register as a value of the given format, unboxing singles.
+    /// A single that is not properly NaN-boxed reads as the canonical NaN.
+    pub fn read(&self, idx: u32, fmt: FpFmt) -> u64 {
+        let val = self.raw(idx);
+        match fmt {
+            FpFmt::D => val,
+            FpFmt::S if val >> 32 == 0xFFFF_FFFF => val & 0xFFFF_FFFF,
+            FpFmt::S => FpFmt::S.canonical_nan(),
+        }
+    }
+
+    /// Register contents after writing a value of the given format
+    pub fn boxed(val: u64, fmt: FpFmt) -> u64 {
+        match fmt {
+            FpFmt::D => val,
+            FpFmt::S => 0xFFFF_FFFF_0000_0000 | (val & 0xFFFF_FFFF),
+        }
+    }
+
+    pub fn frm(&self) -> u32 {
+        self.frm
+    }
+    pub fn set_frm(&mut self, frm: u32) {
+        self.frm = frm & 0x7;
+    }
+    pub fn fflags(&self) -> u8 {
+        self.fflags
+    }
+    pub fn set_fflags(&mut self, fflags: u8) {
+        self.fflags = fflags & 0x1F;
+    }
+    /// Accrues exception flags raised by an instruction
+    pub fn raise(&mut self, flags: u8) {
+        self.fflags |= flags;
+    }
+    /// `fcsr`, the rounding mode and flags together
+    pub fn fcsr(&self) -> u32 {
+        (self.frm << 5) | self.fflags as u32
+    }
+    pub fn set_fcsr(&mut self, fcsr: u32) {
+        self.set_frm(fcsr >> 5);
+        self.set_fflags(fcsr as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// xorshift64, so tests are reproducible without an extra dependency
+    struct Rng(u64);
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        /// Random bits biased towards interesting exponents
+        fn f64_bits(&mut self) -> u64 {
+            let r = self.next();
+            match r % 8 {
+                0 => r & 0x800F_FFFF_FFFF_FFFF, // subnormal or zero
+                1 => (r & 0x8000_0000_0000_0000) | 0x7FF0_0000_0000_0000, // infinity
+                2 => r | 0x7FF0_0000_0000_0001, // NaN
+                _ => r,
+            }
+        }
+        fn f32_bits(&mut self) -> u64 {
+            let r = self.next() & 0xFFFF_FFFF;
+            match r % 8 {
+                0 => r & 0x807F_FFFF,
+                1 => (r & 0x8000_0000) | 0x7F80_0000,
+                2 => r | 0x7F80_0001,
+                _ => r,
+            }
+        }
+    }
+
+    fn host64(bits: u64) -> u64 {
+        let f = f64::from_bits(bits);
+        if f.is_nan() {
+            FpFmt::D.canonical_nan()
+        } else {
+            bits
+        }
+    }
+    fn host32(f: f32) -> u64 {
+        if f.is_nan() {
+            FpFmt::S.canonical_nan()
+        } else {
+            f.to_bits() as u64
+        }
+    }
+
+    #[test]
+    fn matches_host_round_to_nearest_f64() {
+        let mut rng = Rng(0x1234_5678_9ABC_DEF1);
+        let rne = RoundingMode::Rne;
+        for _ in 0..200_000 {
+            let (a, b, c) = (rng.f64_bits(), rng.f64_bits(), rng.f64_bits());
+            let (fa, fb, fc) = (f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+            assert_eq!(add(a, b, FpFmt::D, rne).0, host64((fa + fb).to_bits()));
+            assert_eq!(sub(a, b, FpFmt::D, rne).0, host64((fa - fb).to_bits()));
+            assert_eq!(mul(a, b, FpFmt::D, rne).0, host64((fa * fb).to_bits()));
+            assert_eq!(div(a, b, FpFmt::D, rne).0, host64((fa / fb).to_bits()));
+            assert_eq!(sqrt(a, FpFmt::D, rne).0, host64(fa.sqrt().to_bits()));
+            assert_eq!(
+                fma(a, b, c, false, false, FpFmt::D, rne).0,
+                host64(fa.mul_add(fb, fc).to_bits()),
+                "fma {:016x} {:016x} {:016x}",
+                a,
+                b,
+                c
+            );
+        }
+    }
+
+    #[test]
+    fn matches_host_round_to_nearest_f32() {
+        let mut rng = Rng(0x0FED_CBA9_8765_4321);
+        let rne = RoundingMode::Rne;
+        for _ in 0..200_000 {
+            let (a, b) = (rng.f32_bits(), rng.f32_bits());
+            let (fa, fb) = (f32::from_bits(a as u32), f32::from_bits(b as u32));
+            assert_eq!(add(a, b, FpFmt::S, rne).0, host32(fa + fb));
+            assert_eq!(mul(a, b, FpFmt::S, rne).0, host32(fa * fb));
+            assert_eq!(div(a, b, FpFmt::S, rne).0, host32(fa / fb));
+            assert_eq!(sqrt(a, FpFmt::S, rne).0, host32(fa.sqrt()));
+            assert_eq!(
+                convert(a, FpFmt::S, FpFmt::D, rne).0,
+                host64((fa as f64).to_bits())
+            );
+            let d = rng.f64_bits();
+            assert_eq!(
+                convert(d, FpFmt::D, FpFmt::S, rne).0,
+                host32(f64::from_bits(d) as f32)
+            );
+        }
+    }
+
+    #[test]
+    fn directed_rounding_brackets_exact_product() {
+        // f32 products are exact in f64, so directed results must bracket them within one ulp
+        let mut rng = Rng(0xDEAD_BEEF_CAFE_F00D);
+        for _ in 0..100_000 {
+            let (a, b) = (rng.next() & 0x3FFF_FFFF, rng.next() & 0xBFFF_FFFF);
+            let exact = f32::from_bits(a as u32) as f64 * f32::from_bits(b as u32) as f64;
+            let (down, down_flags) = mul(a, b, FpFmt::S, RoundingMode::Rdn);
+            let (up, _) = mul(a, b, FpFmt::S, RoundingMode::Rup);
+            let (zero, _) = mul(a, b, FpFmt::S, RoundingMode::Rtz);
+            let (down, up, zero) = (
+                f32::from_bits(down as u32) as f64,
+                f32::from_bits(up as u32) as f64,
+                f32::from_bits(zero as u32) as f64,
+            );
+            assert!(down <= exact && exact <= up);
+            assert_eq!(zero, if exact < 0.0 { up } else { down });
+            if down == exact {
+                assert_eq!(up, exact);
+                assert_eq!(down_flags & FLAG_NX, 0);
+            } else {
+                assert_eq!(down_flags & FLAG_NX, FLAG_NX);
+                assert!(
+                    f32::from_bits((down as f32).to_bits() + 1) as f64 == up
+                        || f32::from_bits((down as f32).to_bits() - 1) as f64 == up
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn exception_flags() {
+        let rne = RoundingMode::Rne;
+        let one = 1.0f64.to_bits();
+        let zero = 0.0f64.to_bits();
+        let max = f64::MAX.to_bits();
+        let min_sub = 1u64;
+        assert_eq!(
+            div(one, zero, FpFmt::D, rne),
+            (f64::INFINITY.to_bits(), FLAG_DZ)
+        );
+        assert_eq!(
+            div(zero, zero, FpFmt::D, rne),
+            (FpFmt::D.canonical_nan(), FLAG_NV)
+        );
+        assert_eq!(add(max, max, FpFmt::D, rne).1, FLAG_OF | FLAG_NX);
+        assert_eq!(add(max, max, FpFmt::D, RoundingMode::Rtz).0, max);
+        assert_eq!(
+            mul(min_sub, 0.5f64.to_bits(), FpFmt::D, rne),
+            (0, FLAG_UF | FLAG_NX)
+        );
+        assert_eq!(add(one, 1e-30f64.to_bits(), FpFmt::D, rne), (one, FLAG_NX));
+        // Signaling NaN input raises invalid, quiet NaN does not
+        assert_eq!(add(0x7FF0_0000_0000_0001, one, FpFmt::D, rne).1, FLAG_NV);
+        assert_eq!(add(0x7FF8_0000_0000_0001, one, FpFmt::D, rne).1, 0);
+        // x - x is -0 only when rounding down
+        assert_eq!(sub(one, one, FpFmt::D, rne).0, 0);
+        assert_eq!(
+            sub(one, one, FpFmt::D, RoundingMode::Rdn).0,
+            (-0.0f64).to_bits()
+        );
+    }
+
+    #[test]
+    fn integer_conversions() {
+        let s = |f: f32| f.to_bits() as u64;
+        let rne = RoundingMode::Rne;
+        assert_eq!(to_int(s(2.5), FpFmt::S, true, rne), (2, FLAG_NX));
+        assert_eq!(
+            to_int(s(2.5), FpFmt::S, true, RoundingMode::Rmm),
+            (3, FLAG_NX)
+        );
+        assert_eq!(
+            to_int(s(-2.5), FpFmt::S, true, RoundingMode::Rdn),
+            (-3i32 as u32, FLAG_NX)
+        );
+        assert_eq!(
+            to_int(s(3e9), FpFmt::S, true, rne),
+            (i32::MAX as u32, FLAG_NV)
+        );
+        assert_eq!(to_int(s(3e9), FpFmt::S, false, rne), (3_000_000_000, 0));
+        assert_eq!(to_int(s(-1.0), FpFmt::S, false, rne), (0, FLAG_NV));
+        assert_eq!(to_int(s(-0.4), FpFmt::S, false, rne), (0, FLAG_NX));
+        assert_eq!(
+            to_int(s(f32::NAN), FpFmt::S, false, rne),
+            (u32::MAX, FLAG_NV)
+        );
+        assert_eq!(
+            to_int(s(-2147483648.0), FpFmt::S, true, rne),
+            (i32::MIN as u32, 0)
+        );
+        assert_eq!(from_int(-1i32 as u32, true, FpFmt::S, rne), (s(-1.0), 0));
+        assert_eq!(
+            from_int(u32::MAX, false, FpFmt::S, rne),
+            (s(4294967296.0), FLAG_NX)
+        );
+        assert_eq!(
+            from_int(u32::MAX, false, FpFmt::D, rne),
+            (4294967295.0f64.to_bits(), 0)
+        );
+    }
+
+    #[test]
+    fn min_max_and_compare() {
+        let s = |f: f32| f.to_bits() as u64;
+        let qnan = FpFmt::S.canonical_nan();
+        let snan = 0x7F80_0001;
+        assert_eq!(min_max(s(-0.0), s(0.0), FpFmt::S, false), (s(-0.0), 0));
+        assert_eq!(min_max(s(-0.0), s(0.0), FpFmt::S, true), (s(0.0), 0));
+        assert_eq!(min_max(qnan, s(1.0), FpFmt::S, false), (s(1.0), 0));
+        assert_eq!(min_max(snan, s(1.0), FpFmt::S, true), (s(1.0), FLAG_NV));
+        assert_eq!(min_max(snan, qnan, FpFmt::S, true), (qnan, FLAG_NV));
+        assert_eq!(compare(s(-0.0), s(0.0), FpFmt::S, Compare::Eq), (true, 0));
+        assert_eq!(compare(qnan, s(0.0), FpFmt::S, Compare::Eq), (false, 0));
+        assert_eq!(
+            compare(qnan, s(0.0), FpFmt::S, Compare::Lt),
+            (false, FLAG_NV)
+        );
+        assert_eq!(compare(s(-1.0), s(-0.5), FpFmt::S, Compare::Lt), (true, 0));
+        assert_eq!(compare(s(1.0), s(1.0), FpFmt::S, Compare::Le), (true, 0));
+        assert_eq!(classify(s(-0.0), FpFmt::S), 1 << 3);
+        assert_eq!(classify(1, FpFmt::S), 1 << 5);
+        assert_eq!(classify(snan, FpFmt::S), 1 << 8);
+    }
+
+    #[test]
+    fn nan_boxing() {
+        let mut regs = FpRegFile::new();
+        regs.set_raw(1, FpRegFile::boxed(0x3F80_0000, FpFmt::S));
+        assert_eq!(regs.read(1, FpFmt::S), 0x3F80_0000);
+        regs.set_raw(2, 0x0000_0000_3F80_0000);
+        assert_eq!(regs.read(2, FpFmt::S), FpFmt::S.canonical_nan());
+        assert_eq!(regs.read(1, FpFmt::D), 0xFFFF_FFFF_3F80_0000);
+    }
+}
@@ -0,0 +1,946 @@
+use crate::components::memory::{ElfInfo, Memory, MemoryMap, QueueMem, BLOCK_SIZE};
+use crate::compressed::inst_len;
+use crate::csr::{self, Counters, CsrFile, CsrOp};
+use crate::disasm::Symbols;
+use crate::error::SimError;
+use crate::exec::{MemWrite, Retired};
+use crate::fpu::{self, Compare, FpFmt, FpRegFile, RoundingMode, SignInject};
+use crate::htif::Htif;
+use crate::instructions::{decode_inst, Instruction, MemWidth};
+use crate::spike::CommitWriter;
+use crate::syscall::{SyscallResult, Syscalls};
+use crate::trap::Exception;
+
+/// Initial value of the stack pointer
+pub const STACK_START: u32 = 0x40000000;
+/// Size of the stack below `STACK_START`, the limit of the stack under the trap model
+pub const STACK_SIZE: u32 = 1 << 23;
+/// Return address the program starts with, returning to it ends the simulation
+pub const RETURN_ADDR: u32 = 0x00000000;
+
+/// Functional instruction set simulator, executes one instruction per step with no timing.
+/// Used as the golden reference for the timing model.
+#[derive(Debug)]
+pub struct FunctionalSim {
+    /// Architectural register file, x0 is kept at zero
+    regs: [u32; 32],
+    /// FP register file along with `fcsr`
+    fregs: FpRegFile,
+    csrs: CsrFile,
+    pc: u32,
+    mem: QueueMem,
+    /// Addresses data accesses may touch under the trap model
+    map: MemoryMap,
+    /// Block reserved by the last `lr.w`, cleared by any `sc.w`
+    reservation: Option<u32>,
+    halted: bool,
+    /// Number of instructions retired
+    retired: u64,
+    syscalls: Syscalls,
+    htif: Option<Htif>,
+    /// Status the program exited with, or a0 if it returned to `RETURN_ADDR`
+    exit_code: i32,
+    /// ELF symbols, for the instruction trace
+    symbols: Symbols,
+    /// Spike-format log of retired instructions, for `--trace-file`
+    commit_log: Option<CommitWriter>,
+}
+
+impl FunctionalSim {
+    pub fn new(mem: QueueMem, info: &ElfInfo) -> Self {
+        let mut regs = [0; 32];
+        regs[1] = RETURN_ADDR;
+        regs[2] = STACK_START;
+        Self {
+            regs,
+            fregs: FpRegFile::new(),
+            csrs: CsrFile::new(),
+            pc: info.entry,
+            mem,
+            map: info.map.clone(),
+            reservation: None,
+            halted: false,
+            retired: 0,
+            syscalls: Syscalls::new(info.brk),
+            htif: info.tohost.map(|tohost| Htif::new(tohost, info.fromhost)),
+            exit_code: 0,
+            symbols: info.symbols.clone(),
+            commit_log: None,
+        }
+    }
+
+    pub fn regs(&self) -> &[u32; 32] {
+        &self.regs
+    }
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+    pub fn syscalls(&mut self) -> &mut Syscalls {
+        &mut self.syscalls
+    }
+
+    /// Writes every retired instruction to `log` from now on
+    pub fn set_commit_log(&mut self, log: CommitWriter) {
+        self.commit_log = Some(log);
+    }
+
+    /// Enables the trap model, see `CsrFile::handles_traps`
+    pub fn set_traps(&mut self, enabled: bool) {
+        self.csrs.set_traps(enabled);
+    }
+
+    /// Fetches, decodes and executes the instruction at the current PC
+    pub fn step(&mut self) -> Result<Retired, SimError> {
+        let record = match self.fetch() {
+            Ok((word, len)) => {
+                let inst = decode_inst(word);
+                log::trace!(
+                    "0x{:08x}: 0x{:08x} {}",
+                    self.pc,
+                    word,
+                    inst.disasm(self.pc, Some(&self.symbols))
+                );
+                self.execute(word, &inst, len)
+            }
+            Err((exception, word)) => self.raise(exception, word, Instruction::Ill),
+        }?;
+        if let Some(log) = &mut self.commit_log {
+            log.write(&record).map_err(SimError::CommitLog)?;
+        }
+        Ok(record)
+    }
+
+    /// Runs until the program returns to `RETURN_ADDR`
+    pub fn run(&mut self) -> Result<(), SimError> {
+        while !self.halted {
+            self.step()?;
+        }
+        if let Some(log) = &mut self.commit_log {
+            log.flush().map_err(SimError::CommitLog)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the instruction word at the PC and its length in bytes. On a fault, returns
+    /// the exception along with the part of the word fetched before it.
+    fn fetch(&mut self) -> Result<(u32, u32), (Exception, u32)> {
+        let pc = self.pc;
+        if !pc.is_multiple_of(2) {
+            return Err((Exception::InstructionMisaligned { addr: pc }, 0));
+        }
+        // Fetch 16 bits at a time, the upper half of a 32-bit instruction may be on another page
+        if !self.mem.is_mapped(pc) {
+            return Err((Exception::InstructionFault { addr: pc }, 0));
+        }
+        let low = self.fetch_h(pc);
+        let len = inst_len(low);
+        if len == 2 {
+            return Ok((low as u32, len));
+        }
+        let addr = pc.wrapping_add(2);
+        if !self.mem.is_mapped(addr) {
+            return Err((Exception::InstructionFault { addr }, low as u32));
+        }
+        Ok((low as u32 | (self.fetch_h(addr) as u32) << 16, len))
+    }
+
+    /// Executes a single decoded instruction of `len` bytes and advances the PC. The
+    /// semantics are written out here rather than shared with `exec`, which the timing core
+    /// runs, so that co-simulation checks one implementation against another. Only the FP
+    /// arithmetic itself comes from `fpu`.
+    pub fn execute(
+        &mut self,
+        word: u32,
+        inst: &Instruction,
+        len: u32,
+    ) -> Result<Retired, SimError> {
+        let pc = self.pc;
+        let mut record = Retired::new(pc, word, *inst);
+        let next_pc = match self.perform(inst, word, len, &mut record) {
+            Ok(next_pc) => next_pc,
+            Err(Stop::Trap(exception)) => return self.raise(exception, word, *inst),
+            Err(Stop::Error(e)) => return Err(e),
+        };
+        if let Some(store) = record.mem_write {
+            if let Some(code) = self.htif.and_then(|htif| htif.check(&store, &mut self.mem)) {
+                self.exit_code = code;
+                self.halted = true;
+            }
+        }
+        self.pc = next_pc;
+        self.retired += 1;
+        if self.pc == RETURN_ADDR {
+            log::info!("Program returned after {} instructions", self.retired);
+            self.exit_code = self.regs[10] as i32;
+            self.halted = true;
+        }
+        Ok(record)
+    }
+
+    /// Applies an instruction to the architectural state, filling in its record, and
+    /// returns the next PC. Nothing is changed if it raises an exception.
+    fn perform(
+        &mut self,
+        inst: &Instruction,
+        word: u32,
+        len: u32,
+        record: &mut Retired,
+    ) -> Result<u32, Stop> {
+        use Instruction::*;
+        let pc = self.pc;
+        let x = self.regs;
+        let next = pc.wrapping_add(len);
+        let ea = |rs1: u32, imm: i32| x[rs1 as usize].wrapping_add(imm as u32);
+        let taken = |cond: bool, imm: i32| if cond { pc.wrapping_add(imm as u32) } else { next };
+        let signed = |idx: u32| x[idx as usize] as i32;
+
+        if let Some((rd, val)) = int_op(inst, pc, &x) {
+            record.reg_write = self.set_reg(rd, val);
+            return Ok(next);
+        }
+        match *inst {
+            Beq { rs1, rs2, imm } => return Ok(taken(x[rs1 as usize] == x[rs2 as usize], imm)),
+            Bne { rs1, rs2, imm } => return Ok(taken(x[rs1 as usize] != x[rs2 as usize], imm)),
+            Blt { rs1, rs2, imm } => return Ok(taken(signed(rs1) < signed(rs2), imm)),
+            Bge { rs1, rs2, imm } => return Ok(taken(signed(rs1) >= signed(rs2), imm)),
+            Bltu { rs1, rs2, imm } => return Ok(taken(x[rs1 as usize] < x[rs2 as usize], imm)),
+            Bgeu { rs1, rs2, imm } => return Ok(taken(x[rs1 as usize] >= x[rs2 as usize], imm)),
+            Jal { rd, imm } => {
+                record.reg_write = self.set_reg(rd, next);
+                return Ok(pc.wrapping_add(imm as u32));
+            }
+            Jalr { rd, rs1, imm } => {
+                // The target is taken before rd is written, rd may be rs1
+                let target = ea(rs1, imm) & !1;
+                record.reg_write = self.set_reg(rd, next);
+                return Ok(target);
+            }
+
+            Lb { rd, rs1, imm } => self.load(record, rd, ea(rs1, imm), 1, true)?,
+            Lh { rd, rs1, imm } => self.load(record, rd, ea(rs1, imm), 2, true)?,
+            Lw { rd, rs1, imm } => self.load(record, rd, ea(rs1, imm), 4, true)?,
+            Lbu { rd, rs1, imm } => self.load(record, rd, ea(rs1, imm), 1, false)?,
+            Lhu { rd, rs1, imm } => self.load(record, rd, ea(rs1, imm), 2, false)?,
+            Sb { rs1, rs2, imm } => self.store(record, ea(rs1, imm), 1, x[rs2 as usize] as u64)?,
+            Sh { rs1, rs2, imm } => self.store(record, ea(rs1, imm), 2, x[rs2 as usize] as u64)?,
+            Sw { rs1, rs2, imm } => self.store(record, ea(rs1, imm), 4, x[rs2 as usize] as u64)?,
+            Flw { rd, rs1, imm } => {
+                let addr = self.checked(ea(rs1, imm), 4, false)?;
+                let val = FpRegFile::boxed(self.read(addr, 4), FpFmt::S);
+                record.freg_write = self.set_freg(rd, val);
+                record.mem_read = Some(addr);
+            }
+            Fld { rd, rs1, imm } => {
+                let addr = self.checked(ea(rs1, imm), 8, false)?;
+                let val = self.read(addr, 8);
+                record.freg_write = self.set_freg(rd, val);
+                record.mem_read = Some(addr);
+            }
+            // Stores write the raw register bits
+            Fsw { rs1, rs2, imm } => {
+                let val = self.fregs.raw(rs2) & 0xFFFF_FFFF;
+                self.store(record, ea(rs1, imm), 4, val)?
+            }
+            Fsd { rs1, rs2, imm } => {
+                let val = self.fregs.raw(rs2);
+                self.store(record, ea(rs1, imm), 8, val)?
+            }
+
+            // Reservations cover the block the address is in
+            LrW { rd, rs1, .. } => {
+                let addr = self.checked(x[rs1 as usize], 4, false)?;
+                self.reservation = Some(addr / BLOCK_SIZE);
+                let val = self.read(addr, 4) as u32;
+                record.reg_write = self.set_reg(rd, val);
+                record.mem_read = Some(addr);
+            }
+            ScW { rd, rs1, rs2, .. } => {
+                let addr = self.checked(x[rs1 as usize], 4, true)?;
+                let success = self.reservation.take() == Some(addr / BLOCK_SIZE);
+                if success {
+                    self.store(record, addr, 4, x[rs2 as usize] as u64)?;
+                }
+                record.reg_write = self.set_reg(rd, if success { 0 } else { 1 });
+            }
+            AmoswapW { rd, rs1, rs2, .. } => {
+                self.amo(record, rd, x[rs1 as usize], |_| x[rs2 as usize])?
+            }
+            AmoaddW { rd, rs1, rs2, .. } => {
+                self.amo(record, rd, x[rs1 as usize], |old| old.wrapping_add(x[rs2 as usize]))?
+            }
+            AmoxorW { rd, rs1, rs2, .. } => {
+                self.amo(record, rd, x[rs1 as usize], |old| old ^ x[rs2 as usize])?
+            }
+            AmoandW { rd, rs1, rs2, .. } => {
+                self.amo(record, rd, x[rs1 as usize], |old| old & x[rs2 as usize])?
+            }
+            AmoorW { rd, rs1, rs2, .. } => {
+                self.amo(record, rd, x[rs1 as usize], |old| old | x[rs2 as usize])?
+            }
+            AmominW { rd, rs1, rs2, .. } => self.amo(record, rd, x[rs1 as usize], |old| {
+                if (old as i32) < signed(rs2) { old } else { x[rs2 as usize] }
+            })?,
+            AmomaxW { rd, rs1, rs2, .. } => self.amo(record, rd, x[rs1 as usize], |old| {
+                if (old as i32) > signed(rs2) { old } else { x[rs2 as usize] }
+            })?,
+            AmominuW { rd, rs1, rs2, .. } => {
+                self.amo(record, rd, x[rs1 as usize], |old| old.min(x[rs2 as usize]))?
+            }
+            AmomaxuW { rd, rs1, rs2, .. } => {
+                self.amo(record, rd, x[rs1 as usize], |old| old.max(x[rs2 as usize]))?
+            }
+
+            // Accesses are made one at a time in program order, so fence has nothing to do
+            Fence { .. } => {}
+            FenceI => self.mem.fence_i(),
+            Ecall if self.csrs.handles_traps() => return Err(Exception::Ecall.into()),
+            Ecall => match self.syscalls.call(pc, &self.regs, &mut self.mem, self.retired)? {
+                SyscallResult::Return(val) => record.reg_write = self.set_reg(10, val),
+                SyscallResult::Exit(status) => {
+                    let code = self.htif.map_or(status, |htif| htif.exit_code(status));
+                    log::info!("Program exited with {}", code);
+                    self.exit_code = code;
+                    self.halted = true;
+                }
+            },
+            Ebreak => return Err(Exception::Breakpoint.into()),
+            Mret => return Ok(self.csrs.mret()),
+
+            // csrrs and csrrc with x0 or a zero immediate only read
+            Csrrw { rd, rs1, csr } => {
+                self.csr(record, word, rd, csr, CsrOp::Write, x[rs1 as usize])?
+            }
+            Csrrs { rd, rs1, csr } => {
+                let op = if rs1 == 0 { CsrOp::Read } else { CsrOp::Set };
+                self.csr(record, word, rd, csr, op, x[rs1 as usize])?
+            }
+            Csrrc { rd, rs1, csr } => {
+                let op = if rs1 == 0 { CsrOp::Read } else { CsrOp::Clear };
+                self.csr(record, word, rd, csr, op, x[rs1 as usize])?
+            }
+            Csrrwi { rd, uimm, csr } => self.csr(record, word, rd, csr, CsrOp::Write, uimm)?,
+            Csrrsi { rd, uimm, csr } => {
+                let op = if uimm == 0 { CsrOp::Read } else { CsrOp::Set };
+                self.csr(record, word, rd, csr, op, uimm)?
+            }
+            Csrrci { rd, uimm, csr } => {
+                let op = if uimm == 0 { CsrOp::Read } else { CsrOp::Clear };
+                self.csr(record, word, rd, csr, op, uimm)?
+            }
+
+            Ill => return Err(Exception::Illegal { word }.into()),
+            _ => self.perform_fp(inst, word, record)?,
+        }
+        Ok(next)
+    }
+
+    /// Loads `size` bytes into rd, sign or zero extending them
+    fn load(
+        &mut self,
+        record: &mut Retired,
+        rd: u32,
+        addr: u32,
+        size: u32,
+        signed: bool,
+    ) -> Result<(), Exception> {
+        let addr = self.checked(addr, size, false)?;
+        let raw = self.read(addr, size) as u32;
+        let shift = 32 - 8 * size;
+        let val = if signed {
+            ((raw << shift) as i32 >> shift) as u32
+        } else {
+            raw
+        };
+        record.reg_write = self.set_reg(rd, val);
+        record.mem_read = Some(addr);
+        Ok(())
+    }
+
+    /// Stores the low `size` bytes of `val`, recording all of it as the core does
+    fn store(
+        &mut self,
+        record: &mut Retired,
+        addr: u32,
+        size: u32,
+        val: u64,
+    ) -> Result<(), Exception> {
+        let addr = self.checked(addr, size, true)?;
+        self.write(addr, size, val);
+        let width = match size {
+            1 => MemWidth::Byte,
+            2 => MemWidth::Half,
+            4 => MemWidth::Word,
+            _ => MemWidth::Double,
+        };
+        record.mem_write = Some(MemWrite { addr, width, val });
+        Ok(())
+    }
+
+    /// Replaces the word at `addr` with `op` applied to it, the old value going to rd
+    fn amo(
+        &mut self,
+        record: &mut Retired,
+        rd: u32,
+        addr: u32,
+        op: impl Fn(u32) -> u32,
+    ) -> Result<(), Exception> {
+        let addr = self.checked(addr, 4, true)?;
+        let old = self.read(addr, 4) as u32;
+        self.store(record, addr, 4, op(old) as u64)?;
+        record.reg_write = self.set_reg(rd, old);
+        record.mem_read = Some(addr);
+        Ok(())
+    }
+
+    /// Checks a data access is naturally aligned and, under the trap model, mapped. Stores,
+    /// SCs and AMOs raise the store/AMO exceptions, everything else the load ones.
+    fn checked(&self, addr: u32, size: u32, store: bool) -> Result<u32, Exception> {
+        let mapped = !self.csrs.handles_traps() || self.map.contains(addr, size);
+        match (addr % size, mapped, store) {
+            (0, true, _) => Ok(addr),
+            (0, false, false) => Err(Exception::LoadFault { addr }),
+            (0, false, true) => Err(Exception::StoreFault { addr }),
+            (_, _, false) => Err(Exception::LoadMisaligned { addr, size }),
+            (_, _, true) => Err(Exception::StoreMisaligned { addr, size }),
+        }
+    }
+
+    /// Reads and writes a CSR, the old value going to rd. Unknown CSRs and writes to
+    /// read-only ones are illegal.
+    fn csr(
+        &mut self,
+        record: &mut Retired,
+        word: u32,
+        rd: u32,
+        csr: u32,
+        op: CsrOp,
+        val: u32,
+    ) -> Result<(), Exception> {
+        // Executing one instruction per step, so a cycle is an instruction
+        let counters = Counters {
+            cycle: self.retired,
+            instret: self.retired,
+        };
+        let access = self
+            .csrs
+            .access(csr, op, val, &mut self.fregs, counters)
+            .ok_or(Exception::Illegal { word })?;
+        record.reg_write = self.set_reg(rd, access.old);
+        record.csr_write = access.written.map(|new| (csr, new));
+        Ok(())
+    }
+
+    /// Performs an F or D extension instruction other than a load or store, accruing the
+    /// exception flags it raises
+    fn perform_fp(
+        &mut self,
+        inst: &Instruction,
+        word: u32,
+        record: &mut Retired,
+    ) -> Result<(), Exception> {
+        use FpFmt::{D, S};
+        use Instruction::*;
+        /// Register an FP instruction writes, and the value
+        enum Dest {
+            X(u32, u32),
+            F(u32, u64),
+        }
+        let fregs = &self.fregs;
+        let x = |idx: u32| self.regs[idx as usize];
+        let f = |idx: u32, fmt: FpFmt| fregs.read(idx, fmt);
+        // Resolves the rounding mode, the reserved ones are illegal
+        let illegal = Exception::Illegal { word };
+        let rm = |rm: u32| RoundingMode::from_rm(rm, fregs.frm()).ok_or(illegal);
+        let to_f = |rd: u32, fmt: FpFmt, (val, flags): (u64, u8)| {
+            (Dest::F(rd, FpRegFile::boxed(val, fmt)), flags)
+        };
+        let to_x = |rd: u32, (val, flags): (u32, u8)| (Dest::X(rd, val), flags);
+        let fma = |rd, rs1, rs2, rs3, m, fmt, negate_product, negate_addend| {
+            let (a, b, c) = (f(rs1, fmt), f(rs2, fmt), f(rs3, fmt));
+            let result = fpu::fma(a, b, c, negate_product, negate_addend, fmt, rm(m)?);
+            Ok::<_, Exception>(to_f(rd, fmt, result))
+        };
+        let compare = |rd, rs1, rs2, fmt, op| {
+            let (result, flags) = fpu::compare(f(rs1, fmt), f(rs2, fmt), fmt, op);
+            (Dest::X(rd, result as u32), flags)
+        };
+        let (dest, flags) = match *inst {
+            FmaddS { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, S, false, false)?,
+            FmsubS { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, S, false, true)?,
+            FnmsubS { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, S, true, false)?,
+            FnmaddS { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, S, true, true)?,
+            FmaddD { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, D, false, false)?,
+            FmsubD { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, D, false, true)?,
+            FnmsubD { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, D, true, false)?,
+            FnmaddD { rd, rs1, rs2, rs3, rm } => fma(rd, rs1, rs2, rs3, rm, D, true, true)?,
+
+            FaddS { rd, rs1, rs2, rm: m } => to_f(rd, S, fpu::add(f(rs1, S), f(rs2, S), S, rm(m)?)),
+            FsubS { rd, rs1, rs2, rm: m } => to_f(rd, S, fpu::sub(f(rs1, S), f(rs2, S), S, rm(m)?)),
+            FmulS { rd, rs1, rs2, rm: m } => to_f(rd, S, fpu::mul(f(rs1, S), f(rs2, S), S, rm(m)?)),
+            FdivS { rd, rs1, rs2, rm: m } => to_f(rd, S, fpu::div(f(rs1, S), f(rs2, S), S, rm(m)?)),
+            FsqrtS { rd, rs1, rm: m } => to_f(rd, S, fpu::sqrt(f(rs1, S), S, rm(m)?)),
+            FaddD { rd, rs1, rs2, rm: m } => to_f(rd, D, fpu::add(f(rs1, D), f(rs2, D), D, rm(m)?)),
+            FsubD { rd, rs1, rs2, rm: m } => to_f(rd, D, fpu::sub(f(rs1, D), f(rs2, D), D, rm(m)?)),
+            FmulD { rd, rs1, rs2, rm: m } => to_f(rd, D, fpu::mul(f(rs1, D), f(rs2, D), D, rm(m)?)),
+            FdivD { rd, rs1, rs2, rm: m } => to_f(rd, D, fpu::div(f(rs1, D), f(rs2, D), D, rm(m)?)),
+            FsqrtD { rd, rs1, rm: m } => to_f(rd, D, fpu::sqrt(f(rs1, D), D, rm(m)?)),
+
+            FsgnjS { rd, rs1, rs2 } => {
+                to_f(rd, S, (fpu::sign_inject(f(rs1, S), f(rs2, S), S, SignInject::Copy), 0))
+            }
+            FsgnjnS { rd, rs1, rs2 } => {
+                to_f(rd, S, (fpu::sign_inject(f(rs1, S), f(rs2, S), S, SignInject::Negate), 0))
+            }
+            FsgnjxS { rd, rs1, rs2 } => {
+                to_f(rd, S, (fpu::sign_inject(f(rs1, S), f(rs2, S), S, SignInject::Xor), 0))
+            }
+            FsgnjD { rd, rs1, rs2 } => {
+                to_f(rd, D, (fpu::sign_inject(f(rs1, D), f(rs2, D), D, SignInject::Copy), 0))
+            }
+            FsgnjnD { rd, rs1, rs2 } => {
+                to_f(rd, D, (fpu::sign_inject(f(rs1, D), f(rs2, D), D, SignInject::Negate), 0))
+            }
+            FsgnjxD { rd, rs1, rs2 } => {
+                to_f(rd, D, (fpu::sign_inject(f(rs1, D), f(rs2, D), D, SignInject::Xor), 0))
+            }
+            FminS { rd, rs1, rs2 } => to_f(rd, S, fpu::min_max(f(rs1, S), f(rs2, S), S, false)),
+            FmaxS { rd, rs1, rs2 } => to_f(rd, S, fpu::min_max(f(rs1, S), f(rs2, S), S, true)),
+            FminD { rd, rs1, rs2 } => to_f(rd, D, fpu::min_max(f(rs1, D), f(rs2, D), D, false)),
+            FmaxD { rd, rs1, rs2 } => to_f(rd, D, fpu::min_max(f(rs1, D), f(rs2, D), D, true)),
+
+            FeqS { rd, rs1, rs2 } => compare(rd, rs1, rs2, S, Compare::Eq),
+            FltS { rd, rs1, rs2 } => compare(rd, rs1, rs2, S, Compare::Lt),
+            FleS { rd, rs1, rs2 } => compare(rd, rs1, rs2, S, Compare::Le),
+            FeqD { rd, rs1, rs2 } => compare(rd, rs1, rs2, D, Compare::Eq),
+            FltD { rd, rs1, rs2 } => compare(rd, rs1, rs2, D, Compare::Lt),
+            FleD { rd, rs1, rs2 } => compare(rd, rs1, rs2, D, Compare::Le),
+            FclassS { rd, rs1 } => (Dest::X(rd, fpu::classify(f(rs1, S), S)), 0),
+            FclassD { rd, rs1 } => (Dest::X(rd, fpu::classify(f(rs1, D), D)), 0),
+
+            FcvtWS { rd, rs1, rm: m } => to_x(rd, fpu::to_int(f(rs1, S), S, true, rm(m)?)),
+            FcvtWuS { rd, rs1, rm: m } => to_x(rd, fpu::to_int(f(rs1, S), S, false, rm(m)?)),
+            FcvtWD { rd, rs1, rm: m } => to_x(rd, fpu::to_int(f(rs1, D), D, true, rm(m)?)),
+            FcvtWuD { rd, rs1, rm: m } => to_x(rd, fpu::to_int(f(rs1, D), D, false, rm(m)?)),
+            FcvtSW { rd, rs1, rm: m } => to_f(rd, S, fpu::from_int(x(rs1), true, S, rm(m)?)),
+            FcvtSWu { rd, rs1, rm: m } => to_f(rd, S, fpu::from_int(x(rs1), false, S, rm(m)?)),
+            FcvtDW { rd, rs1, rm: m } => to_f(rd, D, fpu::from_int(x(rs1), true, D, rm(m)?)),
+            FcvtDWu { rd, rs1, rm: m } => to_f(rd, D, fpu::from_int(x(rs1), false, D, rm(m)?)),
+            FcvtSD { rd, rs1, rm: m } => to_f(rd, S, fpu::convert(f(rs1, D), D, S, rm(m)?)),
+            FcvtDS { rd, rs1, rm: m } => to_f(rd, D, fpu::convert(f(rs1, S), S, D, rm(m)?)),
+            // Moves copy the raw bits, without unboxing
+            FmvXW { rd, rs1 } => (Dest::X(rd, fregs.raw(rs1) as u32), 0),
+            FmvWX { rd, rs1 } => to_f(rd, S, (x(rs1) as u64, 0)),
+
+            _ => unreachable!("{} is not a floating point instruction", inst),
+        };
+        match dest {
+            Dest::X(rd, val) => record.reg_write = self.set_reg(rd, val),
+            Dest::F(rd, val) => record.freg_write = self.set_freg(rd, val),
+        }
+        if flags != 0 {
+            self.fregs.raise(flags);
+            record.csr_write = Some((csr::FFLAGS, self.fregs.fflags() as u32));
+        }
+        Ok(())
+    }
+
+    /// Takes a trap to the handler for an exception raised by the instruction at the PC,
+    /// or stops the simulation without the trap model. The instruction does not retire.
+    /// A handler that cannot be fetched would trap to itself forever, so that stops too.
+    fn raise(
+        &mut self,
+        exception: Exception,
+        word: u32,
+        inst: Instruction,
+    ) -> Result<Retired, SimError> {
+        let pc = self.pc;
+        let unfetchable_handler = matches!(exception, Exception::InstructionFault { .. })
+            && pc == self.csrs.trap_vector();
+        if !self.csrs.handles_traps() || unfetchable_handler {
+            return Err(exception.into_error(pc, word));
+        }
+        log::debug!("0x{:08x}: {:?} trapped", pc, exception);
+        self.pc = self.csrs.trap(pc, exception.cause(), exception.tval(pc));
+        Ok(Retired {
+            trap: Some(exception),
+            ..Retired::new(pc, word, inst)
+        })
+    }
+
+    /// Overwrites a register outside of execution, used by co-simulation to adopt
+    /// values that legitimately differ between models
+    pub fn override_reg(&mut self, idx: u32, val: u32) {
+        self.set_reg(idx, val);
+    }
+
+    /// Writes a register, returning the write if it was not to x0
+    fn set_reg(&mut self, idx: u32, val: u32) -> Option<(u32, u32)> {
+        if idx != 0 {
+            self.regs[idx as usize] = val;
+            Some((idx, val))
+        } else {
+            None
+        }
+    }
+
+    fn set_freg(&mut self, idx: u32, val: u64) -> Option<(u32, u64)> {
+        self.fregs.set_raw(idx, val);
+        Some((idx, val))
+    }
+
+    /// Reads `size` little-endian bytes
+    fn read(&mut self, addr: u32, size: u32) -> u64 {
+        (0..size).rev().fold(0, |val, i| {
+            val << 8 | self.mem.backdoor_read(addr.wrapping_add(i)) as u64
+        })
+    }
+    /// Writes the low `size` bytes of `val`, little-endian
+    fn write(&mut self, addr: u32, size: u32, val: u64) {
+        for i in 0..size {
+            self.mem.backdoor_write(addr.wrapping_add(i), (val >> (8 * i)) as u8);
+        }
+    }
+    /// Reads a halfword as instruction fetch sees it, stores are only seen after a `fence.i`
+    fn fetch_h(&mut self, addr: u32) -> u16 {
+        u16::from_le_bytes([
+            self.mem.backdoor_fetch(addr),
+            self.mem.backdoor_fetch(addr.wrapping_add(1)),
+        ])
+    }
+}
+
+/// Why an instruction stopped short of retiring
+enum Stop {
+    /// Exception taken as a trap, or stopping the simulation without a handler
+    Trap(Exception),
+    /// Error ending the simulation, such as an unsupported syscall
+    Error(SimError),
+}
+
+impl From<Exception> for Stop {
+    fn from(exception: Exception) -> Self {
+        Stop::Trap(exception)
+    }
+}
+
+impl From<SimError> for Stop {
+    fn from(e: SimError) -> Self {
+        Stop::Error(e)
+    }
+}
+
+/// Register and value written by an instruction that only computes on integer registers,
+/// or `None` for any other instruction
+fn int_op(inst: &Instruction, pc: u32, x: &[u32; 32]) -> Option<(u32, u32)> {
+    use Instruction::*;
+    let u = |idx: u32| x[idx as usize];
+    let s = |idx: u32| x[idx as usize] as i32;
+    // Register shift amounts and bit indices only use the low five bits
+    let sh = |idx: u32| x[idx as usize] & 31;
+    let result = match *inst {
+        Lui { rd, imm } => (rd, imm as u32),
+        Auipc { rd, imm } => (rd, pc.wrapping_add(imm as u32)),
+        Addi { rd, rs1, imm } => (rd, s(rs1).wrapping_add(imm) as u32),
+        Slti { rd, rs1, imm } => (rd, u32::from(s(rs1) < imm)),
+        Sltiu { rd, rs1, imm } => (rd, u32::from(u(rs1) < imm as u32)),
+        Xori { rd, rs1, imm } => (rd, (s(rs1) ^ imm) as u32),
+        Ori { rd, rs1, imm } => (rd, (s(rs1) | imm) as u32),
+        Andi { rd, rs1, imm } => (rd, (s(rs1) & imm) as u32),
+        Slli { rd, rs1, shamt } => (rd, u(rs1) << shamt),
+        Srli { rd, rs1, shamt } => (rd, u(rs1) >> shamt),
+        Srai { rd, rs1, shamt } => (rd, (s(rs1) >> shamt) as u32),
+        Add { rd, rs1, rs2 } => (rd, s(rs1).wrapping_add(s(rs2)) as u32),
+        Sub { rd, rs1, rs2 } => (rd, s(rs1).wrapping_sub(s(rs2)) as u32),
+        Sll { rd, rs1, rs2 } => (rd, u(rs1) << sh(rs2)),
+        Srl { rd, rs1, rs2 } => (rd, u(rs1) >> sh(rs2)),
+        Sra { rd, rs1, rs2 } => (rd, (s(rs1) >> sh(rs2)) as u32),
+        Slt { rd, rs1, rs2 } => (rd, u32::from(s(rs1) < s(rs2))),
+        Sltu { rd, rs1, rs2 } => (rd, u32::from(u(rs1) < u(rs2))),
+        Xor { rd, rs1, rs2 } => (rd, u(rs1) ^ u(rs2)),
+        Or { rd, rs1, rs2 } => (rd, u(rs1) | u(rs2)),
+        And { rd, rs1, rs2 } => (rd, u(rs1) & u(rs2)),
+
+        // The full product fits in 64 bits whatever the signedness of the operands
+        Mul { rd, rs1, rs2 } => (rd, (s(rs1) as i64 * s(rs2) as i64) as u32),
+        Mulh { rd, rs1, rs2 } => (rd, ((s(rs1) as i64 * s(rs2) as i64) >> 32) as u32),
+        Mulhsu { rd, rs1, rs2 } => (rd, ((s(rs1) as i64 * u(rs2) as i64) >> 32) as u32),
+        Mulhu { rd, rs1, rs2 } => (rd, ((u(rs1) as u64 * u(rs2) as u64) >> 32) as u32),
+        Div { rd, rs1, rs2 } => match (s(rs1), s(rs2)) {
+            (_, 0) => (rd, u32::MAX),
+            (i32::MIN, -1) => (rd, i32::MIN as u32),
+            (a, b) => (rd, (a / b) as u32),
+        },
+        Divu { rd, rs1, rs2 } => match u(rs2) {
+            0 => (rd, u32::MAX),
+            b => (rd, u(rs1) / b),
+        },
+        Rem { rd, rs1, rs2 } => match (s(rs1), s(rs2)) {
+            (a, 0) => (rd, a as u32),
+            (i32::MIN, -1) => (rd, 0),
+            (a, b) => (rd, (a % b) as u32),
+        },
+        Remu { rd, rs1, rs2 } => match u(rs2) {
+            0 => (rd, u(rs1)),
+            b => (rd, u(rs1) % b),
+        },
+
+        Sh1add { rd, rs1, rs2 } => (rd, u(rs1).wrapping_mul(2).wrapping_add(u(rs2))),
+        Sh2add { rd, rs1, rs2 } => (rd, u(rs1).wrapping_mul(4).wrapping_add(u(rs2))),
+        Sh3add { rd, rs1, rs2 } => (rd, u(rs1).wrapping_mul(8).wrapping_add(u(rs2))),
+        Andn { rd, rs1, rs2 } => (rd, u(rs1) & !u(rs2)),
+        Orn { rd, rs1, rs2 } => (rd, u(rs1) | !u(rs2)),
+        Xnor { rd, rs1, rs2 } => (rd, u(rs1) ^ !u(rs2)),
+        Clz { rd, rs1 } => {
+            (rd, (0..32).take_while(|i| u(rs1) & (1 << (31 - i)) == 0).count() as u32)
+        }
+        Ctz { rd, rs1 } => (rd, (0..32).take_while(|i| u(rs1) & (1 << i) == 0).count() as u32),
+        Cpop { rd, rs1 } => (rd, (0..32).filter(|i| u(rs1) & (1 << i) != 0).count() as u32),
+        Max { rd, rs1, rs2 } => (rd, if s(rs1) > s(rs2) { u(rs1) } else { u(rs2) }),
+        Maxu { rd, rs1, rs2 } => (rd, if u(rs1) > u(rs2) { u(rs1) } else { u(rs2) }),
+        Min { rd, rs1, rs2 } => (rd, if s(rs1) < s(rs2) { u(rs1) } else { u(rs2) }),
+        Minu { rd, rs1, rs2 } => (rd, if u(rs1) < u(rs2) { u(rs1) } else { u(rs2) }),
+        SextB { rd, rs1 } => (rd, ((u(rs1) << 24) as i32 >> 24) as u32),
+        SextH { rd, rs1 } => (rd, ((u(rs1) << 16) as i32 >> 16) as u32),
+        ZextH { rd, rs1 } => (rd, u(rs1) & 0xFFFF),
+        Rol { rd, rs1, rs2 } => (rd, u(rs1).rotate_left(sh(rs2))),
+        Ror { rd, rs1, rs2 } => (rd, u(rs1).rotate_right(sh(rs2))),
+        Rori { rd, rs1, shamt } => (rd, u(rs1).rotate_right(shamt)),
+        OrcB { rd, rs1 } => {
+            let bytes = (0..4).filter(|i| (u(rs1) >> (8 * i)) & 0xFF != 0);
+            (rd, bytes.fold(0, |acc, i| acc | 0xFF << (8 * i)))
+        }
+        Rev8 { rd, rs1 } => (rd, u32::from_be_bytes(u(rs1).to_le_bytes())),
+        Bclr { rd, rs1, rs2 } => (rd, u(rs1) & !(1 << sh(rs2))),
+        Bclri { rd, rs1, shamt } => (rd, u(rs1) & !(1 << shamt)),
+        Bext { rd, rs1, rs2 } => (rd, u32::from(u(rs1) & (1 << sh(rs2)) != 0)),
+        Bexti { rd, rs1, shamt } => (rd, u32::from(u(rs1) & (1 << shamt) != 0)),
+        Binv { rd, rs1, rs2 } => (rd, u(rs1) ^ (1 << sh(rs2))),
+        Binvi { rd, rs1, shamt } => (rd, u(rs1) ^ (1 << shamt)),
+        Bset { rd, rs1, rs2 } => (rd, u(rs1) | (1 << sh(rs2))),
+        Bseti { rd, rs1, shamt } => (rd, u(rs1) | (1 << shamt)),
+        Clmul { rd, rs1, rs2 } => (rd, carryless_product(u(rs1), u(rs2)) as u32),
+        Clmulh { rd, rs1, rs2 } => (rd, (carryless_product(u(rs1), u(rs2)) >> 32) as u32),
+        Clmulr { rd, rs1, rs2 } => (rd, (carryless_product(u(rs1), u(rs2)) >> 31) as u32),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// 64-bit carry-less product, shifting `a` up past each bit of `b` in turn
+fn carryless_product(a: u32, mut b: u32) -> u64 {
+    let (mut a, mut product) = (a as u64, 0);
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a <<= 1;
+        b >>= 1;
+    }
+    product
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::exec::{self, Effect};
+    use proptest::prelude::*;
+
+    /// Functional model of `src` assembled at 0x1000
+    fn program(src: &str) -> FunctionalSim {
+        let (mem, info) = QueueMem::with_program(0x1000, &assemble(src, 0x1000).unwrap());
+        FunctionalSim::new(mem, &info)
+    }
+
+    #[test]
+    fn runs_programs_to_completion() {
+        let mut sim = program("
+                li a0, 0
+                li t0, 10
+            loop:
+                add a0, a0, t0
+                addi t0, t0, -1
+                bnez t0, loop
+                jal t1, skip
+                li a0, -1           # jumped over
+            skip:
+                lui t2, 0xFFFFF
+                srai t2, t2, 4
+                sltu t3, zero, t2
+                ret                 # to RETURN_ADDR with the sum in a0
+        ");
+        sim.run().unwrap();
+        assert_eq!(sim.exit_code(), 55);
+        assert_eq!(sim.regs[6], 0x1000 + 6 * 4);
+        assert_eq!(sim.regs[7], 0xFFFF_FF00);
+        assert_eq!(sim.regs[28], 1);
+        assert_eq!(sim.retired(), 2 + 3 * 10 + 5);
+        assert_eq!(sim.pc, RETURN_ADDR);
+    }
+
+    #[test]
+    fn loads_extend_and_stores_write_their_width() {
+        let mut sim = program("
+                li t0, 0x2000
+                li t1, 0x80FF7F01
+                sw t1, 0(t0)
+                lb a0, 3(t0)
+                lbu a1, 3(t0)
+                lh a2, 2(t0)
+                lhu a3, 2(t0)
+                sb zero, 1(t0)
+                sh t1, 6(t0)
+                lw a4, 0(t0)
+                lw a5, 4(t0)
+                li a0, 0
+                ret
+        ");
+        // li, li (lui and addi), sw
+        for _ in 0..4 {
+            sim.step().unwrap();
+        }
+        let record = sim.step().unwrap();
+        assert_eq!(record.mem_read, Some(0x2003));
+        assert_eq!(record.reg_write, Some((10, 0xFFFF_FF80)));
+        sim.run().unwrap();
+        assert_eq!(&sim.regs[11..16], [0x80, 0xFFFF_80FF, 0x80FF, 0x80FF_0001, 0x7F01_0000]);
+    }
+
+    #[test]
+    fn ecalls_are_serviced_and_unknown_ones_stop_the_run() {
+        let mut sim = program("
+                li a0, 0
+                li a7, 214          # brk
+                ecall
+                mv s0, a0
+                li a0, 7
+                li a7, 93           # exit
+                ecall
+        ");
+        sim.run().unwrap();
+        assert_eq!(sim.exit_code(), 7);
+        assert_eq!(sim.regs[8], 0x1000 + 7 * 4);
+
+        let mut sim = program("
+                li a7, 56           # openat
+                ecall
+        ");
+        sim.step().unwrap();
+        assert!(matches!(
+            sim.step(),
+            Err(SimError::UnsupportedSyscall { pc: 0x1004, num: 56 })
+        ));
+    }
+
+    /// Traps on load, store and AMO access faults and an ecall, summing the causes in s1
+    const TRAPPING: &str = "
+            la t0, handler
+            csrw mtvec, t0
+            li t1, 0x20000000
+            lw a0, 0(t1)            # load access fault
+            sw a0, 0(t1)            # store access fault
+            amoadd.w a0, a0, (t1)   # store/AMO access fault
+            sw t1, -4(sp)           # the stack is mapped
+            lw a1, -4(sp)
+            ecall
+            mv a0, s1
+            ret
+        handler:
+            addi s0, s0, 1
+            csrr t5, mcause
+            add s1, s1, t5
+            csrr s2, mtval
+            csrr t5, mepc
+            addi t5, t5, 4
+            csrw mepc, t5
+            mret
+    ";
+
+    #[test]
+    fn access_faults_and_ecall_trap_under_the_trap_model() {
+        let mut sim = program(TRAPPING);
+        sim.set_traps(true);
+        for _ in 0..4 {
+            sim.step().unwrap();
+        }
+        let record = sim.step().unwrap();
+        assert_eq!(record.trap, Some(Exception::LoadFault { addr: 0x2000_0000 }));
+        assert_eq!((sim.csrs.mcause(), sim.csrs.mtval()), (5, 0x2000_0000));
+        sim.run().unwrap();
+        assert_eq!(sim.exit_code(), 5 + 7 + 7 + 11);
+        assert_eq!(sim.regs[8], 4);
+        assert_eq!(sim.regs[11], 0x2000_0000);
+        assert_eq!(sim.regs[18], 0);
+
+        // Without it memory is sparse
+        let mut sim = program("
+                li t1, 0x20000000
+                sw t1, 0(t1)
+                lw a0, 0(t1)
+                ret
+        ");
+        sim.run().unwrap();
+        assert_eq!(sim.exit_code(), 0x2000_0000);
+    }
+
+    #[test]
+    fn exceptions_trap_before_a_handler_is_installed() {
+        let mut sim = program(".word 0xFFFFFFFF");
+        sim.set_traps(true);
+        let record = sim.step().unwrap();
+        assert_eq!(record.trap, Some(Exception::Illegal { word: 0xFFFF_FFFF }));
+        assert_eq!((sim.pc, sim.csrs.mepc(), sim.csrs.mcause()), (0, 0x1000, 2));
+        // Nothing can be fetched at address 0, so the handler would fault forever
+        assert!(matches!(
+            sim.step(),
+            Err(SimError::AddressFault { pc: 0, addr: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn fetch_faults_report_the_address_and_fetched_bits() {
+        // c.nop, then the low half of an addi whose upper half is past the end of the program
+        let mut sim = program(".word 0x00130001");
+        sim.step().unwrap();
+        match sim.step() {
+            Err(SimError::AddressFault { pc, addr, word }) => {
+                assert_eq!((pc, addr, word), (0x1002, 0x1004, 0x0013));
+            }
+            other => panic!("expected an address fault, got {:?}", other),
+        }
+
+        let mut sim = program("
+                li t0, 0x3000
+                jr t0
+        ");
+        match sim.run() {
+            Err(SimError::AddressFault { pc, addr, word }) => {
+                assert_eq!((pc, addr, word), (0x3000, 0x3000, 0));
+            }
+            other => panic!("expected an address fault, got {:?}", other),
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20_000))]
+
+        #[test]
+        fn int_ops_agree_with_exec(
+            word in any::<u32>(),
+            opcode in prop::sample::select(vec![0b0010011, 0b0110011, 0b0010111, 0b0110111]),
+            mut regs in any::<[u32; 32]>(),
+        ) {
+            let inst = decode_inst(word & !0x7F | opcode);
+            regs[0] = 0;
+            let outcome = exec::execute(&inst, 0x1000, 4, &regs, &FpRegFile::new());
+            let expected = match outcome.effect {
+                Effect::WriteReg { rd, val } => Some((rd, val)),
+                _ => None,
+            };
+            prop_assert_eq!(int_op(&inst, 0x1000, &regs), expected, "{}", inst);
+        }
+    }
+}
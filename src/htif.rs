@@ -0,0 +1,118 @@
+use crate::components::memory::Memory;
+use crate::exec::MemWrite;
+
+/// Host-target interface used by the riscv-tests suite. The program signals
+/// completion by storing `(code << 1) | 1` to the `tohost` symbol, where a code
+/// of zero is a pass and anything else is the number of the failing test.
+#[derive(Debug, Clone, Copy)]
+pub struct Htif {
+    tohost: u32,
+    fromhost: Option<u32>,
+}
+
+impl Htif {
+    pub fn new(tohost: u32, fromhost: Option<u32>) -> Self {
+        Self { tohost, fromhost }
+    }
+
+    /// Checks a retired store for a write to `tohost`. Returns the exit code the
+    /// program signalled, reading the whole word back from memory so it does not
+    /// matter which part of it the store wrote.
+    ///
+    /// Other commands are not serviced. They are acknowledged the way a host does once it
+    /// has handled one, by clearing `tohost` and writing 1 to `fromhost`, so a program
+    /// waiting for the reply carries on.
+    pub fn check(&self, store: &MemWrite, mem: &mut impl Memory) -> Option<i32> {
+        let end = store.addr.wrapping_add(store.width.bytes());
+        if store.addr >= self.tohost.wrapping_add(4) || end <= self.tohost {
+            return None;
+        }
+        let val = read_word(mem, self.tohost);
+        if val & 1 == 0 {
+            if val != 0 {
+                log::warn!("Unsupported HTIF command 0x{:08x} written to tohost", val);
+                write_word(mem, self.tohost, 0);
+                if let Some(fromhost) = self.fromhost {
+                    write_word(mem, fromhost, 1);
+                }
+            }
+            return None;
+        }
+        let code = self.exit_code(val as i32);
+        if code == 0 {
+            log::info!("HTIF: test passed");
+        } else {
+            log::info!("HTIF: test {} failed", code);
+        }
+        Some(code)
+    }
+
+    /// Exit code for a status the program exited with through the `exit` syscall. Under
+    /// syscall emulation riscv-tests pass the value they would have written to `tohost`,
+    /// so it is decoded the same way and both paths end with the failing test number.
+    pub fn exit_code(&self, status: i32) -> i32 {
+        if status & 1 == 1 {
+            (status as u32 >> 1) as i32
+        } else {
+            status
+        }
+    }
+}
+
+fn read_word(mem: &mut impl Memory, addr: u32) -> u32 {
+    u32::from_le_bytes([0, 1, 2, 3].map(|i| mem.backdoor_read(addr.wrapping_add(i))))
+}
+
+fn write_word(mem: &mut impl Memory, addr: u32, val: u32) {
+    for (i, b) in val.to_le_bytes().into_iter().enumerate() {
+        mem.backdoor_write(addr.wrapping_add(i as u32), b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::memory::QueueMem;
+    use crate::instructions::MemWidth;
+
+    const TOHOST: u32 = 0x1000;
+    const FROMHOST: u32 = 0x1040;
+
+    /// Stores `val` to `addr` in `mem` and has `htif` check it
+    fn store(htif: &Htif, mem: &mut QueueMem, addr: u32, width: MemWidth, val: u32) -> Option<i32> {
+        for i in 0..width.bytes() {
+            mem.backdoor_write(addr + i, (val >> (8 * i)) as u8);
+        }
+        htif.check(&MemWrite { addr, width, val: val.into() }, mem)
+    }
+
+    #[test]
+    fn tohost_signals_pass_or_the_failing_test() {
+        let (mut mem, _) = QueueMem::with_program(TOHOST, &[0; 32]);
+        let htif = Htif::new(TOHOST, Some(FROMHOST));
+        assert_eq!(store(&htif, &mut mem, TOHOST, MemWidth::Word, 1), Some(0));
+        assert_eq!(store(&htif, &mut mem, TOHOST, MemWidth::Word, (3 << 1) | 1), Some(3));
+        // The word is read back whole, here the byte stored completes a failure of test 0x101
+        mem.backdoor_write(TOHOST + 1, 2);
+        assert_eq!(store(&htif, &mut mem, TOHOST, MemWidth::Byte, 3), Some(0x101));
+        // Stores next to tohost are not checked
+        assert_eq!(store(&htif, &mut mem, TOHOST + 4, MemWidth::Word, 1), None);
+        assert_eq!(store(&htif, &mut mem, TOHOST - 4, MemWidth::Word, 1), None);
+    }
+
+    #[test]
+    fn other_commands_are_acknowledged_through_fromhost() {
+        let (mut mem, _) = QueueMem::with_program(TOHOST, &[0; 32]);
+        let htif = Htif::new(TOHOST, Some(FROMHOST));
+        assert_eq!(store(&htif, &mut mem, TOHOST, MemWidth::Word, 0x2000), None);
+        assert_eq!(read_word(&mut mem, TOHOST), 0);
+        assert_eq!(read_word(&mut mem, FROMHOST), 1);
+    }
+
+    #[test]
+    fn exit_syscalls_use_the_tohost_encoding() {
+        let htif = Htif::new(TOHOST, None);
+        assert_eq!(htif.exit_code(0), 0);
+        assert_eq!(htif.exit_code((5 << 1) | 1), 5);
+    }
+}
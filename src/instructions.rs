@@ -1,18 +1,34 @@
+use crate::compressed::{expand_compressed, inst_len};
+
+/// ABI names of the integer registers
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// ABI names of the FP registers
+pub const FREG_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
 /// All instructions in an enum that are easy to use. Essentially decode.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     // Loads
-    Lb { rd: u32, rs1: u32, imm: u32 },
-    Lh { rd: u32, rs1: u32, imm: u32 },
-    Lw { rd: u32, rs1: u32, imm: u32 },
+    Lb { rd: u32, rs1: u32, imm: i32 },
+    Lh { rd: u32, rs1: u32, imm: i32 },
+    Lw { rd: u32, rs1: u32, imm: i32 },
 
-    Lbu { rd: u32, rs1: u32, imm: u32 },
-    Lhu { rd: u32, rs1: u32, imm: u32 },
+    Lbu { rd: u32, rs1: u32, imm: i32 },
+    Lhu { rd: u32, rs1: u32, imm: i32 },
 
     // Stores
-    Sb { rs1: u32, rs2: u32, imm: u32 },
-    Sh { rs1: u32, rs2: u32, imm: u32 },
-    Sw { rs1: u32, rs2: u32, imm: u32 },
+    Sb { rs1: u32, rs2: u32, imm: i32 },
+    Sh { rs1: u32, rs2: u32, imm: i32 },
+    Sw { rs1: u32, rs2: u32, imm: i32 },
 
     // Shifts
     Sll { rd: u32, rs1: u32, rs2: u32 },
@@ -24,45 +40,576 @@ pub enum Instruction {
 
     // Arithmetic
     Add { rd: u32, rs1: u32, rs2: u32 },
-    Addi { rd: u32, rs1: u32, imm: u32 },
+    Addi { rd: u32, rs1: u32, imm: i32 },
     Sub { rd: u32, rs1: u32, rs2: u32 },
-    Subi { rd: u32, rs1: u32, imm: u32 },
-    Lui { rd: u32, imm: u32 },
-    Auipc { rd: u32, imm: u32 },
+    Lui { rd: u32, imm: i32 },
+    Auipc { rd: u32, imm: i32 },
 
     // Logical
     Xor { rd: u32, rs1: u32, rs2: u32 },
-    Xori { rd: u32, rs1: u32, imm: u32 },
+    Xori { rd: u32, rs1: u32, imm: i32 },
     Or { rd: u32, rs1: u32, rs2: u32 },
-    Ori { rd: u32, rs1: u32, imm: u32 },
+    Ori { rd: u32, rs1: u32, imm: i32 },
     And { rd: u32, rs1: u32, rs2: u32 },
-    Andi { rd: u32, rs1: u32, imm: u32 },
+    Andi { rd: u32, rs1: u32, imm: i32 },
 
     // Compare
     Slt { rd: u32, rs1: u32, rs2: u32 },
-    Slti { rd: u32, rs1: u32, imm: u32 },
+    Slti { rd: u32, rs1: u32, imm: i32 },
     Sltu { rd: u32, rs1: u32, rs2: u32 },
-    Sltiu { rd: u32, rs1: u32, imm: u32 },
+    Sltiu { rd: u32, rs1: u32, imm: i32 },
 
     // Branches
-    Beq { rs1: u32, rs2: u32, imm: u32 },
-    Bne { rs1: u32, rs2: u32, imm: u32 },
-    Blt { rs1: u32, rs2: u32, imm: u32 },
-    Bge { rs1: u32, rs2: u32, imm: u32 },
-    Bltu { rs1: u32, rs2: u32, imm: u32 },
-    Bgeu { rs1: u32, rs2: u32, imm: u32 },
+    Beq { rs1: u32, rs2: u32, imm: i32 },
+    Bne { rs1: u32, rs2: u32, imm: i32 },
+    Blt { rs1: u32, rs2: u32, imm: i32 },
+    Bge { rs1: u32, rs2: u32, imm: i32 },
+    Bltu { rs1: u32, rs2: u32, imm: i32 },
+    Bgeu { rs1: u32, rs2: u32, imm: i32 },
 
     // Jumps
-    Jal { rd: u32, imm: u32 },
-    Jalr { rd: u32, rs1: u32, imm: u32 },
+    Jal { rd: u32, imm: i32 },
+    Jalr { rd: u32, rs1: u32, imm: i32 },
+
+    // Multiply (M extension)
+    Mul { rd: u32, rs1: u32, rs2: u32 },
+    Mulh { rd: u32, rs1: u32, rs2: u32 },
+    Mulhsu { rd: u32, rs1: u32, rs2: u32 },
+    Mulhu { rd: u32, rs1: u32, rs2: u32 },
+
+    // Divide (M extension)
+    Div { rd: u32, rs1: u32, rs2: u32 },
+    Divu { rd: u32, rs1: u32, rs2: u32 },
+    Rem { rd: u32, rs1: u32, rs2: u32 },
+    Remu { rd: u32, rs1: u32, rs2: u32 },
+
+    // Address generation (Zba)
+    Sh1add { rd: u32, rs1: u32, rs2: u32 },
+    Sh2add { rd: u32, rs1: u32, rs2: u32 },
+    Sh3add { rd: u32, rs1: u32, rs2: u32 },
+
+    // Basic bit manipulation (Zbb)
+    Andn { rd: u32, rs1: u32, rs2: u32 },
+    Orn { rd: u32, rs1: u32, rs2: u32 },
+    Xnor { rd: u32, rs1: u32, rs2: u32 },
+    Clz { rd: u32, rs1: u32 },
+    Ctz { rd: u32, rs1: u32 },
+    Cpop { rd: u32, rs1: u32 },
+    Max { rd: u32, rs1: u32, rs2: u32 },
+    Maxu { rd: u32, rs1: u32, rs2: u32 },
+    Min { rd: u32, rs1: u32, rs2: u32 },
+    Minu { rd: u32, rs1: u32, rs2: u32 },
+    SextB { rd: u32, rs1: u32 },
+    SextH { rd: u32, rs1: u32 },
+    ZextH { rd: u32, rs1: u32 },
+    Rol { rd: u32, rs1: u32, rs2: u32 },
+    Ror { rd: u32, rs1: u32, rs2: u32 },
+    Rori { rd: u32, rs1: u32, shamt: u32 },
+    OrcB { rd: u32, rs1: u32 },
+    Rev8 { rd: u32, rs1: u32 },
+
+    // Single-bit operations (Zbs)
+    Bclr { rd: u32, rs1: u32, rs2: u32 },
+    Bclri { rd: u32, rs1: u32, shamt: u32 },
+    Bext { rd: u32, rs1: u32, rs2: u32 },
+    Bexti { rd: u32, rs1: u32, shamt: u32 },
+    Binv { rd: u32, rs1: u32, rs2: u32 },
+    Binvi { rd: u32, rs1: u32, shamt: u32 },
+    Bset { rd: u32, rs1: u32, rs2: u32 },
+    Bseti { rd: u32, rs1: u32, shamt: u32 },
+
+    // Carry-less multiplication (Zbc)
+    Clmul { rd: u32, rs1: u32, rs2: u32 },
+    Clmulh { rd: u32, rs1: u32, rs2: u32 },
+    Clmulr { rd: u32, rs1: u32, rs2: u32 },
+
+    // Atomics (A extension), aq/rl are the acquire and release ordering bits
+    LrW { rd: u32, rs1: u32, aq: bool, rl: bool },
+    ScW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmoswapW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmoaddW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmoxorW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmoandW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmoorW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmominW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmomaxW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmominuW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
+    AmomaxuW { rd: u32, rs1: u32, rs2: u32, aq: bool, rl: bool },
 
-    // // Sync
-    // Fence,
-    // FenceI,
+    // Floating point loads and stores (F and D extensions), rd/rs2 are FP registers
+    Flw { rd: u32, rs1: u32, imm: i32 },
+    Fld { rd: u32, rs1: u32, imm: i32 },
+    Fsw { rs1: u32, rs2: u32, imm: i32 },
+    Fsd { rs1: u32, rs2: u32, imm: i32 },
+
+    // Fused multiply-add, rm is the raw rounding mode field
+    FmaddS { rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+    FmsubS { rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+    FnmsubS { rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+    FnmaddS { rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+    FmaddD { rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+    FmsubD { rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+    FnmsubD { rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+    FnmaddD { rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+
+    // Floating point arithmetic
+    FaddS { rd: u32, rs1: u32, rs2: u32, rm: u32 },
+    FsubS { rd: u32, rs1: u32, rs2: u32, rm: u32 },
+    FmulS { rd: u32, rs1: u32, rs2: u32, rm: u32 },
+    FdivS { rd: u32, rs1: u32, rs2: u32, rm: u32 },
+    FsqrtS { rd: u32, rs1: u32, rm: u32 },
+    FaddD { rd: u32, rs1: u32, rs2: u32, rm: u32 },
+    FsubD { rd: u32, rs1: u32, rs2: u32, rm: u32 },
+    FmulD { rd: u32, rs1: u32, rs2: u32, rm: u32 },
+    FdivD { rd: u32, rs1: u32, rs2: u32, rm: u32 },
+    FsqrtD { rd: u32, rs1: u32, rm: u32 },
+
+    // Floating point sign injection and min/max
+    FsgnjS { rd: u32, rs1: u32, rs2: u32 },
+    FsgnjnS { rd: u32, rs1: u32, rs2: u32 },
+    FsgnjxS { rd: u32, rs1: u32, rs2: u32 },
+    FminS { rd: u32, rs1: u32, rs2: u32 },
+    FmaxS { rd: u32, rs1: u32, rs2: u32 },
+    FsgnjD { rd: u32, rs1: u32, rs2: u32 },
+    FsgnjnD { rd: u32, rs1: u32, rs2: u32 },
+    FsgnjxD { rd: u32, rs1: u32, rs2: u32 },
+    FminD { rd: u32, rs1: u32, rs2: u32 },
+    FmaxD { rd: u32, rs1: u32, rs2: u32 },
+
+    // Floating point compare and classify, rd is an integer register
+    FeqS { rd: u32, rs1: u32, rs2: u32 },
+    FltS { rd: u32, rs1: u32, rs2: u32 },
+    FleS { rd: u32, rs1: u32, rs2: u32 },
+    FclassS { rd: u32, rs1: u32 },
+    FeqD { rd: u32, rs1: u32, rs2: u32 },
+    FltD { rd: u32, rs1: u32, rs2: u32 },
+    FleD { rd: u32, rs1: u32, rs2: u32 },
+    FclassD { rd: u32, rs1: u32 },
+
+    // Floating point conversions and moves
+    FcvtWS { rd: u32, rs1: u32, rm: u32 },
+    FcvtWuS { rd: u32, rs1: u32, rm: u32 },
+    FcvtSW { rd: u32, rs1: u32, rm: u32 },
+    FcvtSWu { rd: u32, rs1: u32, rm: u32 },
+    FcvtWD { rd: u32, rs1: u32, rm: u32 },
+    FcvtWuD { rd: u32, rs1: u32, rm: u32 },
+    FcvtDW { rd: u32, rs1: u32, rm: u32 },
+    FcvtDWu { rd: u32, rs1: u32, rm: u32 },
+    FcvtSD { rd: u32, rs1: u32, rm: u32 },
+    FcvtDS { rd: u32, rs1: u32, rm: u32 },
+    FmvXW { rd: u32, rs1: u32 },
+    FmvWX { rd: u32, rs1: u32 },
+
+    // Sync
+    /// `pred`/`succ` are the IORW sets ordered before and after, `fm` is 0b1000 for `fence.tso`
+    Fence { pred: u32, succ: u32, fm: u32 },
+    FenceI,
+
+    // System
+    Ecall,
+    Ebreak,
+    /// Return from an M-mode trap handler
+    Mret,
+
+    // Control and status registers
+    Csrrw { rd: u32, rs1: u32, csr: u32 },
+    Csrrs { rd: u32, rs1: u32, csr: u32 },
+    Csrrc { rd: u32, rs1: u32, csr: u32 },
+    Csrrwi { rd: u32, uimm: u32, csr: u32 },
+    Csrrsi { rd: u32, uimm: u32, csr: u32 },
+    Csrrci { rd: u32, uimm: u32, csr: u32 },
 
     // Illegal instruction
     Ill,
 }
+/// Functional unit an instruction executes on, which sets its latency in the timing model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuClass {
+    /// Single cycle integer ops, branches, jumps and address generation
+    Alu,
+    Mul,
+    Div,
+    /// Bit counts, byte reversal and `orc.b`, the rest of Zba/Zbb/Zbs is single cycle ALU work
+    Bitmanip,
+    /// Carry-less multiply
+    Clmul,
+    /// FP add/sub, compares, conversions, sign injection and moves
+    FpAdd,
+    /// FP multiply and fused multiply-add
+    FpMul,
+    /// FP divide and square root
+    FpDiv,
+    /// CSR accesses, fences and `mret`, which serialize: they wait for all older
+    /// instructions to complete and nothing younger issues until they do
+    System,
+}
+
+/// Architectural register an instruction reads or writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg {
+    /// Integer register, never x0
+    X(u32),
+    /// FP register
+    F(u32),
+}
+
+/// How an instruction accesses memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemKind {
+    /// Includes `lr.w`
+    Load,
+    /// Includes `sc.w`
+    Store,
+    /// Atomic read-modify-write
+    Amo,
+}
+
+/// Width of a memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemWidth {
+    Byte,
+    Half,
+    Word,
+    /// Only used by FP loads and stores
+    Double,
+}
+
+impl MemWidth {
+    pub fn bytes(self) -> u32 {
+        match self {
+            MemWidth::Byte => 1,
+            MemWidth::Half => 2,
+            MemWidth::Word => 4,
+            MemWidth::Double => 8,
+        }
+    }
+
+    /// Zero or sign extends the low `bytes()` bytes of a raw loaded value
+    pub fn extend(self, raw: u32, signed: bool) -> u32 {
+        match (self, signed) {
+            (MemWidth::Byte, true) => raw as u8 as i8 as u32,
+            (MemWidth::Byte, false) => raw as u8 as u32,
+            (MemWidth::Half, true) => raw as u16 as i16 as u32,
+            (MemWidth::Half, false) => raw as u16 as u32,
+            (MemWidth::Word | MemWidth::Double, _) => raw,
+        }
+    }
+}
+
+/// Memory access made by a load, store or atomic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemOp {
+    pub kind: MemKind,
+    pub width: MemWidth,
+    /// Loaded value is sign extended
+    pub signed: bool,
+}
+
+/// What an instruction does to the PC, for branch prediction and fetch redirection.
+/// Calls and returns follow the RAS hints in the spec: x1 and x5 are link registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlowKind {
+    /// Falls through to the next instruction
+    None,
+    /// Conditional, PC-relative
+    Branch,
+    /// `jal` not writing a link register
+    Jump,
+    /// `jalr` that is neither a call nor a return
+    IndirectJump,
+    /// `jal` writing a link register
+    Call,
+    /// `jalr` writing a link register
+    IndirectCall,
+    /// `jalr` through a link register that doesn't write one
+    Return,
+    /// Enters or leaves a trap handler: `ecall`, `ebreak` and `mret`
+    Trap,
+}
+
+impl Instruction {
+    pub fn fu_class(&self) -> FuClass {
+        match self {
+            Instruction::Mul { .. }
+            | Instruction::Mulh { .. }
+            | Instruction::Mulhsu { .. }
+            | Instruction::Mulhu { .. } => FuClass::Mul,
+            Instruction::Div { .. }
+            | Instruction::Divu { .. }
+            | Instruction::Rem { .. }
+            | Instruction::Remu { .. } => FuClass::Div,
+            Instruction::Clz { .. }
+            | Instruction::Ctz { .. }
+            | Instruction::Cpop { .. }
+            | Instruction::OrcB { .. }
+            | Instruction::Rev8 { .. } => FuClass::Bitmanip,
+            Instruction::Clmul { .. }
+            | Instruction::Clmulh { .. }
+            | Instruction::Clmulr { .. } => FuClass::Clmul,
+            Instruction::FmulS { .. }
+            | Instruction::FmulD { .. }
+            | Instruction::FmaddS { .. }
+            | Instruction::FmsubS { .. }
+            | Instruction::FnmsubS { .. }
+            | Instruction::FnmaddS { .. }
+            | Instruction::FmaddD { .. }
+            | Instruction::FmsubD { .. }
+            | Instruction::FnmsubD { .. }
+            | Instruction::FnmaddD { .. } => FuClass::FpMul,
+            Instruction::FdivS { .. }
+            | Instruction::FdivD { .. }
+            | Instruction::FsqrtS { .. }
+            | Instruction::FsqrtD { .. } => FuClass::FpDiv,
+            Instruction::FaddS { .. }
+            | Instruction::FsubS { .. }
+            | Instruction::FaddD { .. }
+            | Instruction::FsubD { .. }
+            | Instruction::FsgnjS { .. }
+            | Instruction::FsgnjnS { .. }
+            | Instruction::FsgnjxS { .. }
+            | Instruction::FminS { .. }
+            | Instruction::FmaxS { .. }
+            | Instruction::FsgnjD { .. }
+            | Instruction::FsgnjnD { .. }
+            | Instruction::FsgnjxD { .. }
+            | Instruction::FminD { .. }
+            | Instruction::FmaxD { .. }
+            | Instruction::FeqS { .. }
+            | Instruction::FltS { .. }
+            | Instruction::FleS { .. }
+            | Instruction::FclassS { .. }
+            | Instruction::FeqD { .. }
+            | Instruction::FltD { .. }
+            | Instruction::FleD { .. }
+            | Instruction::FclassD { .. }
+            | Instruction::FcvtWS { .. }
+            | Instruction::FcvtWuS { .. }
+            | Instruction::FcvtSW { .. }
+            | Instruction::FcvtSWu { .. }
+            | Instruction::FcvtWD { .. }
+            | Instruction::FcvtWuD { .. }
+            | Instruction::FcvtDW { .. }
+            | Instruction::FcvtDWu { .. }
+            | Instruction::FcvtSD { .. }
+            | Instruction::FcvtDS { .. }
+            | Instruction::FmvXW { .. }
+            | Instruction::FmvWX { .. } => FuClass::FpAdd,
+            Instruction::Csrrw { .. }
+            | Instruction::Csrrs { .. }
+            | Instruction::Csrrc { .. }
+            | Instruction::Csrrwi { .. }
+            | Instruction::Csrrsi { .. }
+            | Instruction::Csrrci { .. }
+            | Instruction::Fence { .. }
+            | Instruction::FenceI
+            | Instruction::Mret => FuClass::System,
+            _ => FuClass::Alu,
+        }
+    }
+
+    /// Registers read, x0 left out as it never carries a dependency. Registers
+    /// `ecall` reads for syscall emulation are not included.
+    // Like `dst` and `control_flow_kind`, for rename and branch prediction, which the
+    // in-order timing core has no use for
+    #[allow(dead_code)]
+    pub fn srcs(&self) -> [Option<Reg>; 3] {
+        self.operands().1
+    }
+
+    /// Register written, `None` for x0
+    #[allow(dead_code)]
+    pub fn dst(&self) -> Option<Reg> {
+        self.operands().0
+    }
+
+    #[rustfmt::skip]
+    fn operands(&self) -> (Option<Reg>, [Option<Reg>; 3]) {
+        let x = |r: u32| (r != 0).then_some(Reg::X(r));
+        let f = |r: u32| Some(Reg::F(r));
+        match *self {
+            Instruction::Sll { rd, rs1, rs2 } | Instruction::Srl { rd, rs1, rs2 }
+            | Instruction::Sra { rd, rs1, rs2 } | Instruction::Add { rd, rs1, rs2 }
+            | Instruction::Sub { rd, rs1, rs2 } | Instruction::Xor { rd, rs1, rs2 }
+            | Instruction::Or { rd, rs1, rs2 } | Instruction::And { rd, rs1, rs2 }
+            | Instruction::Slt { rd, rs1, rs2 } | Instruction::Sltu { rd, rs1, rs2 }
+            | Instruction::Mul { rd, rs1, rs2 } | Instruction::Mulh { rd, rs1, rs2 }
+            | Instruction::Mulhsu { rd, rs1, rs2 } | Instruction::Mulhu { rd, rs1, rs2 }
+            | Instruction::Div { rd, rs1, rs2 } | Instruction::Divu { rd, rs1, rs2 }
+            | Instruction::Rem { rd, rs1, rs2 } | Instruction::Remu { rd, rs1, rs2 }
+            | Instruction::Sh1add { rd, rs1, rs2 } | Instruction::Sh2add { rd, rs1, rs2 }
+            | Instruction::Sh3add { rd, rs1, rs2 } | Instruction::Andn { rd, rs1, rs2 }
+            | Instruction::Orn { rd, rs1, rs2 } | Instruction::Xnor { rd, rs1, rs2 }
+            | Instruction::Max { rd, rs1, rs2 } | Instruction::Maxu { rd, rs1, rs2 }
+            | Instruction::Min { rd, rs1, rs2 } | Instruction::Minu { rd, rs1, rs2 }
+            | Instruction::Rol { rd, rs1, rs2 } | Instruction::Ror { rd, rs1, rs2 }
+            | Instruction::Bclr { rd, rs1, rs2 } | Instruction::Bext { rd, rs1, rs2 }
+            | Instruction::Binv { rd, rs1, rs2 } | Instruction::Bset { rd, rs1, rs2 }
+            | Instruction::Clmul { rd, rs1, rs2 } | Instruction::Clmulh { rd, rs1, rs2 }
+            | Instruction::Clmulr { rd, rs1, rs2 }
+            | Instruction::ScW { rd, rs1, rs2, .. } | Instruction::AmoswapW { rd, rs1, rs2, .. }
+            | Instruction::AmoaddW { rd, rs1, rs2, .. } | Instruction::AmoxorW { rd, rs1, rs2, .. }
+            | Instruction::AmoandW { rd, rs1, rs2, .. } | Instruction::AmoorW { rd, rs1, rs2, .. }
+            | Instruction::AmominW { rd, rs1, rs2, .. } | Instruction::AmomaxW { rd, rs1, rs2, .. }
+            | Instruction::AmominuW { rd, rs1, rs2, .. }
+            | Instruction::AmomaxuW { rd, rs1, rs2, .. } => (x(rd), [x(rs1), x(rs2), None]),
+
+            Instruction::Lb { rd, rs1, .. } | Instruction::Lh { rd, rs1, .. }
+            | Instruction::Lw { rd, rs1, .. } | Instruction::Lbu { rd, rs1, .. }
+            | Instruction::Lhu { rd, rs1, .. } | Instruction::Slli { rd, rs1, .. }
+            | Instruction::Srli { rd, rs1, .. } | Instruction::Srai { rd, rs1, .. }
+            | Instruction::Addi { rd, rs1, .. } | Instruction::Xori { rd, rs1, .. }
+            | Instruction::Ori { rd, rs1, .. } | Instruction::Andi { rd, rs1, .. }
+            | Instruction::Slti { rd, rs1, .. } | Instruction::Sltiu { rd, rs1, .. }
+            | Instruction::Jalr { rd, rs1, .. } | Instruction::Clz { rd, rs1 }
+            | Instruction::Ctz { rd, rs1 } | Instruction::Cpop { rd, rs1 }
+            | Instruction::SextB { rd, rs1 } | Instruction::SextH { rd, rs1 }
+            | Instruction::ZextH { rd, rs1 } | Instruction::Rori { rd, rs1, .. }
+            | Instruction::OrcB { rd, rs1 } | Instruction::Rev8 { rd, rs1 }
+            | Instruction::Bclri { rd, rs1, .. } | Instruction::Bexti { rd, rs1, .. }
+            | Instruction::Binvi { rd, rs1, .. } | Instruction::Bseti { rd, rs1, .. }
+            | Instruction::LrW { rd, rs1, .. } | Instruction::Csrrw { rd, rs1, .. }
+            | Instruction::Csrrs { rd, rs1, .. }
+            | Instruction::Csrrc { rd, rs1, .. } => (x(rd), [x(rs1), None, None]),
+
+            Instruction::Lui { rd, .. } | Instruction::Auipc { rd, .. }
+            | Instruction::Jal { rd, .. } | Instruction::Csrrwi { rd, .. }
+            | Instruction::Csrrsi { rd, .. }
+            | Instruction::Csrrci { rd, .. } => (x(rd), [None; 3]),
+
+            Instruction::Sb { rs1, rs2, .. } | Instruction::Sh { rs1, rs2, .. }
+            | Instruction::Sw { rs1, rs2, .. } | Instruction::Beq { rs1, rs2, .. }
+            | Instruction::Bne { rs1, rs2, .. } | Instruction::Blt { rs1, rs2, .. }
+            | Instruction::Bge { rs1, rs2, .. } | Instruction::Bltu { rs1, rs2, .. }
+            | Instruction::Bgeu { rs1, rs2, .. } => (None, [x(rs1), x(rs2), None]),
+
+            Instruction::Flw { rd, rs1, .. }
+            | Instruction::Fld { rd, rs1, .. } => (f(rd), [x(rs1), None, None]),
+            Instruction::Fsw { rs1, rs2, .. }
+            | Instruction::Fsd { rs1, rs2, .. } => (None, [x(rs1), f(rs2), None]),
+
+            Instruction::FmaddS { rd, rs1, rs2, rs3, .. }
+            | Instruction::FmsubS { rd, rs1, rs2, rs3, .. }
+            | Instruction::FnmsubS { rd, rs1, rs2, rs3, .. }
+            | Instruction::FnmaddS { rd, rs1, rs2, rs3, .. }
+            | Instruction::FmaddD { rd, rs1, rs2, rs3, .. }
+            | Instruction::FmsubD { rd, rs1, rs2, rs3, .. }
+            | Instruction::FnmsubD { rd, rs1, rs2, rs3, .. }
+            | Instruction::FnmaddD { rd, rs1, rs2, rs3, .. } => (f(rd), [f(rs1), f(rs2), f(rs3)]),
+
+            Instruction::FaddS { rd, rs1, rs2, .. } | Instruction::FsubS { rd, rs1, rs2, .. }
+            | Instruction::FmulS { rd, rs1, rs2, .. } | Instruction::FdivS { rd, rs1, rs2, .. }
+            | Instruction::FaddD { rd, rs1, rs2, .. } | Instruction::FsubD { rd, rs1, rs2, .. }
+            | Instruction::FmulD { rd, rs1, rs2, .. } | Instruction::FdivD { rd, rs1, rs2, .. }
+            | Instruction::FsgnjS { rd, rs1, rs2 } | Instruction::FsgnjnS { rd, rs1, rs2 }
+            | Instruction::FsgnjxS { rd, rs1, rs2 } | Instruction::FminS { rd, rs1, rs2 }
+            | Instruction::FmaxS { rd, rs1, rs2 } | Instruction::FsgnjD { rd, rs1, rs2 }
+            | Instruction::FsgnjnD { rd, rs1, rs2 } | Instruction::FsgnjxD { rd, rs1, rs2 }
+            | Instruction::FminD { rd, rs1, rs2 }
+            | Instruction::FmaxD { rd, rs1, rs2 } => (f(rd), [f(rs1), f(rs2), None]),
+
+            Instruction::FsqrtS { rd, rs1, .. } | Instruction::FsqrtD { rd, rs1, .. }
+            | Instruction::FcvtSD { rd, rs1, .. }
+            | Instruction::FcvtDS { rd, rs1, .. } => (f(rd), [f(rs1), None, None]),
+
+            Instruction::FeqS { rd, rs1, rs2 } | Instruction::FltS { rd, rs1, rs2 }
+            | Instruction::FleS { rd, rs1, rs2 } | Instruction::FeqD { rd, rs1, rs2 }
+            | Instruction::FltD { rd, rs1, rs2 }
+            | Instruction::FleD { rd, rs1, rs2 } => (x(rd), [f(rs1), f(rs2), None]),
+
+            Instruction::FclassS { rd, rs1 } | Instruction::FclassD { rd, rs1 }
+            | Instruction::FcvtWS { rd, rs1, .. } | Instruction::FcvtWuS { rd, rs1, .. }
+            | Instruction::FcvtWD { rd, rs1, .. } | Instruction::FcvtWuD { rd, rs1, .. }
+            | Instruction::FmvXW { rd, rs1 } => (x(rd), [f(rs1), None, None]),
+
+            Instruction::FcvtSW { rd, rs1, .. } | Instruction::FcvtSWu { rd, rs1, .. }
+            | Instruction::FcvtDW { rd, rs1, .. } | Instruction::FcvtDWu { rd, rs1, .. }
+            | Instruction::FmvWX { rd, rs1 } => (f(rd), [x(rs1), None, None]),
+
+            Instruction::Fence { .. } | Instruction::FenceI | Instruction::Ecall
+            | Instruction::Ebreak | Instruction::Mret | Instruction::Ill => (None, [None; 3]),
+        }
+    }
+
+    /// Memory access the instruction makes, `None` if it isn't a load, store or atomic
+    pub fn mem_op(&self) -> Option<MemOp> {
+        let (kind, width, signed) = match self {
+            Instruction::Lb { .. } => (MemKind::Load, MemWidth::Byte, true),
+            Instruction::Lh { .. } => (MemKind::Load, MemWidth::Half, true),
+            Instruction::Lw { .. } | Instruction::LrW { .. } => {
+                (MemKind::Load, MemWidth::Word, true)
+            }
+            Instruction::Lbu { .. } => (MemKind::Load, MemWidth::Byte, false),
+            Instruction::Lhu { .. } => (MemKind::Load, MemWidth::Half, false),
+            Instruction::Flw { .. } => (MemKind::Load, MemWidth::Word, false),
+            Instruction::Fld { .. } => (MemKind::Load, MemWidth::Double, false),
+            Instruction::Sb { .. } => (MemKind::Store, MemWidth::Byte, false),
+            Instruction::Sh { .. } => (MemKind::Store, MemWidth::Half, false),
+            Instruction::Sw { .. } | Instruction::ScW { .. } | Instruction::Fsw { .. } => {
+                (MemKind::Store, MemWidth::Word, false)
+            }
+            Instruction::Fsd { .. } => (MemKind::Store, MemWidth::Double, false),
+            Instruction::AmoswapW { .. }
+            | Instruction::AmoaddW { .. }
+            | Instruction::AmoxorW { .. }
+            | Instruction::AmoandW { .. }
+            | Instruction::AmoorW { .. }
+            | Instruction::AmominW { .. }
+            | Instruction::AmomaxW { .. }
+            | Instruction::AmominuW { .. }
+            | Instruction::AmomaxuW { .. } => (MemKind::Amo, MemWidth::Word, true),
+            _ => return None,
+        };
+        Some(MemOp {
+            kind,
+            width,
+            signed,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn control_flow_kind(&self) -> ControlFlowKind {
+        let link = |r: u32| r == 1 || r == 5;
+        match *self {
+            Instruction::Beq { .. }
+            | Instruction::Bne { .. }
+            | Instruction::Blt { .. }
+            | Instruction::Bge { .. }
+            | Instruction::Bltu { .. }
+            | Instruction::Bgeu { .. } => ControlFlowKind::Branch,
+            Instruction::Jal { rd, .. } if link(rd) => ControlFlowKind::Call,
+            Instruction::Jal { .. } => ControlFlowKind::Jump,
+            Instruction::Jalr { rd, .. } if link(rd) => ControlFlowKind::IndirectCall,
+            Instruction::Jalr { rs1, .. } if link(rs1) => ControlFlowKind::Return,
+            Instruction::Jalr { .. } => ControlFlowKind::IndirectJump,
+            Instruction::Ecall | Instruction::Ebreak | Instruction::Mret => ControlFlowKind::Trap,
+            _ => ControlFlowKind::None,
+        }
+    }
+
+    /// CSR accessed by a Zicsr instruction
+    pub fn csr(&self) -> Option<u32> {
+        match *self {
+            Instruction::Csrrw { csr, .. }
+            | Instruction::Csrrs { csr, .. }
+            | Instruction::Csrrc { csr, .. }
+            | Instruction::Csrrwi { csr, .. }
+            | Instruction::Csrrsi { csr, .. }
+            | Instruction::Csrrci { csr, .. } => Some(csr),
+            _ => None,
+        }
+    }
+}
+
+/// rs3 = inst[31:27], fmt = inst[26:25]
+fn parse_r4_type(inst: u32) -> (u32, u32, u32, u32, u32, u32) {
+    let rs3 = inst >> 27;
+    let fmt = (inst >> 25) & 0x3;
+    let rs2 = (inst >> 20) & 0x1F;
+    let rs1 = (inst >> 15) & 0x1F;
+    let rm = (inst >> 12) & 0x7;
+    let rd = (inst >> 7) & 0x1F;
+    (rs3, fmt, rs2, rs1, rm, rd)
+}
 fn parse_r_type(inst: u32) -> (u32, u32, u32, u32, u32, u32) {
     let funct7 = (inst >> 25) & 0x7F;
     let rs2 = (inst >> 20) & 0x1F;
@@ -72,47 +619,60 @@ fn parse_r_type(inst: u32) -> (u32, u32, u32, u32, u32, u32) {
     let opcode = inst & 0x7F;
     (funct7, rs2, rs1, funct3, rd, opcode)
 }
-fn parse_i_type(inst: u32) -> (u32, u32, u32, u32, u32) {
-    let imm = (inst >> 20) & 0xFFF;
+/// imm[11:0] = inst[31:20]
+fn parse_i_type(inst: u32) -> (i32, u32, u32, u32, u32) {
+    let imm = (inst as i32) >> 20;
     let rs1 = (inst >> 15) & 0x1F;
     let funct3 = (inst >> 12) & 0x7;
     let rd: u32 = (inst >> 7) & 0x1F;
     let opcode = inst & 0x7F;
     (imm, rs1, funct3, rd, opcode)
 }
-fn parse_s_type(inst: u32) -> (u32, u32, u32, u32, u32) {
-    let imm = ((inst >> 20) & 0x7F) | ((inst >> 7) & 0x1F);
+/// imm[11:5] = inst[31:25], imm[4:0] = inst[11:7]
+fn parse_s_type(inst: u32) -> (i32, u32, u32, u32, u32) {
+    let imm = (((inst & 0xFE00_0000) as i32) >> 20) | ((inst >> 7) & 0x1F) as i32;
     let rs2 = (inst >> 20) & 0x1F;
     let rs1 = (inst >> 15) & 0x1F;
     let funct3 = (inst >> 12) & 0x7;
     let opcode = inst & 0x7F;
     (imm, rs2, rs1, funct3, opcode)
 }
-// TODO: FIX IMMEDIATE INDEXING
-fn parse_b_type(inst: u32) -> (u32, u32, u32, u32, u32) {
-    let imm = ((inst >> 20) & 0x7F) | ((inst >> 7) & 0x1F);
+/// imm[12|10:5] = inst[31:25], imm[4:1|11] = inst[11:7]
+fn parse_b_type(inst: u32) -> (i32, u32, u32, u32, u32) {
+    let imm = (((inst & 0x8000_0000) as i32) >> 19)
+        | ((inst & 0x80) << 4) as i32
+        | ((inst >> 20) & 0x7E0) as i32
+        | ((inst >> 7) & 0x1E) as i32;
     let rs2 = (inst >> 20) & 0x1F;
     let rs1 = (inst >> 15) & 0x1F;
     let funct3 = (inst >> 12) & 0x7;
     let opcode = inst & 0x7F;
     (imm, rs2, rs1, funct3, opcode)
 }
-fn parse_u_type(inst: u32) -> (u32, u32, u32) {
-    let imm = inst >> 12;
+/// imm[31:12] = inst[31:12], returned already shifted into place
+fn parse_u_type(inst: u32) -> (i32, u32, u32) {
+    let imm = (inst & 0xFFFF_F000) as i32;
     let rd: u32 = (inst >> 7) & 0x1F;
     let opcode = inst & 0x7F;
     (imm, rd, opcode)
 }
-// TODO: FIX IMMEDIATE INDEXING
-fn parse_j_type(inst: u32) -> (u32, u32, u32) {
-    let imm = inst >> 12;
+/// imm[20|10:1|11|19:12] = inst[31:12]
+fn parse_j_type(inst: u32) -> (i32, u32, u32) {
+    let imm = (((inst & 0x8000_0000) as i32) >> 11)
+        | (inst & 0xF_F000) as i32
+        | ((inst >> 9) & 0x800) as i32
+        | ((inst >> 20) & 0x7FE) as i32;
     let rd: u32 = (inst >> 7) & 0x1F;
     let opcode = inst & 0x7F;
     (imm, rd, opcode)
 }
 
+/// Decodes a 32-bit instruction, or a compressed one held in the low 16 bits
 pub fn decode_inst(inst: u32) -> Instruction {
-    let mut opcode: u32 = inst & 0x7F;
+    if inst_len(inst as u16) == 2 {
+        return expand_compressed(inst as u16);
+    }
+    let opcode: u32 = inst & 0x7F;
 
     match opcode {
         0b0110111 => {
@@ -125,11 +685,14 @@ pub fn decode_inst(inst: u32) -> Instruction {
         }
         0b1101111 => {
             let (imm, rd, _) = parse_j_type(inst);
-            Instruction::Auipc { imm, rd }
+            Instruction::Jal { imm, rd }
         }
         0b1100111 => {
-            let (imm, rs1, _, rd, _) = parse_i_type(inst);
-            Instruction::Jalr { rd, rs1, imm }
+            let (imm, rs1, funct3, rd, _) = parse_i_type(inst);
+            match funct3 {
+                0b000 => Instruction::Jalr { rd, rs1, imm },
+                _ => Instruction::Ill,
+            }
         }
         // Branches
         0b1100011 => {
@@ -169,8 +732,8 @@ pub fn decode_inst(inst: u32) -> Instruction {
         // Immediates
         0b0010011 => {
             let (imm, rs1, funct3, rd, _) = parse_i_type(inst);
-            let shamt = imm & 0x1F;
-            let funct7 = (imm & 0xFE) >> 5;
+            let shamt = (inst >> 20) & 0x1F;
+            let funct7 = inst >> 25;
             match funct3 {
                 0b000 => Instruction::Addi { rd, rs1, imm },
                 0b010 => Instruction::Slti { rd, rs1, imm },
@@ -178,10 +741,26 @@ pub fn decode_inst(inst: u32) -> Instruction {
                 0b100 => Instruction::Xori { rd, rs1, imm },
                 0b110 => Instruction::Ori { rd, rs1, imm },
                 0b111 => Instruction::Andi { rd, rs1, imm },
-                0b001 => Instruction::Slli { rd, rs1, shamt },
-                0b101 => match funct7 {
-                    0b0000000 => Instruction::Srli { rd, rs1, shamt },
-                    0b0100000 => Instruction::Srai { rd, rs1, shamt },
+                // Zbb unary ops put their selector in the shamt field
+                0b001 => match (funct7, shamt) {
+                    (0b0000000, _) => Instruction::Slli { rd, rs1, shamt },
+                    (0b0110000, 0b00000) => Instruction::Clz { rd, rs1 },
+                    (0b0110000, 0b00001) => Instruction::Ctz { rd, rs1 },
+                    (0b0110000, 0b00010) => Instruction::Cpop { rd, rs1 },
+                    (0b0110000, 0b00100) => Instruction::SextB { rd, rs1 },
+                    (0b0110000, 0b00101) => Instruction::SextH { rd, rs1 },
+                    (0b0100100, _) => Instruction::Bclri { rd, rs1, shamt },
+                    (0b0010100, _) => Instruction::Bseti { rd, rs1, shamt },
+                    (0b0110100, _) => Instruction::Binvi { rd, rs1, shamt },
+                    _ => Instruction::Ill,
+                },
+                0b101 => match (funct7, shamt) {
+                    (0b0000000, _) => Instruction::Srli { rd, rs1, shamt },
+                    (0b0100000, _) => Instruction::Srai { rd, rs1, shamt },
+                    (0b0110000, _) => Instruction::Rori { rd, rs1, shamt },
+                    (0b0100100, _) => Instruction::Bexti { rd, rs1, shamt },
+                    (0b0010100, 0b00111) => Instruction::OrcB { rd, rs1 },
+                    (0b0110100, 0b11000) => Instruction::Rev8 { rd, rs1 },
                     _ => Instruction::Ill,
                 },
                 _ => Instruction::Ill,
@@ -190,27 +769,705 @@ pub fn decode_inst(inst: u32) -> Instruction {
         // R-type (standard)
         0b0110011 => {
             let (funct7, rs2, rs1, funct3, rd, _) = parse_r_type(inst);
+            match (funct7, funct3) {
+                (0b0000000, 0b000) => Instruction::Add { rd, rs1, rs2 },
+                (0b0100000, 0b000) => Instruction::Sub { rd, rs1, rs2 },
+                (0b0000000, 0b001) => Instruction::Sll { rd, rs1, rs2 },
+                (0b0000000, 0b010) => Instruction::Slt { rd, rs1, rs2 },
+                (0b0000000, 0b011) => Instruction::Sltu { rd, rs1, rs2 },
+                (0b0000000, 0b100) => Instruction::Xor { rd, rs1, rs2 },
+                (0b0000000, 0b101) => Instruction::Srl { rd, rs1, rs2 },
+                (0b0100000, 0b101) => Instruction::Sra { rd, rs1, rs2 },
+                (0b0000000, 0b110) => Instruction::Or { rd, rs1, rs2 },
+                (0b0000000, 0b111) => Instruction::And { rd, rs1, rs2 },
+                (0b0000001, 0b000) => Instruction::Mul { rd, rs1, rs2 },
+                (0b0000001, 0b001) => Instruction::Mulh { rd, rs1, rs2 },
+                (0b0000001, 0b010) => Instruction::Mulhsu { rd, rs1, rs2 },
+                (0b0000001, 0b011) => Instruction::Mulhu { rd, rs1, rs2 },
+                (0b0000001, 0b100) => Instruction::Div { rd, rs1, rs2 },
+                (0b0000001, 0b101) => Instruction::Divu { rd, rs1, rs2 },
+                (0b0000001, 0b110) => Instruction::Rem { rd, rs1, rs2 },
+                (0b0000001, 0b111) => Instruction::Remu { rd, rs1, rs2 },
+                (0b0010000, 0b010) => Instruction::Sh1add { rd, rs1, rs2 },
+                (0b0010000, 0b100) => Instruction::Sh2add { rd, rs1, rs2 },
+                (0b0010000, 0b110) => Instruction::Sh3add { rd, rs1, rs2 },
+                (0b0100000, 0b111) => Instruction::Andn { rd, rs1, rs2 },
+                (0b0100000, 0b110) => Instruction::Orn { rd, rs1, rs2 },
+                (0b0100000, 0b100) => Instruction::Xnor { rd, rs1, rs2 },
+                (0b0000101, 0b110) => Instruction::Max { rd, rs1, rs2 },
+                (0b0000101, 0b111) => Instruction::Maxu { rd, rs1, rs2 },
+                (0b0000101, 0b100) => Instruction::Min { rd, rs1, rs2 },
+                (0b0000101, 0b101) => Instruction::Minu { rd, rs1, rs2 },
+                (0b0000100, 0b100) if rs2 == 0 => Instruction::ZextH { rd, rs1 },
+                (0b0110000, 0b001) => Instruction::Rol { rd, rs1, rs2 },
+                (0b0110000, 0b101) => Instruction::Ror { rd, rs1, rs2 },
+                (0b0100100, 0b001) => Instruction::Bclr { rd, rs1, rs2 },
+                (0b0100100, 0b101) => Instruction::Bext { rd, rs1, rs2 },
+                (0b0110100, 0b001) => Instruction::Binv { rd, rs1, rs2 },
+                (0b0010100, 0b001) => Instruction::Bset { rd, rs1, rs2 },
+                (0b0000101, 0b001) => Instruction::Clmul { rd, rs1, rs2 },
+                (0b0000101, 0b011) => Instruction::Clmulh { rd, rs1, rs2 },
+                (0b0000101, 0b010) => Instruction::Clmulr { rd, rs1, rs2 },
+                _ => Instruction::Ill,
+            }
+        }
+        // Atomics, funct7 holds funct5 followed by the aq and rl bits
+        0b0101111 => {
+            let (funct7, rs2, rs1, funct3, rd, _) = parse_r_type(inst);
+            let (aq, rl) = (funct7 & 0b10 != 0, funct7 & 0b1 != 0);
+            match (funct7 >> 2, funct3) {
+                (0b00010, 0b010) if rs2 == 0 => Instruction::LrW { rd, rs1, aq, rl },
+                (0b00011, 0b010) => Instruction::ScW { rd, rs1, rs2, aq, rl },
+                (0b00001, 0b010) => Instruction::AmoswapW { rd, rs1, rs2, aq, rl },
+                (0b00000, 0b010) => Instruction::AmoaddW { rd, rs1, rs2, aq, rl },
+                (0b00100, 0b010) => Instruction::AmoxorW { rd, rs1, rs2, aq, rl },
+                (0b01100, 0b010) => Instruction::AmoandW { rd, rs1, rs2, aq, rl },
+                (0b01000, 0b010) => Instruction::AmoorW { rd, rs1, rs2, aq, rl },
+                (0b10000, 0b010) => Instruction::AmominW { rd, rs1, rs2, aq, rl },
+                (0b10100, 0b010) => Instruction::AmomaxW { rd, rs1, rs2, aq, rl },
+                (0b11000, 0b010) => Instruction::AmominuW { rd, rs1, rs2, aq, rl },
+                (0b11100, 0b010) => Instruction::AmomaxuW { rd, rs1, rs2, aq, rl },
+                _ => Instruction::Ill,
+            }
+        }
+        // FP loads
+        0b0000111 => {
+            let (imm, rs1, funct3, rd, _) = parse_i_type(inst);
             match funct3 {
-                0b000 => match funct7 {
-                    0b0000000 => Instruction::Add { rd, rs1, rs2 },
-                    0b0100000 => Instruction::Sub { rd, rs1, rs2 },
-                    _ => Instruction::Ill,
-                },
-
-                0b001 => Instruction::Sll { rd, rs1, rs2 },
-                0b010 => Instruction::Slt { rd, rs1, rs2 },
-                0b011 => Instruction::Sltu { rd, rs1, rs2 },
-                0b100 => Instruction::Xor { rd, rs1, rs2 },
-                0x101 => match funct7 {
-                    0b0000000 => Instruction::Srl { rd, rs1, rs2 },
-                    0b0100000 => Instruction::Sra { rd, rs1, rs2 },
+                0b010 => Instruction::Flw { rd, rs1, imm },
+                0b011 => Instruction::Fld { rd, rs1, imm },
+                _ => Instruction::Ill,
+            }
+        }
+        // FP stores
+        0b0100111 => {
+            let (imm, rs2, rs1, funct3, _) = parse_s_type(inst);
+            match funct3 {
+                0b010 => Instruction::Fsw { rs1, rs2, imm },
+                0b011 => Instruction::Fsd { rs1, rs2, imm },
+                _ => Instruction::Ill,
+            }
+        }
+        // Fused multiply-add
+        0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => {
+            let (rs3, fmt, rs2, rs1, rm, rd) = parse_r4_type(inst);
+            match (opcode, fmt) {
+                (0b1000011, 0b00) => Instruction::FmaddS { rd, rs1, rs2, rs3, rm },
+                (0b1000111, 0b00) => Instruction::FmsubS { rd, rs1, rs2, rs3, rm },
+                (0b1001011, 0b00) => Instruction::FnmsubS { rd, rs1, rs2, rs3, rm },
+                (0b1001111, 0b00) => Instruction::FnmaddS { rd, rs1, rs2, rs3, rm },
+                (0b1000011, 0b01) => Instruction::FmaddD { rd, rs1, rs2, rs3, rm },
+                (0b1000111, 0b01) => Instruction::FmsubD { rd, rs1, rs2, rs3, rm },
+                (0b1001011, 0b01) => Instruction::FnmsubD { rd, rs1, rs2, rs3, rm },
+                (0b1001111, 0b01) => Instruction::FnmaddD { rd, rs1, rs2, rs3, rm },
+                _ => Instruction::Ill,
+            }
+        }
+        // FP arithmetic, funct3 is the rounding mode where the op rounds
+        0b1010011 => {
+            let (funct7, rs2, rs1, rm, rd, _) = parse_r_type(inst);
+            match (funct7, rs2, rm) {
+                (0b0000000, _, _) => Instruction::FaddS { rd, rs1, rs2, rm },
+                (0b0000100, _, _) => Instruction::FsubS { rd, rs1, rs2, rm },
+                (0b0001000, _, _) => Instruction::FmulS { rd, rs1, rs2, rm },
+                (0b0001100, _, _) => Instruction::FdivS { rd, rs1, rs2, rm },
+                (0b0101100, 0b00000, _) => Instruction::FsqrtS { rd, rs1, rm },
+                (0b0000001, _, _) => Instruction::FaddD { rd, rs1, rs2, rm },
+                (0b0000101, _, _) => Instruction::FsubD { rd, rs1, rs2, rm },
+                (0b0001001, _, _) => Instruction::FmulD { rd, rs1, rs2, rm },
+                (0b0001101, _, _) => Instruction::FdivD { rd, rs1, rs2, rm },
+                (0b0101101, 0b00000, _) => Instruction::FsqrtD { rd, rs1, rm },
+                (0b0010000, _, 0b000) => Instruction::FsgnjS { rd, rs1, rs2 },
+                (0b0010000, _, 0b001) => Instruction::FsgnjnS { rd, rs1, rs2 },
+                (0b0010000, _, 0b010) => Instruction::FsgnjxS { rd, rs1, rs2 },
+                (0b0010100, _, 0b000) => Instruction::FminS { rd, rs1, rs2 },
+                (0b0010100, _, 0b001) => Instruction::FmaxS { rd, rs1, rs2 },
+                (0b0010001, _, 0b000) => Instruction::FsgnjD { rd, rs1, rs2 },
+                (0b0010001, _, 0b001) => Instruction::FsgnjnD { rd, rs1, rs2 },
+                (0b0010001, _, 0b010) => Instruction::FsgnjxD { rd, rs1, rs2 },
+                (0b0010101, _, 0b000) => Instruction::FminD { rd, rs1, rs2 },
+                (0b0010101, _, 0b001) => Instruction::FmaxD { rd, rs1, rs2 },
+                (0b1010000, _, 0b010) => Instruction::FeqS { rd, rs1, rs2 },
+                (0b1010000, _, 0b001) => Instruction::FltS { rd, rs1, rs2 },
+                (0b1010000, _, 0b000) => Instruction::FleS { rd, rs1, rs2 },
+                (0b1010001, _, 0b010) => Instruction::FeqD { rd, rs1, rs2 },
+                (0b1010001, _, 0b001) => Instruction::FltD { rd, rs1, rs2 },
+                (0b1010001, _, 0b000) => Instruction::FleD { rd, rs1, rs2 },
+                (0b1110000, 0b00000, 0b001) => Instruction::FclassS { rd, rs1 },
+                (0b1110001, 0b00000, 0b001) => Instruction::FclassD { rd, rs1 },
+                (0b1110000, 0b00000, 0b000) => Instruction::FmvXW { rd, rs1 },
+                (0b1111000, 0b00000, 0b000) => Instruction::FmvWX { rd, rs1 },
+                (0b1100000, 0b00000, _) => Instruction::FcvtWS { rd, rs1, rm },
+                (0b1100000, 0b00001, _) => Instruction::FcvtWuS { rd, rs1, rm },
+                (0b1101000, 0b00000, _) => Instruction::FcvtSW { rd, rs1, rm },
+                (0b1101000, 0b00001, _) => Instruction::FcvtSWu { rd, rs1, rm },
+                (0b1100001, 0b00000, _) => Instruction::FcvtWD { rd, rs1, rm },
+                (0b1100001, 0b00001, _) => Instruction::FcvtWuD { rd, rs1, rm },
+                (0b1101001, 0b00000, _) => Instruction::FcvtDW { rd, rs1, rm },
+                (0b1101001, 0b00001, _) => Instruction::FcvtDWu { rd, rs1, rm },
+                (0b0100000, 0b00001, _) => Instruction::FcvtSD { rd, rs1, rm },
+                (0b0100001, 0b00000, _) => Instruction::FcvtDS { rd, rs1, rm },
+                _ => Instruction::Ill,
+            }
+        }
+        // Sync, rd and rs1 are reserved and ignored
+        0b0001111 => match (inst >> 12) & 0b111 {
+            0b000 => Instruction::Fence {
+                pred: (inst >> 24) & 0xF,
+                succ: (inst >> 20) & 0xF,
+                fm: inst >> 28,
+            },
+            0b001 => Instruction::FenceI,
+            _ => Instruction::Ill,
+        },
+        // System
+        0b1110011 => {
+            // CSR number is the unsigned I-type immediate, the immediate forms put uimm in rs1
+            let (_, rs1, funct3, rd, _) = parse_i_type(inst);
+            let csr = inst >> 20;
+            match funct3 {
+                0b000 => match inst {
+                    0x00000073 => Instruction::Ecall,
+                    0x00100073 => Instruction::Ebreak,
+                    0x30200073 => Instruction::Mret,
                     _ => Instruction::Ill,
                 },
-                0x110 => Instruction::Or { rd, rs1, rs2 },
-                0x111 => Instruction::And { rd, rs1, rs2 },
+                0b001 => Instruction::Csrrw { rd, rs1, csr },
+                0b010 => Instruction::Csrrs { rd, rs1, csr },
+                0b011 => Instruction::Csrrc { rd, rs1, csr },
+                0b101 => Instruction::Csrrwi { rd, uimm: rs1, csr },
+                0b110 => Instruction::Csrrsi { rd, uimm: rs1, csr },
+                0b111 => Instruction::Csrrci { rd, uimm: rs1, csr },
                 _ => Instruction::Ill,
             }
         }
         _ => Instruction::Ill,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::encode;
+    use crate::exec::{execute, Effect};
+    use crate::fpu::FpRegFile;
+    use proptest::prelude::*;
+    use Instruction::*;
+
+    /// Encodings produced by `llvm-mc -triple=riscv32 -show-encoding -riscv-no-aliases`
+    const KNOWN_ENCODINGS: &[(u32, Instruction)] = &[
+            (0x00030293, Addi { rd: 5, rs1: 6, imm: 0 }), // addi x5, x6, 0
+            (0x00032293, Slti { rd: 5, rs1: 6, imm: 0 }), // slti x5, x6, 0
+            (0x00033293, Sltiu { rd: 5, rs1: 6, imm: 0 }), // sltiu x5, x6, 0
+            (0x00034293, Xori { rd: 5, rs1: 6, imm: 0 }), // xori x5, x6, 0
+            (0x00036293, Ori { rd: 5, rs1: 6, imm: 0 }), // ori x5, x6, 0
+            (0x00037293, Andi { rd: 5, rs1: 6, imm: 0 }), // andi x5, x6, 0
+            (0x00130293, Addi { rd: 5, rs1: 6, imm: 1 }), // addi x5, x6, 1
+            (0x00132293, Slti { rd: 5, rs1: 6, imm: 1 }), // slti x5, x6, 1
+            (0x00133293, Sltiu { rd: 5, rs1: 6, imm: 1 }), // sltiu x5, x6, 1
+            (0x00134293, Xori { rd: 5, rs1: 6, imm: 1 }), // xori x5, x6, 1
+            (0x00136293, Ori { rd: 5, rs1: 6, imm: 1 }), // ori x5, x6, 1
+            (0x00137293, Andi { rd: 5, rs1: 6, imm: 1 }), // andi x5, x6, 1
+            (0xfff30293, Addi { rd: 5, rs1: 6, imm: -1 }), // addi x5, x6, -1
+            (0xfff32293, Slti { rd: 5, rs1: 6, imm: -1 }), // slti x5, x6, -1
+            (0xfff33293, Sltiu { rd: 5, rs1: 6, imm: -1 }), // sltiu x5, x6, -1
+            (0xfff34293, Xori { rd: 5, rs1: 6, imm: -1 }), // xori x5, x6, -1
+            (0xfff36293, Ori { rd: 5, rs1: 6, imm: -1 }), // ori x5, x6, -1
+            (0xfff37293, Andi { rd: 5, rs1: 6, imm: -1 }), // andi x5, x6, -1
+            (0x7ff30293, Addi { rd: 5, rs1: 6, imm: 2047 }), // addi x5, x6, 2047
+            (0x7ff32293, Slti { rd: 5, rs1: 6, imm: 2047 }), // slti x5, x6, 2047
+            (0x7ff33293, Sltiu { rd: 5, rs1: 6, imm: 2047 }), // sltiu x5, x6, 2047
+            (0x7ff34293, Xori { rd: 5, rs1: 6, imm: 2047 }), // xori x5, x6, 2047
+            (0x7ff36293, Ori { rd: 5, rs1: 6, imm: 2047 }), // ori x5, x6, 2047
+            (0x7ff37293, Andi { rd: 5, rs1: 6, imm: 2047 }), // andi x5, x6, 2047
+            (0x80030293, Addi { rd: 5, rs1: 6, imm: -2048 }), // addi x5, x6, -2048
+            (0x80032293, Slti { rd: 5, rs1: 6, imm: -2048 }), // slti x5, x6, -2048
+            (0x80033293, Sltiu { rd: 5, rs1: 6, imm: -2048 }), // sltiu x5, x6, -2048
+            (0x80034293, Xori { rd: 5, rs1: 6, imm: -2048 }), // xori x5, x6, -2048
+            (0x80036293, Ori { rd: 5, rs1: 6, imm: -2048 }), // ori x5, x6, -2048
+            (0x80037293, Andi { rd: 5, rs1: 6, imm: -2048 }), // andi x5, x6, -2048
+            (0x55530293, Addi { rd: 5, rs1: 6, imm: 1365 }), // addi x5, x6, 1365
+            (0x55532293, Slti { rd: 5, rs1: 6, imm: 1365 }), // slti x5, x6, 1365
+            (0x55533293, Sltiu { rd: 5, rs1: 6, imm: 1365 }), // sltiu x5, x6, 1365
+            (0x55534293, Xori { rd: 5, rs1: 6, imm: 1365 }), // xori x5, x6, 1365
+            (0x55536293, Ori { rd: 5, rs1: 6, imm: 1365 }), // ori x5, x6, 1365
+            (0x55537293, Andi { rd: 5, rs1: 6, imm: 1365 }), // andi x5, x6, 1365
+            (0xaaa30293, Addi { rd: 5, rs1: 6, imm: -1366 }), // addi x5, x6, -1366
+            (0xaaa32293, Slti { rd: 5, rs1: 6, imm: -1366 }), // slti x5, x6, -1366
+            (0xaaa33293, Sltiu { rd: 5, rs1: 6, imm: -1366 }), // sltiu x5, x6, -1366
+            (0xaaa34293, Xori { rd: 5, rs1: 6, imm: -1366 }), // xori x5, x6, -1366
+            (0xaaa36293, Ori { rd: 5, rs1: 6, imm: -1366 }), // ori x5, x6, -1366
+            (0xaaa37293, Andi { rd: 5, rs1: 6, imm: -1366 }), // andi x5, x6, -1366
+            (0x00010503, Lb { rd: 10, rs1: 2, imm: 0 }), // lb x10, 0(x2)
+            (0x00011503, Lh { rd: 10, rs1: 2, imm: 0 }), // lh x10, 0(x2)
+            (0x00012503, Lw { rd: 10, rs1: 2, imm: 0 }), // lw x10, 0(x2)
+            (0x00014503, Lbu { rd: 10, rs1: 2, imm: 0 }), // lbu x10, 0(x2)
+            (0x00015503, Lhu { rd: 10, rs1: 2, imm: 0 }), // lhu x10, 0(x2)
+            (0x000380e7, Jalr { rd: 1, rs1: 7, imm: 0 }), // jalr x1, 0(x7)
+            (0xffc10503, Lb { rd: 10, rs1: 2, imm: -4 }), // lb x10, -4(x2)
+            (0xffc11503, Lh { rd: 10, rs1: 2, imm: -4 }), // lh x10, -4(x2)
+            (0xffc12503, Lw { rd: 10, rs1: 2, imm: -4 }), // lw x10, -4(x2)
+            (0xffc14503, Lbu { rd: 10, rs1: 2, imm: -4 }), // lbu x10, -4(x2)
+            (0xffc15503, Lhu { rd: 10, rs1: 2, imm: -4 }), // lhu x10, -4(x2)
+            (0xffc380e7, Jalr { rd: 1, rs1: 7, imm: -4 }), // jalr x1, -4(x7)
+            (0x7ff10503, Lb { rd: 10, rs1: 2, imm: 2047 }), // lb x10, 2047(x2)
+            (0x7ff11503, Lh { rd: 10, rs1: 2, imm: 2047 }), // lh x10, 2047(x2)
+            (0x7ff12503, Lw { rd: 10, rs1: 2, imm: 2047 }), // lw x10, 2047(x2)
+            (0x7ff14503, Lbu { rd: 10, rs1: 2, imm: 2047 }), // lbu x10, 2047(x2)
+            (0x7ff15503, Lhu { rd: 10, rs1: 2, imm: 2047 }), // lhu x10, 2047(x2)
+            (0x7ff380e7, Jalr { rd: 1, rs1: 7, imm: 2047 }), // jalr x1, 2047(x7)
+            (0x80010503, Lb { rd: 10, rs1: 2, imm: -2048 }), // lb x10, -2048(x2)
+            (0x80011503, Lh { rd: 10, rs1: 2, imm: -2048 }), // lh x10, -2048(x2)
+            (0x80012503, Lw { rd: 10, rs1: 2, imm: -2048 }), // lw x10, -2048(x2)
+            (0x80014503, Lbu { rd: 10, rs1: 2, imm: -2048 }), // lbu x10, -2048(x2)
+            (0x80015503, Lhu { rd: 10, rs1: 2, imm: -2048 }), // lhu x10, -2048(x2)
+            (0x800380e7, Jalr { rd: 1, rs1: 7, imm: -2048 }), // jalr x1, -2048(x7)
+            (0x06410503, Lb { rd: 10, rs1: 2, imm: 100 }), // lb x10, 100(x2)
+            (0x06411503, Lh { rd: 10, rs1: 2, imm: 100 }), // lh x10, 100(x2)
+            (0x06412503, Lw { rd: 10, rs1: 2, imm: 100 }), // lw x10, 100(x2)
+            (0x06414503, Lbu { rd: 10, rs1: 2, imm: 100 }), // lbu x10, 100(x2)
+            (0x06415503, Lhu { rd: 10, rs1: 2, imm: 100 }), // lhu x10, 100(x2)
+            (0x064380e7, Jalr { rd: 1, rs1: 7, imm: 100 }), // jalr x1, 100(x7)
+            (0x00b10023, Sb { rs1: 2, rs2: 11, imm: 0 }), // sb x11, 0(x2)
+            (0x00b11023, Sh { rs1: 2, rs2: 11, imm: 0 }), // sh x11, 0(x2)
+            (0x00b12023, Sw { rs1: 2, rs2: 11, imm: 0 }), // sw x11, 0(x2)
+            (0xfeb10c23, Sb { rs1: 2, rs2: 11, imm: -8 }), // sb x11, -8(x2)
+            (0xfeb11c23, Sh { rs1: 2, rs2: 11, imm: -8 }), // sh x11, -8(x2)
+            (0xfeb12c23, Sw { rs1: 2, rs2: 11, imm: -8 }), // sw x11, -8(x2)
+            (0x7eb10fa3, Sb { rs1: 2, rs2: 11, imm: 2047 }), // sb x11, 2047(x2)
+            (0x7eb11fa3, Sh { rs1: 2, rs2: 11, imm: 2047 }), // sh x11, 2047(x2)
+            (0x7eb12fa3, Sw { rs1: 2, rs2: 11, imm: 2047 }), // sw x11, 2047(x2)
+            (0x80b10023, Sb { rs1: 2, rs2: 11, imm: -2048 }), // sb x11, -2048(x2)
+            (0x80b11023, Sh { rs1: 2, rs2: 11, imm: -2048 }), // sh x11, -2048(x2)
+            (0x80b12023, Sw { rs1: 2, rs2: 11, imm: -2048 }), // sw x11, -2048(x2)
+            (0x7eb100a3, Sb { rs1: 2, rs2: 11, imm: 2017 }), // sb x11, 2017(x2)
+            (0x7eb110a3, Sh { rs1: 2, rs2: 11, imm: 2017 }), // sh x11, 2017(x2)
+            (0x7eb120a3, Sw { rs1: 2, rs2: 11, imm: 2017 }), // sw x11, 2017(x2)
+            (0x82b10fa3, Sb { rs1: 2, rs2: 11, imm: -1985 }), // sb x11, -1985(x2)
+            (0x82b11fa3, Sh { rs1: 2, rs2: 11, imm: -1985 }), // sh x11, -1985(x2)
+            (0x82b12fa3, Sw { rs1: 2, rs2: 11, imm: -1985 }), // sw x11, -1985(x2)
+            (0x01f50063, Beq { rs1: 10, rs2: 31, imm: 0 }), // beq x10, x31, 0
+            (0x01f51063, Bne { rs1: 10, rs2: 31, imm: 0 }), // bne x10, x31, 0
+            (0x01f54063, Blt { rs1: 10, rs2: 31, imm: 0 }), // blt x10, x31, 0
+            (0x01f55063, Bge { rs1: 10, rs2: 31, imm: 0 }), // bge x10, x31, 0
+            (0x01f56063, Bltu { rs1: 10, rs2: 31, imm: 0 }), // bltu x10, x31, 0
+            (0x01f57063, Bgeu { rs1: 10, rs2: 31, imm: 0 }), // bgeu x10, x31, 0
+            (0xfff50ee3, Beq { rs1: 10, rs2: 31, imm: -4 }), // beq x10, x31, -4
+            (0xfff51ee3, Bne { rs1: 10, rs2: 31, imm: -4 }), // bne x10, x31, -4
+            (0xfff54ee3, Blt { rs1: 10, rs2: 31, imm: -4 }), // blt x10, x31, -4
+            (0xfff55ee3, Bge { rs1: 10, rs2: 31, imm: -4 }), // bge x10, x31, -4
+            (0xfff56ee3, Bltu { rs1: 10, rs2: 31, imm: -4 }), // bltu x10, x31, -4
+            (0xfff57ee3, Bgeu { rs1: 10, rs2: 31, imm: -4 }), // bgeu x10, x31, -4
+            (0x7ff50fe3, Beq { rs1: 10, rs2: 31, imm: 4094 }), // beq x10, x31, 4094
+            (0x7ff51fe3, Bne { rs1: 10, rs2: 31, imm: 4094 }), // bne x10, x31, 4094
+            (0x7ff54fe3, Blt { rs1: 10, rs2: 31, imm: 4094 }), // blt x10, x31, 4094
+            (0x7ff55fe3, Bge { rs1: 10, rs2: 31, imm: 4094 }), // bge x10, x31, 4094
+            (0x7ff56fe3, Bltu { rs1: 10, rs2: 31, imm: 4094 }), // bltu x10, x31, 4094
+            (0x7ff57fe3, Bgeu { rs1: 10, rs2: 31, imm: 4094 }), // bgeu x10, x31, 4094
+            (0x81f50063, Beq { rs1: 10, rs2: 31, imm: -4096 }), // beq x10, x31, -4096
+            (0x81f51063, Bne { rs1: 10, rs2: 31, imm: -4096 }), // bne x10, x31, -4096
+            (0x81f54063, Blt { rs1: 10, rs2: 31, imm: -4096 }), // blt x10, x31, -4096
+            (0x81f55063, Bge { rs1: 10, rs2: 31, imm: -4096 }), // bge x10, x31, -4096
+            (0x81f56063, Bltu { rs1: 10, rs2: 31, imm: -4096 }), // bltu x10, x31, -4096
+            (0x81f57063, Bgeu { rs1: 10, rs2: 31, imm: -4096 }), // bgeu x10, x31, -4096
+            (0x01f500e3, Beq { rs1: 10, rs2: 31, imm: 2048 }), // beq x10, x31, 2048
+            (0x01f510e3, Bne { rs1: 10, rs2: 31, imm: 2048 }), // bne x10, x31, 2048
+            (0x01f540e3, Blt { rs1: 10, rs2: 31, imm: 2048 }), // blt x10, x31, 2048
+            (0x01f550e3, Bge { rs1: 10, rs2: 31, imm: 2048 }), // bge x10, x31, 2048
+            (0x01f560e3, Bltu { rs1: 10, rs2: 31, imm: 2048 }), // bltu x10, x31, 2048
+            (0x01f570e3, Bgeu { rs1: 10, rs2: 31, imm: 2048 }), // bgeu x10, x31, 2048
+            (0x81f500e3, Beq { rs1: 10, rs2: 31, imm: -2048 }), // beq x10, x31, -2048
+            (0x81f510e3, Bne { rs1: 10, rs2: 31, imm: -2048 }), // bne x10, x31, -2048
+            (0x81f540e3, Blt { rs1: 10, rs2: 31, imm: -2048 }), // blt x10, x31, -2048
+            (0x81f550e3, Bge { rs1: 10, rs2: 31, imm: -2048 }), // bge x10, x31, -2048
+            (0x81f560e3, Bltu { rs1: 10, rs2: 31, imm: -2048 }), // bltu x10, x31, -2048
+            (0x81f570e3, Bgeu { rs1: 10, rs2: 31, imm: -2048 }), // bgeu x10, x31, -2048
+            (0x55f50a63, Beq { rs1: 10, rs2: 31, imm: 1364 }), // beq x10, x31, 1364
+            (0x55f51a63, Bne { rs1: 10, rs2: 31, imm: 1364 }), // bne x10, x31, 1364
+            (0x55f54a63, Blt { rs1: 10, rs2: 31, imm: 1364 }), // blt x10, x31, 1364
+            (0x55f55a63, Bge { rs1: 10, rs2: 31, imm: 1364 }), // bge x10, x31, 1364
+            (0x55f56a63, Bltu { rs1: 10, rs2: 31, imm: 1364 }), // bltu x10, x31, 1364
+            (0x55f57a63, Bgeu { rs1: 10, rs2: 31, imm: 1364 }), // bgeu x10, x31, 1364
+            (0xabf505e3, Beq { rs1: 10, rs2: 31, imm: -1366 }), // beq x10, x31, -1366
+            (0xabf515e3, Bne { rs1: 10, rs2: 31, imm: -1366 }), // bne x10, x31, -1366
+            (0xabf545e3, Blt { rs1: 10, rs2: 31, imm: -1366 }), // blt x10, x31, -1366
+            (0xabf555e3, Bge { rs1: 10, rs2: 31, imm: -1366 }), // bge x10, x31, -1366
+            (0xabf565e3, Bltu { rs1: 10, rs2: 31, imm: -1366 }), // bltu x10, x31, -1366
+            (0xabf575e3, Bgeu { rs1: 10, rs2: 31, imm: -1366 }), // bgeu x10, x31, -1366
+            (0x000000ef, Jal { rd: 1, imm: 0 }), // jal x1, 0
+            (0x004000ef, Jal { rd: 1, imm: 4 }), // jal x1, 4
+            (0xffdff0ef, Jal { rd: 1, imm: -4 }), // jal x1, -4
+            (0x7ffff0ef, Jal { rd: 1, imm: 1048574 }), // jal x1, 1048574
+            (0x800000ef, Jal { rd: 1, imm: -1048576 }), // jal x1, -1048576
+            (0x001000ef, Jal { rd: 1, imm: 2048 }), // jal x1, 2048
+            (0x554550ef, Jal { rd: 1, imm: 349524 }), // jal x1, 349524
+            (0xd56d50ef, Jal { rd: 1, imm: -174762 }), // jal x1, -174762
+            (0x000007b7, Lui { rd: 15, imm: 0 }), // lui x15, 0
+            (0x00000297, Auipc { rd: 5, imm: 0 }), // auipc x5, 0
+            (0x000017b7, Lui { rd: 15, imm: 4096 }), // lui x15, 1
+            (0x00001297, Auipc { rd: 5, imm: 4096 }), // auipc x5, 1
+            (0xfffff7b7, Lui { rd: 15, imm: -4096 }), // lui x15, 1048575
+            (0xfffff297, Auipc { rd: 5, imm: -4096 }), // auipc x5, 1048575
+            (0x800007b7, Lui { rd: 15, imm: -2147483648 }), // lui x15, 524288
+            (0x80000297, Auipc { rd: 5, imm: -2147483648 }), // auipc x5, 524288
+            (0x123457b7, Lui { rd: 15, imm: 305418240 }), // lui x15, 74565
+            (0x12345297, Auipc { rd: 5, imm: 305418240 }), // auipc x5, 74565
+            (0x01f881b3, Add { rd: 3, rs1: 17, rs2: 31 }), // add x3, x17, x31
+            (0x41f881b3, Sub { rd: 3, rs1: 17, rs2: 31 }), // sub x3, x17, x31
+            (0x01f891b3, Sll { rd: 3, rs1: 17, rs2: 31 }), // sll x3, x17, x31
+            (0x01f8a1b3, Slt { rd: 3, rs1: 17, rs2: 31 }), // slt x3, x17, x31
+            (0x01f8b1b3, Sltu { rd: 3, rs1: 17, rs2: 31 }), // sltu x3, x17, x31
+            (0x01f8c1b3, Xor { rd: 3, rs1: 17, rs2: 31 }), // xor x3, x17, x31
+            (0x01f8d1b3, Srl { rd: 3, rs1: 17, rs2: 31 }), // srl x3, x17, x31
+            (0x41f8d1b3, Sra { rd: 3, rs1: 17, rs2: 31 }), // sra x3, x17, x31
+            (0x01f8e1b3, Or { rd: 3, rs1: 17, rs2: 31 }), // or x3, x17, x31
+            (0x01f8f1b3, And { rd: 3, rs1: 17, rs2: 31 }), // and x3, x17, x31
+            (0x00049413, Slli { rd: 8, rs1: 9, shamt: 0 }), // slli x8, x9, 0
+            (0x0004d413, Srli { rd: 8, rs1: 9, shamt: 0 }), // srli x8, x9, 0
+            (0x4004d413, Srai { rd: 8, rs1: 9, shamt: 0 }), // srai x8, x9, 0
+            (0x00149413, Slli { rd: 8, rs1: 9, shamt: 1 }), // slli x8, x9, 1
+            (0x0014d413, Srli { rd: 8, rs1: 9, shamt: 1 }), // srli x8, x9, 1
+            (0x4014d413, Srai { rd: 8, rs1: 9, shamt: 1 }), // srai x8, x9, 1
+            (0x01f49413, Slli { rd: 8, rs1: 9, shamt: 31 }), // slli x8, x9, 31
+            (0x01f4d413, Srli { rd: 8, rs1: 9, shamt: 31 }), // srli x8, x9, 31
+            (0x41f4d413, Srai { rd: 8, rs1: 9, shamt: 31 }), // srai x8, x9, 31
+            (0x03f881b3, Mul { rd: 3, rs1: 17, rs2: 31 }), // mul x3, x17, x31
+            (0x03f891b3, Mulh { rd: 3, rs1: 17, rs2: 31 }), // mulh x3, x17, x31
+            (0x03f8a1b3, Mulhsu { rd: 3, rs1: 17, rs2: 31 }), // mulhsu x3, x17, x31
+            (0x03f8b1b3, Mulhu { rd: 3, rs1: 17, rs2: 31 }), // mulhu x3, x17, x31
+            (0x03f8c1b3, Div { rd: 3, rs1: 17, rs2: 31 }), // div x3, x17, x31
+            (0x03f8d1b3, Divu { rd: 3, rs1: 17, rs2: 31 }), // divu x3, x17, x31
+            (0x03f8e1b3, Rem { rd: 3, rs1: 17, rs2: 31 }), // rem x3, x17, x31
+            (0x03f8f1b3, Remu { rd: 3, rs1: 17, rs2: 31 }), // remu x3, x17, x31
+            (0x203120b3, Sh1add { rd: 1, rs1: 2, rs2: 3 }), // sh1add x1, x2, x3
+            (0x203140b3, Sh2add { rd: 1, rs1: 2, rs2: 3 }), // sh2add x1, x2, x3
+            (0x203160b3, Sh3add { rd: 1, rs1: 2, rs2: 3 }), // sh3add x1, x2, x3
+            (0x403170b3, Andn { rd: 1, rs1: 2, rs2: 3 }), // andn x1, x2, x3
+            (0x403160b3, Orn { rd: 1, rs1: 2, rs2: 3 }), // orn x1, x2, x3
+            (0x403140b3, Xnor { rd: 1, rs1: 2, rs2: 3 }), // xnor x1, x2, x3
+            (0x60059513, Clz { rd: 10, rs1: 11 }), // clz x10, x11
+            (0x60159513, Ctz { rd: 10, rs1: 11 }), // ctz x10, x11
+            (0x60259513, Cpop { rd: 10, rs1: 11 }), // cpop x10, x11
+            (0x0a3160b3, Max { rd: 1, rs1: 2, rs2: 3 }), // max x1, x2, x3
+            (0x0a3170b3, Maxu { rd: 1, rs1: 2, rs2: 3 }), // maxu x1, x2, x3
+            (0x0a3140b3, Min { rd: 1, rs1: 2, rs2: 3 }), // min x1, x2, x3
+            (0x0a3150b3, Minu { rd: 1, rs1: 2, rs2: 3 }), // minu x1, x2, x3
+            (0x60459513, SextB { rd: 10, rs1: 11 }), // sext.b x10, x11
+            (0x60559513, SextH { rd: 10, rs1: 11 }), // sext.h x10, x11
+            (0x0805c533, ZextH { rd: 10, rs1: 11 }), // zext.h x10, x11
+            (0x603110b3, Rol { rd: 1, rs1: 2, rs2: 3 }), // rol x1, x2, x3
+            (0x603150b3, Ror { rd: 1, rs1: 2, rs2: 3 }), // ror x1, x2, x3
+            (0x61f5d513, Rori { rd: 10, rs1: 11, shamt: 31 }), // rori x10, x11, 31
+            (0x2875d513, OrcB { rd: 10, rs1: 11 }), // orc.b x10, x11
+            (0x6985d513, Rev8 { rd: 10, rs1: 11 }), // rev8 x10, x11
+            (0x483110b3, Bclr { rd: 1, rs1: 2, rs2: 3 }), // bclr x1, x2, x3
+            (0x48559513, Bclri { rd: 10, rs1: 11, shamt: 5 }), // bclri x10, x11, 5
+            (0x483150b3, Bext { rd: 1, rs1: 2, rs2: 3 }), // bext x1, x2, x3
+            (0x49f5d513, Bexti { rd: 10, rs1: 11, shamt: 31 }), // bexti x10, x11, 31
+            (0x683110b3, Binv { rd: 1, rs1: 2, rs2: 3 }), // binv x1, x2, x3
+            (0x68059513, Binvi { rd: 10, rs1: 11, shamt: 0 }), // binvi x10, x11, 0
+            (0x283110b3, Bset { rd: 1, rs1: 2, rs2: 3 }), // bset x1, x2, x3
+            (0x29159513, Bseti { rd: 10, rs1: 11, shamt: 17 }), // bseti x10, x11, 17
+            (0x0a3110b3, Clmul { rd: 1, rs1: 2, rs2: 3 }), // clmul x1, x2, x3
+            (0x0a3130b3, Clmulh { rd: 1, rs1: 2, rs2: 3 }), // clmulh x1, x2, x3
+            (0x0a3120b3, Clmulr { rd: 1, rs1: 2, rs2: 3 }), // clmulr x1, x2, x3
+            (0x1005a52f, LrW { rd: 10, rs1: 11, aq: false, rl: false }), // lr.w x10, (x11)
+            (0x1405a52f, LrW { rd: 10, rs1: 11, aq: true, rl: false }), // lr.w.aq x10, (x11)
+            (0x18d7262f, ScW { rd: 12, rs1: 14, rs2: 13, aq: false, rl: false }), // sc.w x12, x13, (x14)
+            (0x1ad7262f, ScW { rd: 12, rs1: 14, rs2: 13, aq: false, rl: true }), // sc.w.rl x12, x13, (x14)
+            (0x0821a0af, AmoswapW { rd: 1, rs1: 3, rs2: 2, aq: false, rl: false }), // amoswap.w x1, x2, (x3)
+            (0x0621a0af, AmoaddW { rd: 1, rs1: 3, rs2: 2, aq: true, rl: true }), // amoadd.w.aqrl x1, x2, (x3)
+            (0x2021a0af, AmoxorW { rd: 1, rs1: 3, rs2: 2, aq: false, rl: false }), // amoxor.w x1, x2, (x3)
+            (0x6021a0af, AmoandW { rd: 1, rs1: 3, rs2: 2, aq: false, rl: false }), // amoand.w x1, x2, (x3)
+            (0x4021a0af, AmoorW { rd: 1, rs1: 3, rs2: 2, aq: false, rl: false }), // amoor.w x1, x2, (x3)
+            (0x8021a0af, AmominW { rd: 1, rs1: 3, rs2: 2, aq: false, rl: false }), // amomin.w x1, x2, (x3)
+            (0xa021a0af, AmomaxW { rd: 1, rs1: 3, rs2: 2, aq: false, rl: false }), // amomax.w x1, x2, (x3)
+            (0xc021a0af, AmominuW { rd: 1, rs1: 3, rs2: 2, aq: false, rl: false }), // amominu.w x1, x2, (x3)
+            (0xe421a0af, AmomaxuW { rd: 1, rs1: 3, rs2: 2, aq: true, rl: false }), // amomaxu.w.aq x1, x2, (x3)
+            (0xffc12087, Flw { rd: 1, rs1: 2, imm: -4 }), // flw f1, -4(x2)
+            (0x7ff13087, Fld { rd: 1, rs1: 2, imm: 2047 }), // fld f1, 2047(x2)
+            (0x80312027, Fsw { rs1: 2, rs2: 3, imm: -2048 }), // fsw f3, -2048(x2)
+            (0x00313427, Fsd { rs1: 2, rs2: 3, imm: 8 }), // fsd f3, 8(x2)
+            (0x203100c3, FmaddS { rd: 1, rs1: 2, rs2: 3, rs3: 4, rm: 0 }), // fmadd.s f1, f2, f3, f4, rne
+            (0x203110c7, FmsubS { rd: 1, rs1: 2, rs2: 3, rs3: 4, rm: 1 }), // fmsub.s f1, f2, f3, f4, rtz
+            (0x203120cb, FnmsubS { rd: 1, rs1: 2, rs2: 3, rs3: 4, rm: 2 }), // fnmsub.s f1, f2, f3, f4, rdn
+            (0x203130cf, FnmaddS { rd: 1, rs1: 2, rs2: 3, rs3: 4, rm: 3 }), // fnmadd.s f1, f2, f3, f4, rup
+            (0x223140c3, FmaddD { rd: 1, rs1: 2, rs2: 3, rs3: 4, rm: 4 }), // fmadd.d f1, f2, f3, f4, rmm
+            (0x223170c7, FmsubD { rd: 1, rs1: 2, rs2: 3, rs3: 4, rm: 7 }), // fmsub.d f1, f2, f3, f4, dyn
+            (0x223170cb, FnmsubD { rd: 1, rs1: 2, rs2: 3, rs3: 4, rm: 7 }), // fnmsub.d f1, f2, f3, f4, dyn
+            (0xe3df7fcf, FnmaddD { rd: 31, rs1: 30, rs2: 29, rs3: 28, rm: 7 }), // fnmadd.d f31, f30, f29, f28, dyn
+            (0x003170d3, FaddS { rd: 1, rs1: 2, rs2: 3, rm: 7 }), // fadd.s f1, f2, f3, dyn
+            (0x083170d3, FsubS { rd: 1, rs1: 2, rs2: 3, rm: 7 }), // fsub.s f1, f2, f3, dyn
+            (0x103170d3, FmulS { rd: 1, rs1: 2, rs2: 3, rm: 7 }), // fmul.s f1, f2, f3, dyn
+            (0x183170d3, FdivS { rd: 1, rs1: 2, rs2: 3, rm: 7 }), // fdiv.s f1, f2, f3, dyn
+            (0x580170d3, FsqrtS { rd: 1, rs1: 2, rm: 7 }), // fsqrt.s f1, f2, dyn
+            (0x023110d3, FaddD { rd: 1, rs1: 2, rs2: 3, rm: 1 }), // fadd.d f1, f2, f3, rtz
+            (0x0a3170d3, FsubD { rd: 1, rs1: 2, rs2: 3, rm: 7 }), // fsub.d f1, f2, f3, dyn
+            (0x123170d3, FmulD { rd: 1, rs1: 2, rs2: 3, rm: 7 }), // fmul.d f1, f2, f3, dyn
+            (0x1a3170d3, FdivD { rd: 1, rs1: 2, rs2: 3, rm: 7 }), // fdiv.d f1, f2, f3, dyn
+            (0x5a0170d3, FsqrtD { rd: 1, rs1: 2, rm: 7 }), // fsqrt.d f1, f2, dyn
+            (0x203100d3, FsgnjS { rd: 1, rs1: 2, rs2: 3 }), // fsgnj.s f1, f2, f3
+            (0x203110d3, FsgnjnS { rd: 1, rs1: 2, rs2: 3 }), // fsgnjn.s f1, f2, f3
+            (0x203120d3, FsgnjxS { rd: 1, rs1: 2, rs2: 3 }), // fsgnjx.s f1, f2, f3
+            (0x283100d3, FminS { rd: 1, rs1: 2, rs2: 3 }), // fmin.s f1, f2, f3
+            (0x283110d3, FmaxS { rd: 1, rs1: 2, rs2: 3 }), // fmax.s f1, f2, f3
+            (0x223100d3, FsgnjD { rd: 1, rs1: 2, rs2: 3 }), // fsgnj.d f1, f2, f3
+            (0x223110d3, FsgnjnD { rd: 1, rs1: 2, rs2: 3 }), // fsgnjn.d f1, f2, f3
+            (0x223120d3, FsgnjxD { rd: 1, rs1: 2, rs2: 3 }), // fsgnjx.d f1, f2, f3
+            (0x2a3100d3, FminD { rd: 1, rs1: 2, rs2: 3 }), // fmin.d f1, f2, f3
+            (0x2a3110d3, FmaxD { rd: 1, rs1: 2, rs2: 3 }), // fmax.d f1, f2, f3
+            (0xa0312553, FeqS { rd: 10, rs1: 2, rs2: 3 }), // feq.s x10, f2, f3
+            (0xa0311553, FltS { rd: 10, rs1: 2, rs2: 3 }), // flt.s x10, f2, f3
+            (0xa0310553, FleS { rd: 10, rs1: 2, rs2: 3 }), // fle.s x10, f2, f3
+            (0xe0011553, FclassS { rd: 10, rs1: 2 }), // fclass.s x10, f2
+            (0xa2312553, FeqD { rd: 10, rs1: 2, rs2: 3 }), // feq.d x10, f2, f3
+            (0xa2311553, FltD { rd: 10, rs1: 2, rs2: 3 }), // flt.d x10, f2, f3
+            (0xa2310553, FleD { rd: 10, rs1: 2, rs2: 3 }), // fle.d x10, f2, f3
+            (0xe2011553, FclassD { rd: 10, rs1: 2 }), // fclass.d x10, f2
+            (0xc0017553, FcvtWS { rd: 10, rs1: 2, rm: 7 }), // fcvt.w.s x10, f2, dyn
+            (0xc0111553, FcvtWuS { rd: 10, rs1: 2, rm: 1 }), // fcvt.wu.s x10, f2, rtz
+            (0xd005f0d3, FcvtSW { rd: 1, rs1: 11, rm: 7 }), // fcvt.s.w f1, x11, dyn
+            (0xd015f0d3, FcvtSWu { rd: 1, rs1: 11, rm: 7 }), // fcvt.s.wu f1, x11, dyn
+            (0xc2017553, FcvtWD { rd: 10, rs1: 2, rm: 7 }), // fcvt.w.d x10, f2, dyn
+            (0xc2117553, FcvtWuD { rd: 10, rs1: 2, rm: 7 }), // fcvt.wu.d x10, f2, dyn
+            (0xd20580d3, FcvtDW { rd: 1, rs1: 11, rm: 0 }), // fcvt.d.w f1, x11
+            (0xd21580d3, FcvtDWu { rd: 1, rs1: 11, rm: 0 }), // fcvt.d.wu f1, x11
+            (0x401170d3, FcvtSD { rd: 1, rs1: 2, rm: 7 }), // fcvt.s.d f1, f2, dyn
+            (0x420100d3, FcvtDS { rd: 1, rs1: 2, rm: 0 }), // fcvt.d.s f1, f2
+            (0xe0010553, FmvXW { rd: 10, rs1: 2 }), // fmv.x.w x10, f2
+            (0xf00580d3, FmvWX { rd: 1, rs1: 11 }), // fmv.w.x f1, x11
+            (0x0ff0000f, Fence { pred: 0xF, succ: 0xF, fm: 0 }), // fence iorw, iorw
+            (0x0310000f, Fence { pred: 0x3, succ: 0x1, fm: 0 }), // fence rw, w
+            (0x0840000f, Fence { pred: 0x8, succ: 0x4, fm: 0 }), // fence i, o
+            (0x8330000f, Fence { pred: 0x3, succ: 0x3, fm: 0x8 }), // fence.tso
+            (0x0100000f, Fence { pred: 0x1, succ: 0x0, fm: 0 }), // pause
+            (0x0000100f, FenceI), // fence.i
+            (0x00000073, Ecall), // ecall
+            (0x00100073, Ebreak), // ebreak
+            (0x30200073, Mret), // mret
+            (0x30059573, Csrrw { rd: 10, rs1: 11, csr: 0x300 }), // csrrw x10, mstatus, x11
+            (0x34102573, Csrrs { rd: 10, rs1: 0, csr: 0x341 }), // csrrs x10, mepc, x0
+            (0x3052b073, Csrrc { rd: 0, rs1: 5, csr: 0x305 }), // csrrc x0, mtvec, x5
+            (0x340fd573, Csrrwi { rd: 10, uimm: 31, csr: 0x340 }), // csrrwi x10, mscratch, 31
+            (0x0010e0f3, Csrrsi { rd: 1, uimm: 1, csr: 0x001 }), // csrrsi x1, fflags, 1
+            (0x00207173, Csrrci { rd: 2, uimm: 0, csr: 0x002 }), // csrrci x2, frm, 0
+            (0xc00021f3, Csrrs { rd: 3, rs1: 0, csr: 0xc00 }), // csrrs x3, cycle, x0
+            (0xc82021f3, Csrrs { rd: 3, rs1: 0, csr: 0xc82 }), // csrrs x3, instreth, x0
+            (0xf1409073, Csrrw { rd: 0, rs1: 1, csr: 0xf14 }), // csrrw x0, mhartid, x1
+    ];
+
+    #[test]
+    fn decodes_known_encodings() {
+        for &(word, expected) in KNOWN_ENCODINGS {
+            assert_eq!(decode_inst(word), expected, "decoding 0x{:08x}", word);
+            assert_eq!(encode(&expected), word, "encoding {:?}", expected);
+        }
+    }
+
+    #[test]
+    fn i_type_immediates_exhaustive() {
+        for imm in -2048..2048 {
+            let word = ((imm as u32) << 20) | (6 << 15) | (5 << 7) | 0b0010011;
+            assert_eq!(decode_inst(word), Addi { rd: 5, rs1: 6, imm });
+        }
+    }
+
+    #[test]
+    fn s_type_immediates_exhaustive() {
+        for imm in -2048..2048 {
+            let u = imm as u32;
+            let word = (((u >> 5) & 0x7F) << 25)
+                | (11 << 20)
+                | (2 << 15)
+                | (0b010 << 12)
+                | ((u & 0x1F) << 7)
+                | 0b0100011;
+            assert_eq!(decode_inst(word), Sw { rs1: 2, rs2: 11, imm });
+        }
+    }
+
+    #[test]
+    fn b_type_immediates_exhaustive() {
+        for imm in (-4096..4096).step_by(2) {
+            let u = imm as u32;
+            let word = (((u >> 12) & 0x1) << 31)
+                | (((u >> 5) & 0x3F) << 25)
+                | (31 << 20)
+                | (10 << 15)
+                | (((u >> 1) & 0xF) << 8)
+                | (((u >> 11) & 0x1) << 7)
+                | 0b1100011;
+            assert_eq!(decode_inst(word), Beq { rs1: 10, rs2: 31, imm });
+        }
+    }
+
+    #[test]
+    fn u_type_immediates_exhaustive() {
+        for upper in 0..(1u32 << 20) {
+            let word = (upper << 12) | (15 << 7) | 0b0110111;
+            assert_eq!(decode_inst(word), Lui { rd: 15, imm: (upper << 12) as i32 });
+        }
+    }
+
+    #[test]
+    fn j_type_immediates_exhaustive() {
+        for imm in (-(1 << 20)..(1 << 20)).step_by(2) {
+            let u = imm as u32;
+            let word = (((u >> 20) & 0x1) << 31)
+                | (((u >> 1) & 0x3FF) << 21)
+                | (((u >> 11) & 0x1) << 20)
+                | (((u >> 12) & 0xFF) << 12)
+                | (1 << 7)
+                | 0b1101111;
+            assert_eq!(decode_inst(word), Jal { rd: 1, imm });
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_encodings() {
+        for word in [
+            0x0000_0000, // all zeros
+            0xFFFF_FFFF, // all ones
+            0x0000_2063, // branch funct3 0b010
+            0x0000_3003, // load funct3 0b011
+            0x0000_3023, // store funct3 0b011
+            0x0000_1067, // jalr funct3 0b001
+            0x4000_1013, // slli with funct7 0b0100000
+            0x2000_5013, // srli/srai with funct7 0b0010000
+            0x4000_1033, // sll with funct7 0b0100000
+            0x0400_0033, // funct7 0b0000010
+            0x0000_00F3, // ecall with rd set
+            0x0000_4073, // system funct3 0b100
+            0x0000_200F, // misc-mem funct3 0b010
+            0x1025_A52F, // lr.w with rs2 set
+            0x0821_B0AF, // amoswap with funct3 0b011
+            0x2821_A0AF, // atomic funct5 0b00101
+            0x6035_9513, // clz/ctz/cpop selector 0b00011
+            0x2865_D513, // orc.b with a different shamt
+            0x6905_D513, // rev8 with a different shamt
+            0x0815_C533, // zext.h with rs2 set
+            0x0020_0073, // reserved SYSTEM funct12
+            0x0001_1087, // FP load funct3 0b001
+            0x5811_70D3, // fsqrt.s with rs2 set
+            0x2431_00C3, // fmadd with fmt 0b10 (half precision)
+            0x2031_30D3, // fsgnj.s funct3 0b011
+            0xE001_2553, // fmv.x.w funct3 0b010
+        ] {
+            assert_eq!(decode_inst(word), Ill, "decoding 0x{:08x}", word);
+        }
+    }
+
+    #[test]
+    fn operand_metadata() {
+        let add = decode_inst(0x00a5_85b3); // add a1, a1, a0
+        assert_eq!(add.dst(), Some(Reg::X(11)));
+        assert_eq!(add.srcs(), [Some(Reg::X(11)), Some(Reg::X(10)), None]);
+        assert_eq!(add.control_flow_kind(), ControlFlowKind::None);
+        assert_eq!(add.mem_op(), None);
+
+        let li = decode_inst(0xfff0_0513); // addi a0, zero, -1
+        assert_eq!(li.srcs(), [None; 3]);
+        let fsd = decode_inst(0x00a5_3427); // fsd fa0, 8(a0)
+        assert_eq!(fsd.dst(), None);
+        assert_eq!(fsd.srcs(), [Some(Reg::X(10)), Some(Reg::F(10)), None]);
+        let fmadd = decode_inst(0x1820_9043); // fmadd.s ft0, ft1, ft2, ft3, rtz
+        assert_eq!(fmadd.fu_class(), FuClass::FpMul);
+        assert_eq!(fmadd.srcs(), [Some(Reg::F(1)), Some(Reg::F(2)), Some(Reg::F(3))]);
+        let feq = decode_inst(0xa2b5_2553); // feq.d a0, fa0, fa1
+        assert_eq!(feq.dst(), Some(Reg::X(10)));
+        assert_eq!(feq.srcs(), [Some(Reg::F(10)), Some(Reg::F(11)), None]);
+        assert_eq!(decode_inst(0x0000_0073).dst(), None); // ecall
+
+        for (word, kind, width, signed) in [
+            (0x0005_4503, MemKind::Load, MemWidth::Byte, false),    // lbu a0, 0(a0)
+            (0x0005_1503, MemKind::Load, MemWidth::Half, true),     // lh a0, 0(a0)
+            (0x1005_252f, MemKind::Load, MemWidth::Word, true),     // lr.w a0, (a0)
+            (0x00a5_3427, MemKind::Store, MemWidth::Double, false), // fsd fa0, 8(a0)
+            (0x06b6_252f, MemKind::Amo, MemWidth::Word, true),      // amoadd.w.aqrl a0, a1, (a2)
+        ] {
+            let op = MemOp { kind, width, signed };
+            assert_eq!(decode_inst(word).mem_op(), Some(op), "0x{:08x}", word);
+        }
+
+        for (word, kind) in [
+            (0x00b5_0463, ControlFlowKind::Branch),       // beq a0, a1, 8
+            (0x0080_006f, ControlFlowKind::Jump),         // j 8
+            (0x0080_00ef, ControlFlowKind::Call),         // jal ra, 8
+            (0x0080_02ef, ControlFlowKind::Call),         // jal t0, 8
+            (0x0000_8067, ControlFlowKind::Return),       // ret
+            (0x0002_8067, ControlFlowKind::Return),       // jr t0
+            (0x0005_0067, ControlFlowKind::IndirectJump), // jr a0
+            (0x0005_00e7, ControlFlowKind::IndirectCall), // jalr a0
+            (0x0000_80e7, ControlFlowKind::IndirectCall), // jalr ra
+            (0x3020_0073, ControlFlowKind::Trap),         // mret
+        ] {
+            assert_eq!(decode_inst(word).control_flow_kind(), kind, "0x{:08x}", word);
+        }
+    }
+
+    /// Register the effect of an instruction writes, as `dst` reports it
+    fn effect_dst(effect: &Effect) -> Option<Reg> {
+        let x = |rd: u32| (rd != 0).then_some(Reg::X(rd));
+        match *effect {
+            Effect::WriteReg { rd, .. }
+            | Effect::Load { rd, .. }
+            | Effect::LoadReserved { rd, .. }
+            | Effect::StoreConditional { rd, .. }
+            | Effect::Amo { rd, .. }
+            | Effect::Csr { rd, .. } => x(rd),
+            Effect::WriteFreg { rd, .. } | Effect::LoadFp { rd, .. } => Some(Reg::F(rd)),
+            _ => None,
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20_000))]
+
+        #[test]
+        fn metadata_matches_execute(
+            word in any::<u32>(),
+            opcode in 0u32..32,
+            mut regs in any::<[u32; 32]>(),
+            fregs in any::<[u64; 32]>(),
+            mut other_regs in any::<[u32; 32]>(),
+            other_fregs in any::<[u64; 32]>(),
+        ) {
+            let inst = decode_inst(word & !0x7F | opcode << 2 | 0b11);
+            let fp_file = |fregs: [u64; 32]| {
+                let mut file = FpRegFile::new();
+                for (i, &val) in fregs.iter().enumerate() {
+                    file.set_raw(i as u32, val);
+                }
+                file
+            };
+            let fregs = fp_file(fregs);
+            regs[0] = 0;
+            let outcome = execute(&inst, 0x1000, 4, &regs, &fregs);
+
+            // Registers that aren't sources can't change the outcome
+            let mut other_fregs = fp_file(other_fregs);
+            other_regs[0] = 0;
+            for src in inst.srcs().into_iter().flatten() {
+                match src {
+                    Reg::X(r) => other_regs[r as usize] = regs[r as usize],
+                    Reg::F(r) => other_fregs.set_raw(r, fregs.raw(r)),
+                }
+            }
+            prop_assert_eq!(execute(&inst, 0x1000, 4, &other_regs, &other_fregs), outcome);
+
+            if outcome.effect != Effect::Illegal {
+                prop_assert_eq!(inst.dst(), effect_dst(&outcome.effect));
+            }
+            match outcome.effect {
+                Effect::Load { width, signed, .. } => {
+                    let op = inst.mem_op().unwrap();
+                    prop_assert_eq!((op.kind, op.width, op.signed), (MemKind::Load, width, signed));
+                }
+                Effect::LoadFp { width, .. } | Effect::Store { width, .. } => {
+                    prop_assert_eq!(inst.mem_op().map(|op| op.width), Some(width));
+                }
+                _ => {}
+            }
+        }
+    }
+}
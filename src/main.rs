@@ -1,45 +1,360 @@
+#[cfg(test)]
+mod asm;
 mod components;
+mod compressed;
+mod cosim;
+mod csr;
+mod disasm;
+#[cfg(test)]
+mod encode;
+mod error;
+mod exec;
+mod fpu;
+mod functional;
+mod htif;
 mod instructions;
+mod spike;
+mod syscall;
+mod trap;
 
-use clap::Parser;
-use std::{cell::RefCell, path::PathBuf, rc::Rc};
-use log;
+use clap::{Parser, ValueEnum};
+use std::cell::Ref;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
 
-use crate::components::{component::Component, memory::{MemType, Memory, QueueMem}};
+use crate::components::cache::{CacheConfig, Inclusion, WriteMiss, WritePolicy};
+use crate::components::core::{CoreConfig, TimingCore};
+use crate::components::hierarchy::LevelCache;
+use crate::components::memory::{ElfInfo, QueueMem};
+use crate::components::replacement::Replacement;
+use crate::components::trace_core::TraceCore;
+use crate::cosim::CoSim;
+use crate::error::SimError;
+use crate::functional::FunctionalSim;
+use crate::spike::{CommitLog, CommitWriter};
 
+/// Exit code for a failing program whose status has no bits set in its low 8, which would
+/// otherwise exit 0
+const TRUNCATED_FAILURE: i32 = 1;
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Mode {
+    /// Functional model only, no timing
+    Functional,
+    /// Cycle level timing core
+    Timing,
+    /// Timing core checked against the functional model every retired instruction
+    Cosim,
+    /// Timing core replaying a Spike `--log-commits` trace instead of running an ELF
+    Trace,
+}
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    #[arg(value_name = "ELF_FILE", help = "ELF file to run")]
+    #[arg(value_name = "FILE", help = "ELF file to run, or Spike commit log to replay in trace mode")]
     binary: PathBuf,
 
-    #[arg(short, long, value_name = "FILE", default_value = "stats.txt", help = "Output location of run stats")]
+    #[arg(short, long, value_name = "FILE", default_value = "stats.txt", help = "Write run stats, such as cycles and cache hits and misses, to FILE")]
     stats_file: PathBuf,
 
-    #[arg(short, long, value_name = "FILE", default_value = "trace.txt", help = "Output location of program trace")]
-    trace_file: PathBuf,
+    #[arg(short, long, value_name = "FILE", help = "Write a Spike-format commit log of every retired instruction to FILE")]
+    trace_file: Option<PathBuf>,
+
+    #[arg(short, long, value_enum, default_value_t = Mode::Functional, help = "Simulation model to run")]
+    mode: Mode,
+
+    #[arg(long, help = "Take exceptions, ecall and access faults as M-mode traps to mtvec instead of stopping the run and emulating syscalls, for bare-metal programs with their own trap handlers")]
+    traps: bool,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().mul_latency, help = "Latency of multiply instructions in the timing core")]
+    mul_latency: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().div_latency, help = "Latency of divide and remainder instructions in the timing core")]
+    div_latency: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().bitmanip_latency, help = "Latency of bit count, byte reverse and orc.b instructions in the timing core")]
+    bitmanip_latency: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().clmul_latency, help = "Latency of carry-less multiply instructions in the timing core")]
+    clmul_latency: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().fp_add_latency, help = "Latency of FP add, compare, conversion and move instructions in the timing core")]
+    fp_add_latency: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().fp_mul_latency, help = "Latency of FP multiply and fused multiply-add instructions in the timing core")]
+    fp_mul_latency: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().fp_div_latency, help = "Latency of FP divide and square root instructions in the timing core")]
+    fp_div_latency: u32,
+
+    #[arg(long, value_name = "BYTES", default_value_t = CoreConfig::default().icache.size, help = "Size of the L1 instruction cache")]
+    icache_size: u32,
+
+    #[arg(long, value_name = "WAYS", default_value_t = CoreConfig::default().icache.ways, help = "Associativity of the L1 instruction cache")]
+    icache_ways: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().icache.hit_latency, help = "Hit latency of the L1 instruction cache")]
+    icache_latency: u32,
+
+    #[arg(long, value_name = "COUNT", default_value_t = CoreConfig::default().icache.mshrs, help = "Misses the L1 instruction cache can have outstanding")]
+    icache_mshrs: u32,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::default().icache.replacement, help = "Replacement policy of the L1 instruction cache")]
+    icache_policy: Replacement,
+
+    #[arg(long, value_name = "BYTES", default_value_t = CoreConfig::default().dcache.size, help = "Size of the L1 data cache")]
+    dcache_size: u32,
+
+    #[arg(long, value_name = "WAYS", default_value_t = CoreConfig::default().dcache.ways, help = "Associativity of the L1 data cache")]
+    dcache_ways: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::default().dcache.hit_latency, help = "Hit latency of the L1 data cache")]
+    dcache_latency: u32,
+
+    #[arg(long, value_name = "COUNT", default_value_t = CoreConfig::default().dcache.mshrs, help = "Misses the L1 data cache can have outstanding")]
+    dcache_mshrs: u32,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::default().dcache.replacement, help = "Replacement policy of the L1 data cache")]
+    dcache_policy: Replacement,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::default().dcache.write_policy, help = "When writes to the L1 data cache reach memory")]
+    dcache_write_policy: WritePolicy,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::default().dcache.write_miss, help = "Whether a write miss in the L1 data cache fills the line")]
+    dcache_write_miss: WriteMiss,
+
+    #[arg(long, value_name = "ENTRIES", default_value_t = CoreConfig::default().dcache.write_buffer, help = "Entries of the L1 data cache's coalescing write buffer, 0 for none")]
+    dcache_write_buffer: u32,
+
+    #[arg(long, value_name = "BYTES", default_value_t = 0, help = "Size of the unified L2 cache behind the L1s, 0 for none")]
+    l2_size: u32,
+
+    #[arg(long, value_name = "WAYS", default_value_t = CoreConfig::L2.ways, help = "Associativity of the L2 cache")]
+    l2_ways: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::L2.hit_latency, help = "Hit latency of the L2 cache")]
+    l2_latency: u32,
+
+    #[arg(long, value_name = "COUNT", default_value_t = CoreConfig::L2.mshrs, help = "Misses the L2 cache can have outstanding")]
+    l2_mshrs: u32,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::L2.replacement, help = "Replacement policy of the L2 cache")]
+    l2_policy: Replacement,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::L2.write_policy, help = "When writes to the L2 cache reach memory")]
+    l2_write_policy: WritePolicy,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::L2.inclusion, help = "Whether the L2 cache holds the blocks the L1s hold")]
+    l2_inclusion: Inclusion,
+
+    #[arg(long, value_name = "BYTES", default_value_t = 0, help = "Size of the unified L3 cache behind the L2, 0 for none")]
+    l3_size: u32,
+
+    #[arg(long, value_name = "WAYS", default_value_t = CoreConfig::L3.ways, help = "Associativity of the L3 cache")]
+    l3_ways: u32,
+
+    #[arg(long, value_name = "CYCLES", default_value_t = CoreConfig::L3.hit_latency, help = "Hit latency of the L3 cache")]
+    l3_latency: u32,
+
+    #[arg(long, value_name = "COUNT", default_value_t = CoreConfig::L3.mshrs, help = "Misses the L3 cache can have outstanding")]
+    l3_mshrs: u32,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::L3.replacement, help = "Replacement policy of the L3 cache")]
+    l3_policy: Replacement,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::L3.write_policy, help = "When writes to the L3 cache reach memory")]
+    l3_write_policy: WritePolicy,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CoreConfig::L3.inclusion, help = "Whether the L3 cache holds the blocks the caches in front of it hold")]
+    l3_inclusion: Inclusion,
+
+    #[arg(long, value_name = "SEED", default_value_t = CoreConfig::default().dcache.seed, help = "Seed for the random and BRRIP replacement policies")]
+    replacement_seed: u64,
 }
 
 fn main() {
     env_logger::init();
     let cli = Cli::parse();
-    log::info!("Loading elf into memory...");
-    let (mut mem, pc) = QueueMem::load_elf(cli.binary);
-    log::info!("Loaded elf into memory");
-    let nums: [u8;64] = [0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63];
-    let t1 = mem.write_block(0x00000000, nums, MemType::DMem);
-    mem.cycle();
-    let t2: Rc<RefCell<components::memory::MemoryTransaction>> = mem.read_block(0x00000000, MemType::DMem);
-    log::info!("{:?}", *t1);
-    log::info!("{:?}", *t2);
-
-    for _ in 0..60 {
-        mem.cycle();
+    match run(cli) {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(exit_status(code)),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(e.exit_code());
+        }
     }
-    log::info!("{:?}", *t1);
-    log::info!("{:?}", *t2);
+}
 
+/// Process exit code for a nonzero program status. Only its low 8 bits reach the shell,
+/// so a status such as 256 that would read as a pass exits with `TRUNCATED_FAILURE`.
+fn exit_status(status: i32) -> i32 {
+    if status & 0xff == 0 {
+        TRUNCATED_FAILURE
+    } else {
+        status
+    }
+}
 
+/// Runs the program, returning the status it exited with
+fn run(cli: Cli) -> Result<i32, SimError> {
+    let config = CoreConfig {
+        mul_latency: cli.mul_latency.max(1),
+        div_latency: cli.div_latency.max(1),
+        bitmanip_latency: cli.bitmanip_latency.max(1),
+        clmul_latency: cli.clmul_latency.max(1),
+        fp_add_latency: cli.fp_add_latency.max(1),
+        fp_mul_latency: cli.fp_mul_latency.max(1),
+        fp_div_latency: cli.fp_div_latency.max(1),
+        icache: CacheConfig {
+            size: cli.icache_size,
+            ways: cli.icache_ways,
+            hit_latency: cli.icache_latency,
+            mshrs: cli.icache_mshrs,
+            write_policy: CoreConfig::default().icache.write_policy,
+            write_miss: CoreConfig::default().icache.write_miss,
+            write_buffer: CoreConfig::default().icache.write_buffer,
+            inclusion: CoreConfig::default().icache.inclusion,
+            replacement: cli.icache_policy,
+            seed: cli.replacement_seed,
+        },
+        dcache: CacheConfig {
+            size: cli.dcache_size,
+            ways: cli.dcache_ways,
+            hit_latency: cli.dcache_latency,
+            mshrs: cli.dcache_mshrs,
+            write_policy: cli.dcache_write_policy,
+            write_miss: cli.dcache_write_miss,
+            write_buffer: cli.dcache_write_buffer,
+            inclusion: CoreConfig::default().dcache.inclusion,
+            replacement: cli.dcache_policy,
+            seed: cli.replacement_seed,
+        },
+        l2: (cli.l2_size > 0).then_some(CacheConfig {
+            size: cli.l2_size,
+            ways: cli.l2_ways,
+            hit_latency: cli.l2_latency,
+            mshrs: cli.l2_mshrs,
+            write_policy: cli.l2_write_policy,
+            inclusion: cli.l2_inclusion,
+            replacement: cli.l2_policy,
+            seed: cli.replacement_seed,
+            ..CoreConfig::L2
+        }),
+        l3: (cli.l3_size > 0).then_some(CacheConfig {
+            size: cli.l3_size,
+            ways: cli.l3_ways,
+            hit_latency: cli.l3_latency,
+            mshrs: cli.l3_mshrs,
+            write_policy: cli.l3_write_policy,
+            inclusion: cli.l3_inclusion,
+            replacement: cli.l3_policy,
+            seed: cli.replacement_seed,
+            ..CoreConfig::L3
+        }),
+    };
+    let l1s = [("L1I", config.icache), ("L1D", config.dcache)];
+    for (name, cache) in l1s.into_iter().chain(config.shared_levels()) {
+        cache
+            .validate()
+            .map_err(|msg| SimError::BadConfig(format!("{}: {}", name, msg)))?;
+    }
+    let load = || -> Result<(QueueMem, ElfInfo), SimError> {
+        log::info!("Loading elf into memory...");
+        let (mem, info) = QueueMem::load_elf(cli.binary.clone())?;
+        log::info!("Loaded elf into memory, starting at PC 0x{:08x}", info.entry);
+        Ok((mem, info))
+    };
+    let commit_log = match &cli.trace_file {
+        Some(path) => {
+            let file = File::create(path).map_err(SimError::CommitLog)?;
+            Some(CommitWriter::new(BufWriter::new(file)))
+        }
+        None => None,
+    };
+    let (regs, exit_code, stats) = match cli.mode {
+        Mode::Functional => {
+            let (mem, info) = load()?;
+            let mut sim = FunctionalSim::new(mem, &info);
+            sim.set_traps(cli.traps);
+            if let Some(log) = commit_log {
+                sim.set_commit_log(log);
+            }
+            sim.run()?;
+            log::info!("Retired {} instructions", sim.retired());
+            let stats = format!("instructions {}\n", sim.retired());
+            (*sim.regs(), sim.exit_code(), stats)
+        }
+        Mode::Timing => {
+            let (mem, info) = load()?;
+            let mut core = TimingCore::new(mem, &info, config);
+            core.set_traps(cli.traps);
+            if let Some(log) = commit_log {
+                core.set_commit_log(log);
+            }
+            core.run()?;
+            log::info!("Retired {} instructions in {} cycles", core.retired(), core.cycles());
+            let stats = report(core.cycles(), core.retired(), [core.icache(), core.dcache()], &core.hierarchy().levels());
+            (*core.regs(), core.exit_code(), stats)
+        }
+        Mode::Cosim => {
+            let (mem, info) = load()?;
+            let mut cosim = CoSim::new(mem, &info, config);
+            cosim.set_traps(cli.traps);
+            if let Some(log) = commit_log {
+                cosim.set_commit_log(log);
+            }
+            cosim.run()?;
+            let core = cosim.core();
+            let stats = report(core.cycles(), core.retired(), [core.icache(), core.dcache()], &core.hierarchy().levels());
+            (*core.regs(), core.exit_code(), stats)
+        }
+        Mode::Trace => {
+            if commit_log.is_some() {
+                log::warn!("Replaying a trace does not write a commit log");
+            }
+            let commits = CommitLog::new(BufReader::new(File::open(&cli.binary)?));
+            let mut core = TraceCore::new(QueueMem::empty(), commits, config);
+            core.run()?;
+            log::info!("Replayed {} instructions in {} cycles", core.retired(), core.cycles());
+            let stats = report(core.cycles(), core.retired(), [core.icache(), core.dcache()], &core.hierarchy().levels());
+            (*core.regs(), 0, stats)
+        }
+    };
+    for (i, val) in regs.iter().enumerate() {
+        log::info!("x{:<2} = 0x{:08x}", i, val);
+    }
+    std::fs::write(&cli.stats_file, stats).map_err(SimError::StatsFile)?;
+    Ok(exit_code)
+}
+
+/// Logs the caches' counts and returns the run's stats as `name value` lines
+fn report(cycles: u64, retired: u64, l1s: [&LevelCache; 2], shared: &[Ref<'_, LevelCache>]) -> String {
+    let mut stats = format!("cycles {}\ninstructions {}\n", cycles, retired);
+    let caches = l1s.into_iter().map(|cache| (cache, false));
+    for (cache, shared) in caches.chain(shared.iter().map(|level| (&**level, true))) {
+        let replacement = cache.config().replacement;
+        log::info!("{} ({}): {}", cache.name(), replacement, cache.stats());
+        let name = cache.name().to_lowercase();
+        let counts = cache.stats();
+        stats += &format!("{}.replacement {}\n", name, replacement);
+        stats += &format!("{}.write_policy {}\n", name, cache.config().write_policy);
+        stats += &format!("{}.write_miss {}\n", name, cache.config().write_miss);
+        if shared {
+            stats += &format!("{}.inclusion {}\n", name, cache.config().inclusion);
+        }
+        stats += &format!("{}.hits {}\n", name, counts.hits);
+        stats += &format!("{}.misses {}\n", name, counts.misses);
+        stats += &format!("{}.evictions {}\n", name, counts.evictions);
+        stats += &format!("{}.dirty_evictions {}\n", name, counts.dirty_evictions);
+        stats += &format!("{}.mshr_merges {}\n", name, counts.mshr_merges);
+        stats += &format!("{}.mshr_full {}\n", name, counts.mshr_full);
+        stats += &format!("{}.writes_coalesced {}\n", name, counts.writes_coalesced);
+        stats += &format!("{}.back_invalidations {}\n", name, counts.back_invalidations);
+        for (counter, val) in cache.policy_counters() {
+            stats += &format!("{}.{} {}\n", name, counter, val);
+        }
+    }
+    stats
 }
@@ -0,0 +1,391 @@
+use crate::compressed::inst_len;
+use crate::csr;
+use crate::error::SimError;
+use crate::exec::Retired;
+use crate::instructions::{decode_inst, Reg};
+use std::fmt;
+use std::io::{BufRead, Write};
+
+/// One retired instruction from a Spike `--log-commits` trace, such as
+/// `core   0: 3 0x80000010 (0x0182a283) x5  0x00000000 mem 0x80000018`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    /// Privilege level the instruction retired in, 3 for M-mode
+    pub privilege: u8,
+    pub pc: u32,
+    /// Instruction word, a compressed instruction in the low 16 bits
+    pub word: u32,
+    /// Registers written and their new values, FP values as raw 64-bit patterns
+    pub reg_writes: Vec<(Reg, u64)>,
+    /// CSRs written and their new values
+    pub csr_writes: Vec<(u32, u32)>,
+    /// Addresses read by loads, AMOs and `lr.w`
+    pub loads: Vec<u32>,
+    /// Addresses written by stores, AMOs and successful `sc.w`, with the value written
+    pub stores: Vec<(u32, u64)>,
+}
+
+/// Reads commit records from a Spike log, skipping lines that aren't commits such as
+/// the `-l` instruction log and exception reports
+pub struct CommitLog<R: BufRead> {
+    reader: R,
+    /// Number of the line last read, from 1
+    line: usize,
+}
+
+impl<R: BufRead> CommitLog<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, line: 0 }
+    }
+
+    fn error(&self, msg: String) -> SimError {
+        SimError::BadTrace {
+            line: self.line,
+            msg,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CommitLog<R> {
+    type Item = Result<Commit, SimError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut text = String::new();
+        loop {
+            text.clear();
+            self.line += 1;
+            match self.reader.read_line(&mut text) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(self.error(e.to_string()))),
+            }
+            match parse_commit(&text) {
+                Ok(Some(commit)) => return Some(Ok(commit)),
+                Ok(None) => {}
+                Err(msg) => return Some(Err(self.error(msg))),
+            }
+        }
+    }
+}
+
+/// Writes retired instructions in Spike's `--log-commits` format, so a run can be diffed
+/// line by line against Spike. Everything runs in M-mode on core 0.
+pub struct CommitWriter {
+    out: Box<dyn Write>,
+}
+
+impl fmt::Debug for CommitWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommitWriter").finish_non_exhaustive()
+    }
+}
+
+impl CommitWriter {
+    pub fn new(out: impl Write + 'static) -> Self {
+        Self { out: Box::new(out) }
+    }
+
+    /// Writes one line for a retired instruction. Spike doesn't log instructions that
+    /// trap, so neither does this.
+    pub fn write(&mut self, record: &Retired) -> std::io::Result<()> {
+        if record.trap.is_some() {
+            return Ok(());
+        }
+        // Values are printed as wide as the register or access, compressed words as 16 bits
+        let digits = if inst_len(record.word as u16) == 2 { 4 } else { 8 };
+        write!(self.out, "core   0: 3 0x{:08x} (0x{:0digits$x})", record.pc, record.word)?;
+        if let Some((rd, val)) = record.reg_write {
+            write!(self.out, " x{:<2} 0x{:08x}", rd, val)?;
+        }
+        if let Some((rd, val)) = record.freg_write {
+            write!(self.out, " f{:<2} 0x{:016x}", rd, val)?;
+        }
+        if let Some((num, val)) = record.csr_write {
+            let name = csr::name(num).unwrap_or("unknown");
+            write!(self.out, " c{}_{} 0x{:08x}", num, name, val)?;
+        }
+        if let Some(addr) = record.mem_read {
+            write!(self.out, " mem 0x{:08x}", addr)?;
+        }
+        if let Some(store) = record.mem_write {
+            let bits = 8 * store.width.bytes();
+            let val = store.val & (u64::MAX >> (64 - bits));
+            let digits = bits as usize / 4;
+            write!(self.out, " mem 0x{:08x} 0x{:0digits$x}", store.addr, val)?;
+        }
+        writeln!(self.out)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Parses a line of a commit log, `None` if it is not a commit record
+fn parse_commit(text: &str) -> Result<Option<Commit>, String> {
+    let Some((_core, rest)) = text.trim().strip_prefix("core").and_then(|s| s.split_once(':')) else {
+        return Ok(None);
+    };
+    let mut tokens = rest.split_whitespace().peekable();
+    // The instruction log has the PC straight after the core number, commits the privilege level
+    let privilege = match tokens.next().map(str::parse::<u8>) {
+        Some(Ok(privilege)) => privilege,
+        _ => return Ok(None),
+    };
+    let pc = hex32(tokens.next().ok_or("missing PC")?)?;
+    let word = tokens
+        .next()
+        .and_then(|s| s.strip_prefix('(')?.strip_suffix(')'))
+        .ok_or("missing instruction word")?;
+    let mut commit = Commit {
+        privilege,
+        pc,
+        word: hex32(word)?,
+        reg_writes: Vec::new(),
+        csr_writes: Vec::new(),
+        loads: Vec::new(),
+        stores: Vec::new(),
+    };
+    while let Some(token) = tokens.next() {
+        if token == "mem" {
+            let addr = hex32(tokens.next().ok_or("missing memory address")?)?;
+            // A store is followed by the value written, a load by nothing or the next write
+            match tokens.next_if(|s| s.starts_with("0x")) {
+                Some(val) => commit.stores.push((addr, hex(val)?)),
+                None => commit.loads.push(addr),
+            }
+            continue;
+        }
+        let val = hex(tokens.next().ok_or_else(|| format!("missing value for {}", token))?)?;
+        let num = |s: &str| s.parse::<u32>().ok();
+        match token.split_at_checked(1) {
+            Some(("x", reg)) => match num(reg).filter(|&r| r < 32) {
+                // x0 writes are dropped by the register file
+                Some(0) => {}
+                Some(r) => commit.reg_writes.push((Reg::X(r), val)),
+                None => return Err(format!("bad register {}", token)),
+            },
+            Some(("f", reg)) => match num(reg).filter(|&r| r < 32) {
+                Some(r) => commit.reg_writes.push((Reg::F(r), val)),
+                None => return Err(format!("bad register {}", token)),
+            },
+            // CSRs are written as the number followed by the name, `c768_mstatus`
+            Some(("c", csr)) => match csr.split('_').next().and_then(num) {
+                Some(csr) => commit.csr_writes.push((csr, val as u32)),
+                None => return Err(format!("bad CSR {}", token)),
+            },
+            _ => return Err(format!("unsupported write {}", token)),
+        }
+    }
+    // Replay goes through caches that only take naturally aligned accesses, which never
+    // cross a line
+    if let Some(op) = decode_inst(commit.word).mem_op() {
+        let size = op.width.bytes();
+        let addrs = commit.loads.iter().chain(commit.stores.iter().map(|(addr, _)| addr));
+        if let Some(addr) = addrs.copied().find(|addr| !addr.is_multiple_of(size)) {
+            return Err(format!("misaligned {} byte access to 0x{:08x}", size, addr));
+        }
+    }
+    Ok(Some(commit))
+}
+
+fn hex(s: &str) -> Result<u64, String> {
+    s.strip_prefix("0x")
+        .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| format!("bad hex value {}", s))
+}
+
+/// Addresses and instruction words must fit RV32
+fn hex32(s: &str) -> Result<u32, String> {
+    u32::try_from(hex(s)?).map_err(|_| format!("{} is wider than 32 bits, only RV32 is supported", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::components::core::{CoreConfig, TimingCore};
+    use crate::components::memory::QueueMem;
+    use crate::components::trace_core::TraceCore;
+    use crate::exec::MemWrite;
+    use crate::instructions::MemWidth;
+    use crate::instructions::decode_inst;
+    use crate::trap::Exception;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Buffer the test keeps a handle on while a `CommitWriter` owns the other
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn commits(text: &str) -> Vec<Result<Commit, SimError>> {
+        CommitLog::new(text.as_bytes()).collect()
+    }
+
+    #[test]
+    fn parses_commit_records() {
+        let log = "\
+core   0: 0x00001000 (0x00000297) auipc   t0, 0x0
+core   0: 3 0x00001000 (0x00000297) x5  0x00001000
+core   0: 3 0x8000000c (0x0182a283) x5  0x00000000 mem 0x80000018
+core   0: 3 0x80000010 (0x00b2a023) mem 0x80000000 0x00000005
+core   0: 3 0x80000014 (0x4501) x10 0x00000000
+core   0: 3 0x80000016 (0x0cb6252f) x10 0x00000007 mem 0x80001000 mem 0x80001000 0x0000000c
+core   0: 3 0x8000001a (0x00b57553) f10 0xffffffff3f800000 c1_fflags 0x00000001
+core   0: exception trap_illegal_instruction, epc 0x8000001e
+core   0:           tval 0x00000000
+core   0: 3 0x80000020 (0x30529073) c773_mtvec 0x80000100
+";
+        let commits: Vec<_> = commits(log).into_iter().map(Result::unwrap).collect();
+        assert_eq!(commits.len(), 7);
+        assert_eq!(commits[0].pc, 0x1000);
+        assert_eq!(commits[0].privilege, 3);
+        assert_eq!(commits[0].reg_writes, [(Reg::X(5), 0x1000)]);
+        assert_eq!(commits[1].loads, [0x8000_0018]);
+        assert_eq!(commits[2].stores, [(0x8000_0000, 5)]);
+        assert!(commits[2].reg_writes.is_empty());
+        assert_eq!(commits[3].word, 0x4501);
+        assert_eq!(commits[4].loads, [0x8000_1000]);
+        assert_eq!(commits[4].stores, [(0x8000_1000, 0xc)]);
+        assert_eq!(commits[5].reg_writes, [(Reg::F(10), 0xffff_ffff_3f80_0000)]);
+        assert_eq!(commits[5].csr_writes, [(0x001, 1)]);
+        assert_eq!(commits[6].csr_writes, [(0x305, 0x8000_0100)]);
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let log = "core   0: 3 0x80000000 (0x00000013)\ncore   0: 3 0xffffffff80000004 (0x00000013)\n";
+        let results = commits(log);
+        assert!(results[0].is_ok());
+        match &results[1] {
+            Err(SimError::BadTrace { line: 2, msg }) => assert!(msg.contains("only RV32")),
+            other => panic!("expected a bad trace error, got {:?}", other),
+        }
+        let log = "core   0: 3 0x80000000 (0x00000013) v8 0x0\n";
+        assert!(matches!(commits(log)[0], Err(SimError::BadTrace { line: 1, .. })));
+        // lw from and sw to the last bytes of a line, which the caches can't take
+        let log = "core   0: 3 0x80000000 (0x00052503) x10 0x00000000 mem 0x8000103e\n\
+                   core   0: 3 0x80000004 (0x00a52023) mem 0x8000103d 0x00000000\n\
+                   core   0: 3 0x80000008 (0x00a51023) mem 0x8000103e 0x0000\n";
+        let results = commits(log);
+        for (i, line) in [(0, 1), (1, 2)] {
+            match &results[i] {
+                Err(SimError::BadTrace { line: l, msg }) if *l == line => {
+                    assert!(msg.contains("misaligned"), "{}", msg)
+                }
+                other => panic!("expected a misaligned access error, got {:?}", other),
+            }
+        }
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn writes_spike_format() {
+        let record = |pc, word| Retired::new(pc, word, decode_inst(word));
+        let store = |addr, width, val| Some(MemWrite { addr, width, val });
+        let records = [
+            Retired {
+                reg_write: Some((5, 0x8000_0000)),
+                ..record(0x8000_0000, 0x0000_0297)
+            },
+            Retired {
+                reg_write: Some((10, 0xffff_ff80)),
+                mem_read: Some(0x8000_1000),
+                ..record(0x8000_0004, 0x0005_0503)
+            },
+            Retired {
+                mem_write: store(0x8000_1001, MemWidth::Byte, 0x1234_5678),
+                ..record(0x8000_0008, 0x00b5_00a3)
+            },
+            Retired {
+                reg_write: Some((10, 0)),
+                ..record(0x8000_000c, 0x4501)
+            },
+            Retired {
+                trap: Some(Exception::Illegal { word: 0 }),
+                ..record(0x8000_000e, 0)
+            },
+            Retired {
+                freg_write: Some((10, 0xffff_ffff_3f80_0000)),
+                csr_write: Some((csr::FFLAGS, 1)),
+                ..record(0x8000_0100, 0x00b5_7553)
+            },
+            Retired {
+                reg_write: Some((7, 5)),
+                mem_read: Some(0x8000_1000),
+                mem_write: store(0x8000_1000, MemWidth::Word, 0xc),
+                ..record(0x8000_0104, 0x0cb6_23af)
+            },
+        ];
+        let out = Shared::default();
+        let mut writer = CommitWriter::new(out.clone());
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        let text = String::from_utf8(out.0.take()).unwrap();
+        assert_eq!(
+            text,
+            "\
+core   0: 3 0x80000000 (0x00000297) x5  0x80000000
+core   0: 3 0x80000004 (0x00050503) x10 0xffffff80 mem 0x80001000
+core   0: 3 0x80000008 (0x00b500a3) mem 0x80001001 0x78
+core   0: 3 0x8000000c (0x4501) x10 0x00000000
+core   0: 3 0x80000100 (0x00b57553) f10 0xffffffff3f800000 c1_fflags 0x00000001
+core   0: 3 0x80000104 (0x0cb623af) x7  0x00000005 mem 0x80001000 mem 0x80001000 0x0000000c
+"
+        );
+        // What is written parses back
+        let commits: Vec<_> = CommitLog::new(text.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(commits.len(), 6);
+        assert_eq!(commits[2].stores, [(0x8000_1001, 0x78)]);
+        assert_eq!(commits[5].loads, [0x8000_1000]);
+    }
+
+    #[test]
+    fn replays_own_commit_log() {
+        let program = assemble(
+            "
+            lui a0, 2
+            li a1, 10
+            li a2, 0
+        loop:
+            sw a1, 0(a0)
+            lw t0, 0(a0)
+            mul t1, t0, a1
+            add a2, a2, t1
+            sb t1, 4(a0)
+            addi a0, a0, 64
+            addi a1, a1, -1
+            bnez a1, loop
+            divu a0, a2, a1
+            ret
+            ",
+            0x1000,
+        )
+        .unwrap();
+        let (mem, info) = QueueMem::with_program(0x1000, &program);
+        let mut core = TimingCore::new(mem, &info, CoreConfig::default());
+        let out = Shared::default();
+        core.set_commit_log(CommitWriter::new(out.clone()));
+        core.run().unwrap();
+
+        let text = out.0.take();
+        let commits = CommitLog::new(text.as_slice());
+        let mut replay = TraceCore::new(QueueMem::empty(), commits, CoreConfig::default());
+        replay.run().unwrap();
+        assert_eq!(replay.retired(), core.retired());
+        assert_eq!(replay.cycles(), core.cycles());
+        // sp is set up before the first instruction, so the trace never has it
+        assert_eq!(replay.regs()[5..], core.regs()[5..]);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::components::memory::Memory;
+use crate::error::SimError;
+use std::io::Write;
+
+// Syscall numbers used by newlib and the RISC-V proxy kernel
+const SYS_CLOSE: u32 = 57;
+const SYS_WRITE: u32 = 64;
+const SYS_FSTAT: u32 = 80;
+const SYS_EXIT: u32 = 93;
+const SYS_EXIT_GROUP: u32 = 94;
+const SYS_GETTIMEOFDAY: u32 = 169;
+const SYS_BRK: u32 = 214;
+
+const EBADF: i32 = 9;
+
+/// Size of the proxy kernel's `struct kernel_stat` on RV32
+const KERNEL_STAT_SIZE: u32 = 128;
+const KERNEL_STAT_MODE_OFFSET: u32 = 16;
+const KERNEL_STAT_NLINK_OFFSET: u32 = 20;
+const KERNEL_STAT_BLKSIZE_OFFSET: u32 = 56;
+/// Character device, so newlib line buffers stdout like a terminal
+const S_IFCHR: u32 = 0o020000;
+
+/// Instructions retired per simulated microsecond, the clock `gettimeofday` reports.
+/// Deriving time from retired instructions keeps runs deterministic and identical across models.
+const INSTS_PER_US: u64 = 1000;
+
+/// Result of servicing an `ecall`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallResult {
+    /// Value written back to a0
+    Return(u32),
+    /// Program exited with a status code
+    Exit(i32),
+}
+
+/// Emulates the newlib syscalls a bare-metal program makes, against host
+/// stdout/stderr and the simulated memory.
+#[derive(Debug, Clone)]
+pub struct Syscalls {
+    /// Current program break
+    brk: u32,
+    /// Drop program output, used when another model is already printing it
+    quiet: bool,
+}
+
+impl Syscalls {
+    pub fn new(brk: u32) -> Self {
+        Self { brk, quiet: false }
+    }
+
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Services the syscall in a7 with arguments from a0-a5
+    pub fn call(
+        &mut self,
+        pc: u32,
+        regs: &[u32; 32],
+        mem: &mut impl Memory,
+        retired: u64,
+    ) -> Result<SyscallResult, SimError> {
+        let num = regs[17];
+        let (a0, a1, a2) = (regs[10], regs[11], regs[12]);
+        log::debug!(
+            "Syscall {} (0x{:08x}, 0x{:08x}, 0x{:08x}) at 0x{:08x}",
+            num,
+            a0,
+            a1,
+            a2,
+            pc
+        );
+        let ret = match num {
+            SYS_WRITE => self.write(a0, a1, a2, mem),
+            SYS_CLOSE => {
+                if a0 <= 2 {
+                    0
+                } else {
+                    -EBADF as u32
+                }
+            }
+            SYS_FSTAT => {
+                if a0 <= 2 {
+                    for i in 0..KERNEL_STAT_SIZE {
+                        mem.backdoor_write(a1.wrapping_add(i), 0);
+                    }
+                    write_word(mem, a1.wrapping_add(KERNEL_STAT_MODE_OFFSET), S_IFCHR | 0o620);
+                    write_word(mem, a1.wrapping_add(KERNEL_STAT_NLINK_OFFSET), 1);
+                    write_word(mem, a1.wrapping_add(KERNEL_STAT_BLKSIZE_OFFSET), 1024);
+                    0
+                } else {
+                    -EBADF as u32
+                }
+            }
+            SYS_EXIT | SYS_EXIT_GROUP => return Ok(SyscallResult::Exit(a0 as i32)),
+            SYS_GETTIMEOFDAY => {
+                // struct timeval with a 64-bit time_t
+                if a0 != 0 {
+                    let us = retired / INSTS_PER_US;
+                    let secs = us / 1_000_000;
+                    write_word(mem, a0, secs as u32);
+                    write_word(mem, a0.wrapping_add(4), (secs >> 32) as u32);
+                    write_word(mem, a0.wrapping_add(8), (us % 1_000_000) as u32);
+                }
+                0
+            }
+            SYS_BRK => {
+                if a0 >= self.brk {
+                    self.brk = a0;
+                }
+                self.brk
+            }
+            _ => return Err(SimError::UnsupportedSyscall { pc, num }),
+        };
+        Ok(SyscallResult::Return(ret))
+    }
+
+    fn write(&mut self, fd: u32, buf: u32, len: u32, mem: &mut impl Memory) -> u32 {
+        let data: Vec<u8> = (0..len)
+            .map(|i| mem.backdoor_read(buf.wrapping_add(i)))
+            .collect();
+        if self.quiet && fd <= 2 {
+            return len;
+        }
+        let result = match fd {
+            1 => std::io::stdout().write_all(&data),
+            2 => std::io::stderr().write_all(&data),
+            _ => return -EBADF as u32,
+        };
+        match result {
+            Ok(()) => len,
+            Err(e) => {
+                log::warn!("Failed to write program output: {}", e);
+                -EBADF as u32
+            }
+        }
+    }
+}
+
+fn write_word(mem: &mut impl Memory, addr: u32, val: u32) {
+    for (i, b) in val.to_le_bytes().into_iter().enumerate() {
+        mem.backdoor_write(addr.wrapping_add(i as u32), b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::memory::QueueMem;
+
+    const BUF: u32 = 0x3000_0000;
+
+    /// Registers for syscall `num` with arguments `args` in a0 upwards
+    fn regs(num: u32, args: &[u32]) -> [u32; 32] {
+        let mut regs = [0; 32];
+        regs[17] = num;
+        regs[10..10 + args.len()].copy_from_slice(args);
+        regs
+    }
+
+    fn call(syscalls: &mut Syscalls, mem: &mut QueueMem, num: u32, args: &[u32]) -> SyscallResult {
+        syscalls.call(0x1000, &regs(num, args), mem, 2_500_000_000).unwrap()
+    }
+
+    fn read_word(mem: &mut QueueMem, addr: u32) -> u32 {
+        u32::from_le_bytes([0, 1, 2, 3].map(|i| mem.backdoor_read(addr + i)))
+    }
+
+    #[test]
+    fn services_newlib_syscalls() {
+        let (mut mem, info) = QueueMem::with_program(0x1000, &[0x13]);
+        let mut sys = Syscalls::new(info.brk);
+        sys.set_quiet(true);
+        let ret = SyscallResult::Return;
+        let ebadf = ret(-EBADF as u32);
+
+        assert_eq!(call(&mut sys, &mut mem, SYS_WRITE, &[1, BUF, 5]), ret(5));
+        assert_eq!(call(&mut sys, &mut mem, SYS_WRITE, &[2, BUF, 3]), ret(3));
+        assert_eq!(call(&mut sys, &mut mem, SYS_WRITE, &[3, BUF, 3]), ebadf);
+
+        assert_eq!(call(&mut sys, &mut mem, SYS_CLOSE, &[1]), ret(0));
+        assert_eq!(call(&mut sys, &mut mem, SYS_CLOSE, &[3]), ebadf);
+
+        mem.backdoor_write(BUF, 0xFF);
+        assert_eq!(call(&mut sys, &mut mem, SYS_FSTAT, &[1, BUF]), ret(0));
+        assert_eq!(read_word(&mut mem, BUF), 0);
+        assert_eq!(read_word(&mut mem, BUF + KERNEL_STAT_MODE_OFFSET), S_IFCHR | 0o620);
+        assert_eq!(read_word(&mut mem, BUF + KERNEL_STAT_NLINK_OFFSET), 1);
+        assert_eq!(read_word(&mut mem, BUF + KERNEL_STAT_BLKSIZE_OFFSET), 1024);
+        assert_eq!(call(&mut sys, &mut mem, SYS_FSTAT, &[3, BUF]), ebadf);
+
+        // 2.5e9 instructions retired is 2.5 simulated seconds
+        assert_eq!(call(&mut sys, &mut mem, SYS_GETTIMEOFDAY, &[BUF, 0]), ret(0));
+        assert_eq!(read_word(&mut mem, BUF), 2);
+        assert_eq!(read_word(&mut mem, BUF + 4), 0);
+        assert_eq!(read_word(&mut mem, BUF + 8), 500_000);
+        assert_eq!(call(&mut sys, &mut mem, SYS_GETTIMEOFDAY, &[0, 0]), ret(0));
+
+        // brk(0) queries the break, which only grows
+        assert_eq!(call(&mut sys, &mut mem, SYS_BRK, &[0]), ret(info.brk));
+        assert_eq!(call(&mut sys, &mut mem, SYS_BRK, &[0x2000]), ret(0x2000));
+        assert_eq!(call(&mut sys, &mut mem, SYS_BRK, &[0x1800]), ret(0x2000));
+
+        let exit = SyscallResult::Exit(-3);
+        assert_eq!(call(&mut sys, &mut mem, SYS_EXIT, &[-3i32 as u32]), exit);
+        assert_eq!(call(&mut sys, &mut mem, SYS_EXIT_GROUP, &[-3i32 as u32]), exit);
+    }
+
+    #[test]
+    fn unknown_syscalls_are_errors() {
+        let (mut mem, info) = QueueMem::with_program(0x1000, &[0x13]);
+        let mut sys = Syscalls::new(info.brk);
+        // openat
+        let result = sys.call(0x1000, &regs(56, &[0, BUF, 0]), &mut mem, 0);
+        assert!(matches!(
+            result,
+            Err(SimError::UnsupportedSyscall { pc: 0x1000, num: 56 })
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::components::memory::MemoryMap;
+use crate::error::SimError;
+use crate::exec::Effect;
+
+/// Synchronous exception raised by an instruction. Under the trap model these are taken
+/// as M-mode traps to `mtvec`, whether or not the program has installed a handler there,
+/// otherwise they stop the simulation with the matching `SimError`. Data accesses only
+/// raise access faults under the trap model, without it data memory is sparse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionMisaligned { addr: u32 },
+    /// Fetch from an address with nothing loaded at it
+    InstructionFault { addr: u32 },
+    Illegal { word: u32 },
+    Breakpoint,
+    LoadMisaligned { addr: u32, size: u32 },
+    /// Load or LR from an address outside the memory map
+    LoadFault { addr: u32 },
+    /// Misaligned store, SC or AMO
+    StoreMisaligned { addr: u32, size: u32 },
+    /// Store, SC or AMO to an address outside the memory map
+    StoreFault { addr: u32 },
+    /// Environment call from M-mode
+    Ecall,
+}
+
+impl Exception {
+    /// Value written to `mcause`
+    pub fn cause(self) -> u32 {
+        match self {
+            Exception::InstructionMisaligned { .. } => 0,
+            Exception::InstructionFault { .. } => 1,
+            Exception::Illegal { .. } => 2,
+            Exception::Breakpoint => 3,
+            Exception::LoadMisaligned { .. } => 4,
+            Exception::LoadFault { .. } => 5,
+            Exception::StoreMisaligned { .. } => 6,
+            Exception::StoreFault { .. } => 7,
+            Exception::Ecall => 11,
+        }
+    }
+
+    /// Value written to `mtval` for an exception raised by the instruction at `pc`
+    pub fn tval(self, pc: u32) -> u32 {
+        match self {
+            Exception::InstructionMisaligned { addr }
+            | Exception::InstructionFault { addr }
+            | Exception::LoadMisaligned { addr, .. }
+            | Exception::LoadFault { addr }
+            | Exception::StoreMisaligned { addr, .. }
+            | Exception::StoreFault { addr } => addr,
+            Exception::Illegal { word } => word,
+            Exception::Breakpoint => pc,
+            Exception::Ecall => 0,
+        }
+    }
+
+    /// Error stopping the simulation when there is no handler to take the trap. `word` is
+    /// the instruction, or as much of it as was fetched.
+    pub fn into_error(self, pc: u32, word: u32) -> SimError {
+        match self {
+            Exception::InstructionMisaligned { addr } => {
+                SimError::MisalignedAccess { pc, addr, size: 2 }
+            }
+            Exception::InstructionFault { addr }
+            | Exception::LoadFault { addr }
+            | Exception::StoreFault { addr } => SimError::AddressFault { pc, addr, word },
+            Exception::Illegal { word } => SimError::IllegalInstruction { pc, word },
+            Exception::Breakpoint => SimError::Breakpoint { pc },
+            Exception::LoadMisaligned { addr, size }
+            | Exception::StoreMisaligned { addr, size } => {
+                SimError::MisalignedAccess { pc, addr, size }
+            }
+            Exception::Ecall => {
+                unreachable!("ecall without a handler is serviced by syscall emulation")
+            }
+        }
+    }
+}
+
+/// Exception for a data access that is not naturally aligned
+pub fn misaligned(effect: &Effect) -> Option<Exception> {
+    match *effect {
+        Effect::Load { addr, width, .. } | Effect::LoadFp { addr, width, .. }
+            if !addr.is_multiple_of(width.bytes()) =>
+        {
+            Some(Exception::LoadMisaligned {
+                addr,
+                size: width.bytes(),
+            })
+        }
+        Effect::Store { addr, width, .. } if !addr.is_multiple_of(width.bytes()) => {
+            Some(Exception::StoreMisaligned {
+                addr,
+                size: width.bytes(),
+            })
+        }
+        // LR raises a load exception, SC and AMOs raise store/AMO exceptions
+        Effect::LoadReserved { addr, .. } if !addr.is_multiple_of(4) => {
+            Some(Exception::LoadMisaligned { addr, size: 4 })
+        }
+        Effect::StoreConditional { addr, .. } | Effect::Amo { addr, .. }
+            if !addr.is_multiple_of(4) =>
+        {
+            Some(Exception::StoreMisaligned { addr, size: 4 })
+        }
+        _ => None,
+    }
+}
+
+/// Exception for a data access to bytes outside `map`
+pub fn access_fault(effect: &Effect, map: &MemoryMap) -> Option<Exception> {
+    match *effect {
+        Effect::Load { addr, width, .. } | Effect::LoadFp { addr, width, .. }
+            if !map.contains(addr, width.bytes()) =>
+        {
+            Some(Exception::LoadFault { addr })
+        }
+        Effect::Store { addr, width, .. } if !map.contains(addr, width.bytes()) => {
+            Some(Exception::StoreFault { addr })
+        }
+        Effect::LoadReserved { addr, .. } if !map.contains(addr, 4) => {
+            Some(Exception::LoadFault { addr })
+        }
+        Effect::StoreConditional { addr, .. } | Effect::Amo { addr, .. }
+            if !map.contains(addr, 4) =>
+        {
+            Some(Exception::StoreFault { addr })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::memory::QueueMem;
+    use crate::instructions::MemWidth;
+    use crate::functional::STACK_START;
+
+    #[test]
+    fn misaligned_data_accesses() {
+        let load = |addr, width| Effect::Load {
+            rd: 1,
+            addr,
+            width,
+            signed: true,
+        };
+        let store = |addr, width| Effect::Store { addr, width, val: 0 };
+        assert_eq!(misaligned(&load(0x1002, MemWidth::Half)), None);
+        assert_eq!(
+            misaligned(&load(0x1002, MemWidth::Word)),
+            Some(Exception::LoadMisaligned { addr: 0x1002, size: 4 })
+        );
+        assert_eq!(misaligned(&store(0x1003, MemWidth::Byte)), None);
+        let fsd = misaligned(&store(0x1004, MemWidth::Double)).unwrap();
+        assert_eq!((fsd.cause(), fsd.tval(0x400)), (6, 0x1004));
+        assert_eq!(misaligned(&Effect::Ecall), None);
+    }
+
+    #[test]
+    fn access_faults_outside_the_map() {
+        let (_, info) = QueueMem::with_program(0x1000, &[0x13; 4]);
+        let load = |addr| Effect::Load {
+            rd: 1,
+            addr,
+            width: MemWidth::Word,
+            signed: true,
+        };
+        assert_eq!(access_fault(&load(0x100C), &info.map), None);
+        assert_eq!(
+            access_fault(&load(0x1010), &info.map),
+            Some(Exception::LoadFault { addr: 0x1010 })
+        );
+        let fsd = Effect::Store {
+            addr: 0x100C,
+            width: MemWidth::Double,
+            val: 0,
+        };
+        let fault = access_fault(&fsd, &info.map).unwrap();
+        assert_eq!((fault.cause(), fault.tval(0x1000)), (7, 0x100C));
+        let stack = Effect::Store {
+            addr: STACK_START - 4,
+            width: MemWidth::Word,
+            val: 0,
+        };
+        assert_eq!(access_fault(&stack, &info.map), None);
+    }
+
+    #[test]
+    fn tval_per_cause() {
+        let pc = 0x8000;
+        assert_eq!(Exception::Illegal { word: 0xFFFF_FFFF }.tval(pc), 0xFFFF_FFFF);
+        assert_eq!(Exception::Breakpoint.tval(pc), pc);
+        assert_eq!(Exception::Ecall.tval(pc), 0);
+        assert_eq!(Exception::InstructionFault { addr: 0x8002 }.tval(pc), 0x8002);
+        assert_eq!(Exception::Ecall.cause(), 11);
+    }
+}